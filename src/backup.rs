@@ -0,0 +1,134 @@
+use crate::bootloader::grub::Grub;
+use crate::common::backup_archive::{BACKUP_VERSION, BackupArchive, BackupItem, BackupPart};
+use crate::common::{dry_run, prompt, timestamp};
+use crate::platform::Platform;
+use std::io::{ErrorKind, Result};
+
+/// The item name of the exported BCD store
+const BCD_ITEM: &str = "BCD";
+
+/// Save the boot configuration to a backup archive
+///
+/// Parts missing on this system, e.g. GRUB on Windows without a GRUB
+/// partition or EFI variables on a BIOS system, are skipped with a note.
+/// # Arguments
+/// * `grub` - The GRUB boot loader
+/// * `file` - The archive to write
+pub(crate) fn create_backup(platform: &mut dyn Platform, grub: &Grub, file: &str) -> Result<()> {
+    let mut items = Vec::new();
+    match grub.backup_files(platform) {
+        Ok(files) => items.extend(files),
+        Err(e) if e.kind() == ErrorKind::NotFound => println!("Skipping GRUB: {}", e),
+        Err(e) => return Err(e),
+    }
+    match platform.get_boot_var_names() {
+        Ok(names) => {
+            for name in names {
+                if let Some(data) = platform.get_efi_var(&name)? {
+                    items.push(BackupItem {
+                        part: BackupPart::Efi,
+                        name,
+                        data,
+                    });
+                }
+            }
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => println!("Skipping EFI variables: {}", e),
+        Err(e) => return Err(e),
+    }
+    if let Some(data) = platform.export_fw_store()? {
+        items.push(BackupItem {
+            part: BackupPart::Bcd,
+            name: BCD_ITEM.to_string(),
+            data,
+        });
+    }
+
+    let archive = BackupArchive {
+        version: BACKUP_VERSION,
+        created: timestamp::now(),
+        items,
+    };
+    for part in BackupPart::ALL {
+        let names: Vec<&str> = archive
+            .items
+            .iter()
+            .filter(|i| i.part == part)
+            .map(|i| i.name.as_str())
+            .collect();
+        if !names.is_empty() {
+            println!("{}: {}", part.name(), names.join(", "));
+        }
+    }
+    let data = archive.to_bytes();
+    match dry_run::is_enabled() {
+        true => println!("Would write backup {} ({} bytes)", file, data.len()),
+        false => {
+            std::fs::write(file, &data)?;
+            println!("Saved backup to {}", file)
+        }
+    }
+    Ok(())
+}
+
+/// Restore parts of a backup archive
+///
+/// The changes are shown first as in dry-run mode, then made after the user
+/// agrees. EFI variables created after the backup are left in place.
+/// # Arguments
+/// * `grub` - The GRUB boot loader
+/// * `file` - The archive to read
+/// * `parts` - The parts to restore, all if empty
+/// * `yes` - Restore without asking
+pub(crate) fn restore_backup(
+    platform: &mut dyn Platform,
+    grub: &Grub,
+    file: &str,
+    parts: &[BackupPart],
+    yes: bool,
+) -> Result<()> {
+    let archive = BackupArchive::parse(&std::fs::read(file)?)?;
+    println!(
+        "Backup of {} (format version {})",
+        timestamp::format_utc(archive.created),
+        archive.version
+    );
+    let items: Vec<&BackupItem> = archive
+        .items
+        .iter()
+        .filter(|i| parts.is_empty() || parts.contains(&i.part))
+        .collect();
+    if items.is_empty() {
+        println!("Nothing to restore");
+        return Ok(());
+    }
+
+    dry_run::preview(|| restore_items(platform, grub, &items))?;
+    if dry_run::is_enabled() {
+        return Ok(());
+    }
+    if !yes && !prompt::confirm("Restore these changes?")? {
+        println!("Restore cancelled");
+        return Ok(());
+    }
+    restore_items(platform, grub, &items)?;
+    println!("Restored {} items from {}", items.len(), file);
+    Ok(())
+}
+
+/// Write back the items of a backup, skipping EFI variables that did not change
+fn restore_items(platform: &mut dyn Platform, grub: &Grub, items: &[&BackupItem]) -> Result<()> {
+    for item in items {
+        match item.part {
+            BackupPart::Grub => grub.restore_file(platform, &item.name, &item.data)?,
+            BackupPart::Efi => match platform.get_efi_var(&item.name)? {
+                Some(data) if data == item.data => {
+                    println!("EFI variable {}: unchanged", item.name)
+                }
+                _ => platform.set_efi_var(&item.name, &item.data)?,
+            },
+            BackupPart::Bcd => platform.import_fw_store(&item.data)?,
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,215 @@
+use crate::bootloader::{BootEntry, Bootloader, BootloaderKind};
+use crate::common::dry_run;
+use crate::common::extlinux::{ExtlinuxConf, ExtlinuxConfig};
+use crate::common::journal::{self, ChangeTarget};
+use crate::common::output;
+use crate::common::selector::{Selectable, select};
+use crate::platform::Platform;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+/// The places of the extlinux/syslinux configuration below /boot, in the order searched
+const EXTLINUX_PATHS: [&str; 4] = [
+    "extlinux/extlinux.conf",
+    "syslinux/syslinux.cfg",
+    "syslinux/extlinux.conf",
+    "extlinux.conf",
+];
+
+/// extlinux/syslinux, managed through its configuration in /boot
+pub(crate) struct Extlinux;
+
+impl Extlinux {
+    /// Show the extlinux/syslinux default, timeout and entries
+    pub fn show_extlinux_entry(&self, platform: &mut dyn Platform) -> Result<()> {
+        let config = read_extlinux_config(platform)?;
+        match config.get("default") {
+            Some(default) => println!("DEFAULT: {}", default),
+            None => println!("DEFAULT: not set"),
+        }
+        match config.get("timeout").and_then(|t| t.parse::<u32>().ok()) {
+            Some(0) => println!("TIMEOUT: 0, waits forever"),
+            Some(timeout) => println!("TIMEOUT: {} ({:.1}s)", timeout, timeout as f32 / 10.0),
+            None => println!("TIMEOUT: not set"),
+        }
+        println!("The extlinux entries:");
+        for i in config.entries() {
+            println!(
+                "{} {} ({}){}",
+                if i.entry_is_default { "*" } else { " " },
+                i.title(),
+                i.entry_label,
+                if i.entry_hidden { " [hidden]" } else { "" }
+            );
+        }
+        Ok(())
+    }
+
+    /// Show the boot details of an extlinux/syslinux entry
+    /// # Arguments
+    /// * `entry` - The selector of the extlinux entry to show
+    pub fn show_extlinux_details(&self, platform: &mut dyn Platform, entry: &str) -> Result<()> {
+        let entries = read_extlinux_config(platform)?.entries();
+        let entry = select(&entries, entry, "extlinux")?;
+        let field = |name: &str, value: Option<String>| {
+            if let Some(value) = value.filter(|v| !v.is_empty()) {
+                println!("{:<12} {}", format!("{}:", name), value);
+            }
+        };
+
+        field("Label", Some(entry.entry_label.clone()));
+        field("Title", entry.entry_menu_label.clone());
+        field("File", Some(entry.entry_file.clone()));
+        field("Kernel", entry.entry_kernel.clone());
+        field("Initrd", entry.entry_initrd.clone());
+        field("Append", entry.entry_append.clone());
+        field("Device tree", entry.entry_fdt.clone());
+        Ok(())
+    }
+}
+
+impl Bootloader for Extlinux {
+    fn kind(&self) -> BootloaderKind {
+        BootloaderKind::Extlinux
+    }
+
+    fn list_entries(&mut self, platform: &mut dyn Platform) -> Result<Vec<BootEntry>> {
+        Ok(read_extlinux_config(platform)?
+            .entries()
+            .into_iter()
+            .map(|e| BootEntry {
+                title: e.title().to_string(),
+                id: e.entry_label,
+                is_default: e.entry_is_default,
+                is_oneshot: false,
+            })
+            .collect())
+    }
+
+    /// Set the default extlinux/syslinux entry by selector
+    fn set_default(&mut self, platform: &mut dyn Platform, entry: &str) -> Result<()> {
+        let mut config = read_extlinux_config(platform)?;
+        let entries = config.entries();
+        let entry = select(&entries, entry, "extlinux")?;
+        let changed = config.set_default(&entry.entry_label);
+        write_extlinux_config(platform, &config, &changed)?;
+        output::note(&format!("Set default extlinux entry: {:?}", entry.title()));
+        Ok(())
+    }
+
+    fn get_timeout(&mut self, platform: &mut dyn Platform) -> Result<Option<String>> {
+        Ok(read_extlinux_config(platform)?.get("timeout"))
+    }
+
+    /// Set `TIMEOUT` of the extlinux/syslinux configuration
+    ///
+    /// The timeout is tenths of a second, 0 to wait forever.
+    fn set_timeout(&mut self, platform: &mut dyn Platform, timeout: &str) -> Result<()> {
+        let tenths = timeout.parse::<u32>().map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid extlinux timeout {}", timeout),
+            )
+        })?;
+        let mut config = read_extlinux_config(platform)?;
+        let file = config.set("timeout", &tenths.to_string());
+        write_extlinux_config(platform, &config, &[file])?;
+        output::note(&format!("Set extlinux timeout: {}", tenths));
+        Ok(())
+    }
+}
+
+/// Find the extlinux/syslinux configuration
+/// # Returns
+/// * `Result<PathBuf>` - The path of extlinux.conf or syslinux.cfg in /boot
+pub(crate) fn find_config(platform: &mut dyn Platform) -> Result<PathBuf> {
+    let boot = platform.get_boot_dir()?;
+    EXTLINUX_PATHS
+        .iter()
+        .map(|path| boot.join(path))
+        .find(|path| path.is_file())
+        .ok_or(Error::new(
+            ErrorKind::NotFound,
+            "No extlinux.conf or syslinux.cfg found in /boot",
+        ))
+}
+
+/// Read the extlinux/syslinux configuration and the files it includes
+///
+/// Missing includes are skipped like syslinux does.
+fn read_extlinux_config(platform: &mut dyn Platform) -> Result<ExtlinuxConfig> {
+    let path = find_config(platform)?;
+    let dir = path.parent().unwrap_or(Path::new("/"));
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut config = ExtlinuxConfig {
+        files: vec![ExtlinuxConf::parse(&name, &std::fs::read_to_string(&path)?)],
+    };
+    let mut i = 0;
+    while i < config.files.len() {
+        for name in config.files[i].includes() {
+            if config.files.iter().any(|f| f.name == name) {
+                continue;
+            }
+            if let Some(include) = resolve_extlinux_include(dir, &name) {
+                let content = std::fs::read_to_string(include)?;
+                config.files.push(ExtlinuxConf::parse(&name, &content));
+            }
+        }
+        i += 1;
+    }
+    Ok(config)
+}
+
+/// Write files of the extlinux/syslinux configuration
+///
+/// Each file is replaced through a temporary file, so the boot loader
+/// never reads a partly written one.
+/// # Arguments
+/// * `config` - The configuration
+/// * `files` - The indices of the files to write
+fn write_extlinux_config(
+    platform: &mut dyn Platform,
+    config: &ExtlinuxConfig,
+    files: &[usize],
+) -> Result<()> {
+    let path = find_config(platform)?;
+    let dir = path.parent().unwrap_or(Path::new("/"));
+    for &file in files {
+        let conf = &config.files[file];
+        let target = match file {
+            0 => path.clone(),
+            _ => resolve_extlinux_include(dir, &conf.name).ok_or(Error::new(
+                ErrorKind::NotFound,
+                format!("{} not found", conf.name),
+            ))?,
+        };
+        let content = conf.to_content();
+        journal::track(
+            ChangeTarget::File(target.clone()),
+            || journal::read_file(&target),
+            || {
+                dry_run::write_file(&target, &content, || {
+                    let temp = target.with_extension("bootmgr-tmp");
+                    std::fs::write(&temp, &content)?;
+                    std::fs::rename(&temp, &target)
+                })
+            },
+        )?;
+    }
+    Ok(())
+}
+
+/// Find an included extlinux/syslinux file
+///
+/// Relative paths are relative to the directory of the main file, absolute
+/// ones to the root of its filesystem, which is tried from the innermost
+/// directory up since /boot may be a filesystem of its own.
+fn resolve_extlinux_include(dir: &Path, name: &str) -> Option<PathBuf> {
+    match name.strip_prefix('/') {
+        Some(name) => dir
+            .ancestors()
+            .map(|d| d.join(name))
+            .find(|path| path.is_file()),
+        None => Some(dir.join(name)).filter(|path| path.is_file()),
+    }
+}
@@ -0,0 +1,896 @@
+use crate::bootloader::{BootEntry, Bootloader, BootloaderKind};
+use crate::common::backup_archive::{BackupItem, BackupPart};
+use crate::common::default_grub::DefaultGrub;
+use crate::common::dry_run;
+use crate::common::file_operations;
+use crate::common::grub_cfg;
+use crate::common::grub_helper;
+use crate::common::grub_menu::{GrubMenuAction, describe_menu_state, recordfail_timeout};
+use crate::common::grub_script::{self, GrubContext};
+use crate::common::grub_selector::{STORED_SELECTOR_KEY, resolve_grub_selector};
+use crate::common::grubenv::{GRUB_ENV_SIZE, GrubEnv};
+use crate::common::journal::{self, ChangeTarget};
+use crate::common::output;
+use crate::common::selector::Selectable;
+use crate::platform::Platform;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Result};
+use std::path::{Path, PathBuf};
+
+/// GRUB, managed through grub.cfg, grubenv and /etc/default/grub
+/// Fields:
+/// * `ctx` - The machine grub.cfg is evaluated for
+#[derive(Clone, Default)]
+pub(crate) struct Grub {
+    pub ctx: GrubContext,
+}
+
+impl Grub {
+    /// Get the grub entries from the grub.cfg file
+    /// # Returns
+    /// * `Result<Vec<GrubEntry>>` - A vector of GrubEntry objects
+    pub fn get_grub_entry(&self, platform: &mut dyn Platform) -> Result<Vec<GrubEntry>> {
+        let grub_cfg = get_file(platform, file_operations::GRUB_CFG_PATH)?;
+        let grub_env = get_file(platform, file_operations::GRUB_ENV_PATH)?;
+        self.parse_grub_entries(grub_cfg, grub_env)
+    }
+
+    /// Parse the grub.cfg file to get the grub entries
+    /// # Arguments
+    /// * `cfg` - A File object representing the grub.cfg file
+    /// * `env` - A File object representing the grubenv file
+    /// # Returns
+    /// * `Result<Vec<GrubEntry>>` - A vector of GrubEntry objects
+    fn parse_grub_entries(&self, mut cfg: File, mut env: File) -> Result<Vec<GrubEntry>> {
+        let mut cfg_content = String::new();
+        cfg.read_to_string(&mut cfg_content)?;
+        let mut env_content = String::new();
+        env.read_to_string(&mut env_content)?;
+        let env = GrubEnv::parse(&env_content);
+        let saved_entry = env.get("saved_entry").unwrap_or_default();
+
+        let mut entries = grub_script::evaluate(&cfg_content, &env, &self.ctx)?;
+        for entry in entries.iter_mut() {
+            entry.entry_is_default = entry.is_named_by(saved_entry);
+        }
+        Ok(entries)
+    }
+
+    /// Read the variables of the grubenv file
+    /// # Returns
+    /// * `Result<GrubEnv>` - The variables of the grubenv file
+    pub fn read_grub_env(&self, platform: &mut dyn Platform) -> Result<GrubEnv> {
+        let mut content = String::new();
+        get_file(platform, file_operations::GRUB_ENV_PATH)?.read_to_string(&mut content)?;
+        Ok(GrubEnv::parse(&content))
+    }
+
+    /// Change the variables of the grubenv file, keeping its block size
+    ///
+    /// The block is written through [`write_grub_env`], padded to the length
+    /// the file already has.
+    /// # Arguments
+    /// * `update` - Changes the variables
+    /// # Returns
+    /// * `Result<GrubEnv>` - The variables as written
+    pub fn update_grub_env(
+        &self,
+        platform: &mut dyn Platform,
+        update: impl FnOnce(&mut GrubEnv) -> Result<()>,
+    ) -> Result<GrubEnv> {
+        let mut written = None;
+        write_grub_env(platform, |current| {
+            let mut env = GrubEnv::parse(&String::from_utf8_lossy(current));
+            update(&mut env)?;
+            let size = match current.len() {
+                0 => GRUB_ENV_SIZE,
+                len => len,
+            };
+            let block = env.to_block(size)?;
+            written = Some(env);
+            Ok(block.into_bytes())
+        })?;
+        Ok(written.unwrap_or_default())
+    }
+
+    /// Set the default grub entry in the grubenv file
+    /// # Arguments
+    /// * `grub_entry` - A GrubEntry object representing the grub entry to set as default
+    /// * `selector` - The selector to store and re-resolve on each run, if any
+    /// # Returns
+    /// * `Result<()>` - Ok if successful, Err otherwise
+    fn set_default_grub_entry(
+        &self,
+        platform: &mut dyn Platform,
+        grub_entry: &GrubEntry,
+        selector: Option<&str>,
+    ) -> Result<()> {
+        output::note(&format!(
+            "Set default GRUB entry: {:?}",
+            grub_entry.entry_name
+        ));
+        self.update_grub_env(platform, |env| {
+            env.set("saved_entry", &grub_entry.saved_id());
+            match selector {
+                Some(selector) => env.set(STORED_SELECTOR_KEY, selector),
+                None => env.remove(STORED_SELECTOR_KEY),
+            }
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    /// Re-resolve the stored GRUB selector and update the default entry if it moved
+    pub fn refresh_grub_selector(&self, platform: &mut dyn Platform) -> Result<()> {
+        if let Some((selector, entry)) = self.moved_grub_selector(platform)? {
+            output::note(&format!("Stored GRUB selector '{}' moved", selector));
+            self.set_default_grub_entry(platform, &entry, Some(&selector))?;
+        }
+        Ok(())
+    }
+
+    /// Tell whether the default entry is the one the stored GRUB selector resolves to
+    /// # Returns
+    /// * `Result<bool>` - True if it is or no selector is stored
+    pub fn grub_selector_is_current(&self, platform: &mut dyn Platform) -> Result<bool> {
+        Ok(self.moved_grub_selector(platform)?.is_none())
+    }
+
+    /// Re-resolve the stored GRUB selector
+    /// # Returns
+    /// * `Result<Option<(String, GrubEntry)>>` - The selector and the entry it resolves to now, None if it did not move
+    fn moved_grub_selector(
+        &self,
+        platform: &mut dyn Platform,
+    ) -> Result<Option<(String, GrubEntry)>> {
+        let env = self.read_grub_env(platform)?;
+        let Some(selector) = env.get(STORED_SELECTOR_KEY) else {
+            return Ok(None);
+        };
+        let entries = self.get_grub_entry(platform)?;
+        let entry = resolve_grub_selector(&entries, selector)?;
+        Ok((!env
+            .get("saved_entry")
+            .is_some_and(|saved| entry.is_named_by(saved)))
+        .then(|| (selector.to_string(), entry.clone())))
+    }
+
+    /// Show the grub entries
+    pub fn show_grub_entry(&self, platform: &mut dyn Platform) -> Result<()> {
+        let entries = self.get_grub_entry(platform)?;
+        let env = self.read_grub_env(platform)?;
+        println!("Grub entry:");
+        if let Some(selector) = env.get(STORED_SELECTOR_KEY) {
+            println!("Stored selector: {}", selector);
+        }
+        for i in entries {
+            println!(
+                "{} {}{} ({})",
+                if i.entry_is_default { "*" } else { " " },
+                "  ".repeat(i.entry_parents.len()),
+                i.entry_name,
+                i.entry_id
+            );
+        }
+        let menu_state = describe_menu_state(&env);
+        if !menu_state.is_empty() {
+            println!("Menu state:");
+            for line in menu_state {
+                println!("  {}", line);
+            }
+        }
+        Ok(())
+    }
+
+    /// Change whether the GRUB menu is shown on the next boots
+    /// # Arguments
+    /// * `action` - The change to apply to the grubenv variables
+    pub fn set_grub_menu(&self, platform: &mut dyn Platform, action: GrubMenuAction) -> Result<()> {
+        let mut cfg = String::new();
+        get_file(platform, file_operations::GRUB_CFG_PATH)?.read_to_string(&mut cfg)?;
+        let env = self.update_grub_env(platform, |env| action.apply(env, &cfg))?;
+        if matches!(action, GrubMenuAction::ShowOnce) && env.get("recordfail") == Some("1") {
+            match recordfail_timeout(&cfg) {
+                Some(-1) => output::note(
+                    "GRUB_RECORDFAIL_TIMEOUT is -1, GRUB waits at the menu until a key is pressed",
+                ),
+                Some(timeout) => output::note(&format!(
+                    "GRUB waits at the menu for {} seconds, GRUB_RECORDFAIL_TIMEOUT",
+                    timeout
+                )),
+                None => {}
+            }
+        }
+        let menu_state = describe_menu_state(&env);
+        if menu_state.is_empty() {
+            println!("The GRUB menu follows the timeout of grub.cfg");
+        }
+        for line in menu_state {
+            println!("{}", line);
+        }
+        Ok(())
+    }
+
+    /// Set the grub entry by selector
+    /// # Arguments
+    /// * `entry_id` - The selector of the grub entry to set as default
+    /// * `sticky` - Whether to store the selector and re-resolve it on each `set`
+    /// * `force` - Whether to set an entry whose boot files are missing
+    pub fn set_grub_entry(
+        &self,
+        platform: &mut dyn Platform,
+        entry_id: &str,
+        sticky: bool,
+        force: bool,
+    ) -> Result<()> {
+        let entries = self.get_grub_entry(platform)?;
+        let entry = resolve_grub_selector(&entries, entry_id)?;
+        self.check_bootable(platform, entry, force)?;
+        self.set_default_grub_entry(platform, entry, sticky.then_some(entry_id))
+    }
+
+    /// Refuse an entry whose boot files are missing unless forced
+    fn check_bootable(
+        &self,
+        platform: &mut dyn Platform,
+        entry: &GrubEntry,
+        force: bool,
+    ) -> Result<()> {
+        let missing: Vec<String> = self
+            .check_grub_files(platform, entry)?
+            .into_iter()
+            .filter(|(_, exists)| *exists == Some(false))
+            .map(|(file, _)| file)
+            .collect();
+        if !missing.is_empty() {
+            if !force {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!(
+                        "GRUB entry {:?} is missing {}, use --force to set it anyway",
+                        entry.entry_name,
+                        missing.join(", ")
+                    ),
+                ));
+            }
+            eprintln!("Warning: GRUB entry is missing {}", missing.join(", "));
+        }
+        Ok(())
+    }
+
+    /// Get a setting of /etc/default/grub
+    /// # Arguments
+    /// * `key` - The setting, e.g. `GRUB_DEFAULT`
+    /// # Returns
+    /// * `Result<Option<String>>` - The value, None if it is not set
+    pub fn get_grub_default(
+        &self,
+        platform: &mut dyn Platform,
+        key: &str,
+    ) -> Result<Option<String>> {
+        let path = platform
+            .get_root_dir()?
+            .join(grub_helper::DEFAULT_GRUB_PATH);
+        Ok(read_default_grub(&path)?.get(key))
+    }
+
+    /// Change /etc/default/grub and regenerate grub.cfg from it
+    ///
+    /// Without any change the current settings are shown.
+    /// # Arguments
+    /// * `update` - The settings to change
+    /// * `regenerate` - Whether to regenerate grub.cfg afterwards
+    pub fn update_grub_defaults(
+        &self,
+        platform: &mut dyn Platform,
+        update: &GrubDefaultsUpdate,
+        regenerate: bool,
+    ) -> Result<()> {
+        let path = platform
+            .get_root_dir()?
+            .join(grub_helper::DEFAULT_GRUB_PATH);
+        let mut default_grub = read_default_grub(&path)?;
+        let settings = [
+            ("GRUB_DEFAULT", update.default.clone()),
+            ("GRUB_TIMEOUT", update.timeout.map(|t| t.to_string())),
+            (
+                "GRUB_SAVEDEFAULT",
+                update.savedefault.map(|b| b.to_string()),
+            ),
+            (
+                "GRUB_DISABLE_SUBMENU",
+                update.disable_submenu.map(|b| b.to_string()),
+            ),
+        ];
+        if settings.iter().all(|(_, value)| value.is_none()) {
+            for (key, _) in settings {
+                println!("{}={}", key, default_grub.get(key).unwrap_or_default());
+            }
+            return Ok(());
+        }
+
+        // Entries leave their submenu once grub.cfg is regenerated without submenus
+        let submenus_disabled = match update.disable_submenu {
+            Some(disabled) => disabled,
+            None => matches!(
+                default_grub.get("GRUB_DISABLE_SUBMENU").as_deref(),
+                Some("y" | "true")
+            ),
+        };
+        for (key, value) in settings {
+            let Some(mut value) = value else {
+                continue;
+            };
+            if key == "GRUB_DEFAULT" && value != "saved" {
+                let entries = self.get_grub_entry(platform)?;
+                let entry = resolve_grub_selector(&entries, &value)?;
+                value = match submenus_disabled {
+                    true => entry.entry_id.clone(),
+                    false => entry.saved_id(),
+                };
+            }
+            output::note(&format!("Set {}={}", key, value));
+            default_grub.set(key, &value);
+        }
+        if update.savedefault == Some(true)
+            && default_grub.get("GRUB_DEFAULT").as_deref() != Some("saved")
+        {
+            eprintln!("Warning: GRUB_SAVEDEFAULT only takes effect with GRUB_DEFAULT=saved");
+        }
+        let content = default_grub.to_content();
+        journal::track(
+            ChangeTarget::File(path.clone()),
+            || journal::read_file(&path),
+            || dry_run::write_file(&path, &content, || std::fs::write(&path, &content)),
+        )?;
+
+        if regenerate {
+            let command = grub_helper::regenerate_command(&platform.get_root_dir()?)?;
+            let dir = platform.mount_grub_dir()?;
+            let cfg = grub_path(&dir.path, file_operations::GRUB_CFG_PATH);
+            journal::track(
+                ChangeTarget::GrubFile(relative_name(&dir.path, &cfg)),
+                || journal::read_file(&cfg),
+                || platform.run_in_root(&command),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Check whether the files an entry boots exist
+    /// # Arguments
+    /// * `entry` - The GRUB entry to check
+    /// # Returns
+    /// * `Result<Vec<(String, Option<bool>)>>` - Each file and whether it exists, None if it cannot be checked
+    fn check_grub_files(
+        &self,
+        platform: &mut dyn Platform,
+        entry: &GrubEntry,
+    ) -> Result<Vec<(String, Option<bool>)>> {
+        grub_cfg::entry_files(entry)
+            .into_iter()
+            .map(|file| {
+                let exists = grub_file_exists(platform, entry, &file)?;
+                Ok((file, exists))
+            })
+            .collect()
+    }
+
+    /// Check the boot files of every grub entry and report the broken ones
+    pub fn check_grub_entries(&self, platform: &mut dyn Platform) -> Result<()> {
+        let entries = self.get_grub_entry(platform)?;
+        let mut broken = 0;
+        for entry in &entries {
+            let files = self.check_grub_files(platform, entry)?;
+            let state = if files.iter().any(|(_, e)| *e == Some(false)) {
+                broken += 1;
+                "BROKEN"
+            } else if files.iter().any(|(_, e)| e.is_none()) {
+                "UNKNOWN"
+            } else {
+                "OK"
+            };
+            println!("{:<8} {} ({})", state, entry.entry_name, entry.entry_id);
+            for (file, exists) in files {
+                match exists {
+                    Some(true) => {}
+                    Some(false) => println!("           missing: {}", file),
+                    None => println!("           unknown: {}", file),
+                }
+            }
+        }
+        match broken {
+            0 => Ok(()),
+            n => Err(Error::new(
+                ErrorKind::NotFound,
+                format!("{} GRUB entries are missing boot files", n),
+            )),
+        }
+    }
+
+    /// Show the boot details of a grub entry
+    /// # Arguments
+    /// * `entry_id` - The selector of the grub entry to show
+    pub fn show_grub_details(&self, platform: &mut dyn Platform, entry_id: &str) -> Result<()> {
+        let entries = self.get_grub_entry(platform)?;
+        let entry = resolve_grub_selector(&entries, entry_id)?;
+        let field = |name: &str, value: Option<String>| {
+            if let Some(value) = value.filter(|v| !v.is_empty()) {
+                println!("{:<13} {}", format!("{}:", name), value);
+            }
+        };
+
+        field("Title", Some(entry.entry_name.clone()));
+        field("Id", Some(entry.entry_id.clone()));
+        field("Classes", Some(entry.entry_classes.join(", ")));
+        field("Root UUID", entry.entry_root_uuid.clone());
+        field("Root device", entry.entry_root_device.clone());
+        field("Kernel", entry.entry_kernel.clone());
+        field("Command line", entry.entry_cmdline.clone());
+        field("Initrd", Some(entry.entry_initrd.join(" ")));
+        field("Chainloader", entry.entry_chainloader.clone());
+        field(
+            "Users",
+            match entry.entry_unrestricted {
+                true => Some("unrestricted".to_string()),
+                false => entry.entry_users.clone(),
+            },
+        );
+        field("Hotkey", entry.entry_hotkey.clone());
+        Ok(())
+    }
+
+    /// Show the currently booted, next and default GRUB entries
+    pub fn show_status(&self, platform: &mut dyn Platform) -> Result<()> {
+        let entries = match self.get_grub_entry(platform) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                println!("GRUB: not found");
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        let env = self.read_grub_env(platform)?;
+        let describe = |id: Option<&str>| match id.filter(|id| !id.is_empty()) {
+            Some(id) => match entries.iter().find(|e| e.is_named_by(id)) {
+                Some(e) => format!("{} ({})", e.entry_name, e.entry_id),
+                None => id.to_string(),
+            },
+            None => "not set".to_string(),
+        };
+
+        println!("GRUB:");
+        if let Some(cmdline) = platform.get_boot_cmdline()? {
+            let current = match find_booted_grub_entry(&entries, &cmdline) {
+                Some(e) => describe(Some(&e.saved_id())),
+                None => "unknown".to_string(),
+            };
+            println!("  Current: {}", current);
+        }
+        println!("  Next:    {}", describe(env.get("next_entry")));
+        println!("  Default: {}", describe(env.get("saved_entry")));
+        Ok(())
+    }
+
+    /// Get the GRUB files for a backup
+    ///
+    /// These are grub.cfg, the files it reads with `source` or `configfile`,
+    /// and grubenv.
+    /// # Returns
+    /// * `Result<Vec<BackupItem>>` - The files, named relative to the directory holding GRUB
+    pub fn backup_files(&self, platform: &mut dyn Platform) -> Result<Vec<BackupItem>> {
+        let dir = platform.mount_grub_dir()?;
+        let cfg = grub_path(&dir.path, file_operations::GRUB_CFG_PATH);
+        let prefix = cfg
+            .parent()
+            .and_then(Path::file_name)
+            .map(|name| format!("/{}", name.to_string_lossy()))
+            .unwrap_or_default();
+
+        let mut paths = vec![cfg];
+        let mut items = Vec::new();
+        let mut i = 0;
+        while i < paths.len() {
+            let data = std::fs::read(&paths[i])?;
+            for file in grub_cfg::sourced_files(&String::from_utf8_lossy(&data), &prefix) {
+                let relative = file.trim_start_matches('/');
+                let found = [relative, relative.strip_prefix("boot/").unwrap_or(relative)]
+                    .iter()
+                    .map(|path| dir.path.join(path))
+                    .find(|path| path.is_file());
+                if let Some(path) = found
+                    && !paths.contains(&path)
+                {
+                    paths.push(path);
+                }
+            }
+            items.push(BackupItem {
+                part: BackupPart::Grub,
+                name: relative_name(&dir.path, &paths[i]),
+                data,
+            });
+            i += 1;
+        }
+
+        let env = grub_path(&dir.path, file_operations::GRUB_ENV_PATH);
+        if env.is_file() {
+            items.push(BackupItem {
+                part: BackupPart::Grub,
+                name: relative_name(&dir.path, &env),
+                data: std::fs::read(&env)?,
+            });
+        }
+        Ok(items)
+    }
+
+    /// Write back a GRUB file of a backup
+    /// # Arguments
+    /// * `name` - The path of the file relative to the directory holding GRUB
+    /// * `data` - The saved content
+    pub fn restore_file(&self, platform: &mut dyn Platform, name: &str, data: &[u8]) -> Result<()> {
+        if Path::new(name)
+            .components()
+            .any(|c| !matches!(c, std::path::Component::Normal(_)))
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Refusing to restore GRUB file outside the GRUB directory: {}",
+                    name
+                ),
+            ));
+        }
+        if name == file_operations::GRUB_ENV_PATH {
+            return write_grub_env(platform, |_| Ok(data.to_vec()));
+        }
+        let dir = platform.mount_grub_dir()?;
+        let path = dir.path.join(name);
+        journal::track(
+            ChangeTarget::GrubFile(name.to_string()),
+            || journal::read_file(&path),
+            || {
+                dry_run::write_file(&path, &String::from_utf8_lossy(data), || {
+                    std::fs::write(&path, data)
+                })
+            },
+        )
+    }
+}
+
+impl Bootloader for Grub {
+    fn kind(&self) -> BootloaderKind {
+        BootloaderKind::Grub
+    }
+
+    fn list_entries(&mut self, platform: &mut dyn Platform) -> Result<Vec<BootEntry>> {
+        let next = self
+            .read_grub_env(platform)?
+            .get("next_entry")
+            .map(str::to_string);
+        Ok(self
+            .get_grub_entry(platform)?
+            .into_iter()
+            .map(|e| BootEntry {
+                is_oneshot: next.as_deref().is_some_and(|next| e.is_named_by(next)),
+                id: e.entry_id,
+                title: e.entry_name,
+                is_default: e.entry_is_default,
+            })
+            .collect())
+    }
+
+    fn set_default(&mut self, platform: &mut dyn Platform, entry: &str) -> Result<()> {
+        self.set_grub_entry(platform, entry, false, false)
+    }
+
+    /// Check an entry picked by a GRUB selector, which may name a kernel
+    fn is_selected(
+        &mut self,
+        platform: &mut dyn Platform,
+        entry: &str,
+        oneshot: bool,
+    ) -> Result<bool> {
+        let entries = self.get_grub_entry(platform)?;
+        let id = resolve_grub_selector(&entries, entry)?.entry_id.clone();
+        Ok(self.list_entries(platform)?.into_iter().any(|e| {
+            e.id == id
+                && match oneshot {
+                    true => e.is_oneshot,
+                    false => e.is_default,
+                }
+        }))
+    }
+
+    /// Boot an entry once through `next_entry` of grubenv, like grub-reboot
+    fn set_oneshot(&mut self, platform: &mut dyn Platform, entry: &str) -> Result<()> {
+        let entries = self.get_grub_entry(platform)?;
+        let entry = resolve_grub_selector(&entries, entry)?;
+        self.check_bootable(platform, entry, false)?;
+        self.update_grub_env(platform, |env| {
+            env.set("next_entry", &entry.saved_id());
+            Ok(())
+        })?;
+        output::note(&format!("Boot GRUB entry once: {:?}", entry.title()));
+        Ok(())
+    }
+
+    fn get_timeout(&mut self, platform: &mut dyn Platform) -> Result<Option<String>> {
+        self.get_grub_default(platform, "GRUB_TIMEOUT")
+    }
+
+    fn set_timeout(&mut self, platform: &mut dyn Platform, timeout: &str) -> Result<()> {
+        let timeout = timeout.parse::<u32>().map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid GRUB timeout '{}'", timeout),
+            )
+        })?;
+        let update = GrubDefaultsUpdate {
+            timeout: Some(timeout),
+            ..Default::default()
+        };
+        self.update_grub_defaults(platform, &update, true)
+    }
+}
+
+/// Resolve a GRUB relative path, Fedora and openSUSE install GRUB into `grub2/`
+pub(crate) fn grub_path(dir: &Path, path: &str) -> PathBuf {
+    let full = dir.join(path);
+    match path.strip_prefix("grub/") {
+        Some(rest) if !full.exists() => dir.join("grub2").join(rest),
+        _ => full,
+    }
+}
+
+/// Read /etc/default/grub, naming the file in errors
+fn read_default_grub(path: &Path) -> Result<DefaultGrub> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+    Ok(DefaultGrub::parse(&content))
+}
+
+/// Get the path of a file relative to a directory, with `/` separators
+fn relative_name(dir: &Path, path: &Path) -> String {
+    path.strip_prefix(dir)
+        .unwrap_or(path)
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Replace the content of the grubenv file
+///
+/// Every write of grubenv goes through here. The file is locked from reading
+/// to writing, so two bootmgr runs cannot lose each other's changes. Right
+/// before writing it is read again, and if something that does not lock it,
+/// like grub-editenv, changed it meanwhile nothing is written. The new content
+/// replaces the old one in a single write, is flushed to disk and read back to
+/// check it.
+/// # Arguments
+/// * `replace` - Makes the new content from the current one
+pub(crate) fn write_grub_env(
+    platform: &mut dyn Platform,
+    replace: impl FnOnce(&[u8]) -> Result<Vec<u8>>,
+) -> Result<()> {
+    let dir = platform.mount_grub_dir()?;
+    let path = grub_path(&dir.path, file_operations::GRUB_ENV_PATH);
+    let file = match dry_run::is_enabled() {
+        true => file_operations::open_file_ro(path.clone())?,
+        false => {
+            let file = file_operations::open_file_rw(path.clone())?;
+            file_operations::lock_exclusive(&file, &path)?;
+            file
+        }
+    };
+    let current = file_operations::read_from_start(&file)?;
+    let content = replace(&current)?;
+    journal::track(
+        ChangeTarget::GrubFile(relative_name(&dir.path, &path)),
+        || file_operations::read_from_start(&file).map(Some),
+        || {
+            dry_run::write_file(&path, &String::from_utf8_lossy(&content), || {
+                if file_operations::read_from_start(&file)? != current {
+                    return Err(Error::new(
+                        ErrorKind::Interrupted,
+                        format!(
+                            "{} was changed by another program while it was updated, try again",
+                            path.display()
+                        ),
+                    ));
+                }
+                file_operations::write_verified(&file, &path, &content)
+            })
+        },
+    )
+}
+
+/// Open a file of the GRUB directory
+/// # Arguments
+/// * `path` - The path below the directory holding `grub/`, e.g. `grub/grub.cfg`
+fn get_file(platform: &mut dyn Platform, path: &str) -> Result<File> {
+    let dir = platform.mount_grub_dir()?;
+    file_operations::open_file_ro(grub_path(&dir.path, path))
+}
+
+/// Check whether a file referenced by a grub entry exists
+///
+/// Paths are resolved on the filesystem the entry selects with
+/// `search --fs-uuid`, or the one holding GRUB otherwise. GRUB device names
+/// given by `set root` or in the path cannot be mapped to a filesystem. A
+/// file is only missing when its directory exists, paths into btrfs
+/// subvolumes that are not mounted, e.g. `/@/boot/vmlinuz`, are unknown.
+/// # Arguments
+/// * `entry` - The GRUB entry referencing the file
+/// * `path` - The path as written in grub.cfg
+/// # Returns
+/// * `Result<Option<bool>>` - Whether the file exists, None if its path cannot be mapped
+fn grub_file_exists(
+    platform: &mut dyn Platform,
+    entry: &GrubEntry,
+    path: &str,
+) -> Result<Option<bool>> {
+    let (device, path) = grub_cfg::split_device(path);
+    if device.is_some() && device != entry.entry_root_device.as_deref() {
+        return Ok(None);
+    }
+    let root = match (&entry.entry_root_uuid, &entry.entry_root_device) {
+        (Some(uuid), _) => platform.find_uuid_volume(uuid)?,
+        // GRUB device names cannot be mapped to a filesystem
+        (None, Some(_)) => None,
+        (None, None) => {
+            let dir = platform.mount_grub_dir()?;
+            platform.find_volume_root(&dir.path)?
+        }
+    };
+    let Some(root) = root else {
+        return Ok(None);
+    };
+    let file = root.join(path.trim_start_matches('/'));
+    Ok(match file.exists() {
+        true => Some(true),
+        false if file.parent().is_some_and(|dir| dir.is_dir()) => Some(false),
+        false => None,
+    })
+}
+
+/// Find the GRUB entry matching the kernel command line of the running system
+/// # Arguments
+/// * `entries` - The parsed GRUB entries
+/// * `cmdline` - The kernel command line, as found in `/proc/cmdline`
+/// # Returns
+/// * `Option<&GrubEntry>` - The entry with the same kernel and the most similar options
+fn find_booted_grub_entry<'a>(entries: &'a [GrubEntry], cmdline: &str) -> Option<&'a GrubEntry> {
+    let strip_device = |path: &str| match path.strip_prefix('(') {
+        Some(rest) => rest.split_once(')').map(|(_, p)| p.to_string()),
+        None => Some(path.to_string()),
+    };
+    let mut boot_image = None;
+    let mut options = Vec::new();
+    for arg in cmdline.split_whitespace() {
+        match arg.strip_prefix("BOOT_IMAGE=") {
+            Some(image) => boot_image = strip_device(image),
+            None => options.push(arg),
+        }
+    }
+    let boot_image = boot_image?;
+
+    entries
+        .iter()
+        .filter_map(|e| {
+            let kernel = strip_device(e.entry_kernel.as_deref()?)?;
+            let args = e
+                .entry_cmdline
+                .as_deref()
+                .unwrap_or_default()
+                .split_whitespace();
+            if !kernel.ends_with(&boot_image) && !boot_image.ends_with(&kernel) {
+                return None;
+            }
+            let score: i32 = args
+                .map(|a| if options.contains(&a) { 1 } else { -1 })
+                .sum();
+            Some((score, e))
+        })
+        .fold(
+            None,
+            |best: Option<(i32, &GrubEntry)>, (score, e)| match best {
+                Some((best_score, _)) if best_score >= score => best,
+                _ => Some((score, e)),
+            },
+        )
+        .map(|(_, e)| e)
+}
+
+/// A struct representing a GRUB entry
+/// Fields:
+/// * `entry_name` - The name of the GRUB menuentry
+/// * `entry_id` - The menuentry_id_option of the GRUB menuentry
+/// * `entry_parents` - The ids and titles of the submenus holding the entry, outermost first
+/// * `entry_is_default` - Whether the GRUB entry is the default entry
+/// * `entry_kernel` - The kernel loaded by `linux`
+/// * `entry_cmdline` - The kernel command line passed by `linux`
+/// * `entry_initrd` - The images loaded by `initrd`
+/// * `entry_chainloader` - The loader started by `chainloader`
+/// * `entry_root_uuid` - The filesystem uuid `search --fs-uuid` sets as root
+/// * `entry_root_device` - The GRUB device `set root` selects, e.g. `hd0,gpt2`
+/// * `entry_classes` - The `--class` tags of the menuentry
+/// * `entry_users` - The users allowed by `--users`
+/// * `entry_unrestricted` - Whether the menuentry is `--unrestricted`
+/// * `entry_hotkey` - The `--hotkey` of the menuentry
+#[derive(Clone, Debug, Default)]
+pub struct GrubEntry {
+    pub entry_name: String,
+    pub entry_id: String,
+    pub entry_parents: Vec<(String, String)>,
+    pub entry_is_default: bool,
+    pub entry_kernel: Option<String>,
+    pub entry_cmdline: Option<String>,
+    pub entry_initrd: Vec<String>,
+    pub entry_chainloader: Option<String>,
+    pub entry_root_uuid: Option<String>,
+    pub entry_root_device: Option<String>,
+    pub entry_classes: Vec<String>,
+    pub entry_users: Option<String>,
+    pub entry_unrestricted: bool,
+    pub entry_hotkey: Option<String>,
+}
+
+impl GrubEntry {
+    /// Get the value naming the entry in saved_entry, next_entry and GRUB_DEFAULT
+    ///
+    /// GRUB only finds an entry of a submenu through the submenus holding it,
+    /// their ids and the id of the entry joined by `>`.
+    pub fn saved_id(&self) -> String {
+        let mut path: Vec<&str> = self
+            .entry_parents
+            .iter()
+            .map(|(id, _)| id.as_str())
+            .collect();
+        path.push(&self.entry_id);
+        path.join(">")
+    }
+
+    /// Check whether a saved_entry, next_entry or GRUB_DEFAULT value names the entry
+    ///
+    /// Like GRUB, each part of the value may be the id or the title of the
+    /// submenu or entry it stands for.
+    /// # Arguments
+    /// * `value` - The value, e.g. `gnulinux-advanced-1234>gnulinux-6.1.0-advanced-1234`
+    pub fn is_named_by(&self, value: &str) -> bool {
+        let parts: Vec<&str> = value.split('>').collect();
+        let Some((last, parents)) = parts.split_last() else {
+            return false;
+        };
+        parents.len() == self.entry_parents.len()
+            && parents
+                .iter()
+                .zip(&self.entry_parents)
+                .all(|(part, (id, title))| part == id || part == title)
+            && (*last == self.entry_id || *last == self.entry_name)
+    }
+}
+
+impl Selectable for GrubEntry {
+    fn id(&self) -> &str {
+        &self.entry_id
+    }
+
+    fn title(&self) -> &str {
+        &self.entry_name
+    }
+}
+
+/// A struct describing changes to /etc/default/grub
+/// Fields:
+/// * `default` - The new `GRUB_DEFAULT`, `saved` or a GRUB entry selector
+/// * `timeout` - The new `GRUB_TIMEOUT` in seconds
+/// * `savedefault` - Whether GRUB saves the booted entry as default
+/// * `disable_submenu` - Whether older kernels are listed at the top level
+#[derive(Default)]
+pub struct GrubDefaultsUpdate {
+    pub default: Option<String>,
+    pub timeout: Option<u32>,
+    pub savedefault: Option<bool>,
+    pub disable_submenu: Option<bool>,
+}
@@ -0,0 +1,243 @@
+pub(crate) mod extlinux;
+pub(crate) mod grub;
+pub(crate) mod refind;
+pub(crate) mod systemd_boot;
+
+use crate::bootloader::extlinux::Extlinux;
+use crate::bootloader::grub::Grub;
+use crate::bootloader::refind::Refind;
+use crate::bootloader::systemd_boot::SystemdBoot;
+use crate::common::selector::{Selectable, select};
+use crate::platform::Platform;
+use std::io::{Error, ErrorKind, Result};
+
+/// A boot loader whose menu is managed on top of a platform
+///
+/// Only the operations all boot loaders share are part of the trait, each
+/// boot loader offers its own details besides.
+pub(crate) trait Bootloader {
+    /// The kind of the boot loader
+    fn kind(&self) -> BootloaderKind;
+
+    /// Get the entries of the boot menu
+    /// # Arguments
+    /// * `platform` - The platform the boot loader is reached through
+    /// # Returns
+    /// * `Result<Vec<BootEntry>>` - The entries in menu order
+    fn list_entries(&mut self, platform: &mut dyn Platform) -> Result<Vec<BootEntry>>;
+
+    /// Get the default entry
+    /// # Arguments
+    /// * `platform` - The platform the boot loader is reached through
+    /// # Returns
+    /// * `Result<Option<BootEntry>>` - The default entry, None if none is set or it does not exist
+    fn get_default(&mut self, platform: &mut dyn Platform) -> Result<Option<BootEntry>> {
+        Ok(self
+            .list_entries(platform)?
+            .into_iter()
+            .find(|e| e.is_default))
+    }
+
+    /// Set the default entry
+    /// # Arguments
+    /// * `platform` - The platform the boot loader is reached through
+    /// * `entry` - The selector of the entry
+    fn set_default(&mut self, platform: &mut dyn Platform, entry: &str) -> Result<()>;
+
+    /// Boot an entry on the next boot only
+    /// # Arguments
+    /// * `platform` - The platform the boot loader is reached through
+    /// * `entry` - The selector of the entry
+    fn set_oneshot(&mut self, _platform: &mut dyn Platform, _entry: &str) -> Result<()> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            format!("{} cannot boot an entry once", self.kind().title()),
+        ))
+    }
+
+    /// Check whether an entry is the default or the one-shot entry
+    /// # Arguments
+    /// * `platform` - The platform the boot loader is reached through
+    /// * `entry` - The selector of the entry
+    /// * `oneshot` - Check the one-shot entry instead of the default
+    fn is_selected(
+        &mut self,
+        platform: &mut dyn Platform,
+        entry: &str,
+        oneshot: bool,
+    ) -> Result<bool> {
+        let entries = self.list_entries(platform)?;
+        let entry = select(&entries, entry, self.kind().title())?;
+        Ok(match oneshot {
+            true => entry.is_oneshot,
+            false => entry.is_default,
+        })
+    }
+
+    /// Get the menu timeout as written in the configuration
+    /// # Arguments
+    /// * `platform` - The platform the boot loader is reached through
+    /// # Returns
+    /// * `Result<Option<String>>` - The timeout, None if it is not set
+    fn get_timeout(&mut self, platform: &mut dyn Platform) -> Result<Option<String>>;
+
+    /// Set the menu timeout
+    /// # Arguments
+    /// * `platform` - The platform the boot loader is reached through
+    /// * `timeout` - The timeout in the unit of the boot loader
+    fn set_timeout(&mut self, platform: &mut dyn Platform, timeout: &str) -> Result<()>;
+
+    /// Show the entries of the boot menu, marking the default and next ones
+    fn show_entries(&mut self, platform: &mut dyn Platform) -> Result<()> {
+        let entries = self.list_entries(platform)?;
+        println!("The {} entries:", self.kind().title());
+        for i in entries {
+            println!(
+                "{}{} {} ({})",
+                if i.is_default { "*" } else { " " },
+                if i.is_oneshot { ">" } else { " " },
+                i.title,
+                i.id
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The boot loaders bootmgr manages
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum BootloaderKind {
+    Grub,
+    SystemdBoot,
+    Refind,
+    Extlinux,
+}
+
+impl BootloaderKind {
+    pub const ALL: [BootloaderKind; 4] = [
+        BootloaderKind::Grub,
+        BootloaderKind::SystemdBoot,
+        BootloaderKind::Refind,
+        BootloaderKind::Extlinux,
+    ];
+
+    /// The name of the boot loader, as accepted by `--loader`
+    pub fn name(self) -> &'static str {
+        match self {
+            BootloaderKind::Grub => "grub",
+            BootloaderKind::SystemdBoot => "systemd-boot",
+            BootloaderKind::Refind => "refind",
+            BootloaderKind::Extlinux => "extlinux",
+        }
+    }
+
+    /// The name of the boot loader as shown to the user
+    pub fn title(self) -> &'static str {
+        match self {
+            BootloaderKind::Grub => "GRUB",
+            BootloaderKind::SystemdBoot => "systemd-boot",
+            BootloaderKind::Refind => "rEFInd",
+            BootloaderKind::Extlinux => "extlinux",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|k| k.name() == name)
+    }
+
+    /// Tell which boot loader a firmware entry starts from the path of its loader
+    ///
+    /// shim is taken for GRUB, which it starts on every distribution. The
+    /// removable media path `EFI/BOOT/BOOTX64.EFI` tells nothing.
+    /// # Arguments
+    /// * `path` - The path of the loader on its partition
+    pub fn from_loader_path(path: &str) -> Option<Self> {
+        let path = path.to_lowercase().replace('\\', "/");
+        let file = path.rsplit('/').next().unwrap_or_default();
+        if path.contains("systemd") {
+            Some(BootloaderKind::SystemdBoot)
+        } else if path.contains("refind") {
+            Some(BootloaderKind::Refind)
+        } else if path.contains("syslinux") {
+            Some(BootloaderKind::Extlinux)
+        } else if file.starts_with("grub") || file.starts_with("shim") {
+            Some(BootloaderKind::Grub)
+        } else {
+            None
+        }
+    }
+}
+
+/// A struct representing an entry of any boot loader
+/// Fields:
+/// * `id` - The identifier the boot loader stores for the entry
+/// * `title` - The title shown in the menu
+/// * `is_default` - Whether the entry is the default entry
+/// * `is_oneshot` - Whether the entry is booted on the next boot only
+#[derive(Clone, Debug, Default)]
+pub struct BootEntry {
+    pub id: String,
+    pub title: String,
+    pub is_default: bool,
+    pub is_oneshot: bool,
+}
+
+impl Selectable for BootEntry {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn title(&self) -> &str {
+        &self.title
+    }
+}
+
+/// Make the backend of a boot loader
+/// # Arguments
+/// * `kind` - The boot loader
+/// * `grub` - The GRUB settings given on the command line
+pub(crate) fn make_bootloader(kind: BootloaderKind, grub: &Grub) -> Box<dyn Bootloader> {
+    match kind {
+        BootloaderKind::Grub => Box::new(grub.clone()),
+        BootloaderKind::SystemdBoot => Box::new(SystemdBoot),
+        BootloaderKind::Refind => Box::new(Refind),
+        BootloaderKind::Extlinux => Box::new(Extlinux),
+    }
+}
+
+/// Find the boot loader the machine boots through
+///
+/// The loader of the firmware entry the system was booted from is preferred,
+/// then those of the boot order. Without a firmware entry naming a known
+/// loader, the first boot loader whose configuration is found is taken.
+/// # Arguments
+/// * `platform` - The platform to look for boot loaders on
+/// # Returns
+/// * `Result<BootloaderKind>` - The boot loader
+pub(crate) fn detect_bootloader(platform: &mut dyn Platform) -> Result<BootloaderKind> {
+    if let Ok(mut loaders) = platform.get_fw_loaders() {
+        loaders.sort_by_key(|l| !l.is_current);
+        if let Some(kind) = loaders
+            .iter()
+            .find_map(|l| BootloaderKind::from_loader_path(l.path.as_deref()?))
+        {
+            return Ok(kind);
+        }
+    }
+
+    for kind in BootloaderKind::ALL {
+        let found = match kind {
+            BootloaderKind::Grub => platform.mount_grub_dir().is_ok(),
+            BootloaderKind::SystemdBoot => systemd_boot::mount_esp(platform).is_ok(),
+            BootloaderKind::Refind => refind::mount_esp(platform).is_ok(),
+            BootloaderKind::Extlinux => extlinux::find_config(platform).is_ok(),
+        };
+        if found {
+            return Ok(kind);
+        }
+    }
+    Err(Error::new(
+        ErrorKind::NotFound,
+        "No known boot loader found",
+    ))
+}
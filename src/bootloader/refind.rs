@@ -0,0 +1,190 @@
+use crate::bootloader::{BootEntry, Bootloader, BootloaderKind};
+use crate::common::dry_run;
+use crate::common::journal::{self, ChangeTarget};
+use crate::common::output;
+use crate::common::refind::{self, RefindConf, RefindConfig, RefindEntry};
+use crate::common::selector::{Selectable, select};
+use crate::platform::{Platform, VolumeDir};
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+
+/// rEFInd, managed through refind.conf on the ESP
+pub(crate) struct Refind;
+
+impl Refind {
+    /// Get the manual boot stanzas of rEFInd
+    /// # Returns
+    /// * `Result<Vec<RefindEntry>>` - The `menuentry` stanzas of refind.conf and its includes, in menu order
+    pub fn get_refind_entries(&self, platform: &mut dyn Platform) -> Result<Vec<RefindEntry>> {
+        Ok(read_refind_config(platform)?.entries())
+    }
+
+    /// Show the rEFInd settings and manual boot stanzas
+    pub fn show_refind_entry(&self, platform: &mut dyn Platform) -> Result<()> {
+        let config = read_refind_config(platform)?;
+        for key in ["default_selection", "timeout"] {
+            match config.get(key) {
+                Some(value) => println!("{}: {}", key, value.join(" ")),
+                None => println!("{}: not set", key),
+            }
+        }
+        println!("The rEFInd entries:");
+        for i in config.entries() {
+            println!(
+                "{} {} ({})",
+                if i.entry_is_default { "*" } else { " " },
+                i.entry_title,
+                i.id()
+            );
+        }
+        Ok(())
+    }
+
+    /// Show the boot details of a rEFInd entry
+    /// # Arguments
+    /// * `entry` - The selector of the rEFInd entry to show
+    pub fn show_refind_details(&self, platform: &mut dyn Platform, entry: &str) -> Result<()> {
+        let entries = self.get_refind_entries(platform)?;
+        let entry = select(&entries, entry, "rEFInd")?;
+        let field = |name: &str, value: Option<String>| {
+            if let Some(value) = value.filter(|v| !v.is_empty()) {
+                println!("{:<9} {}", format!("{}:", name), value);
+            }
+        };
+
+        field("Title", Some(entry.entry_title.clone()));
+        field("File", Some(entry.entry_file.clone()));
+        field("Volume", entry.entry_volume.clone());
+        field("Loader", entry.entry_loader.clone());
+        field("Initrd", entry.entry_initrd.clone());
+        field("Options", entry.entry_options.clone());
+        field("OS type", entry.entry_ostype.clone());
+        for submenu in &entry.entry_submenus {
+            field("Submenu", Some(submenu.clone()));
+        }
+        Ok(())
+    }
+}
+
+impl Bootloader for Refind {
+    fn kind(&self) -> BootloaderKind {
+        BootloaderKind::Refind
+    }
+
+    fn list_entries(&mut self, platform: &mut dyn Platform) -> Result<Vec<BootEntry>> {
+        Ok(self
+            .get_refind_entries(platform)?
+            .into_iter()
+            .map(|e| BootEntry {
+                id: e.id().to_string(),
+                title: e.entry_title,
+                is_default: e.entry_is_default,
+                is_oneshot: false,
+            })
+            .collect())
+    }
+
+    /// Set `default_selection` of refind.conf to the title of a rEFInd entry
+    fn set_default(&mut self, platform: &mut dyn Platform, entry: &str) -> Result<()> {
+        let mut config = read_refind_config(platform)?;
+        let entries = config.entries();
+        let entry = select(&entries, entry, "rEFInd")?;
+        let file = config.set("default_selection", &refind::quote(&entry.entry_title));
+        write_refind_config(platform, &config, file)?;
+        output::note(&format!(
+            "Set default rEFInd entry: {:?}",
+            entry.entry_title
+        ));
+        Ok(())
+    }
+
+    fn get_timeout(&mut self, platform: &mut dyn Platform) -> Result<Option<String>> {
+        Ok(read_refind_config(platform)?
+            .get("timeout")
+            .map(|value| value.join(" ")))
+    }
+
+    /// Set `timeout` of refind.conf
+    ///
+    /// The timeout is seconds, 0 to wait forever, -1 to boot at once unless a key is held.
+    fn set_timeout(&mut self, platform: &mut dyn Platform, timeout: &str) -> Result<()> {
+        let invalid = || {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid rEFInd timeout {}", timeout),
+            )
+        };
+        let seconds = timeout.parse::<i32>().map_err(|_| invalid())?;
+        if seconds < -1 {
+            return Err(invalid());
+        }
+        let mut config = read_refind_config(platform)?;
+        let file = config.set("timeout", &seconds.to_string());
+        write_refind_config(platform, &config, file)?;
+        output::note(&format!("Set rEFInd timeout: {}", seconds));
+        Ok(())
+    }
+}
+
+/// Find the EFI system partition rEFInd is installed on
+pub(crate) fn mount_esp(platform: &mut dyn Platform) -> Result<VolumeDir> {
+    let markers = refind::REFIND_DIRS.map(|dir| format!("{}/{}", dir, refind::REFIND_CONF));
+    let markers: Vec<&str> = markers.iter().map(String::as_str).collect();
+    platform.mount_loader_esp("refind", &markers)
+}
+
+/// Find the directory of refind.conf on the ESP
+fn find_refind_dir(esp: &VolumeDir) -> Result<PathBuf> {
+    refind::REFIND_DIRS
+        .iter()
+        .map(|dir| esp.path.join(dir))
+        .find(|dir| dir.join(refind::REFIND_CONF).is_file())
+        .ok_or(Error::new(
+            ErrorKind::NotFound,
+            "refind.conf not found on the EFI system partition",
+        ))
+}
+
+/// Read refind.conf and the files it includes, a missing include is skipped like rEFInd does
+fn read_refind_config(platform: &mut dyn Platform) -> Result<RefindConfig> {
+    let esp = mount_esp(platform)?;
+    let dir = find_refind_dir(&esp)?;
+    let main = RefindConf::parse(
+        refind::REFIND_CONF,
+        &std::fs::read_to_string(dir.join(refind::REFIND_CONF))?,
+    );
+    let mut config = RefindConfig {
+        files: vec![main.clone()],
+    };
+    for name in main.includes() {
+        if let Ok(content) = std::fs::read_to_string(dir.join(&name)) {
+            config.files.push(RefindConf::parse(&name, &content));
+        }
+    }
+    Ok(config)
+}
+
+/// Write a file of the rEFInd configuration
+/// # Arguments
+/// * `config` - The configuration
+/// * `file` - The index of the file to write
+fn write_refind_config(
+    platform: &mut dyn Platform,
+    config: &RefindConfig,
+    file: usize,
+) -> Result<()> {
+    let esp = mount_esp(platform)?;
+    let conf = &config.files[file];
+    let path = find_refind_dir(&esp)?.join(&conf.name);
+    let name = path
+        .strip_prefix(&esp.path)
+        .unwrap_or(&path)
+        .to_string_lossy()
+        .replace('\\', "/");
+    let content = conf.to_content();
+    journal::track(
+        ChangeTarget::EspFile(BootloaderKind::Refind.name().to_string(), name),
+        || journal::read_file(&path),
+        || dry_run::write_file(&path, &content, || std::fs::write(&path, &content)),
+    )
+}
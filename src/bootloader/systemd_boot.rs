@@ -0,0 +1,236 @@
+use crate::bootloader::{BootEntry, Bootloader, BootloaderKind};
+use crate::common::dry_run;
+use crate::common::journal::{self, ChangeTarget};
+use crate::common::output;
+use crate::common::selector::select;
+use crate::common::systemd_boot::{self, LoaderConf, SdBootEntry};
+use crate::platform::{Platform, VolumeDir};
+use std::fs::File;
+use std::io::{Error, ErrorKind, Result};
+
+/// systemd-boot, managed through the ESP and its EFI variables
+pub(crate) struct SystemdBoot;
+
+impl SystemdBoot {
+    /// Get the systemd-boot entries of the ESP
+    /// # Returns
+    /// * `Result<Vec<SdBootEntry>>` - The Type #1 entries and unified kernel images, in menu order
+    pub fn get_sdboot_entries(&self, platform: &mut dyn Platform) -> Result<Vec<SdBootEntry>> {
+        let esp = mount_esp(platform)?;
+        let mut entries = Vec::new();
+        for (dir, uki) in [
+            (systemd_boot::ENTRIES_DIR, false),
+            (systemd_boot::UKI_DIR, true),
+        ] {
+            let Ok(files) = std::fs::read_dir(esp.path.join(dir)) else {
+                continue;
+            };
+            for file in files {
+                let file = file?;
+                let id = file.file_name().to_string_lossy().to_string();
+                let extension = if uki { ".efi" } else { ".conf" };
+                if !id.to_lowercase().ends_with(extension) {
+                    continue;
+                }
+                entries.push(match uki {
+                    true => SdBootEntry::parse_uki(&id, &mut File::open(file.path())?)?,
+                    false => SdBootEntry::parse_conf(&id, &std::fs::read_to_string(file.path())?),
+                });
+            }
+        }
+        drop(esp);
+        entries.sort_by(systemd_boot::compare_entries);
+
+        let conf = read_loader_conf(platform)?;
+        let default = match get_loader_var(platform, systemd_boot::LOADER_ENTRY_DEFAULT)? {
+            Some(default) => Some(default),
+            None => conf.get("default").map(str::to_string),
+        };
+        let oneshot = get_loader_var(platform, systemd_boot::LOADER_ENTRY_ONESHOT)?;
+        // The first entry matching the default pattern wins, like systemd-boot does
+        if let Some(default) = default
+            && let Some(entry) = entries
+                .iter_mut()
+                .find(|e| systemd_boot::default_matches(&default, &e.entry_id))
+        {
+            entry.entry_is_default = true;
+        }
+        for entry in entries.iter_mut() {
+            entry.entry_is_oneshot = oneshot.as_deref() == Some(entry.entry_id.as_str());
+        }
+        Ok(entries)
+    }
+
+    /// Show the boot details of a systemd-boot entry
+    /// # Arguments
+    /// * `entry` - The selector of the systemd-boot entry to show
+    pub fn show_sdboot_details(&self, platform: &mut dyn Platform, entry: &str) -> Result<()> {
+        let entries = self.get_sdboot_entries(platform)?;
+        let entry = select(&entries, entry, "systemd-boot")?;
+        let field = |name: &str, value: Option<String>| {
+            if let Some(value) = value.filter(|v| !v.is_empty()) {
+                println!("{:<13} {}", format!("{}:", name), value);
+            }
+        };
+
+        field("Title", Some(entry.entry_title.clone()));
+        field("Id", Some(entry.entry_id.clone()));
+        field(
+            "Type",
+            Some(match entry.entry_is_uki {
+                true => "unified kernel image".to_string(),
+                false => "boot loader entry".to_string(),
+            }),
+        );
+        field("Version", entry.entry_version.clone());
+        field("Sort key", entry.entry_sort_key.clone());
+        field("Kernel", entry.entry_linux.clone());
+        field("Command line", entry.entry_options.clone());
+        field("Initrd", Some(entry.entry_initrd.join(" ")));
+        field("EFI program", entry.entry_efi.clone());
+        Ok(())
+    }
+
+    /// Show the systemd-boot menu timeout of loader.conf
+    pub fn show_sdboot_timeout(&mut self, platform: &mut dyn Platform) -> Result<()> {
+        match self.get_timeout(platform)? {
+            Some(timeout) => println!("systemd-boot timeout: {}", timeout),
+            None => println!("systemd-boot timeout: not set"),
+        }
+        Ok(())
+    }
+}
+
+impl Bootloader for SystemdBoot {
+    fn kind(&self) -> BootloaderKind {
+        BootloaderKind::SystemdBoot
+    }
+
+    fn list_entries(&mut self, platform: &mut dyn Platform) -> Result<Vec<BootEntry>> {
+        Ok(self
+            .get_sdboot_entries(platform)?
+            .into_iter()
+            .map(|e| BootEntry {
+                id: e.entry_id,
+                title: e.entry_title,
+                is_default: e.entry_is_default,
+                is_oneshot: e.entry_is_oneshot,
+            })
+            .collect())
+    }
+
+    /// Set the systemd-boot entry by selector
+    ///
+    /// The default is written to loader.conf, and to `LoaderEntryDefault`
+    /// too when that variable is set, since it overrides loader.conf.
+    fn set_default(&mut self, platform: &mut dyn Platform, entry: &str) -> Result<()> {
+        let entries = self.get_sdboot_entries(platform)?;
+        let entry = select(&entries, entry, "systemd-boot")?;
+        let mut conf = read_loader_conf(platform)?;
+        conf.set("default", &entry.entry_id);
+        write_loader_conf(platform, &conf)?;
+        if get_loader_var(platform, systemd_boot::LOADER_ENTRY_DEFAULT)?.is_some() {
+            set_loader_var(
+                platform,
+                systemd_boot::LOADER_ENTRY_DEFAULT,
+                &entry.entry_id,
+            )?;
+        }
+        output::note(&format!(
+            "Set default systemd-boot entry: {:?}",
+            entry.entry_title
+        ));
+        Ok(())
+    }
+
+    fn set_oneshot(&mut self, platform: &mut dyn Platform, entry: &str) -> Result<()> {
+        let entries = self.get_sdboot_entries(platform)?;
+        let entry = select(&entries, entry, "systemd-boot")?;
+        set_loader_var(
+            platform,
+            systemd_boot::LOADER_ENTRY_ONESHOT,
+            &entry.entry_id,
+        )?;
+        output::note(&format!(
+            "Boot systemd-boot entry once: {:?}",
+            entry.entry_title
+        ));
+        Ok(())
+    }
+
+    fn get_timeout(&mut self, platform: &mut dyn Platform) -> Result<Option<String>> {
+        Ok(read_loader_conf(platform)?
+            .get("timeout")
+            .map(str::to_string))
+    }
+
+    /// Set the systemd-boot menu timeout of loader.conf
+    ///
+    /// The timeout is seconds, or one of `menu-force`, `menu-hidden` and `menu-disabled`.
+    fn set_timeout(&mut self, platform: &mut dyn Platform, timeout: &str) -> Result<()> {
+        if timeout.parse::<u32>().is_err()
+            && !["menu-force", "menu-hidden", "menu-disabled"].contains(&timeout)
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid systemd-boot timeout '{}'", timeout),
+            ));
+        }
+        let mut conf = read_loader_conf(platform)?;
+        conf.set("timeout", timeout);
+        write_loader_conf(platform, &conf)?;
+        output::note(&format!("Set systemd-boot timeout: {}", timeout));
+        Ok(())
+    }
+}
+
+/// Find the EFI system partition systemd-boot is installed on
+pub(crate) fn mount_esp(platform: &mut dyn Platform) -> Result<VolumeDir> {
+    platform.mount_loader_esp("systemd-boot", &["loader"])
+}
+
+/// Read loader/loader.conf, a missing file has no settings
+fn read_loader_conf(platform: &mut dyn Platform) -> Result<LoaderConf> {
+    let esp = mount_esp(platform)?;
+    match std::fs::read_to_string(esp.path.join(systemd_boot::LOADER_CONF_PATH)) {
+        Ok(content) => Ok(LoaderConf::parse(&content)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(LoaderConf::default()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Write loader/loader.conf
+fn write_loader_conf(platform: &mut dyn Platform, conf: &LoaderConf) -> Result<()> {
+    let esp = mount_esp(platform)?;
+    let path = esp.path.join(systemd_boot::LOADER_CONF_PATH);
+    let content = conf.to_content();
+    journal::track(
+        ChangeTarget::EspFile(
+            BootloaderKind::SystemdBoot.name().to_string(),
+            systemd_boot::LOADER_CONF_PATH.to_string(),
+        ),
+        || journal::read_file(&path),
+        || dry_run::write_file(&path, &content, || std::fs::write(&path, &content)),
+    )
+}
+
+/// Read a systemd-boot EFI variable
+/// # Arguments
+/// * `name` - The variable name, e.g. `LoaderEntryDefault`
+/// # Returns
+/// * `Result<Option<String>>` - The value, None if it is not set
+fn get_loader_var(platform: &dyn Platform, name: &str) -> Result<Option<String>> {
+    let name = format!("{}-{}", name, systemd_boot::LOADER_VENDOR_GUID);
+    Ok(platform
+        .get_efi_var(&name)?
+        .map(|data| systemd_boot::decode_loader_var(&data)))
+}
+
+/// Write a systemd-boot EFI variable
+/// # Arguments
+/// * `name` - The variable name, e.g. `LoaderEntryOneShot`
+/// * `value` - The entry id to store
+fn set_loader_var(platform: &dyn Platform, name: &str, value: &str) -> Result<()> {
+    let name = format!("{}-{}", name, systemd_boot::LOADER_VENDOR_GUID);
+    platform.set_efi_var(&name, &systemd_boot::encode_loader_var(value))
+}
@@ -1,9 +1,84 @@
-use clap::Parser;
+use clap::{ArgGroup, Parser, Subcommand};
 
 #[derive(Parser)]
 #[command(name = "Boot Manager")]
 #[command(about = "A tool to manage boot entries order")]
+#[command(
+    after_help = "Entries are picked by selector: an index, an exact id or title, a case-insensitive substring, a glob, or a regex prefixed with re:"
+)]
+pub(crate) struct Cli {
+    #[command(subcommand)]
+    pub command: Commands,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Root directory of the managed Linux installation, e.g. a chroot",
+        value_name = "DIR"
+    )]
+    pub root: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Show the changes as diffs, commands and EFI variable bytes without making them"
+    )]
+    pub dry_run: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Output format of list and set, text unless the configuration sets another",
+        value_name = "FORMAT",
+        value_parser = ["text", "json"]
+    )]
+    pub format: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Boot loader to manage instead of the detected one",
+        value_name = "NAME",
+        value_parser = ["grub", "systemd-boot", "refind", "extlinux"]
+    )]
+    pub loader: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "GRUB platform grub.cfg is evaluated for, e.g. efi or pc",
+        value_name = "PLATFORM"
+    )]
+    pub grub_platform: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "GRUB cpu grub.cfg is evaluated for, e.g. x86_64 or i386",
+        value_name = "CPU"
+    )]
+    pub grub_cpu: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Set a GRUB feature flag when evaluating grub.cfg",
+        value_name = "NAME=VALUE"
+    )]
+    pub grub_feature: Vec<String>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Override a grubenv variable when evaluating grub.cfg",
+        value_name = "NAME=VALUE"
+    )]
+    pub grub_env: Vec<String>,
+}
+
+#[derive(Subcommand)]
 pub(crate) enum Commands {
+    #[command(about = "List boot entries, those of the detected boot loader if no kind is given")]
     List {
         #[arg(short, long, help = "List the GRUB boot entries")]
         grub: bool,
@@ -11,14 +86,28 @@ pub(crate) enum Commands {
         #[arg(short, long, help = "List the firmware boot entries")]
         firmware: bool,
 
+        #[arg(short, long, help = "List the systemd-boot entries")]
+        systemd_boot: bool,
+
+        #[arg(short, long, help = "List the rEFInd settings and entries")]
+        refind: bool,
+
+        #[arg(
+            short = 'x',
+            long,
+            help = "List the extlinux/syslinux settings and entries"
+        )]
+        extlinux: bool,
+
         #[arg(short, long, help = "Description for the entry of grub")]
         description: Option<String>,
     },
+    #[command(group(ArgGroup::new("oneshot_entry").args(["systemd_boot", "entry"]).multiple(true)))]
     Set {
         #[arg(
             short,
             long,
-            help = "Set the GRUB entry by id or index",
+            help = "Set the GRUB entry by selector",
             value_name = "ENTRY"
         )]
         grub: Option<String>,
@@ -26,12 +115,360 @@ pub(crate) enum Commands {
         #[arg(
             short,
             long,
-            help = "Set the firmware entry by identifier",
+            help = "Set the entry of the detected boot loader by selector",
+            value_name = "ENTRY"
+        )]
+        entry: Option<String>,
+
+        #[arg(
+            long,
+            requires = "grub",
+            help = "Store the GRUB selector and re-resolve it on later changes to GRUB"
+        )]
+        sticky: bool,
+
+        #[arg(
+            long,
+            requires = "grub",
+            help = "Set the GRUB entry even if its kernel or initrd is missing"
+        )]
+        force: bool,
+
+        #[arg(
+            long,
+            requires = "grub",
+            help = "Set GRUB_DEFAULT=saved in /etc/default/grub and regenerate grub.cfg"
+        )]
+        persist: bool,
+
+        #[arg(
+            short,
+            long,
+            help = "Set the firmware entry by selector",
             value_name = "ENTRY"
         )]
         firmware: Option<String>,
 
+        #[arg(
+            short,
+            long,
+            help = "Set the systemd-boot entry by selector",
+            value_name = "ENTRY"
+        )]
+        systemd_boot: Option<String>,
+
+        #[arg(
+            long,
+            requires = "oneshot_entry",
+            help = "Boot the systemd-boot entry or the entry of the boot loader on the next boot only"
+        )]
+        oneshot: bool,
+
+        #[arg(
+            short,
+            long,
+            help = "Set default_selection of refind.conf to the rEFInd entry picked by selector",
+            value_name = "ENTRY"
+        )]
+        refind: Option<String>,
+
+        #[arg(
+            long,
+            allow_negative_numbers = true,
+            help = "Set timeout of refind.conf in seconds, 0 waits forever and -1 boots at once",
+            value_name = "SECONDS"
+        )]
+        refind_timeout: Option<i32>,
+
+        #[arg(
+            short = 'x',
+            long,
+            help = "Set the extlinux/syslinux entry by selector",
+            value_name = "ENTRY"
+        )]
+        extlinux: Option<String>,
+
+        #[arg(
+            short,
+            long,
+            conflicts_with_all = ["grub", "entry", "firmware"],
+            help = "Set the target of the targets view by selector, changing the layers that boot it",
+            value_name = "TARGET"
+        )]
+        target: Option<String>,
+
+        #[arg(short, long, help = "Description for the entry of grub")]
+        description: Option<String>,
+    },
+    #[command(
+        about = "List the bootable operating systems across the firmware and boot loader menus"
+    )]
+    Targets {
         #[arg(short, long, help = "Description for the entry of grub")]
         description: Option<String>,
     },
+    Show {
+        #[arg(
+            short,
+            long,
+            help = "Show the boot details of a GRUB entry",
+            value_name = "ENTRY"
+        )]
+        grub: Option<String>,
+
+        #[arg(
+            short,
+            long,
+            help = "Show the boot details of a systemd-boot entry",
+            value_name = "ENTRY"
+        )]
+        systemd_boot: Option<String>,
+
+        #[arg(
+            short,
+            long,
+            help = "Show the boot details of a rEFInd entry",
+            value_name = "ENTRY"
+        )]
+        refind: Option<String>,
+
+        #[arg(
+            short = 'x',
+            long,
+            help = "Show the boot details of an extlinux/syslinux entry",
+            value_name = "ENTRY"
+        )]
+        extlinux: Option<String>,
+
+        #[arg(short, long, help = "Description for the entry of grub")]
+        description: Option<String>,
+    },
+    Status,
+    #[command(about = "Show the menu timeout of the detected boot loader, or set it")]
+    Timeout {
+        #[arg(
+            help = "New timeout in the unit of the boot loader, shows the current one if omitted",
+            allow_negative_numbers = true
+        )]
+        timeout: Option<String>,
+    },
+    #[command(about = "Show which boot loader each firmware entry starts and the one managed")]
+    Detect,
+    #[command(about = "Save grub.cfg and grubenv, the firmware boot variables and the BCD store")]
+    Backup {
+        #[arg(help = "Archive to write")]
+        file: String,
+    },
+    #[command(
+        about = "Restore a backup, all parts if none is given",
+        group = ArgGroup::new("parts").multiple(true)
+    )]
+    Restore {
+        #[arg(help = "Archive written by backup")]
+        file: String,
+
+        #[arg(long, group = "parts", help = "Restore the GRUB files")]
+        grub: bool,
+
+        #[arg(long, group = "parts", help = "Restore the firmware boot variables")]
+        efi: bool,
+
+        #[arg(long, group = "parts", help = "Restore the BCD store")]
+        bcd: bool,
+
+        #[arg(short, long, help = "Restore without asking after the preview")]
+        yes: bool,
+    },
+    #[command(about = "Show the recorded changes of the boot configuration, newest first")]
+    History {
+        #[arg(short = 'n', long, help = "Show only the newest records")]
+        count: Option<usize>,
+    },
+    #[command(about = "Revert the newest recorded changes if nothing changed them since")]
+    Undo {
+        #[arg(default_value_t = 1, help = "Number of records to revert")]
+        count: usize,
+    },
+    #[command(about = "Show how the machine differs from a desired state file")]
+    Plan {
+        #[arg(help = "TOML file describing the desired boot configuration")]
+        file: String,
+    },
+    #[command(about = "Change the machine to match a desired state file")]
+    Apply {
+        #[arg(help = "TOML file describing the desired boot configuration")]
+        file: String,
+    },
+    #[command(
+        about = "Boot a target on the next boot only and reboot now",
+        group = ArgGroup::new("reboot_target").args(["target", "firmware", "grub"]).required(true).multiple(true)
+    )]
+    Reboot {
+        #[arg(help = "Profile, alias, firmware entry or target selector to boot once")]
+        target: Option<String>,
+
+        #[arg(
+            short,
+            long,
+            help = "Boot the firmware entry once",
+            value_name = "ENTRY"
+        )]
+        firmware: Option<String>,
+
+        #[arg(short, long, help = "Boot the GRUB entry once", value_name = "ENTRY")]
+        grub: Option<String>,
+
+        #[arg(short, long, help = "Reboot without asking")]
+        yes: bool,
+    },
+    #[command(about = "Use or list the named boot profiles of the configuration")]
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCommands,
+    },
+    Check {
+        #[arg(short, long, help = "Description for the entry of grub")]
+        description: Option<String>,
+    },
+    GrubConfig {
+        #[arg(
+            long,
+            help = "Set GRUB_DEFAULT to saved or to the GRUB entry picked by selector",
+            value_name = "ENTRY"
+        )]
+        default: Option<String>,
+
+        #[arg(long, help = "Set GRUB_TIMEOUT in seconds")]
+        timeout: Option<u32>,
+
+        #[arg(long, help = "Set GRUB_SAVEDEFAULT", value_name = "BOOL")]
+        savedefault: Option<bool>,
+
+        #[arg(long, help = "Set GRUB_DISABLE_SUBMENU", value_name = "BOOL")]
+        disable_submenu: Option<bool>,
+
+        #[arg(long, help = "Do not regenerate grub.cfg")]
+        no_update: bool,
+    },
+    Menu {
+        #[command(subcommand)]
+        command: MenuCommands,
+
+        #[arg(short, long, help = "Description for the entry of grub")]
+        description: Option<String>,
+    },
+    SdBoot {
+        #[command(subcommand)]
+        command: SdBootCommands,
+    },
+    Fw {
+        #[command(subcommand)]
+        command: FwCommands,
+    },
+}
+
+#[derive(Subcommand)]
+pub(crate) enum MenuCommands {
+    #[command(about = "Clear the record of a failed boot")]
+    ClearFail,
+    #[command(about = "Show the GRUB menu on the next boot only")]
+    ShowOnce,
+    #[command(about = "Hide the GRUB menu while boots succeed")]
+    Hide,
+}
+
+#[derive(Subcommand)]
+pub(crate) enum ProfileCommands {
+    #[command(about = "Set the firmware and GRUB entries of a profile")]
+    Use {
+        #[arg(help = "Name of the profile")]
+        name: String,
+    },
+    #[command(about = "List the profiles, marking those in effect")]
+    List,
+}
+
+#[derive(Subcommand)]
+pub(crate) enum FwCommands {
+    Create {
+        #[arg(
+            long,
+            help = "Disk holding the loader, a device on Linux or a disk number on Windows"
+        )]
+        disk: String,
+
+        #[arg(long, help = "Partition number of the loader on the disk")]
+        part: u32,
+
+        #[arg(
+            long,
+            value_name = "PATH",
+            help = "Path of the loader on the partition"
+        )]
+        loader_path: String,
+
+        #[arg(long, help = "Description of the firmware entry")]
+        label: String,
+
+        #[arg(long, help = "Optional arguments passed to the loader")]
+        args: Option<String>,
+    },
+    Delete {
+        #[arg(help = "Selector of the firmware entry to delete")]
+        entry: String,
+    },
+    #[command(group(
+        ArgGroup::new("changes")
+            .args(["label", "loader_path", "args", "active", "inactive", "hidden", "visible"])
+            .required(true)
+            .multiple(true)
+    ))]
+    Modify {
+        #[arg(help = "Selector of the firmware entry to modify")]
+        entry: String,
+
+        #[arg(long, help = "New description of the firmware entry")]
+        label: Option<String>,
+
+        #[arg(
+            long,
+            value_name = "PATH",
+            help = "New path of the loader on the same partition"
+        )]
+        loader_path: Option<String>,
+
+        #[arg(long, help = "New arguments passed to the loader, empty to clear")]
+        args: Option<String>,
+
+        #[arg(long, conflicts_with = "inactive", help = "Mark the entry active")]
+        active: bool,
+
+        #[arg(long, help = "Mark the entry inactive")]
+        inactive: bool,
+
+        #[arg(
+            long,
+            conflicts_with = "visible",
+            help = "Hide the entry from the firmware menu"
+        )]
+        hidden: bool,
+
+        #[arg(long, help = "Show the entry in the firmware menu")]
+        visible: bool,
+    },
+    Timeout {
+        #[arg(help = "New firmware menu timeout in seconds, shows the current one if omitted")]
+        seconds: Option<u16>,
+    },
+    Setup,
+}
+
+#[derive(Subcommand)]
+pub(crate) enum SdBootCommands {
+    Timeout {
+        #[arg(
+            help = "New systemd-boot menu timeout in seconds, or menu-force, menu-hidden or menu-disabled, shows the current one if omitted"
+        )]
+        timeout: Option<String>,
+    },
 }
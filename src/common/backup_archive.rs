@@ -0,0 +1,260 @@
+use std::io::{Error, ErrorKind, Result};
+
+/// The archive format version written by this bootmgr
+pub(crate) const BACKUP_VERSION: u32 = 1;
+const BACKUP_MAGIC: &str = "bootmgr-backup";
+
+/// The parts of the boot configuration a backup holds
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum BackupPart {
+    /// Files of the GRUB directory, named relative to the directory holding it
+    Grub,
+    /// EFI variables, named as accepted by `Platform::get_efi_var`
+    Efi,
+    /// The BCD store as exported by `bcdedit /export`
+    Bcd,
+}
+
+impl BackupPart {
+    pub const ALL: [BackupPart; 3] = [BackupPart::Grub, BackupPart::Efi, BackupPart::Bcd];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BackupPart::Grub => "grub",
+            BackupPart::Efi => "efi",
+            BackupPart::Bcd => "bcd",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.name() == name)
+    }
+}
+
+/// A struct representing one saved piece of the boot configuration
+/// Fields:
+/// * `part` - The part the item belongs to
+/// * `name` - The file or variable the data was read from
+/// * `data` - The saved content
+#[derive(Clone, Debug)]
+pub(crate) struct BackupItem {
+    pub part: BackupPart,
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// A struct representing a backup archive
+///
+/// The archive starts with a `bootmgr-backup <version>` line and a `created`
+/// line, followed by one `item <part> <length> <name>` line per item, each
+/// followed by the raw data and a newline, and ends with an `end` line.
+/// Fields:
+/// * `version` - The format version the archive was written with
+/// * `created` - When the backup was taken, in seconds since the Unix epoch
+/// * `items` - The saved items
+#[derive(Clone, Debug)]
+pub(crate) struct BackupArchive {
+    pub version: u32,
+    pub created: u64,
+    pub items: Vec<BackupItem>,
+}
+
+impl BackupArchive {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let invalid =
+            |msg: &str| Error::new(ErrorKind::InvalidData, format!("Invalid backup: {}", msg));
+        let mut pos = 0;
+        let next_line = |pos: &mut usize| -> Result<String> {
+            let rest = &data[*pos..];
+            let end = rest
+                .iter()
+                .position(|&b| b == b'\n')
+                .ok_or(invalid("unexpected end of file"))?;
+            *pos += end + 1;
+            Ok(String::from_utf8_lossy(&rest[..end]).to_string())
+        };
+
+        let header = next_line(&mut pos)?;
+        let version = header
+            .strip_prefix(BACKUP_MAGIC)
+            .and_then(|v| v.trim().parse::<u32>().ok())
+            .ok_or(invalid("not a bootmgr backup"))?;
+        if version > BACKUP_VERSION {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "Backup format version {} is newer than the supported version {}",
+                    version, BACKUP_VERSION
+                ),
+            ));
+        }
+        let created = next_line(&mut pos)?
+            .strip_prefix("created ")
+            .and_then(|v| v.parse::<u64>().ok())
+            .ok_or(invalid("missing creation time"))?;
+
+        let mut items = Vec::new();
+        loop {
+            let line = next_line(&mut pos)?;
+            if line == "end" {
+                break;
+            }
+            let mut fields = line.splitn(4, ' ');
+            let (Some("item"), Some(part), Some(len), Some(name)) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                return Err(invalid(&format!("unexpected line '{}'", line)));
+            };
+            let part =
+                BackupPart::from_name(part).ok_or(invalid(&format!("unknown part '{}'", part)))?;
+            let len = len
+                .parse::<usize>()
+                .map_err(|_| invalid(&format!("bad length of {}", name)))?;
+            if data.len() < pos + len + 1 || data[pos + len] != b'\n' {
+                return Err(invalid(&format!("truncated data of {}", name)));
+            }
+            items.push(BackupItem {
+                part,
+                name: name.to_string(),
+                data: data[pos..pos + len].to_vec(),
+            });
+            pos += len + 1;
+        }
+        Ok(Self {
+            version,
+            created,
+            items,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut ret = format!(
+            "{} {}\ncreated {}\n",
+            BACKUP_MAGIC, self.version, self.created
+        )
+        .into_bytes();
+        for item in &self.items {
+            ret.extend_from_slice(
+                format!(
+                    "item {} {} {}\n",
+                    item.part.name(),
+                    item.data.len(),
+                    item.name
+                )
+                .as_bytes(),
+            );
+            ret.extend_from_slice(&item.data);
+            ret.push(b'\n');
+        }
+        ret.extend_from_slice(b"end\n");
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A backup of a machine booting GRUB and Windows
+    fn dual_boot_backup() -> BackupArchive {
+        BackupArchive {
+            version: BACKUP_VERSION,
+            created: 1761998400,
+            items: vec![
+                BackupItem {
+                    part: BackupPart::Grub,
+                    name: "grub/grub.cfg".to_string(),
+                    data: b"set default=0\nmenuentry 'Debian' {\n}".to_vec(),
+                },
+                // Boot0001 then Boot000A, the second entry holds a newline byte
+                BackupItem {
+                    part: BackupPart::Efi,
+                    name: "BootOrder".to_string(),
+                    data: vec![0x01, 0x00, 0x0a, 0x00],
+                },
+                BackupItem {
+                    part: BackupPart::Bcd,
+                    name: "BCD".to_string(),
+                    data: Vec::new(),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn keeps_binary_and_empty_items_intact() {
+        let backup = dual_boot_backup();
+        let parsed = BackupArchive::parse(&backup.to_bytes()).unwrap();
+        assert_eq!(parsed.version, BACKUP_VERSION);
+        assert_eq!(parsed.created, backup.created);
+        assert_eq!(parsed.items.len(), 3);
+        for (a, b) in parsed.items.iter().zip(&backup.items) {
+            assert_eq!((a.part, &a.name, &a.data), (b.part, &b.name, &b.data));
+        }
+    }
+
+    #[test]
+    fn prefixes_each_item_with_its_length() {
+        let mut backup = dual_boot_backup();
+        backup.items.remove(0);
+        assert_eq!(
+            backup.to_bytes(),
+            b"bootmgr-backup 1\ncreated 1761998400\n\
+              item efi 4 BootOrder\n\x01\x00\n\x00\n\
+              item bcd 0 BCD\n\n\
+              end\n"
+        );
+        assert_eq!(BackupPart::from_name("efi"), Some(BackupPart::Efi));
+        assert_eq!(BackupPart::from_name("EFI"), None);
+    }
+
+    #[test]
+    fn refuses_other_files_and_newer_formats() {
+        let error = |data: &[u8]| BackupArchive::parse(data).unwrap_err().to_string();
+        assert_eq!(
+            error(b"PK\x03\x04\n"),
+            "Invalid backup: not a bootmgr backup"
+        );
+        assert_eq!(
+            error(b"bootmgr-backup 1\nwhen 0\nend\n"),
+            "Invalid backup: missing creation time"
+        );
+        let newer = BackupArchive::parse(b"bootmgr-backup 2\ncreated 0\nend\n").unwrap_err();
+        assert_eq!(newer.kind(), ErrorKind::Unsupported);
+        assert_eq!(
+            newer.to_string(),
+            "Backup format version 2 is newer than the supported version 1"
+        );
+    }
+
+    #[test]
+    fn names_the_item_a_restore_would_lose() {
+        let error = |data: &[u8]| BackupArchive::parse(data).unwrap_err().to_string();
+        let data = dual_boot_backup().to_bytes();
+        assert_eq!(
+            error(&data[..data.len() - b"\nend\n".len()]),
+            "Invalid backup: truncated data of BCD"
+        );
+        assert_eq!(
+            error(b"bootmgr-backup 1\ncreated 0\nitem efi -1 Timeout\nend\n"),
+            "Invalid backup: bad length of Timeout"
+        );
+        assert_eq!(
+            error(b"bootmgr-backup 1\ncreated 0\nitem efi 1 Timeout\nxy\nend\n"),
+            "Invalid backup: truncated data of Timeout"
+        );
+        assert_eq!(
+            error(b"bootmgr-backup 1\ncreated 0\nitem disk 1 sda\nx\nend\n"),
+            "Invalid backup: unknown part 'disk'"
+        );
+        assert_eq!(
+            error(b"bootmgr-backup 1\ncreated 0\nfile BCD\nend\n"),
+            "Invalid backup: unexpected line 'file BCD'"
+        );
+        // An archive cut after an item loses the items that followed
+        assert_eq!(
+            error(b"bootmgr-backup 1\ncreated 0\n"),
+            "Invalid backup: unexpected end of file"
+        );
+    }
+}
@@ -0,0 +1,337 @@
+use crate::common::output::OutputFormat;
+use crate::common::toml;
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+
+/// A struct representing the settings of the configuration files
+/// Fields:
+/// * `grub_keywords` - Extra words of firmware entry descriptions telling they start GRUB
+/// * `grub_location` - Where GRUB is found instead of searching, a directory on Linux and a partition on Windows
+/// * `firmware_aliases` - Short names of firmware entry selectors
+/// * `grub_aliases` - Short names of GRUB entry selectors
+/// * `format` - The output format used without `--format`
+/// * `profiles` - The named boot profiles
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Config {
+    pub grub_keywords: Vec<String>,
+    pub grub_location: Option<String>,
+    pub firmware_aliases: Vec<(String, String)>,
+    pub grub_aliases: Vec<(String, String)>,
+    pub format: Option<OutputFormat>,
+    pub profiles: Vec<Profile>,
+}
+
+/// A struct representing a named boot profile
+/// Fields:
+/// * `name` - The name of the profile, as given to `profile use`
+/// * `firmware` - The selector of the firmware entry
+/// * `grub` - The selector of the GRUB entry
+/// * `oneshot` - Whether the entries are booted on the next boot only
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Profile {
+    pub name: String,
+    pub firmware: Option<String>,
+    pub grub: Option<String>,
+    pub oneshot: bool,
+}
+
+impl Config {
+    /// Read the configuration files, later files override earlier ones
+    ///
+    /// Missing files are skipped. Keywords add up, aliases of the same name
+    /// and single values are replaced.
+    /// # Arguments
+    /// * `files` - The configuration files, system-wide first
+    pub fn load(files: &[PathBuf]) -> Result<Self> {
+        let mut ret = Config::default();
+        for file in files {
+            let content = match std::fs::read_to_string(file) {
+                Ok(content) => content,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(Error::new(e.kind(), format!("{}: {}", file.display(), e))),
+            };
+            let config = Config::parse(&content)
+                .map_err(|e| Error::new(e.kind(), format!("{}: {}", file.display(), e)))?;
+            ret.merge(config);
+        }
+        Ok(ret)
+    }
+
+    /// Parse a configuration file
+    ///
+    /// ```toml
+    /// [grub]
+    /// keywords = ["fedora", "arch"]
+    /// location = "/boot"
+    ///
+    /// [aliases.firmware]
+    /// win = "Windows Boot Manager"
+    ///
+    /// [aliases.grub]
+    /// lts = "kernel:6.1.*"
+    ///
+    /// [output]
+    /// format = "json"
+    ///
+    /// [profiles.gaming]
+    /// firmware = "win"
+    /// oneshot = true
+    /// ```
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut ret = Config::default();
+        for table in toml::parse(content)? {
+            match table.name.as_str() {
+                "grub" => {
+                    table.check_keys(&["keywords", "location"])?;
+                    ret.grub_keywords = table.get_str_array("keywords")?.unwrap_or_default();
+                    ret.grub_location = table.get_str("location")?;
+                }
+                "aliases.firmware" | "aliases.grub" => {
+                    let mut aliases = Vec::new();
+                    for (name, _) in &table.entries {
+                        aliases.push((name.clone(), table.get_str(name)?.unwrap_or_default()));
+                    }
+                    match table.name.as_str() {
+                        "aliases.firmware" => ret.firmware_aliases = aliases,
+                        _ => ret.grub_aliases = aliases,
+                    }
+                }
+                "output" => {
+                    table.check_keys(&["format"])?;
+                    ret.format = table
+                        .get_str("format")?
+                        .map(|name| {
+                            OutputFormat::from_name(&name).ok_or(Error::new(
+                                ErrorKind::InvalidData,
+                                format!("output.format must be text or json, not '{}'", name),
+                            ))
+                        })
+                        .transpose()?;
+                }
+                "" => table.check_keys(&[])?,
+                name if table.path.len() == 2 && table.path[0] == "profiles" => {
+                    table.check_keys(&["firmware", "grub", "oneshot"])?;
+                    let profile = Profile {
+                        name: table.path[1].clone(),
+                        firmware: table.get_str("firmware")?,
+                        grub: table.get_str("grub")?,
+                        oneshot: table.get_bool("oneshot")?.unwrap_or(false),
+                    };
+                    if profile.firmware.is_none() && profile.grub.is_none() {
+                        return Err(Error::new(
+                            ErrorKind::InvalidData,
+                            format!("[{}] sets neither firmware nor grub", name),
+                        ));
+                    }
+                    ret.profiles.push(profile);
+                }
+                name => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Unknown table [{}]", name),
+                    ));
+                }
+            }
+        }
+        Ok(ret)
+    }
+
+    fn merge(&mut self, other: Config) {
+        self.grub_keywords.extend(other.grub_keywords);
+        if other.grub_location.is_some() {
+            self.grub_location = other.grub_location;
+        }
+        merge_aliases(&mut self.firmware_aliases, other.firmware_aliases);
+        merge_aliases(&mut self.grub_aliases, other.grub_aliases);
+        if other.format.is_some() {
+            self.format = other.format;
+        }
+        for profile in other.profiles {
+            self.profiles.retain(|p| p.name != profile.name);
+            self.profiles.push(profile);
+        }
+    }
+
+    /// Find a profile by name
+    pub fn find_profile(&self, name: &str) -> Result<&Profile> {
+        self.profiles
+            .iter()
+            .find(|p| p.name == name)
+            .ok_or_else(|| {
+                let names: Vec<&str> = self.profiles.iter().map(|p| p.name.as_str()).collect();
+                Error::new(
+                    ErrorKind::NotFound,
+                    match names.is_empty() {
+                        true => format!("No profile named '{}', none is configured", name),
+                        false => format!(
+                            "No profile named '{}', configured ones are {}",
+                            name,
+                            names.join(", ")
+                        ),
+                    },
+                )
+            })
+    }
+
+    /// Replace a firmware alias by the selector it names, other selectors are kept
+    pub fn firmware_selector(&self, selector: &str) -> String {
+        resolve_alias(&self.firmware_aliases, selector)
+    }
+
+    /// Replace a GRUB alias by the selector it names, other selectors are kept
+    pub fn grub_selector(&self, selector: &str) -> String {
+        resolve_alias(&self.grub_aliases, selector)
+    }
+}
+
+fn merge_aliases(aliases: &mut Vec<(String, String)>, other: Vec<(String, String)>) {
+    for (name, selector) in other {
+        aliases.retain(|(n, _)| *n != name);
+        aliases.push((name, selector));
+    }
+}
+
+fn resolve_alias(aliases: &[(String, String)], selector: &str) -> String {
+    aliases
+        .iter()
+        .find(|(name, _)| name == selector)
+        .map_or(selector, |(_, target)| target.as_str())
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYSTEM: &str = r#"
+[grub]
+keywords = ["fedora"]
+location = "/boot"
+
+[aliases.firmware]
+win = "Windows Boot Manager"
+
+[aliases.grub]
+lts = "kernel:6.1.*"
+"6.1" = "kernel:6.1.*"
+
+[output]
+format = "json"
+
+[profiles.gaming]
+firmware = "win"
+oneshot = true
+
+[profiles."lts.server"]
+firmware = "debian"
+grub = "lts"
+"#;
+
+    fn error(content: &str) -> String {
+        Config::parse(content).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn parses_every_table() {
+        let config = Config::parse(SYSTEM).unwrap();
+        assert_eq!(config.grub_keywords, ["fedora"]);
+        assert_eq!(config.grub_location.as_deref(), Some("/boot"));
+        assert_eq!(config.format, Some(OutputFormat::Json));
+        assert_eq!(config.firmware_selector("win"), "Windows Boot Manager");
+        assert_eq!(config.firmware_selector("debian"), "debian");
+        assert_eq!(config.grub_selector("6.1"), "kernel:6.1.*");
+
+        let gaming = config.find_profile("gaming").unwrap();
+        assert_eq!(gaming.firmware.as_deref(), Some("win"));
+        assert_eq!(gaming.grub, None);
+        assert!(gaming.oneshot);
+        let lts = config.find_profile("lts.server").unwrap();
+        assert_eq!(lts.grub.as_deref(), Some("lts"));
+        assert!(!lts.oneshot);
+    }
+
+    #[test]
+    fn lets_later_files_win() {
+        let dir = std::env::temp_dir().join(format!("bootmgr-test-{}-config", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("system.toml"), SYSTEM).unwrap();
+        std::fs::write(
+            dir.join("user.toml"),
+            "[grub]\nkeywords = [\"arch\"]\n\n[aliases.firmware]\nwin = \"Boot0000\"\n\n\
+             [profiles.gaming]\ngrub = \"os:windows\"\n",
+        )
+        .unwrap();
+        let config = Config::load(&[
+            dir.join("system.toml"),
+            dir.join("missing.toml"),
+            dir.join("user.toml"),
+        ])
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(config.grub_keywords, ["fedora", "arch"]);
+        assert_eq!(config.grub_location.as_deref(), Some("/boot"));
+        assert_eq!(config.firmware_selector("win"), "Boot0000");
+        assert_eq!(config.grub_selector("lts"), "kernel:6.1.*");
+        // A profile is replaced as a whole
+        let gaming = config.find_profile("gaming").unwrap();
+        assert_eq!(gaming.firmware, None);
+        assert_eq!(gaming.grub.as_deref(), Some("os:windows"));
+        assert!(!gaming.oneshot);
+    }
+
+    #[test]
+    fn names_the_file_of_an_error() {
+        let dir =
+            std::env::temp_dir().join(format!("bootmgr-test-{}-config-error", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        std::fs::write(&path, "[grub]\nlocation = /boot\n").unwrap();
+        let err = Config::load(std::slice::from_ref(&path)).unwrap_err();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            err.to_string(),
+            format!("{}: line 2: expected a value, found '/'", path.display())
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        assert_eq!(error("[boot]\n"), "Unknown table [boot]");
+        assert_eq!(error("[profiles]\n"), "Unknown table [profiles]");
+        assert_eq!(
+            error("[profiles.a.b]\ngrub = \"x\"\n"),
+            "Unknown table [profiles.a.b]"
+        );
+        assert_eq!(error("[grub]\npath = \"/boot\"\n"), "Unknown key grub.path");
+        assert_eq!(
+            error("[aliases.grub]\nlts = 1\n"),
+            "aliases.grub.lts must be a string, not an integer"
+        );
+        assert_eq!(
+            error("[output]\nformat = \"yaml\"\n"),
+            "output.format must be text or json, not 'yaml'"
+        );
+        assert_eq!(
+            error("[profiles.empty]\noneshot = true\n"),
+            "[profiles.empty] sets neither firmware nor grub"
+        );
+    }
+
+    #[test]
+    fn names_the_configured_profiles() {
+        assert_eq!(
+            Config::default()
+                .find_profile("gaming")
+                .unwrap_err()
+                .to_string(),
+            "No profile named 'gaming', none is configured"
+        );
+        let config = Config::parse(SYSTEM).unwrap();
+        assert_eq!(
+            config.find_profile("work").unwrap_err().to_string(),
+            "No profile named 'work', configured ones are gaming, lts.server"
+        );
+    }
+}
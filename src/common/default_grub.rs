@@ -0,0 +1,205 @@
+/// A struct representing the shell variables of /etc/default/grub
+///
+/// Lines are kept as written, so comments and unrelated settings survive edits.
+/// Fields:
+/// * `lines` - The lines of the file
+#[derive(Clone, Debug, Default)]
+pub(crate) struct DefaultGrub {
+    lines: Vec<String>,
+}
+
+impl DefaultGrub {
+    pub fn parse(content: &str) -> Self {
+        Self {
+            lines: content.lines().map(str::to_string).collect(),
+        }
+    }
+
+    /// Get the unquoted value of the last assignment of a variable
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.lines
+            .iter()
+            .rev()
+            .find_map(|line| assignment_value(line, key))
+            .map(|value| unquote(&value))
+    }
+
+    /// Set a variable, replacing its last assignment or adding one
+    ///
+    /// A new assignment goes after the commented out example of the variable
+    /// if there is one, at the end of the file otherwise.
+    pub fn set(&mut self, key: &str, value: &str) {
+        let line = format!("{}={}", key, quote(value));
+        if let Some(pos) = self
+            .lines
+            .iter()
+            .rposition(|l| assignment_value(l, key).is_some())
+        {
+            // Keep the indentation and `export` in front of the assignment
+            let old = &self.lines[pos];
+            let rest = old.trim_start();
+            let rest = rest.strip_prefix("export ").map_or(rest, str::trim_start);
+            self.lines[pos] = format!("{}{}", &old[..old.len() - rest.len()], line);
+        } else if let Some(pos) = self.lines.iter().rposition(|l| {
+            let l = l.trim_start().trim_start_matches('#').trim_start();
+            assignment_value(l, key).is_some()
+        }) {
+            self.lines.insert(pos + 1, line);
+        } else {
+            self.lines.push(line);
+        }
+    }
+
+    pub fn to_content(&self) -> String {
+        let mut ret = self.lines.join("\n");
+        ret.push('\n');
+        ret
+    }
+}
+
+/// Get the raw value if the line assigns the variable
+fn assignment_value(line: &str, key: &str) -> Option<String> {
+    let line = line.trim_start();
+    let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+    line.strip_prefix(key)?
+        .strip_prefix('=')
+        .map(|v| v.trim_end().to_string())
+}
+
+/// Remove shell quoting of a simple value
+fn unquote(value: &str) -> String {
+    let mut ret = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => ret.extend(chars.by_ref().take_while(|c| *c != '\'')),
+            '"' => {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => ret.extend(chars.next()),
+                        c => ret.push(c),
+                    }
+                }
+            }
+            '\\' => ret.extend(chars.next()),
+            '#' => break,
+            c if c.is_whitespace() => break,
+            c => ret.push(c),
+        }
+    }
+    ret
+}
+
+/// Quote a value for the shell unless it is a plain word
+fn quote(value: &str) -> String {
+    let plain = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_.,-+/:".contains(c));
+    if plain {
+        return value.to_string();
+    }
+    let mut ret = String::from("\"");
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            ret.push('\\');
+        }
+        ret.push(c);
+    }
+    ret.push('"');
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The head of Debian's /etc/default/grub
+    const DEBIAN: &str = "# If you change this file, run 'update-grub' afterwards to update\n\
+                          # /boot/grub/grub.cfg.\n\
+                          \n\
+                          GRUB_DEFAULT=0\n\
+                          GRUB_TIMEOUT=5\n\
+                          GRUB_DISTRIBUTOR=`lsb_release -i -s 2> /dev/null || echo Debian`\n\
+                          GRUB_CMDLINE_LINUX_DEFAULT=\"quiet\"\n\
+                          GRUB_CMDLINE_LINUX=\"\"\n\
+                          \n\
+                          # Uncomment to disable graphical terminal\n\
+                          #GRUB_TERMINAL=console\n\
+                          \texport GRUB_DISABLE_OS_PROBER=false  \n";
+
+    #[test]
+    fn reads_shell_quoted_values() {
+        let grub = DefaultGrub::parse(
+            "GRUB_DEFAULT='Advanced options>Debian'\n\
+             GRUB_CMDLINE_LINUX_DEFAULT=\"quiet \\\"x\\\"\" # set by the installer\n\
+             GRUB_TIMEOUT=5 # seconds\n\
+             GRUB_TIMEOUT=10\n",
+        );
+        assert_eq!(
+            grub.get("GRUB_DEFAULT").as_deref(),
+            Some("Advanced options>Debian")
+        );
+        assert_eq!(
+            grub.get("GRUB_CMDLINE_LINUX_DEFAULT").as_deref(),
+            Some("quiet \"x\"")
+        );
+        // The last assignment wins, as when the shell sources the file
+        assert_eq!(grub.get("GRUB_TIMEOUT").as_deref(), Some("10"));
+        assert_eq!(grub.get("GRUB_SAVEDEFAULT"), None);
+    }
+
+    #[test]
+    fn quotes_only_values_that_need_it() {
+        let mut grub = DefaultGrub::parse("");
+        for (key, value) in [
+            ("GRUB_DEFAULT", "saved"),
+            ("GRUB_CMDLINE_LINUX", "quiet splash"),
+            ("GRUB_DISTRIBUTOR", "$(id) `x` \"y\" \\"),
+            ("GRUB_BADRAM", ""),
+        ] {
+            grub.set(key, value);
+            assert_eq!(grub.get(key).as_deref(), Some(value));
+        }
+        assert_eq!(
+            grub.to_content(),
+            "GRUB_DEFAULT=saved\n\
+             GRUB_CMDLINE_LINUX=\"quiet splash\"\n\
+             GRUB_DISTRIBUTOR=\"\\$(id) \\`x\\` \\\"y\\\" \\\\\"\n\
+             GRUB_BADRAM=\"\"\n"
+        );
+    }
+
+    #[test]
+    fn changes_only_the_assigned_line() {
+        let mut grub = DefaultGrub::parse(DEBIAN);
+        grub.set("GRUB_DEFAULT", "saved");
+        grub.set("GRUB_DISABLE_OS_PROBER", "true");
+        assert_eq!(
+            grub.to_content(),
+            DEBIAN
+                .replace("GRUB_DEFAULT=0", "GRUB_DEFAULT=saved")
+                .replace(
+                    "\texport GRUB_DISABLE_OS_PROBER=false  ",
+                    "\texport GRUB_DISABLE_OS_PROBER=true"
+                )
+        );
+    }
+
+    #[test]
+    fn adds_settings_below_their_commented_out_example() {
+        let mut grub = DefaultGrub::parse(DEBIAN);
+        grub.set("GRUB_TERMINAL", "console");
+        grub.set("GRUB_SAVEDEFAULT", "true");
+        // A commented out assignment is no value
+        assert_eq!(DefaultGrub::parse(DEBIAN).get("GRUB_TERMINAL"), None);
+        assert_eq!(
+            grub.to_content(),
+            DEBIAN.replace(
+                "#GRUB_TERMINAL=console\n",
+                "#GRUB_TERMINAL=console\nGRUB_TERMINAL=console\n"
+            ) + "GRUB_SAVEDEFAULT=true\n"
+        );
+    }
+}
@@ -0,0 +1,190 @@
+use crate::common::toml::{self, TomlTable};
+use std::io::{Error, ErrorKind, Result};
+
+/// What to do with a pending GRUB one-shot entry
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum OneshotPolicy {
+    /// No entry may be booted once, `oneshot = false`
+    Clear,
+    /// The entry picked by a selector is booted once, `oneshot = "<selector>"`
+    Entry(String),
+}
+
+/// A struct representing the boot configuration a machine should converge to
+///
+/// Settings left out of the file are not managed and left as they are.
+/// Fields:
+/// * `firmware_order` - Selectors of the firmware entries that lead the boot order, in order
+/// * `firmware_timeout` - The firmware boot menu timeout in seconds
+/// * `grub_default` - The selector of the default GRUB entry
+/// * `grub_sticky` - Whether the GRUB selector is stored and re-resolved on each run
+/// * `grub_oneshot` - What to do with the GRUB entry booted once
+/// * `windows_default` - The selector of the default Windows Boot Manager entry
+#[derive(Clone, Debug, Default)]
+pub(crate) struct DesiredState {
+    pub firmware_order: Option<Vec<String>>,
+    pub firmware_timeout: Option<u16>,
+    pub grub_default: Option<String>,
+    pub grub_sticky: bool,
+    pub grub_oneshot: Option<OneshotPolicy>,
+    pub windows_default: Option<String>,
+}
+
+impl DesiredState {
+    /// Parse a desired state file
+    ///
+    /// ```toml
+    /// [firmware]
+    /// order = ["ubuntu", "Windows Boot Manager"]
+    /// timeout = 3
+    ///
+    /// [grub]
+    /// default = "latest"
+    /// sticky = true
+    /// oneshot = false
+    ///
+    /// [windows]
+    /// default = "Windows 11"
+    /// ```
+    /// # Arguments
+    /// * `content` - The content of the file
+    /// # Returns
+    /// * `Result<Self>` - The desired state
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut ret = DesiredState::default();
+        for table in toml::parse(content)? {
+            match table.name.as_str() {
+                "" if table.entries.is_empty() => {}
+                "firmware" => {
+                    table.check_keys(&["order", "timeout"])?;
+                    ret.firmware_order = table.get_str_array("order")?;
+                    ret.firmware_timeout = table
+                        .get_integer("timeout")?
+                        .map(|t| parse_timeout(&table, t))
+                        .transpose()?;
+                }
+                "grub" => {
+                    table.check_keys(&["default", "sticky", "oneshot"])?;
+                    ret.grub_default = table.get_str("default")?;
+                    ret.grub_sticky = table.get_bool("sticky")?.unwrap_or(false);
+                    ret.grub_oneshot = match table.get("oneshot") {
+                        None => None,
+                        Some(toml::TomlValue::Boolean(false)) => Some(OneshotPolicy::Clear),
+                        Some(_) => Some(OneshotPolicy::Entry(
+                            table
+                                .get_str("oneshot")
+                                .map_err(|_| {
+                                    invalid(
+                                        "grub.oneshot must be false or the selector of an entry",
+                                    )
+                                })?
+                                .unwrap_or_default(),
+                        )),
+                    };
+                    if ret.grub_sticky && ret.grub_default.is_none() {
+                        return Err(invalid("grub.sticky needs grub.default"));
+                    }
+                }
+                "windows" => {
+                    table.check_keys(&["default"])?;
+                    ret.windows_default = table.get_str("default")?;
+                }
+                "" => table.check_keys(&[])?,
+                name => return Err(invalid(&format!("Unknown table [{}]", name))),
+            }
+        }
+        Ok(ret)
+    }
+}
+
+fn parse_timeout(table: &TomlTable, timeout: i64) -> Result<u16> {
+    u16::try_from(timeout).map_err(|_| {
+        invalid(&format!(
+            "{} must be between 0 and {}",
+            table.key_name("timeout"),
+            u16::MAX
+        ))
+    })
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(content: &str) -> String {
+        DesiredState::parse(content).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn parses_every_setting() {
+        let state = DesiredState::parse(
+            "[firmware]\n\
+             order = [\"debian\", \"Windows Boot Manager\"]\n\
+             timeout = 3\n\
+             [grub]\n\
+             default = \"latest\"\n\
+             sticky = true\n\
+             oneshot = \"os:Windows\"\n\
+             [windows]\n\
+             default = \"Windows 11\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            state.firmware_order,
+            Some(vec![
+                "debian".to_string(),
+                "Windows Boot Manager".to_string()
+            ])
+        );
+        assert_eq!(state.firmware_timeout, Some(3));
+        assert_eq!(state.grub_default.as_deref(), Some("latest"));
+        assert!(state.grub_sticky);
+        assert_eq!(
+            state.grub_oneshot,
+            Some(OneshotPolicy::Entry("os:Windows".to_string()))
+        );
+        assert_eq!(state.windows_default.as_deref(), Some("Windows 11"));
+    }
+
+    #[test]
+    fn leaves_missing_settings_unmanaged() {
+        let state = DesiredState::parse("# nothing yet\n[grub]\noneshot = false\n").unwrap();
+        assert_eq!(state.grub_oneshot, Some(OneshotPolicy::Clear));
+        assert_eq!(state.grub_default, None);
+        assert!(!state.grub_sticky);
+        assert_eq!(state.firmware_order, None);
+        assert_eq!(state.firmware_timeout, None);
+        assert_eq!(state.windows_default, None);
+    }
+
+    #[test]
+    fn rejects_invalid_states() {
+        assert_eq!(error("[boot]\n"), "Unknown table [boot]");
+        assert_eq!(error("timeout = 3\n"), "Unknown key timeout");
+        assert_eq!(error("[grub]\ndelay = 1\n"), "Unknown key grub.delay");
+        assert_eq!(
+            error("[firmware]\ntimeout = 70000\n"),
+            "firmware.timeout must be between 0 and 65535"
+        );
+        assert_eq!(
+            error("[firmware]\norder = \"debian\"\n"),
+            "firmware.order must be an array of strings, not a string"
+        );
+        assert_eq!(
+            error("[grub]\noneshot = true\n"),
+            "grub.oneshot must be false or the selector of an entry"
+        );
+        assert_eq!(
+            error("[grub]\nsticky = true\n"),
+            "grub.sticky needs grub.default"
+        );
+        assert_eq!(
+            error("[grub]\ndefault = 'a\n"),
+            "line 2: unterminated string"
+        );
+    }
+}
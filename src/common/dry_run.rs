@@ -0,0 +1,270 @@
+use crate::common::output;
+use std::io::{ErrorKind, Result};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// The lines of context around a change in a unified diff
+const DIFF_CONTEXT: usize = 3;
+/// The largest distance between two changed lines shown in one hunk
+const MERGE_DISTANCE: usize = 2 * DIFF_CONTEXT + 1;
+/// The bytes shown per line of an EFI variable
+const HEX_LINE_BYTES: usize = 16;
+
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+/// Record mutations instead of making them for the rest of the process
+pub(crate) fn enable() {
+    DRY_RUN.store(true, Ordering::Relaxed);
+}
+
+pub(crate) fn is_enabled() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+/// Run changes in dry-run mode to show what they would do
+/// # Arguments
+/// * `f` - Makes the changes
+pub(crate) fn preview<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let was_enabled = DRY_RUN.swap(true, Ordering::Relaxed);
+    let result = f();
+    DRY_RUN.store(was_enabled, Ordering::Relaxed);
+    result
+}
+
+/// Write a file, or print the unified diff of the change in dry-run mode
+/// # Arguments
+/// * `path` - The file to write
+/// * `content` - The new content of the file
+/// * `write` - Writes the content to the file
+pub(crate) fn write_file(
+    path: &Path,
+    content: &str,
+    write: impl FnOnce() -> Result<()>,
+) -> Result<()> {
+    if !is_enabled() {
+        return write();
+    }
+    let old = match std::fs::read_to_string(path) {
+        Ok(old) => Some(old),
+        Err(e) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    match old.as_deref() == Some(content) {
+        true => output::note(&format!("{}: unchanged", path.display())),
+        false => output::note(
+            unified_diff(&path.display().to_string(), old.as_deref(), content)
+                .trim_end_matches('\n'),
+        ),
+    }
+    Ok(())
+}
+
+/// Run a command changing the boot configuration, or print it in dry-run mode
+/// # Arguments
+/// * `command` - The program followed by its arguments
+/// * `run` - Runs the command
+/// # Returns
+/// * `Result<T>` - The result of the command, the default value in dry-run mode
+pub(crate) fn run_command<T: Default>(
+    command: &[&str],
+    run: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    if !is_enabled() {
+        return run();
+    }
+    let command: Vec<String> = command
+        .iter()
+        .map(
+            |arg| match arg.contains(char::is_whitespace) || arg.is_empty() {
+                true => format!("\"{}\"", arg),
+                false => arg.to_string(),
+            },
+        )
+        .collect();
+    output::note(&format!("Would run: {}", command.join(" ")));
+    Ok(T::default())
+}
+
+/// Write an EFI variable, or print the bytes in dry-run mode
+/// # Arguments
+/// * `name` - The name of the variable
+/// * `data` - The variable data
+/// * `write` - Writes the variable
+pub(crate) fn write_efivar(
+    name: &str,
+    data: &[u8],
+    write: impl FnOnce() -> Result<()>,
+) -> Result<()> {
+    if !is_enabled() {
+        return write();
+    }
+    output::note(&format!(
+        "Would write EFI variable {} ({} bytes):",
+        name,
+        data.len()
+    ));
+    for (i, chunk) in data.chunks(HEX_LINE_BYTES).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+        output::note(&format!("  {:04x}: {}", i * HEX_LINE_BYTES, hex.join(" ")));
+    }
+    Ok(())
+}
+
+/// Delete an EFI variable, or print its name in dry-run mode
+/// # Arguments
+/// * `name` - The name of the variable
+/// * `delete` - Deletes the variable
+pub(crate) fn delete_efivar(name: &str, delete: impl FnOnce() -> Result<()>) -> Result<()> {
+    if !is_enabled() {
+        return delete();
+    }
+    output::note(&format!("Would delete EFI variable {}", name));
+    Ok(())
+}
+
+/// Make a unified diff between two versions of a file
+/// # Arguments
+/// * `name` - The name of the file shown in the header
+/// * `old` - The current content, None if the file does not exist
+/// * `new` - The new content
+pub(crate) fn unified_diff(name: &str, old: Option<&str>, new: &str) -> String {
+    let old_lines: Vec<&str> = old.unwrap_or_default().lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let ops = diff_lines(&old_lines, &new_lines);
+
+    let mut ret = format!(
+        "--- {}\n+++ {}\n",
+        if old.is_some() { name } else { "/dev/null" },
+        name
+    );
+    let changes: Vec<usize> = (0..ops.len()).filter(|&i| ops[i].0 != ' ').collect();
+    let mut k = 0;
+    while k < changes.len() {
+        // Changes with at most twice the context of unchanged lines between share a hunk
+        let mut last = k;
+        while last + 1 < changes.len() && changes[last + 1] - changes[last] <= MERGE_DISTANCE {
+            last += 1;
+        }
+        let start = changes[k].saturating_sub(DIFF_CONTEXT);
+        let end = (changes[last] + DIFF_CONTEXT + 1).min(ops.len());
+        let hunk = &ops[start..end];
+
+        let old_count = hunk.iter().filter(|op| op.0 != '+').count();
+        let new_count = hunk.iter().filter(|op| op.0 != '-').count();
+        let old_start = hunk[0].1 + usize::from(old_count > 0);
+        let new_start = hunk[0].2 + usize::from(new_count > 0);
+        ret += &format!(
+            "@@ -{},{} +{},{} @@\n",
+            old_start, old_count, new_start, new_count
+        );
+        for &(tag, i, j) in hunk {
+            let line = match tag {
+                '+' => new_lines[j],
+                _ => old_lines[i],
+            };
+            ret += &format!("{}{}\n", tag, line);
+        }
+        k = last + 1;
+    }
+    ret
+}
+
+/// Get the lines removed and added between two versions of a file
+/// # Returns
+/// * `Vec<String>` - The removed lines prefixed with `-` and the added ones with `+`
+pub(crate) fn changed_lines(old: &str, new: &str) -> Vec<String> {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    diff_lines(&old_lines, &new_lines)
+        .into_iter()
+        .filter_map(|(tag, i, j)| match tag {
+            '-' => Some(format!("-{}", old_lines[i])),
+            '+' => Some(format!("+{}", new_lines[j])),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Compute the line operations turning one text into another
+/// # Returns
+/// * `Vec<(char, usize, usize)>` - The tag (` `, `-` or `+`) with the indices in the old and new lines
+fn diff_lines(old: &[&str], new: &[&str]) -> Vec<(char, usize, usize)> {
+    // lcs[i][j] is the length of the longest common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = match old[i] == new[j] {
+                true => lcs[i + 1][j + 1] + 1,
+                false => lcs[i + 1][j].max(lcs[i][j + 1]),
+            };
+        }
+    }
+
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            ops.push((' ', i, j));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(('-', i, j));
+            i += 1;
+        } else {
+            ops.push(('+', i, j));
+            j += 1;
+        }
+    }
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINES: &str = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+
+    #[test]
+    fn diffs_a_new_file_against_dev_null() {
+        assert_eq!(
+            unified_diff("loader.conf", None, "default arch\ntimeout 3\n"),
+            "--- /dev/null\n+++ loader.conf\n@@ -0,0 +1,2 @@\n+default arch\n+timeout 3\n"
+        );
+    }
+
+    #[test]
+    fn counts_hunks_from_the_first_line() {
+        assert_eq!(
+            unified_diff("grubenv", Some("a\nb\nc\nd\ne\n"), "x\nb\nc\nd\ne\n"),
+            "--- grubenv\n+++ grubenv\n@@ -1,4 +1,4 @@\n-a\n+x\n b\n c\n d\n"
+        );
+    }
+
+    #[test]
+    fn merges_hunks_like_diff_u() {
+        // Six unchanged lines between the changes are the context of both
+        let merged = LINES.replace("2\n", "X\n").replace("9\n", "Y\n");
+        assert_eq!(
+            unified_diff("f", Some(LINES), &merged),
+            "--- f\n+++ f\n@@ -1,10 +1,10 @@\n 1\n-2\n+X\n 3\n 4\n 5\n 6\n 7\n 8\n-9\n+Y\n 10\n"
+        );
+        let apart = LINES.replace("2\n", "X\n").replace("10\n", "Y\n");
+        assert_eq!(
+            unified_diff("f", Some(LINES), &apart),
+            "--- f\n+++ f\n@@ -1,5 +1,5 @@\n 1\n-2\n+X\n 3\n 4\n 5\n\
+             @@ -7,4 +7,4 @@\n 7\n 8\n 9\n-10\n+Y\n"
+        );
+    }
+
+    #[test]
+    fn lists_only_changed_lines() {
+        assert_eq!(
+            changed_lines(
+                "timeout 3\ndefault arch\n",
+                "timeout 3\ndefault win\neditor no\n"
+            ),
+            ["-default arch", "+default win", "+editor no"]
+        );
+        assert!(changed_lines(LINES, LINES).is_empty());
+    }
+}
@@ -0,0 +1,389 @@
+use crate::common::selector::Selectable;
+
+/// The deepest nesting of `INCLUDE` followed, like the limit of syslinux
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// A struct representing a LABEL block of extlinux/syslinux
+/// Fields:
+/// * `entry_label` - The name given to `LABEL`, used by `DEFAULT`
+/// * `entry_menu_label` - The title given by `MENU LABEL`
+/// * `entry_kernel` - The image loaded by `KERNEL` or `LINUX`
+/// * `entry_initrd` - The images loaded by `INITRD`
+/// * `entry_append` - The kernel command line given by `APPEND`
+/// * `entry_fdt` - The device tree given by `FDT` or `FDTDIR`
+/// * `entry_file` - The configuration file defining the entry
+/// * `entry_hidden` - Whether the entry is hidden by `MENU HIDE`
+/// * `entry_is_default` - Whether the entry is the default entry
+#[derive(Clone, Debug, Default)]
+pub struct ExtlinuxEntry {
+    pub entry_label: String,
+    pub entry_menu_label: Option<String>,
+    pub entry_kernel: Option<String>,
+    pub entry_initrd: Option<String>,
+    pub entry_append: Option<String>,
+    pub entry_fdt: Option<String>,
+    pub entry_file: String,
+    pub entry_hidden: bool,
+    pub entry_is_default: bool,
+}
+
+impl Selectable for ExtlinuxEntry {
+    fn id(&self) -> &str {
+        &self.entry_label
+    }
+
+    fn title(&self) -> &str {
+        self.entry_menu_label
+            .as_deref()
+            .unwrap_or(&self.entry_label)
+    }
+}
+
+/// A struct representing one extlinux/syslinux configuration file, keeping comments on edits
+/// Fields:
+/// * `name` - The path of the file as given to `INCLUDE`, the file name for the main file
+/// * `lines` - The lines of the file
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ExtlinuxConf {
+    pub name: String,
+    lines: Vec<String>,
+}
+
+impl ExtlinuxConf {
+    pub fn parse(name: &str, content: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            lines: content.lines().map(str::to_string).collect(),
+        }
+    }
+
+    /// Get the files named by `INCLUDE` and `MENU INCLUDE`
+    pub fn includes(&self) -> Vec<String> {
+        self.lines
+            .iter()
+            .filter_map(|l| include_target(l))
+            .map(str::to_string)
+            .collect()
+    }
+
+    pub fn to_content(&self) -> String {
+        let mut ret = self.lines.join("\n");
+        ret.push('\n');
+        ret
+    }
+}
+
+/// A struct representing extlinux.conf together with the files it includes
+/// Fields:
+/// * `files` - The main file followed by the included files
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ExtlinuxConfig {
+    pub files: Vec<ExtlinuxConf>,
+}
+
+impl ExtlinuxConfig {
+    /// Get the LABEL blocks in menu order
+    ///
+    /// The default is the entry marked `MENU DEFAULT`, or the one named by
+    /// `DEFAULT` otherwise.
+    pub fn entries(&self) -> Vec<ExtlinuxEntry> {
+        let mut entries: Vec<ExtlinuxEntry> = Vec::new();
+        let mut menu_default = None;
+        for (file, line) in self.walk() {
+            let (keyword, value) = split_keyword(&self.files[file].lines[line]);
+            if keyword == "label" {
+                entries.push(ExtlinuxEntry {
+                    entry_label: value.to_string(),
+                    entry_file: self.files[file].name.clone(),
+                    ..ExtlinuxEntry::default()
+                });
+                continue;
+            }
+            let Some(entry) = entries.last_mut() else {
+                continue;
+            };
+            let value = Some(value.to_string()).filter(|v| !v.is_empty());
+            match keyword.as_str() {
+                "menu label" => entry.entry_menu_label = value.map(|v| v.replace('^', "")),
+                "kernel" | "linux" => entry.entry_kernel = value,
+                "initrd" => entry.entry_initrd = value,
+                "append" => entry.entry_append = value.filter(|v| v != "-"),
+                "fdt" | "fdtdir" | "devicetree" => entry.entry_fdt = value,
+                "menu hide" => entry.entry_hidden = true,
+                "menu default" => menu_default = Some(entries.len() - 1),
+                _ => {}
+            }
+        }
+
+        let default = menu_default.or_else(|| {
+            let label = self.get("default")?;
+            let label = label.split_whitespace().next()?;
+            entries.iter().position(|e| e.entry_label == label)
+        });
+        if let Some(index) = default {
+            entries[index].entry_is_default = true;
+        }
+        entries
+    }
+
+    /// Get the value of the effective global setting, the last one wins
+    /// # Arguments
+    /// * `keyword` - The lower case keyword, e.g. `timeout`
+    pub fn get(&self, keyword: &str) -> Option<String> {
+        let (file, line) = *self.find(keyword).last()?;
+        Some(split_keyword(&self.files[file].lines[line]).1.to_string())
+    }
+
+    /// Set a global setting where it takes effect, or add it before the first LABEL of the main file
+    /// # Arguments
+    /// * `keyword` - The lower case keyword, e.g. `timeout`
+    /// * `value` - The new value
+    /// # Returns
+    /// * `usize` - The index of the changed file
+    pub fn set(&mut self, keyword: &str, value: &str) -> usize {
+        match self.find(keyword).last() {
+            Some(&(file, line)) => {
+                self.replace_value(file, line, value);
+                file
+            }
+            None => {
+                let lines = &mut self.files[0].lines;
+                let pos = lines
+                    .iter()
+                    .position(|l| split_keyword(l).0 == "label")
+                    .unwrap_or(lines.len());
+                lines.insert(pos, format!("{} {}", keyword.to_uppercase(), value));
+                0
+            }
+        }
+    }
+
+    /// Make a LABEL the default entry
+    ///
+    /// `DEFAULT` is changed where it takes effect, or added before the first
+    /// LABEL of the main file. A `MENU DEFAULT` mark moves to the label, and
+    /// an `ONTIMEOUT` naming a label is changed too, so the menu and the
+    /// timeout agree with `DEFAULT`.
+    /// # Arguments
+    /// * `label` - The name of the LABEL block
+    /// # Returns
+    /// * `Vec<usize>` - The indices of the changed files
+    pub fn set_default(&mut self, label: &str) -> Vec<usize> {
+        let mut changed = Vec::new();
+        let labels: Vec<String> = self.entries().into_iter().map(|e| e.entry_label).collect();
+
+        changed.push(self.set("default", label));
+
+        if let Some(&(file, line)) = self.find("ontimeout").last() {
+            let (_, value) = split_keyword(&self.files[file].lines[line]);
+            let target = value.split_whitespace().next().unwrap_or_default();
+            if labels.iter().any(|l| l == target) {
+                self.replace_value(file, line, label);
+                changed.push(file);
+            }
+        }
+
+        let marks = self.find("menu default");
+        if !marks.is_empty() {
+            for &(file, line) in marks.iter().rev() {
+                self.files[file].lines.remove(line);
+                changed.push(file);
+            }
+            if let Some((file, line)) = self.walk().into_iter().find(|&(f, l)| {
+                let (keyword, value) = split_keyword(&self.files[f].lines[l]);
+                keyword == "label" && value == label
+            }) {
+                let lines = &mut self.files[file].lines;
+                let indent = match lines.get(line + 1) {
+                    Some(next) if split_keyword(next).0 != "label" => {
+                        next.chars().take_while(|c| c.is_whitespace()).collect()
+                    }
+                    _ => "  ".to_string(),
+                };
+                lines.insert(line + 1, format!("{}MENU DEFAULT", indent));
+                changed.push(file);
+            }
+        }
+        changed.sort();
+        changed.dedup();
+        changed
+    }
+
+    /// Replace the value of a line, keeping its keyword as written
+    fn replace_value(&mut self, file: usize, line: usize, value: &str) {
+        let text = &mut self.files[file].lines[line];
+        let indent = text.len() - text.trim_start().len();
+        let keyword_len = text.split_whitespace().next().unwrap_or("").len();
+        *text = format!("{} {}", &text[..indent + keyword_len], value);
+    }
+
+    /// Find the lines with a keyword, in the order syslinux reads them
+    fn find(&self, keyword: &str) -> Vec<(usize, usize)> {
+        self.walk()
+            .into_iter()
+            .filter(|&(file, line)| split_keyword(&self.files[file].lines[line]).0 == keyword)
+            .collect()
+    }
+
+    /// List the lines of all files in the order syslinux reads them
+    fn walk(&self) -> Vec<(usize, usize)> {
+        let mut ret = Vec::new();
+        if !self.files.is_empty() {
+            self.walk_file(0, 0, &mut ret);
+        }
+        ret
+    }
+
+    fn walk_file(&self, file: usize, depth: usize, ret: &mut Vec<(usize, usize)>) {
+        for (i, line) in self.files[file].lines.iter().enumerate() {
+            ret.push((file, i));
+            if depth < MAX_INCLUDE_DEPTH
+                && let Some(target) = include_target(line)
+                && let Some(included) = self.files.iter().position(|f| f.name == target)
+            {
+                self.walk_file(included, depth + 1, ret);
+            }
+        }
+    }
+}
+
+/// Get the file included by a line
+fn include_target(line: &str) -> Option<&str> {
+    let (keyword, value) = split_keyword(line);
+    match keyword.as_str() {
+        "include" | "menu include" => value.split_whitespace().next(),
+        _ => None,
+    }
+}
+
+/// Split a line into its lower case keyword and its value
+///
+/// `MENU` keywords take their second word along, e.g. `menu label`.
+fn split_keyword(line: &str) -> (String, &str) {
+    let line = line.trim();
+    if line.starts_with('#') {
+        return (String::new(), "");
+    }
+    let (keyword, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let keyword = keyword.to_lowercase();
+    let value = value.trim_start();
+    if keyword == "menu" {
+        let (sub, value) = value.split_once(char::is_whitespace).unwrap_or((value, ""));
+        return (format!("menu {}", sub.to_lowercase()), value.trim());
+    }
+    (keyword, value.trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAIN: &str = "# Generated by u-boot-update
+UI menu.c32
+TIMEOUT 50
+DEFAULT l0
+ONTIMEOUT l0
+
+LABEL l0
+  MENU LABEL ^Debian GNU/Linux, kernel 6.1.0-18-arm64
+  LINUX /boot/vmlinuz-6.1.0-18-arm64
+  INITRD /boot/initrd.img-6.1.0-18-arm64
+  FDTDIR /usr/lib/linux-image-6.1.0-18-arm64/
+  APPEND root=/dev/mmcblk0p2 ro quiet
+
+INCLUDE extra.conf
+";
+
+    const EXTRA: &str = "LABEL rescue
+  MENU LABEL Rescue shell
+  KERNEL /boot/vmlinuz-6.1.0-18-arm64
+  APPEND -
+  MENU HIDE
+";
+
+    fn config() -> ExtlinuxConfig {
+        ExtlinuxConfig {
+            files: vec![
+                ExtlinuxConf::parse("extlinux.conf", MAIN),
+                ExtlinuxConf::parse("extra.conf", EXTRA),
+            ],
+        }
+    }
+
+    #[test]
+    fn parses_labels_across_includes() {
+        let config = config();
+        assert_eq!(config.files[0].includes(), ["extra.conf"]);
+        let entries = config.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            entries[0].title(),
+            "Debian GNU/Linux, kernel 6.1.0-18-arm64"
+        );
+        assert_eq!(
+            entries[0].entry_fdt.as_deref(),
+            Some("/usr/lib/linux-image-6.1.0-18-arm64/")
+        );
+        assert!(entries[0].entry_is_default);
+        assert_eq!(entries[1].id(), "rescue");
+        assert_eq!(entries[1].entry_file, "extra.conf");
+        assert_eq!(entries[1].entry_append, None);
+        assert!(entries[1].entry_hidden);
+        assert!(!entries[1].entry_is_default);
+    }
+
+    #[test]
+    fn reads_the_last_global_setting() {
+        let mut config = config();
+        config.files[1].lines.insert(0, "timeout 10".to_string());
+        assert_eq!(config.get("timeout").as_deref(), Some("10"));
+        assert_eq!(config.get("ui").as_deref(), Some("menu.c32"));
+        assert_eq!(config.get("prompt"), None);
+    }
+
+    #[test]
+    fn adds_global_settings_before_the_first_label() {
+        let mut config = config();
+        assert_eq!(config.set("timeout", "30"), 0);
+        assert!(config.files[0].to_content().contains("\nTIMEOUT 30\n"));
+
+        // A new setting goes before the first LABEL
+        assert_eq!(config.set("prompt", "1"), 0);
+        let content = config.files[0].to_content();
+        assert!(content.contains("ONTIMEOUT l0\n\nPROMPT 1\nLABEL l0\n"));
+        assert!(content.starts_with("# Generated by u-boot-update\n"));
+    }
+
+    #[test]
+    fn moves_every_default_mark() {
+        let mut config = config();
+        config.files[0]
+            .lines
+            .insert(7, "  MENU DEFAULT".to_string());
+        assert!(config.entries()[0].entry_is_default);
+
+        assert_eq!(config.set_default("rescue"), [0, 1]);
+        let main = config.files[0].to_content();
+        assert!(main.contains("\nDEFAULT rescue\nONTIMEOUT rescue\n"));
+        assert!(!main.contains("MENU DEFAULT"));
+        assert!(
+            config.files[1]
+                .to_content()
+                .starts_with("LABEL rescue\n  MENU DEFAULT\n  MENU LABEL Rescue shell\n")
+        );
+        let entries = config.entries();
+        assert!(!entries[0].entry_is_default);
+        assert!(entries[1].entry_is_default);
+    }
+
+    #[test]
+    fn stops_at_recursive_includes() {
+        let config = ExtlinuxConfig {
+            files: vec![ExtlinuxConf::parse(
+                "extlinux.conf",
+                "LABEL a\nINCLUDE extlinux.conf\n",
+            )],
+        };
+        assert_eq!(config.entries().len(), MAX_INCLUDE_DEPTH + 1);
+    }
+}
@@ -1,7 +1,8 @@
 use std::fs::File;
 use std::fs::OpenOptions;
-use std::io::Result;
-use std::path::PathBuf;
+use std::fs::TryLockError;
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 
 pub const GRUB_CFG_PATH: &str = "grub/grub.cfg";
 pub const GRUB_ENV_PATH: &str = "grub/grubenv";
@@ -10,6 +11,263 @@ pub fn open_file_ro(path: PathBuf) -> Result<File> {
     OpenOptions::new().read(true).open(path)
 }
 
-pub fn open_file_wo(path: PathBuf) -> Result<File> {
-    OpenOptions::new().write(true).open(path)
+pub fn open_file_rw(path: PathBuf) -> Result<File> {
+    OpenOptions::new().read(true).write(true).open(path)
+}
+
+/// Convert a loader path to the backslash separated form the firmware and BCD expect
+pub fn normalize_loader_path(loader: &str) -> String {
+    let path = loader.replace('/', "\\");
+    if path.starts_with('\\') {
+        path
+    } else {
+        format!("\\{}", path)
+    }
+}
+
+/// Take an exclusive lock on a file, waiting for another holder to release it
+/// # Arguments
+/// * `file` - The open file to lock, the lock is released when it is closed
+/// * `path` - The path of the file, shown while waiting
+pub fn lock_exclusive(file: &File, path: &Path) -> Result<()> {
+    match file.try_lock() {
+        Ok(()) => Ok(()),
+        Err(TryLockError::WouldBlock) => {
+            eprintln!("Waiting for another process to unlock {}", path.display());
+            file.lock()
+        }
+        Err(TryLockError::Error(e)) => Err(Error::new(
+            e.kind(),
+            format!("Failed to lock {}: {}", path.display(), e),
+        )),
+    }
+}
+
+/// Read a file from its start
+pub fn read_from_start(mut file: &File) -> Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// The operations `write_verified` needs from a file besides reading and writing
+pub trait SyncFile: Read + Write + Seek {
+    /// Get the size of the file in bytes
+    fn size(&self) -> Result<u64>;
+
+    /// Cut or extend the file to a size in bytes
+    fn set_len(&self, size: u64) -> Result<()>;
+
+    /// Flush the data and metadata of the file to disk
+    fn sync_all(&self) -> Result<()>;
+}
+
+impl SyncFile for &File {
+    fn size(&self) -> Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn set_len(&self, size: u64) -> Result<()> {
+        File::set_len(self, size)
+    }
+
+    fn sync_all(&self) -> Result<()> {
+        File::sync_all(self)
+    }
+}
+
+/// Overwrite the start of a file in one write, flush it to disk and read it back
+///
+/// The file is only cut to the length of the content after writing it, so a
+/// fixed size block like grubenv keeps its place on disk.
+/// # Arguments
+/// * `file` - The file, open for reading and writing
+/// * `path` - The path of the file, used in errors
+/// * `content` - The content to write
+pub fn write_verified(mut file: impl SyncFile, path: &Path, content: &[u8]) -> Result<()> {
+    file.seek(SeekFrom::Start(0))?;
+    let written = file.write(content)?;
+    if written != content.len() {
+        return Err(Error::new(
+            ErrorKind::WriteZero,
+            format!(
+                "{}: short write, {} of {} bytes written",
+                path.display(),
+                written,
+                content.len()
+            ),
+        ));
+    }
+    if file.size()? > content.len() as u64 {
+        file.set_len(content.len() as u64)?;
+    }
+    file.sync_all()
+        .map_err(|e| Error::new(e.kind(), format!("{}: fsync failed: {}", path.display(), e)))?;
+
+    file.seek(SeekFrom::Start(0))?;
+    let mut back = vec![0u8; content.len()];
+    file.read_exact(&mut back).map_err(|e| {
+        Error::new(
+            e.kind(),
+            format!(
+                "{}: reading back the written data failed: {}",
+                path.display(),
+                e
+            ),
+        )
+    })?;
+    if let Some(offset) = back.iter().zip(content).position(|(a, b)| a != b) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "{}: verification failed, byte {} reads back as {:#04x} instead of {:#04x}",
+                path.display(),
+                offset,
+                back[offset],
+                content[offset]
+            ),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::io::Cursor;
+
+    /// A file in memory whose writes stop early or whose reads flip a byte
+    #[derive(Default)]
+    struct FaultyFile {
+        data: Cursor<Vec<u8>>,
+        write_limit: Option<usize>,
+        flipped: Option<usize>,
+        synced: Cell<bool>,
+    }
+
+    impl Read for FaultyFile {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let start = self.data.position() as usize;
+            let len = self.data.read(buf)?;
+            if let Some(offset) = self.flipped
+                && (start..start + len).contains(&offset)
+            {
+                buf[offset - start] ^= 0xff;
+            }
+            Ok(len)
+        }
+    }
+
+    impl Write for FaultyFile {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            let len = self.write_limit.unwrap_or(buf.len()).min(buf.len());
+            self.data.write(&buf[..len])
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for FaultyFile {
+        fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+            self.data.seek(pos)
+        }
+    }
+
+    impl SyncFile for &mut FaultyFile {
+        fn size(&self) -> Result<u64> {
+            Ok(self.data.get_ref().len() as u64)
+        }
+
+        fn set_len(&self, _size: u64) -> Result<()> {
+            Err(Error::other("not expected"))
+        }
+
+        fn sync_all(&self) -> Result<()> {
+            self.synced.set(true);
+            Ok(())
+        }
+    }
+
+    /// Make an empty file in the temporary directory
+    fn temp_file(name: &str) -> (PathBuf, File) {
+        let path =
+            std::env::temp_dir().join(format!("bootmgr-test-{}-{}", std::process::id(), name));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        (path, file)
+    }
+
+    #[test]
+    fn cuts_the_file_to_shorter_content() {
+        let (path, file) = temp_file("write-verified");
+        write_verified(&file, &path, b"saved_entry=gnulinux-simple\n####\n").unwrap();
+        write_verified(&file, &path, b"saved_entry=0\n").unwrap();
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(data, b"saved_entry=0\n");
+    }
+
+    #[test]
+    fn reports_short_writes() {
+        let mut file = FaultyFile {
+            write_limit: Some(4),
+            ..Default::default()
+        };
+        let err = write_verified(&mut file, Path::new("grubenv"), b"next_entry=1\n").unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::WriteZero);
+        assert_eq!(
+            err.to_string(),
+            "grubenv: short write, 4 of 13 bytes written"
+        );
+        assert!(!file.synced.get());
+    }
+
+    #[test]
+    fn reports_the_first_byte_read_back_wrong() {
+        let mut file = FaultyFile {
+            flipped: Some(11),
+            ..Default::default()
+        };
+        let err = write_verified(&mut file, Path::new("grubenv"), b"next_entry=1\n").unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(
+            err.to_string(),
+            "grubenv: verification failed, byte 11 reads back as 0xce instead of 0x31"
+        );
+        assert!(file.synced.get());
+    }
+
+    #[test]
+    fn waits_for_the_lock_holder() {
+        let (path, holder) = temp_file("lock");
+        lock_exclusive(&holder, &path).unwrap();
+        let waiter = OpenOptions::new().read(true).open(&path).unwrap();
+        assert!(matches!(waiter.try_lock(), Err(TryLockError::WouldBlock)));
+
+        let locked = std::thread::spawn(move || {
+            lock_exclusive(&waiter, Path::new("grubenv")).map(|_| waiter)
+        });
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert!(!locked.is_finished());
+        drop(holder);
+        let waiter = locked.join().unwrap().unwrap();
+
+        // The waiter holds the lock now
+        let other = File::open(&path).unwrap();
+        assert!(matches!(other.try_lock(), Err(TryLockError::WouldBlock)));
+        drop(waiter);
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -0,0 +1,271 @@
+use crate::bootloader::grub::GrubEntry;
+
+/// Split `--option=value` and `--option value` forms of a menuentry option
+fn option_value<'a>(
+    arg: &'a str,
+    name: &str,
+    rest: &mut impl Iterator<Item = &'a String>,
+) -> Option<String> {
+    match arg.strip_prefix(name) {
+        Some("") => rest.next().cloned(),
+        Some(value) => value.strip_prefix('=').map(str::to_string),
+        None => None,
+    }
+}
+
+/// Build a GRUB entry from the words of a `menuentry` line
+/// # Arguments
+/// * `args` - The words of the line, starting with `menuentry`
+/// * `parents` - The ids and titles of the submenus holding the entry, outermost first
+/// # Returns
+/// * `GrubEntry` - The entry, without the commands of its body
+pub(crate) fn parse_menuentry(args: &[String], parents: &[(String, String)]) -> GrubEntry {
+    let entry_name = args.get(1).cloned().unwrap_or_default();
+    let mut entry = GrubEntry {
+        entry_id: entry_name.clone(),
+        entry_name,
+        entry_parents: parents.to_vec(),
+        ..GrubEntry::default()
+    };
+
+    let mut rest = args.iter().skip(2);
+    while let Some(arg) = rest.next() {
+        let arg = arg.as_str();
+        if arg == "$menuentry_id_option" || arg == "${menuentry_id_option}" {
+            entry.entry_id = rest.next().cloned().unwrap_or_default();
+        } else if arg == "--unrestricted" {
+            entry.entry_unrestricted = true;
+        } else if let Some(id) = option_value(arg, "--id", &mut rest) {
+            entry.entry_id = id;
+        } else if let Some(class) = option_value(arg, "--class", &mut rest) {
+            entry.entry_classes.push(class);
+        } else if let Some(users) = option_value(arg, "--users", &mut rest) {
+            entry.entry_users = Some(users);
+        } else if let Some(hotkey) = option_value(arg, "--hotkey", &mut rest) {
+            entry.entry_hotkey = Some(hotkey);
+        }
+    }
+    entry
+}
+
+/// Record the boot details of a command found in the body of a menuentry
+/// # Arguments
+/// * `entry` - The entry whose body contains the command
+/// * `args` - The words of the command
+pub(crate) fn apply_entry_command(entry: &mut GrubEntry, args: &[String]) {
+    let Some((command, rest)) = args.split_first() else {
+        return;
+    };
+    match command.as_str() {
+        "linux" | "linuxefi" | "linux16" => {
+            entry.entry_kernel = rest.first().cloned();
+            entry.entry_cmdline = rest.get(1..).map(|a| a.join(" "));
+        }
+        "initrd" | "initrdefi" | "initrd16" => {
+            entry.entry_initrd = rest.to_vec();
+        }
+        "chainloader" => {
+            entry.entry_chainloader = rest.iter().find(|a| !a.starts_with("--")).cloned();
+        }
+        "set" => {
+            if let Some(device) = rest.iter().find_map(|a| a.strip_prefix("root=")) {
+                entry.entry_root_device = Some(device.to_string());
+                entry.entry_root_uuid = None;
+            }
+        }
+        "search" | "search.fs_uuid" => {
+            let by_uuid =
+                command == "search.fs_uuid" || rest.iter().any(|a| a == "--fs-uuid" || a == "-u");
+            let mut sets_root = true;
+            let mut uuid = None;
+            for arg in rest {
+                match arg.as_str() {
+                    a if a.starts_with("--set=") => sets_root = a == "--set=root",
+                    a if a.starts_with('-') => {}
+                    a => uuid = Some(a.to_string()),
+                }
+            }
+            if by_uuid && sets_root {
+                entry.entry_root_uuid = uuid;
+                entry.entry_root_device = None;
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Split the GRUB device from a path, e.g. `(hd0,gpt2)/vmlinuz`
+///
+/// `($root)/vmlinuz` is left as `()/vmlinuz` by scripts that never set
+/// `root`, GRUB then reads the file from its root device like `/vmlinuz`.
+/// # Arguments
+/// * `path` - A path as written in grub.cfg
+/// # Returns
+/// * `(Option<&str>, &str)` - The device without parentheses, None for the root device, and the path on it
+pub(crate) fn split_device(path: &str) -> (Option<&str>, &str) {
+    if let Some(rest) = path.strip_prefix('(')
+        && let Some((device, path)) = rest.split_once(')')
+    {
+        return (Some(device).filter(|d| !d.is_empty()), path);
+    }
+    (None, path)
+}
+
+/// Get the files an entry needs to boot
+/// # Arguments
+/// * `entry` - The GRUB entry
+/// # Returns
+/// * `Vec<String>` - The kernel, initrd images and chainloaded file
+pub(crate) fn entry_files(entry: &GrubEntry) -> Vec<String> {
+    let mut files: Vec<String> = entry.entry_kernel.iter().cloned().collect();
+    files.extend(entry.entry_initrd.iter().cloned());
+    // Block lists such as `+1` chainload sectors, not files
+    files.extend(
+        entry
+            .entry_chainloader
+            .iter()
+            .filter(|c| !split_device(c).1.starts_with('+'))
+            .cloned(),
+    );
+    files
+}
+
+/// Get the files a GRUB script reads with `source` or `configfile`
+///
+/// `$prefix` and `$config_directory` are replaced by the given prefix. Paths
+/// using other variables or a GRUB device cannot be resolved and are skipped.
+/// # Arguments
+/// * `script` - The GRUB script, e.g. grub.cfg
+/// * `prefix` - The GRUB directory as seen by GRUB, e.g. `/boot/grub`
+/// # Returns
+/// * `Vec<String>` - The paths of the files, in the order they appear
+pub(crate) fn sourced_files(script: &str, prefix: &str) -> Vec<String> {
+    let mut files = Vec::new();
+    for line in script.lines() {
+        let mut words = line.split_whitespace();
+        if !matches!(words.next(), Some("source" | "configfile")) {
+            continue;
+        }
+        let Some(path) = words.next() else {
+            continue;
+        };
+        let mut path = path.trim_matches(|c| c == '"' || c == '\'').to_string();
+        for var in [
+            "${prefix}",
+            "$prefix",
+            "${config_directory}",
+            "$config_directory",
+        ] {
+            path = path.replace(var, prefix);
+        }
+        if path.contains('$') || split_device(&path).0.is_some() || files.contains(&path) {
+            continue;
+        }
+        files.push(path);
+    }
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn reads_menuentry_options_in_both_forms() {
+        let parents = [("advanced".to_string(), "Advanced options".to_string())];
+        let entry = parse_menuentry(
+            &words(
+                "menuentry Arch --class arch --class=os --id=arch-lts --users alice --hotkey=a --unrestricted",
+            ),
+            &parents,
+        );
+        assert_eq!(entry.entry_name, "Arch");
+        assert_eq!(entry.entry_id, "arch-lts");
+        assert_eq!(entry.entry_classes, ["arch", "os"]);
+        assert_eq!(entry.entry_users.as_deref(), Some("alice"));
+        assert_eq!(entry.entry_hotkey.as_deref(), Some("a"));
+        assert!(entry.entry_unrestricted);
+        assert_eq!(entry.entry_parents, parents);
+
+        // Without an id the title names the entry
+        let entry = parse_menuentry(&words("menuentry Windows --class windows"), &[]);
+        assert_eq!(entry.entry_id, "Windows");
+    }
+
+    #[test]
+    fn keeps_the_last_root_of_an_entry() {
+        let mut entry = GrubEntry::default();
+        apply_entry_command(
+            &mut entry,
+            &words("search --no-floppy --fs-uuid --set=root 1234"),
+        );
+        assert_eq!(entry.entry_root_uuid.as_deref(), Some("1234"));
+
+        // A search setting another variable does not move the root
+        apply_entry_command(&mut entry, &words("search --fs-uuid --set=esp 5678"));
+        assert_eq!(entry.entry_root_uuid.as_deref(), Some("1234"));
+
+        apply_entry_command(&mut entry, &words("set root=hd0,gpt2"));
+        assert_eq!(entry.entry_root_device.as_deref(), Some("hd0,gpt2"));
+        assert_eq!(entry.entry_root_uuid, None);
+
+        apply_entry_command(&mut entry, &words("search.fs_uuid 9abc"));
+        assert_eq!(entry.entry_root_uuid.as_deref(), Some("9abc"));
+        assert_eq!(entry.entry_root_device, None);
+    }
+
+    #[test]
+    fn lists_kernel_initrds_and_chainloaded_files() {
+        let mut entry = GrubEntry::default();
+        for line in [
+            "linuxefi (hd0,gpt2)/vmlinuz root=/dev/sda2 ro",
+            "initrdefi /intel-ucode.img /initramfs.img",
+        ] {
+            apply_entry_command(&mut entry, &words(line));
+        }
+        assert_eq!(entry.entry_cmdline.as_deref(), Some("root=/dev/sda2 ro"));
+        assert_eq!(
+            entry_files(&entry),
+            ["(hd0,gpt2)/vmlinuz", "/intel-ucode.img", "/initramfs.img"]
+        );
+
+        let mut entry = GrubEntry::default();
+        apply_entry_command(
+            &mut entry,
+            &words("chainloader --force /EFI/Microsoft/Boot/bootmgfw.efi"),
+        );
+        assert_eq!(entry_files(&entry), ["/EFI/Microsoft/Boot/bootmgfw.efi"]);
+        // Sectors of a block list are no file
+        apply_entry_command(&mut entry, &words("chainloader (hd1,msdos1)+1"));
+        assert!(entry_files(&entry).is_empty());
+    }
+
+    #[test]
+    fn splits_grub_devices_from_paths() {
+        assert_eq!(
+            split_device("(hd0,gpt2)/vmlinuz"),
+            (Some("hd0,gpt2"), "/vmlinuz")
+        );
+        assert_eq!(split_device("()/vmlinuz"), (None, "/vmlinuz"));
+        assert_eq!(split_device("/boot/vmlinuz"), (None, "/boot/vmlinuz"));
+        assert_eq!(split_device("(hd0"), (None, "(hd0"));
+    }
+
+    #[test]
+    fn finds_files_sourced_from_the_grub_directory() {
+        let script = "source ${prefix}/custom.cfg\n\
+                      configfile \"$config_directory/menu.cfg\"\n\
+                      source $prefix/custom.cfg\n\
+                      source (hd0,gpt1)/grub.cfg\n\
+                      source $theme_dir/theme.cfg\n\
+                      echo source /nothing.cfg\n";
+        assert_eq!(
+            sourced_files(script, "/boot/grub"),
+            ["/boot/grub/custom.cfg", "/boot/grub/menu.cfg"]
+        );
+    }
+}
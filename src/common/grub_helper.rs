@@ -0,0 +1,165 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+/// The path of the GRUB settings below the root directory
+pub(crate) const DEFAULT_GRUB_PATH: &str = "etc/default/grub";
+const OS_RELEASE_PATH: &str = "etc/os-release";
+const SBIN_DIRS: [&str; 4] = ["usr/sbin", "usr/bin", "sbin", "bin"];
+
+/// Get the command regenerating grub.cfg from /etc/default/grub and /etc/grub.d
+///
+/// `update-grub` is used where the distribution ships it, `grub2-mkconfig` or
+/// `grub-mkconfig` otherwise.
+/// # Arguments
+/// * `root` - The root directory of the installation
+/// # Returns
+/// * `Result<Vec<String>>` - The command as seen inside the installation, followed by its arguments
+pub(crate) fn regenerate_command(root: &Path) -> Result<Vec<String>> {
+    let output = grub_cfg_output(root);
+    Ok(match find_command(root, "update-grub") {
+        Some(update) if output == "/boot/grub/grub.cfg" => vec![update],
+        _ => {
+            let mkconfig = find_command(root, "grub2-mkconfig")
+                .or_else(|| find_command(root, "grub-mkconfig"))
+                .ok_or(Error::new(
+                    ErrorKind::NotFound,
+                    "Neither update-grub nor grub-mkconfig is installed",
+                ))?;
+            vec![mkconfig, "-o".to_string(), output]
+        }
+    })
+}
+
+/// Get the path grub.cfg is generated to, as seen inside the root directory
+///
+/// Fedora, RHEL and openSUSE install GRUB into `/boot/grub2`, older Fedora and
+/// RHEL releases booting through UEFI read the full grub.cfg from the ESP.
+fn grub_cfg_output(root: &Path) -> String {
+    let os_release = fs::read_to_string(root.join(OS_RELEASE_PATH)).unwrap_or_default();
+    let field = |key: &str| {
+        os_release
+            .lines()
+            .find_map(|l| l.strip_prefix(key)?.strip_prefix('='))
+            .map(|v| v.trim_matches('"').to_string())
+            .unwrap_or_default()
+    };
+    let id = field("ID");
+    let like = format!("{} {}", id, field("ID_LIKE"));
+
+    if ["fedora", "rhel", "centos", "suse"]
+        .iter()
+        .any(|d| like.contains(d))
+    {
+        let efi_cfg = format!("/boot/efi/EFI/{}/grub.cfg", id);
+        let full_cfg_on_esp =
+            fs::read_to_string(root.join(&efi_cfg[1..])).is_ok_and(|c| !c.contains("configfile"));
+        return match full_cfg_on_esp {
+            true => efi_cfg,
+            false => "/boot/grub2/grub.cfg".to_string(),
+        };
+    }
+    match root.join("boot/grub2/grub.cfg").exists() && !root.join("boot/grub/grub.cfg").exists() {
+        true => "/boot/grub2/grub.cfg".to_string(),
+        false => "/boot/grub/grub.cfg".to_string(),
+    }
+}
+
+/// Find a command in the system directories below the root directory
+fn find_command(root: &Path, name: &str) -> Option<String> {
+    SBIN_DIRS
+        .iter()
+        .map(|dir| Path::new("/").join(dir).join(name))
+        .find(|path| root.join(path.strip_prefix("/").unwrap()).exists())
+        .map(|path| path.to_string_lossy().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Make an installation root holding the given files
+    fn root(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("bootmgr-test-{}-{}", std::process::id(), name));
+        for (path, content) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    fn command(name: &str, files: &[(&str, &str)]) -> Result<Vec<String>> {
+        let dir = root(name, files);
+        let ret = regenerate_command(&dir);
+        let _ = fs::remove_dir_all(&dir);
+        ret
+    }
+
+    #[test]
+    fn uses_update_grub_where_debian_ships_it() {
+        assert_eq!(
+            command(
+                "debian",
+                &[
+                    ("etc/os-release", "ID=debian\n"),
+                    ("usr/sbin/update-grub", ""),
+                    ("usr/sbin/grub-mkconfig", ""),
+                    ("boot/grub/grub.cfg", ""),
+                ]
+            )
+            .unwrap(),
+            ["/usr/sbin/update-grub"]
+        );
+        assert_eq!(
+            command(
+                "arch",
+                &[
+                    ("etc/os-release", "ID=arch\n"),
+                    ("usr/bin/grub-mkconfig", "")
+                ]
+            )
+            .unwrap(),
+            ["/usr/bin/grub-mkconfig", "-o", "/boot/grub/grub.cfg"]
+        );
+    }
+
+    #[test]
+    fn writes_grub2_cfg_on_fedora_family() {
+        let fedora = [
+            ("etc/os-release", "NAME=\"Fedora Linux\"\nID=fedora\n"),
+            ("usr/sbin/grub2-mkconfig", ""),
+            (
+                "boot/efi/EFI/fedora/grub.cfg",
+                "search --fs-uuid --set=dev 1234\nconfigfile ($dev)/grub2/grub.cfg\n",
+            ),
+        ];
+        assert_eq!(
+            command("fedora", &fedora).unwrap(),
+            ["/usr/sbin/grub2-mkconfig", "-o", "/boot/grub2/grub.cfg"]
+        );
+
+        // Older releases booting through UEFI read the full menu from the ESP
+        let rhel = [
+            ("etc/os-release", "ID=\"centos\"\nID_LIKE=\"rhel fedora\"\n"),
+            ("usr/sbin/grub2-mkconfig", ""),
+            ("boot/efi/EFI/centos/grub.cfg", "menuentry 'CentOS' {\n}\n"),
+        ];
+        assert_eq!(
+            command("centos", &rhel).unwrap(),
+            [
+                "/usr/sbin/grub2-mkconfig",
+                "-o",
+                "/boot/efi/EFI/centos/grub.cfg"
+            ]
+        );
+    }
+
+    #[test]
+    fn needs_a_grub_config_generator() {
+        let err = command("none", &[("etc/os-release", "ID=debian\n")]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+    }
+}
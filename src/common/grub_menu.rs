@@ -0,0 +1,145 @@
+use crate::common::grubenv::GrubEnv;
+use regex::Regex;
+use std::io::{Error, ErrorKind, Result};
+
+/// A change to the grubenv variables deciding whether the GRUB menu is shown
+///
+/// Ubuntu keeps `recordfail` set after an unclean shutdown, which makes GRUB
+/// wait at the menu for `GRUB_RECORDFAIL_TIMEOUT` seconds, forever when it is
+/// -1, until a boot reaches the system and clears it. Fedora hides the menu
+/// when `menu_auto_hide` is set and the last boot succeeded according to
+/// `boot_success`/`boot_indeterminate`, and shows it once when
+/// `menu_show_once` is set.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum GrubMenuAction {
+    ClearFail,
+    ShowOnce,
+    Hide,
+}
+
+impl GrubMenuAction {
+    /// Apply the change to the grubenv variables
+    /// # Arguments
+    /// * `env` - The variables of the grubenv file
+    /// * `cfg` - The content of grub.cfg, telling which variables it reads
+    /// # Returns
+    /// * `Result<()>` - Err if grub.cfg reads none of the variables
+    pub fn apply(self, env: &mut GrubEnv, cfg: &str) -> Result<()> {
+        let ubuntu = cfg.contains("recordfail");
+        let fedora = cfg.contains("menu_auto_hide") || cfg.contains("menu_show_once");
+        match self {
+            GrubMenuAction::ClearFail => {
+                env.remove("recordfail");
+                if env.get("boot_success").is_some() {
+                    env.set("boot_success", "1");
+                }
+                if env.get("boot_indeterminate").is_some() {
+                    env.set("boot_indeterminate", "0");
+                }
+            }
+            GrubMenuAction::ShowOnce if fedora => env.set("menu_show_once", "1"),
+            GrubMenuAction::ShowOnce if ubuntu => env.set("recordfail", "1"),
+            GrubMenuAction::Hide if fedora => {
+                env.remove("menu_show_once");
+                env.set("menu_auto_hide", "1");
+                env.set("boot_success", "1");
+            }
+            GrubMenuAction::Hide if ubuntu => env.remove("recordfail"),
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    "grub.cfg reads neither recordfail nor menu_auto_hide",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Find the timeout Ubuntu's grub.cfg sets while `recordfail` is 1
+/// # Arguments
+/// * `cfg` - The content of grub.cfg
+/// # Returns
+/// * `Option<i64>` - The timeout in seconds, -1 waits for a key, None if grub.cfg has none
+pub(crate) fn recordfail_timeout(cfg: &str) -> Option<i64> {
+    let regex =
+        Regex::new(r#"if \[ "\$\{?recordfail\}?" = 1 \] ?; ?then\s+set timeout=(-?\d+)"#).unwrap();
+    regex.captures(cfg).and_then(|c| c[1].parse().ok())
+}
+
+/// Describe the grubenv variables deciding whether the GRUB menu is shown
+/// # Arguments
+/// * `env` - The variables of the grubenv file
+/// # Returns
+/// * `Vec<String>` - One line per mechanism in use, empty if none is
+pub(crate) fn describe_menu_state(env: &GrubEnv) -> Vec<String> {
+    let mut ret = Vec::new();
+    if env.get("recordfail") == Some("1") {
+        ret.push("recordfail=1, the last boot failed and GRUB waits at the menu".to_string());
+    }
+    if env.get("menu_show_once").is_some_and(|v| !v.is_empty()) {
+        ret.push("menu_show_once=1, the menu is shown on the next boot".to_string());
+    }
+    if let Some(auto_hide) = env.get("menu_auto_hide").filter(|v| !v.is_empty()) {
+        let success = env.get("boot_success").unwrap_or("0");
+        let indeterminate = env.get("boot_indeterminate").unwrap_or("0");
+        let hidden = (success == "1" || indeterminate == "1")
+            && env.get("menu_show_once").is_none_or(|v| v.is_empty());
+        ret.push(format!(
+            "menu_auto_hide={}, boot_success={}, boot_indeterminate={}, the menu is {} on the next boot",
+            auto_hide,
+            success,
+            indeterminate,
+            if hidden { "hidden" } else { "shown" }
+        ));
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UBUNTU_HEADER: &str = r#"
+if [ "${recordfail}" = 1 ] ; then
+  set timeout=-1
+else
+  if [ x$feature_timeout_style = xy ] ; then
+    set timeout_style=hidden
+    set timeout=0
+  fi
+fi
+"#;
+
+    #[test]
+    fn finds_recordfail_timeout() {
+        assert_eq!(recordfail_timeout(UBUNTU_HEADER), Some(-1));
+        assert_eq!(
+            recordfail_timeout(&UBUNTU_HEADER.replace("timeout=-1", "timeout=30")),
+            Some(30)
+        );
+        assert_eq!(recordfail_timeout("set timeout=5\n"), None);
+    }
+
+    #[test]
+    fn shows_menu_once_by_scheme() {
+        let mut env = GrubEnv::parse("");
+        GrubMenuAction::ShowOnce
+            .apply(&mut env, UBUNTU_HEADER)
+            .unwrap();
+        assert_eq!(env.get("recordfail"), Some("1"));
+
+        let mut env = GrubEnv::parse("menu_auto_hide=1\nboot_success=1\n");
+        GrubMenuAction::ShowOnce
+            .apply(&mut env, "if [ \"${menu_show_once}\" ]; then\n")
+            .unwrap();
+        assert_eq!(env.get("menu_show_once"), Some("1"));
+        assert_eq!(env.get("recordfail"), None);
+
+        let mut env = GrubEnv::parse("");
+        let err = GrubMenuAction::ShowOnce
+            .apply(&mut env, "set timeout=5\n")
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Unsupported);
+    }
+}
@@ -0,0 +1,1022 @@
+use crate::bootloader::grub::GrubEntry;
+use crate::common::grub_cfg;
+use crate::common::grubenv::GrubEnv;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+
+/// Upper bound of evaluated commands, guards against loops that never end
+const MAX_STEPS: usize = 100_000;
+/// Upper bound of nested function calls
+const MAX_DEPTH: usize = 64;
+
+/// Feature flags defined by GRUB 2.06 and later
+const GRUB_FEATURES: [&str; 10] = [
+    "feature_200_final",
+    "feature_all_video_module",
+    "feature_chainloader_bpb",
+    "feature_default_font_path",
+    "feature_menuentry_id",
+    "feature_menuentry_options",
+    "feature_nativedisk_cmd",
+    "feature_ntldr",
+    "feature_platform_search_hint",
+    "feature_timeout_style",
+];
+
+/// A struct describing the machine a grub.cfg is evaluated for
+/// Fields:
+/// * `platform` - The value of `grub_platform`, e.g. `efi` or `pc`
+/// * `cpu` - The value of `grub_cpu`, e.g. `x86_64` or `i386`
+/// * `features` - The feature flags and their values
+/// * `env` - Overrides of the environment block loaded by `load_env`
+#[derive(Clone, Debug)]
+pub struct GrubContext {
+    pub platform: String,
+    pub cpu: String,
+    pub features: Vec<(String, String)>,
+    pub env: Vec<(String, String)>,
+}
+
+impl Default for GrubContext {
+    fn default() -> Self {
+        let efi = cfg!(windows) || std::path::Path::new("/sys/firmware/efi").exists();
+        let cpu = match std::env::consts::ARCH {
+            "x86" => "i386",
+            "aarch64" => "arm64",
+            "loongarch64" => "loongarch64",
+            "riscv64" => "riscv64",
+            _ => "x86_64",
+        };
+        Self {
+            platform: if efi { "efi" } else { "pc" }.to_string(),
+            cpu: cpu.to_string(),
+            features: GRUB_FEATURES
+                .iter()
+                .map(|f| (f.to_string(), "y".to_string()))
+                .collect(),
+            env: Vec::new(),
+        }
+    }
+}
+
+impl GrubContext {
+    /// Set a feature flag, the `feature_` prefix is optional
+    pub fn set_feature(&mut self, name: &str, value: &str) {
+        let name = match name.starts_with("feature_") {
+            true => name.to_string(),
+            false => format!("feature_{}", name),
+        };
+        self.features.retain(|(k, _)| *k != name);
+        self.features.push((name, value.to_string()));
+    }
+
+    /// Override a variable of the environment block
+    pub fn set_env(&mut self, name: &str, value: &str) {
+        self.env.retain(|(k, _)| k != name);
+        self.env.push((name.to_string(), value.to_string()));
+    }
+}
+
+/// Evaluate a grub.cfg and collect the menu entries GRUB would show
+/// # Arguments
+/// * `script` - The content of grub.cfg
+/// * `env` - The environment block read by `load_env`
+/// * `ctx` - The machine the script is evaluated for
+/// # Returns
+/// * `Result<Vec<GrubEntry>>` - The entries in menu order
+pub(crate) fn evaluate(script: &str, env: &GrubEnv, ctx: &GrubContext) -> Result<Vec<GrubEntry>> {
+    let tokens = lex(script)?;
+    let nodes = Parser { tokens, pos: 0 }.parse_list(&[])?;
+
+    let mut env_block: Vec<(String, String)> = env.vars().to_vec();
+    for (key, value) in &ctx.env {
+        env_block.retain(|(k, _)| k != key);
+        env_block.push((key.clone(), value.clone()));
+    }
+
+    let mut vars = HashMap::new();
+    vars.insert("grub_platform".to_string(), ctx.platform.clone());
+    vars.insert("grub_cpu".to_string(), ctx.cpu.clone());
+    vars.insert("prefix".to_string(), "/boot/grub".to_string());
+    vars.insert("config_directory".to_string(), "/boot/grub".to_string());
+    vars.insert("root".to_string(), String::new());
+    for (key, value) in &ctx.features {
+        vars.insert(key.clone(), value.clone());
+    }
+
+    let mut interp = Interpreter {
+        vars,
+        env_block,
+        functions: HashMap::new(),
+        entries: Vec::new(),
+        submenus: Vec::new(),
+        status: 0,
+        steps: 0,
+        depth: 0,
+        recording: None,
+    };
+    interp.run_list(&nodes)?;
+    Ok(interp.entries)
+}
+
+#[derive(Clone, Debug)]
+enum Part {
+    Lit(String, bool),
+    Var(String, bool),
+}
+
+#[derive(Clone, Debug)]
+struct Word {
+    parts: Vec<Part>,
+    line: usize,
+}
+
+impl Word {
+    /// The word as a bare keyword, if it has no quotes or variables
+    fn keyword(&self) -> Option<&str> {
+        match self.parts.as_slice() {
+            [Part::Lit(s, false)] => Some(s.as_str()),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Token {
+    Word(Word),
+    Separator,
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Command(Vec<Word>),
+    If {
+        branches: Vec<(Vec<Node>, Vec<Node>)>,
+        otherwise: Vec<Node>,
+    },
+    For {
+        var: String,
+        items: Vec<Word>,
+        body: Vec<Node>,
+    },
+    While {
+        until: bool,
+        cond: Vec<Node>,
+        body: Vec<Node>,
+    },
+    Function {
+        name: String,
+        body: Vec<Node>,
+    },
+    Menu {
+        submenu: bool,
+        args: Vec<Word>,
+        body: Vec<Node>,
+    },
+}
+
+enum Flow {
+    Normal,
+    Break,
+    Continue,
+    Return,
+}
+
+fn parse_error(line: usize, msg: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("grub.cfg:{}: {}", line, msg),
+    )
+}
+
+fn lex(script: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = script.chars().peekable();
+    let mut line = 1;
+    let mut parts: Vec<Part> = Vec::new();
+    let mut lit = String::new();
+    let mut in_word = false;
+
+    fn flush_lit(parts: &mut Vec<Part>, lit: &mut String, quoted: bool) {
+        if !lit.is_empty() || quoted {
+            parts.push(Part::Lit(std::mem::take(lit), quoted));
+        }
+    }
+
+    fn read_var(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<String> {
+        match chars.peek().copied() {
+            Some('{') => {
+                chars.next();
+                let mut name = String::new();
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        break;
+                    }
+                    name.push(c);
+                }
+                Some(name)
+            }
+            Some(c) if c == '?' || c == '#' || c == '@' || c == '*' || c.is_ascii_digit() => {
+                chars.next();
+                Some(c.to_string())
+            }
+            Some(c) if c.is_ascii_alphanumeric() || c == '_' => {
+                let mut name = String::new();
+                while let Some(c) = chars.peek().copied() {
+                    if !(c.is_ascii_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    name.push(c);
+                    chars.next();
+                }
+                Some(name)
+            }
+            _ => None,
+        }
+    }
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' | ';' => {
+                if in_word {
+                    flush_lit(&mut parts, &mut lit, false);
+                    tokens.push(Token::Word(Word {
+                        parts: std::mem::take(&mut parts),
+                        line,
+                    }));
+                    in_word = false;
+                }
+                tokens.push(Token::Separator);
+                if c == '\n' {
+                    line += 1;
+                }
+            }
+            c if c.is_whitespace() => {
+                if in_word {
+                    flush_lit(&mut parts, &mut lit, false);
+                    tokens.push(Token::Word(Word {
+                        parts: std::mem::take(&mut parts),
+                        line,
+                    }));
+                    in_word = false;
+                }
+            }
+            '#' if !in_word => {
+                while chars.peek().is_some_and(|c| *c != '\n') {
+                    chars.next();
+                }
+            }
+            '\\' => match chars.next() {
+                Some('\n') => line += 1,
+                Some(c) => {
+                    in_word = true;
+                    flush_lit(&mut parts, &mut lit, false);
+                    parts.push(Part::Lit(c.to_string(), true));
+                }
+                None => {}
+            },
+            '\'' => {
+                in_word = true;
+                flush_lit(&mut parts, &mut lit, false);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            lit.push(c);
+                        }
+                        None => return Err(parse_error(line, "unterminated single quote")),
+                    }
+                }
+                flush_lit(&mut parts, &mut lit, true);
+            }
+            '"' => {
+                in_word = true;
+                flush_lit(&mut parts, &mut lit, false);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$')) => lit.push(c),
+                            Some('\n') => line += 1,
+                            Some(c) => {
+                                lit.push('\\');
+                                lit.push(c);
+                            }
+                            None => {}
+                        },
+                        Some('$') => match read_var(&mut chars) {
+                            Some(name) => {
+                                flush_lit(&mut parts, &mut lit, true);
+                                parts.push(Part::Var(name, true));
+                            }
+                            None => lit.push('$'),
+                        },
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            lit.push(c);
+                        }
+                        None => return Err(parse_error(line, "unterminated double quote")),
+                    }
+                }
+                flush_lit(&mut parts, &mut lit, true);
+            }
+            '$' => {
+                in_word = true;
+                match read_var(&mut chars) {
+                    Some(name) => {
+                        flush_lit(&mut parts, &mut lit, false);
+                        parts.push(Part::Var(name, false));
+                    }
+                    None => lit.push('$'),
+                }
+            }
+            c => {
+                in_word = true;
+                lit.push(c);
+            }
+        }
+    }
+    if in_word {
+        flush_lit(&mut parts, &mut lit, false);
+        tokens.push(Token::Word(Word { parts, line }));
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek_keyword(&self) -> Option<&str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Word(w)) => w.keyword(),
+            _ => None,
+        }
+    }
+
+    fn line(&self) -> usize {
+        self.tokens[..self.pos.min(self.tokens.len())]
+            .iter()
+            .rev()
+            .find_map(|t| match t {
+                Token::Word(w) => Some(w.line),
+                Token::Separator => None,
+            })
+            .unwrap_or(1)
+    }
+
+    fn skip_separators(&mut self) {
+        while matches!(self.tokens.get(self.pos), Some(Token::Separator)) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, keyword: &str) -> Result<()> {
+        self.skip_separators();
+        if self.peek_keyword() == Some(keyword) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(parse_error(self.line(), &format!("expected '{}'", keyword)))
+        }
+    }
+
+    /// Parse statements until one of the terminators is the next keyword
+    fn parse_list(&mut self, terminators: &[&str]) -> Result<Vec<Node>> {
+        let mut nodes = Vec::new();
+        loop {
+            self.skip_separators();
+            match self.peek_keyword() {
+                Some(k) if terminators.contains(&k) => return Ok(nodes),
+                _ => {}
+            }
+            if self.pos >= self.tokens.len() {
+                return match terminators.is_empty() {
+                    true => Ok(nodes),
+                    false => Err(parse_error(
+                        self.line(),
+                        &format!("expected '{}'", terminators.join("' or '")),
+                    )),
+                };
+            }
+            nodes.push(self.parse_statement()?);
+        }
+    }
+
+    fn parse_words(&mut self) -> Vec<Word> {
+        let mut words = Vec::new();
+        while let Some(Token::Word(w)) = self.tokens.get(self.pos) {
+            // Braces end a command, so blocks may share a line with it
+            if !words.is_empty() && matches!(w.keyword(), Some("{" | "}")) {
+                break;
+            }
+            words.push(w.clone());
+            self.pos += 1;
+        }
+        words
+    }
+
+    fn parse_statement(&mut self) -> Result<Node> {
+        match self.peek_keyword() {
+            Some("if") => {
+                self.pos += 1;
+                let mut branches = Vec::new();
+                let mut otherwise = Vec::new();
+                loop {
+                    let cond = self.parse_list(&["then"])?;
+                    self.expect("then")?;
+                    let body = self.parse_list(&["elif", "else", "fi"])?;
+                    branches.push((cond, body));
+                    match self.peek_keyword() {
+                        Some("elif") => self.pos += 1,
+                        Some("else") => {
+                            self.pos += 1;
+                            otherwise = self.parse_list(&["fi"])?;
+                            self.expect("fi")?;
+                            break;
+                        }
+                        _ => {
+                            self.expect("fi")?;
+                            break;
+                        }
+                    }
+                }
+                Ok(Node::If {
+                    branches,
+                    otherwise,
+                })
+            }
+            Some("for") => {
+                self.pos += 1;
+                let mut words = self.parse_words();
+                if words.len() < 2 || words[1].keyword() != Some("in") {
+                    return Err(parse_error(self.line(), "expected 'for NAME in'"));
+                }
+                let var = words[0].keyword().unwrap_or_default().to_string();
+                let items = words.split_off(2);
+                self.expect("do")?;
+                let body = self.parse_list(&["done"])?;
+                self.expect("done")?;
+                Ok(Node::For { var, items, body })
+            }
+            Some(k @ ("while" | "until")) => {
+                let until = k == "until";
+                self.pos += 1;
+                let cond = self.parse_list(&["do"])?;
+                self.expect("do")?;
+                let body = self.parse_list(&["done"])?;
+                self.expect("done")?;
+                Ok(Node::While { until, cond, body })
+            }
+            Some("function") => {
+                self.pos += 1;
+                let name = match self.tokens.get(self.pos) {
+                    Some(Token::Word(w)) => w.keyword().unwrap_or_default().to_string(),
+                    _ => return Err(parse_error(self.line(), "expected function name")),
+                };
+                self.pos += 1;
+                self.expect("{")?;
+                let body = self.parse_list(&["}"])?;
+                self.expect("}")?;
+                Ok(Node::Function { name, body })
+            }
+            Some(k @ ("menuentry" | "submenu")) => {
+                let submenu = k == "submenu";
+                let args = self.parse_words();
+                self.expect("{")?;
+                let body = self.parse_list(&["}"])?;
+                self.expect("}")?;
+                Ok(Node::Menu {
+                    submenu,
+                    args,
+                    body,
+                })
+            }
+            _ => {
+                let words = self.parse_words();
+                Ok(Node::Command(words))
+            }
+        }
+    }
+}
+
+struct Interpreter {
+    vars: HashMap<String, String>,
+    env_block: Vec<(String, String)>,
+    functions: HashMap<String, Vec<Node>>,
+    entries: Vec<GrubEntry>,
+    /// The ids and titles of the submenus being evaluated, outermost first
+    submenus: Vec<(String, String)>,
+    status: i32,
+    steps: usize,
+    depth: usize,
+    /// The entry whose body is being evaluated, its boot commands are recorded
+    recording: Option<GrubEntry>,
+}
+
+impl Interpreter {
+    fn run_list(&mut self, nodes: &[Node]) -> Result<Flow> {
+        for node in nodes {
+            match self.run_node(node)? {
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn run_node(&mut self, node: &Node) -> Result<Flow> {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "grub.cfg evaluation did not terminate",
+            ));
+        }
+        match node {
+            Node::Command(words) => {
+                let args = self.expand_words(words);
+                self.run_command(&args)
+            }
+            Node::If {
+                branches,
+                otherwise,
+            } => {
+                for (cond, body) in branches {
+                    if let flow @ (Flow::Break | Flow::Continue | Flow::Return) =
+                        self.run_list(cond)?
+                    {
+                        return Ok(flow);
+                    }
+                    if self.status == 0 {
+                        return self.run_list(body);
+                    }
+                }
+                self.status = 0;
+                self.run_list(otherwise)
+            }
+            Node::For { var, items, body } => {
+                for item in self.expand_words(items) {
+                    self.vars.insert(var.clone(), item);
+                    match self.run_list(body)? {
+                        Flow::Break => break,
+                        Flow::Return => return Ok(Flow::Return),
+                        _ => {}
+                    }
+                }
+                Ok(Flow::Normal)
+            }
+            Node::While { until, cond, body } => {
+                loop {
+                    self.run_list(cond)?;
+                    if (self.status == 0) == *until {
+                        break;
+                    }
+                    match self.run_list(body)? {
+                        Flow::Break => break,
+                        Flow::Return => return Ok(Flow::Return),
+                        _ => {}
+                    }
+                    self.steps += 1;
+                    if self.steps > MAX_STEPS {
+                        return Err(Error::new(
+                            ErrorKind::InvalidData,
+                            "grub.cfg evaluation did not terminate",
+                        ));
+                    }
+                }
+                Ok(Flow::Normal)
+            }
+            Node::Function { name, body } => {
+                self.functions.insert(name.clone(), body.clone());
+                self.status = 0;
+                Ok(Flow::Normal)
+            }
+            Node::Menu {
+                submenu,
+                args,
+                body,
+            } => {
+                self.run_menu(*submenu, args, body)?;
+                Ok(Flow::Normal)
+            }
+        }
+    }
+
+    fn run_menu(&mut self, submenu: bool, words: &[Word], body: &[Node]) -> Result<()> {
+        // Menu bodies never change the variables of the surrounding script
+        let saved_vars = self.vars.clone();
+        let mut args = vec![match submenu {
+            true => "submenu".to_string(),
+            false => "menuentry".to_string(),
+        }];
+        args.extend(self.expand_words(&words[1..]));
+
+        if submenu {
+            let menu = grub_cfg::parse_menuentry(&args, &self.submenus);
+            self.submenus.push((menu.entry_id, menu.entry_name));
+            let result = self.run_list(body);
+            self.submenus.pop();
+            result?;
+        } else if self.recording.is_none() {
+            let entry = grub_cfg::parse_menuentry(&args, &self.submenus);
+            self.recording = Some(entry);
+            let result = self.run_list(body);
+            let entry = self.recording.take().unwrap();
+            result?;
+            self.entries.push(entry);
+        }
+        self.vars = saved_vars;
+        self.status = 0;
+        Ok(())
+    }
+
+    fn expand_words(&self, words: &[Word]) -> Vec<String> {
+        let mut ret = Vec::new();
+        for word in words {
+            let mut fields: Vec<String> = vec![String::new()];
+            let mut quoted = false;
+            for part in &word.parts {
+                match part {
+                    Part::Lit(s, q) => {
+                        quoted |= *q;
+                        fields.last_mut().unwrap().push_str(s);
+                    }
+                    Part::Var(name, true) => {
+                        quoted = true;
+                        fields.last_mut().unwrap().push_str(&self.get_var(name));
+                    }
+                    Part::Var(name, false) => {
+                        let value = self.get_var(name);
+                        let mut pieces = value.split_whitespace();
+                        if value.starts_with(char::is_whitespace)
+                            && !fields.last().unwrap().is_empty()
+                        {
+                            fields.push(String::new());
+                        }
+                        if let Some(first) = pieces.next() {
+                            fields.last_mut().unwrap().push_str(first);
+                        }
+                        for piece in pieces {
+                            fields.push(piece.to_string());
+                        }
+                        if value.ends_with(char::is_whitespace) && !value.trim().is_empty() {
+                            fields.push(String::new());
+                        }
+                    }
+                }
+            }
+            if !quoted && fields.last().is_some_and(|f| f.is_empty()) {
+                fields.pop();
+            }
+            ret.extend(fields);
+        }
+        ret
+    }
+
+    fn get_var(&self, name: &str) -> String {
+        match name {
+            "?" => self.status.to_string(),
+            _ => self.vars.get(name).cloned().unwrap_or_default(),
+        }
+    }
+
+    fn run_command(&mut self, args: &[String]) -> Result<Flow> {
+        let Some(command) = args.first() else {
+            return Ok(Flow::Normal);
+        };
+        if let Some(entry) = self.recording.as_mut() {
+            grub_cfg::apply_entry_command(entry, args);
+        }
+
+        let mut status = 0;
+        match command.as_str() {
+            "set" => {
+                for arg in &args[1..] {
+                    if let Some((name, value)) = arg.split_once('=') {
+                        self.vars.insert(name.to_string(), value.to_string());
+                    }
+                }
+            }
+            "unset" => {
+                for arg in &args[1..] {
+                    self.vars.remove(arg);
+                }
+            }
+            "load_env" => {
+                let wanted: Vec<&String> = args[1..]
+                    .iter()
+                    .filter(|a| !a.starts_with('-') && !a.contains('/'))
+                    .collect();
+                for (key, value) in &self.env_block {
+                    if wanted.is_empty() || wanted.contains(&key) {
+                        self.vars.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+            "true" => {}
+            "false" => status = 1,
+            "[" | "test" => {
+                let end = match command.as_str() {
+                    "[" if args.last().map(String::as_str) == Some("]") => args.len() - 1,
+                    _ => args.len(),
+                };
+                status = if eval_test(&args[1..end]) { 0 } else { 1 };
+            }
+            "fwsetup" if args.iter().any(|a| a == "--is-supported") => {
+                status = if self.get_var("grub_platform") == "efi" {
+                    0
+                } else {
+                    1
+                };
+            }
+            "break" => return Ok(Flow::Break),
+            "continue" => return Ok(Flow::Continue),
+            "return" => {
+                self.status = args.get(1).and_then(|s| s.parse().ok()).unwrap_or(0);
+                return Ok(Flow::Return);
+            }
+            name if self.functions.contains_key(name) => {
+                if self.depth >= MAX_DEPTH {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("grub.cfg recursion too deep in function {}", name),
+                    ));
+                }
+                let body = self.functions[name].clone();
+                let saved: Vec<(String, Option<String>)> = (1..10)
+                    .map(|i| i.to_string())
+                    .map(|k| (k.clone(), self.vars.get(&k).cloned()))
+                    .collect();
+                for (i, arg) in args[1..].iter().enumerate().take(9) {
+                    self.vars.insert((i + 1).to_string(), arg.clone());
+                }
+                self.depth += 1;
+                self.status = 0;
+                let result = self.run_list(&body);
+                self.depth -= 1;
+                for (k, v) in saved {
+                    match v {
+                        Some(v) => self.vars.insert(k, v),
+                        None => self.vars.remove(&k),
+                    };
+                }
+                // The status of a function is the one of its last command or `return`
+                result?;
+                return Ok(Flow::Normal);
+            }
+            name if name.contains('=') && !name.starts_with('=') => {
+                let (name, value) = name.split_once('=').unwrap();
+                self.vars.insert(name.to_string(), value.to_string());
+            }
+            _ => {}
+        }
+        self.status = status;
+        Ok(Flow::Normal)
+    }
+}
+
+/// Evaluate the arguments of the `test` command
+fn eval_test(args: &[String]) -> bool {
+    if let Some(pos) = args.iter().position(|a| a == "-o") {
+        return eval_test(&args[..pos]) || eval_test(&args[pos + 1..]);
+    }
+    if let Some(pos) = args.iter().position(|a| a == "-a") {
+        return eval_test(&args[..pos]) && eval_test(&args[pos + 1..]);
+    }
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let num = |s: &str| s.parse::<i64>().unwrap_or(0);
+    match args.as_slice() {
+        [] => false,
+        ["!", rest @ ..] => {
+            let rest: Vec<String> = rest.iter().map(|s| s.to_string()).collect();
+            !eval_test(&rest)
+        }
+        [s] => !s.is_empty(),
+        ["-z", s] => s.is_empty(),
+        ["-n", s] => !s.is_empty(),
+        // Files cannot be checked without GRUB's device names, assume they exist
+        ["-e" | "-f" | "-s" | "-d", _] => true,
+        [a, "=" | "==", b] => a == b,
+        [a, "!=", b] => a != b,
+        [a, "<", b] => a < b,
+        [a, ">", b] => a > b,
+        [a, "-eq", b] => num(a) == num(b),
+        [a, "-ne", b] => num(a) != num(b),
+        [a, "-lt", b] => num(a) < num(b),
+        [a, "-le", b] => num(a) <= num(b),
+        [a, "-gt", b] => num(a) > num(b),
+        [a, "-ge", b] => num(a) >= num(b),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A grub.cfg written by Debian's grub-mkconfig, trimmed to the menu
+    const DEBIAN_CFG: &str = r#"
+if [ -s $prefix/grubenv ]; then
+  set have_grubenv=true
+  load_env
+fi
+if [ "${next_entry}" ] ; then
+   set default="${next_entry}"
+   set next_entry=
+   save_env next_entry
+   set boot_once=true
+else
+   set default="${saved_entry}"
+fi
+
+if [ x"${feature_menuentry_id}" = xy ]; then
+  menuentry_id_option="--id"
+else
+  menuentry_id_option=""
+fi
+
+export menuentry_id_option
+
+function load_video {
+  insmod all_video
+}
+
+### BEGIN /etc/grub.d/10_linux ###
+menuentry 'Debian GNU/Linux' --class debian --class gnu-linux --class gnu --class os $menuentry_id_option 'gnulinux-simple-1234' {
+	load_video
+	insmod gzio
+	search --no-floppy --fs-uuid --set=root 1234
+	echo	'Loading Linux 6.1.0-18-amd64 ...'
+	linux	/boot/vmlinuz-6.1.0-18-amd64 root=UUID=1234 ro  quiet
+	initrd	/boot/initrd.img-6.1.0-18-amd64
+}
+submenu 'Advanced options for Debian GNU/Linux' $menuentry_id_option 'gnulinux-advanced-1234' {
+	menuentry 'Debian GNU/Linux, with Linux 6.1.0-18-amd64' --class debian --class gnu-linux --class gnu --class os $menuentry_id_option 'gnulinux-6.1.0-18-amd64-advanced-1234' {
+		search --no-floppy --fs-uuid --set=root 1234
+		linux	/boot/vmlinuz-6.1.0-18-amd64 root=UUID=1234 ro  quiet
+		initrd	/boot/initrd.img-6.1.0-18-amd64
+	}
+	menuentry 'Debian GNU/Linux, with Linux 6.1.0-18-amd64 (recovery mode)' --class debian --class gnu-linux --class gnu --class os $menuentry_id_option 'gnulinux-6.1.0-18-amd64-recovery-1234' {
+		search --no-floppy --fs-uuid --set=root 1234
+		linux	/boot/vmlinuz-6.1.0-18-amd64 root=UUID=1234 ro single
+		initrd	/boot/initrd.img-6.1.0-18-amd64
+	}
+	menuentry 'Debian GNU/Linux, with Linux 6.1.0-17-amd64' --class debian --class gnu-linux --class gnu --class os $menuentry_id_option 'gnulinux-6.1.0-17-amd64-advanced-1234' {
+		search --no-floppy --fs-uuid --set=root 1234
+		linux	/boot/vmlinuz-6.1.0-17-amd64 root=UUID=1234 ro  quiet
+		initrd	/boot/initrd.img-6.1.0-17-amd64
+	}
+}
+### END /etc/grub.d/10_linux ###
+
+### BEGIN /etc/grub.d/30_uefi-firmware ###
+if [ "$grub_platform" = "efi" ]; then
+	fwsetup --is-supported
+	if [ "$?" = 0 ]; then
+		menuentry 'UEFI Firmware Settings' $menuentry_id_option 'uefi-firmware' {
+			fwsetup
+		}
+	fi
+fi
+### END /etc/grub.d/30_uefi-firmware ###
+"#;
+
+    fn context(platform: &str) -> GrubContext {
+        let mut ctx = GrubContext {
+            platform: platform.to_string(),
+            cpu: "x86_64".to_string(),
+            features: Vec::new(),
+            env: Vec::new(),
+        };
+        ctx.set_feature("menuentry_id", "y");
+        ctx
+    }
+
+    fn debian_entries() -> Vec<GrubEntry> {
+        evaluate(DEBIAN_CFG, &GrubEnv::parse(""), &context("efi")).unwrap()
+    }
+
+    #[test]
+    fn evaluates_debian_menu() {
+        let entries = debian_entries();
+        let ids: Vec<&str> = entries.iter().map(|e| e.entry_id.as_str()).collect();
+        assert_eq!(
+            ids,
+            [
+                "gnulinux-simple-1234",
+                "gnulinux-6.1.0-18-amd64-advanced-1234",
+                "gnulinux-6.1.0-18-amd64-recovery-1234",
+                "gnulinux-6.1.0-17-amd64-advanced-1234",
+                "uefi-firmware",
+            ]
+        );
+        let entry = &entries[0];
+        assert_eq!(entry.entry_name, "Debian GNU/Linux");
+        assert_eq!(
+            entry.entry_kernel.as_deref(),
+            Some("/boot/vmlinuz-6.1.0-18-amd64")
+        );
+        assert_eq!(
+            entry.entry_cmdline.as_deref(),
+            Some("root=UUID=1234 ro quiet")
+        );
+        assert_eq!(entry.entry_initrd, ["/boot/initrd.img-6.1.0-18-amd64"]);
+        assert_eq!(entry.entry_root_uuid.as_deref(), Some("1234"));
+        assert_eq!(entry.entry_classes, ["debian", "gnu-linux", "gnu", "os"]);
+    }
+
+    #[test]
+    fn records_parent_submenus() {
+        let entries = debian_entries();
+        assert!(entries[0].entry_parents.is_empty());
+        assert_eq!(entries[0].saved_id(), "gnulinux-simple-1234");
+        assert_eq!(
+            entries[1].entry_parents,
+            [(
+                "gnulinux-advanced-1234".to_string(),
+                "Advanced options for Debian GNU/Linux".to_string()
+            )]
+        );
+        assert_eq!(
+            entries[1].saved_id(),
+            "gnulinux-advanced-1234>gnulinux-6.1.0-18-amd64-advanced-1234"
+        );
+        assert!(entries[4].entry_parents.is_empty());
+    }
+
+    #[test]
+    fn names_submenu_entries_by_path() {
+        let entry = &debian_entries()[1];
+        assert!(entry.is_named_by("gnulinux-advanced-1234>gnulinux-6.1.0-18-amd64-advanced-1234"));
+        assert!(entry.is_named_by(
+            "Advanced options for Debian GNU/Linux>Debian GNU/Linux, with Linux 6.1.0-18-amd64"
+        ));
+        assert!(
+            entry.is_named_by("gnulinux-advanced-1234>Debian GNU/Linux, with Linux 6.1.0-18-amd64")
+        );
+        // GRUB does not look into submenus for a bare id
+        assert!(!entry.is_named_by("gnulinux-6.1.0-18-amd64-advanced-1234"));
+        assert!(!entry.is_named_by("gnulinux-simple-1234>gnulinux-6.1.0-18-amd64-advanced-1234"));
+        assert!(!debian_entries()[0].is_named_by("gnulinux-advanced-1234>gnulinux-simple-1234"));
+    }
+
+    #[test]
+    fn skips_entries_of_other_platforms() {
+        let entries = evaluate(DEBIAN_CFG, &GrubEnv::parse(""), &context("pc")).unwrap();
+        assert_eq!(entries.len(), 4);
+        assert!(entries.iter().all(|e| e.entry_id != "uefi-firmware"));
+    }
+
+    #[test]
+    fn uses_titles_without_menuentry_id() {
+        let mut ctx = context("efi");
+        ctx.set_feature("menuentry_id", "n");
+        let entries = evaluate(DEBIAN_CFG, &GrubEnv::parse(""), &ctx).unwrap();
+        assert_eq!(entries[0].entry_id, "Debian GNU/Linux");
+        assert_eq!(
+            entries[1].saved_id(),
+            "Advanced options for Debian GNU/Linux>Debian GNU/Linux, with Linux 6.1.0-18-amd64"
+        );
+    }
+
+    #[test]
+    fn expands_root_in_entry_paths() {
+        let cfg = "menuentry 'Arch' {\n\
+                   \tlinux ($root)/vmlinuz-linux root=/dev/sda2\n\
+                   }\n\
+                   menuentry 'Fedora' {\n\
+                   \tset root='hd0,gpt2'\n\
+                   \tlinux ($root)/vmlinuz\n\
+                   \tinitrd (hd0,gpt3)/initramfs.img\n\
+                   }\n";
+        let entries = evaluate(cfg, &GrubEnv::parse(""), &context("efi")).unwrap();
+
+        // GRUB sets root at boot, a script leaving it unset reads its root device
+        assert_eq!(entries[0].entry_kernel.as_deref(), Some("()/vmlinuz-linux"));
+        assert_eq!(
+            grub_cfg::split_device("()/vmlinuz-linux"),
+            (None, "/vmlinuz-linux")
+        );
+        assert_eq!(entries[0].entry_root_device, None);
+
+        assert_eq!(entries[1].entry_root_device.as_deref(), Some("hd0,gpt2"));
+        assert_eq!(
+            grub_cfg::entry_files(&entries[1]),
+            ["(hd0,gpt2)/vmlinuz", "(hd0,gpt3)/initramfs.img"]
+        );
+    }
+
+    #[test]
+    fn rejects_unterminated_blocks() {
+        assert!(evaluate("menuentry 'A' {\n", &GrubEnv::parse(""), &context("efi")).is_err());
+        assert!(evaluate("if true; then\n", &GrubEnv::parse(""), &context("efi")).is_err());
+    }
+}
@@ -0,0 +1,325 @@
+use crate::bootloader::grub::GrubEntry;
+use crate::common::selector::{glob_to_regex, select};
+use regex::{Regex, RegexBuilder};
+use std::cmp::Ordering;
+use std::io::{Error, ErrorKind, Result};
+
+/// The grubenv variable holding a selector that is re-resolved on each run
+pub(crate) const STORED_SELECTOR_KEY: &str = "bootmgr_selector";
+
+/// A struct representing a kernel version parsed from a GRUB entry
+/// Fields:
+/// * `raw` - The version as written, e.g. `6.1.0-18-amd64`
+/// * `numbers` - The dotted release numbers, e.g. `[6, 1, 0]`
+/// * `revision` - The package revision numbers, e.g. `[18]`
+/// * `flavour` - The remaining suffix, e.g. `amd64`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct KernelVersion {
+    pub raw: String,
+    pub numbers: Vec<u64>,
+    pub revision: Vec<u64>,
+    pub flavour: String,
+}
+
+impl KernelVersion {
+    pub fn parse(raw: &str) -> Option<Self> {
+        let regex = Regex::new(r"^(\d+(?:\.\d+)*)(?:-(\d+(?:\.\d+)*))?(?:[-.+_~](.+))?$").unwrap();
+        let captures = regex.captures(raw)?;
+        let numbers = |m: Option<regex::Match>| -> Vec<u64> {
+            m.map(|m| {
+                m.as_str()
+                    .split('.')
+                    .filter_map(|n| n.parse().ok())
+                    .collect()
+            })
+            .unwrap_or_default()
+        };
+        Some(Self {
+            raw: raw.to_string(),
+            numbers: numbers(captures.get(1)),
+            revision: numbers(captures.get(2)),
+            flavour: captures
+                .get(3)
+                .map(|m| m.as_str().to_string())
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Get the kernel version booted by a GRUB entry, from its kernel path or its title
+    pub fn from_entry(entry: &GrubEntry) -> Option<Self> {
+        let from_linux = entry
+            .entry_kernel
+            .as_deref()
+            .and_then(|kernel| kernel.rsplit('/').next())
+            .and_then(|image| image.split_once('-'))
+            .and_then(|(_, version)| Self::parse(version));
+        from_linux.or_else(|| {
+            let regex = Regex::new(r"Linux (\d[\w.+~-]*)").unwrap();
+            regex
+                .captures(&entry.entry_name)
+                .and_then(|c| Self::parse(&c[1]))
+        })
+    }
+}
+
+impl PartialOrd for KernelVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for KernelVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.numbers
+            .cmp(&other.numbers)
+            .then_with(|| self.revision.cmp(&other.revision))
+            .then_with(|| self.flavour.cmp(&other.flavour))
+    }
+}
+
+/// Resolve a GRUB selector, including the symbolic forms that survive kernel updates
+///
+/// * `latest` - the newest kernel
+/// * `latest:<flavour>` - the newest kernel of a flavour, e.g. `latest:amd64`
+/// * `os:<name>` - the newest kernel of entries whose title contains the name
+/// * `kernel:<version-glob>` - the newest kernel matching the glob, e.g. `kernel:6.1.*`
+///
+/// Recovery entries are never picked by symbolic selectors, other selectors
+/// are resolved by the common selector language.
+/// # Arguments
+/// * `entries` - The parsed GRUB entries
+/// * `selector` - The selector given by the user
+/// # Returns
+/// * `Result<&GrubEntry>` - The entry the selector currently points at
+pub(crate) fn resolve_grub_selector<'a>(
+    entries: &'a [GrubEntry],
+    selector: &str,
+) -> Result<&'a GrubEntry> {
+    let (kind, arg) = selector.split_once(':').unwrap_or((selector, ""));
+    let versioned = entries
+        .iter()
+        .filter(|e| !e.entry_name.to_lowercase().contains("recovery"))
+        .filter_map(|e| KernelVersion::from_entry(e).map(|v| (v, e)));
+
+    let candidates: Vec<(KernelVersion, &GrubEntry)> = match (kind, arg) {
+        ("latest", "") => versioned.collect(),
+        ("latest", flavour) => versioned
+            .filter(|(v, _)| v.flavour.eq_ignore_ascii_case(flavour))
+            .collect(),
+        ("os", name) => {
+            let name = name.to_lowercase();
+            let matching: Vec<&GrubEntry> = entries
+                .iter()
+                .filter(|e| e.entry_name.to_lowercase().contains(&name))
+                .collect();
+            let versions: Vec<(KernelVersion, &GrubEntry)> = versioned
+                .filter(|(_, e)| matching.iter().any(|m| std::ptr::eq(*m, *e)))
+                .collect();
+            match (versions.is_empty(), matching.as_slice()) {
+                (true, [entry]) => return Ok(entry),
+                _ => versions,
+            }
+        }
+        ("kernel", glob) => {
+            let regex = RegexBuilder::new(&glob_to_regex(glob))
+                .case_insensitive(true)
+                .build()
+                .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+            versioned.filter(|(v, _)| regex.is_match(&v.raw)).collect()
+        }
+        _ => return select(entries, selector, "GRUB"),
+    };
+
+    let latest = candidates.iter().map(|(v, _)| v).max().ok_or(Error::new(
+        ErrorKind::NotFound,
+        format!("No GRUB entry matches '{}'", selector),
+    ))?;
+    // Prefer the entry naming the version over generic ones booting the same kernel
+    let best = candidates
+        .iter()
+        .filter(|(v, _)| v == latest)
+        .rev()
+        .max_by_key(|(v, e)| e.entry_name.contains(&v.raw))
+        .map(|(_, e)| *e)
+        .unwrap();
+    Ok(best)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Make an entry of the "Advanced options" submenu of Debian
+    fn advanced(title: &str, id: &str, kernel: &str) -> GrubEntry {
+        GrubEntry {
+            entry_name: title.to_string(),
+            entry_id: id.to_string(),
+            entry_parents: vec![(
+                "gnulinux-advanced-1234".to_string(),
+                "Advanced options for Debian GNU/Linux".to_string(),
+            )],
+            entry_kernel: Some(kernel.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// The menu of Debian with two kernels, a Fedora entry and Windows
+    fn entries() -> Vec<GrubEntry> {
+        vec![
+            GrubEntry {
+                entry_name: "Debian GNU/Linux".to_string(),
+                entry_id: "gnulinux-simple-1234".to_string(),
+                entry_kernel: Some("/boot/vmlinuz-6.1.0-18-amd64".to_string()),
+                ..Default::default()
+            },
+            advanced(
+                "Debian GNU/Linux, with Linux 6.1.0-18-amd64",
+                "gnulinux-6.1.0-18-amd64-advanced-1234",
+                "/boot/vmlinuz-6.1.0-18-amd64",
+            ),
+            advanced(
+                "Debian GNU/Linux, with Linux 6.1.0-18-amd64 (recovery mode)",
+                "gnulinux-6.1.0-18-amd64-recovery-1234",
+                "/boot/vmlinuz-6.1.0-18-amd64",
+            ),
+            advanced(
+                "Debian GNU/Linux, with Linux 6.1.0-17-amd64",
+                "gnulinux-6.1.0-17-amd64-advanced-1234",
+                "/boot/vmlinuz-6.1.0-17-amd64",
+            ),
+            advanced(
+                "Debian GNU/Linux, with Linux 6.1.0-17-rt-amd64",
+                "gnulinux-6.1.0-17-rt-amd64-advanced-1234",
+                "/boot/vmlinuz-6.1.0-17-rt-amd64",
+            ),
+            GrubEntry {
+                entry_name: "Fedora Linux (6.9.4-200.fc40.x86_64) 40".to_string(),
+                entry_id: "fedora-6.9.4".to_string(),
+                ..Default::default()
+            },
+            GrubEntry {
+                entry_name: "Windows Boot Manager (on /dev/nvme0n1p1)".to_string(),
+                entry_id: "osprober-efi-ABCD".to_string(),
+                entry_chainloader: Some("/EFI/Microsoft/Boot/bootmgfw.efi".to_string()),
+                ..Default::default()
+            },
+        ]
+    }
+
+    fn resolve(selector: &str) -> Result<String> {
+        resolve_grub_selector(&entries(), selector).map(|e| e.entry_id.clone())
+    }
+
+    #[test]
+    fn parses_kernel_versions() {
+        let version = KernelVersion::parse("6.1.0-18-amd64").unwrap();
+        assert_eq!(version.numbers, [6, 1, 0]);
+        assert_eq!(version.revision, [18]);
+        assert_eq!(version.flavour, "amd64");
+
+        let version = KernelVersion::parse("6.9.4-200.fc40.x86_64").unwrap();
+        assert_eq!(version.revision, [200]);
+        assert_eq!(version.flavour, "fc40.x86_64");
+
+        assert_eq!(
+            KernelVersion::parse("6.8.0").unwrap().revision,
+            Vec::<u64>::new()
+        );
+        assert_eq!(KernelVersion::parse("vmlinuz"), None);
+        assert!(KernelVersion::parse("6.10.1").unwrap() > KernelVersion::parse("6.9.12").unwrap());
+        assert!(
+            KernelVersion::parse("6.1.0-18-amd64").unwrap()
+                > KernelVersion::parse("6.1.0-17-amd64").unwrap()
+        );
+    }
+
+    #[test]
+    fn finds_kernel_of_entries() {
+        let entries = entries();
+        // From the kernel path, or from the title without one
+        assert_eq!(
+            KernelVersion::from_entry(&entries[3]).unwrap().raw,
+            "6.1.0-17-amd64"
+        );
+        assert_eq!(
+            KernelVersion::from_entry(&entries[5]).map(|v| v.raw),
+            None,
+            "the Fedora title does not say `Linux <version>`"
+        );
+        assert_eq!(KernelVersion::from_entry(&entries[6]), None);
+    }
+
+    #[test]
+    fn resolves_latest_to_the_entry_naming_the_kernel() {
+        // The submenu entry names the version, the top-level one boots the same kernel
+        assert_eq!(
+            resolve("latest").unwrap(),
+            "gnulinux-6.1.0-18-amd64-advanced-1234"
+        );
+        assert_eq!(
+            resolve("latest:amd64").unwrap(),
+            "gnulinux-6.1.0-18-amd64-advanced-1234"
+        );
+        assert_eq!(
+            resolve("latest:rt-amd64").unwrap(),
+            "gnulinux-6.1.0-17-rt-amd64-advanced-1234"
+        );
+        assert_eq!(
+            resolve("latest:arm64").unwrap_err().to_string(),
+            "No GRUB entry matches 'latest:arm64'"
+        );
+    }
+
+    #[test]
+    fn resolves_os_and_kernel_selectors() {
+        assert_eq!(
+            resolve("os:debian").unwrap(),
+            "gnulinux-6.1.0-18-amd64-advanced-1234"
+        );
+        // A single entry without a known kernel is still picked by its title
+        assert_eq!(resolve("os:windows").unwrap(), "osprober-efi-ABCD");
+        assert_eq!(resolve("os:fedora").unwrap(), "fedora-6.9.4");
+        assert_eq!(
+            resolve("kernel:6.1.0-17-*").unwrap(),
+            "gnulinux-6.1.0-17-rt-amd64-advanced-1234"
+        );
+        assert_eq!(
+            resolve("kernel:6.1.0-17-amd64").unwrap(),
+            "gnulinux-6.1.0-17-amd64-advanced-1234"
+        );
+        assert_eq!(
+            resolve("kernel:5.*").unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn never_picks_recovery_entries() {
+        let entries: Vec<GrubEntry> = entries()
+            .into_iter()
+            .filter(|e| e.entry_id != "gnulinux-6.1.0-18-amd64-advanced-1234")
+            .collect();
+        // Only the top-level entry and the recovery entry boot 6.1.0-18
+        assert_eq!(
+            resolve_grub_selector(&entries, "latest").unwrap().entry_id,
+            "gnulinux-simple-1234"
+        );
+        assert_eq!(
+            resolve_grub_selector(&entries, "kernel:6.1.0-18-*")
+                .unwrap()
+                .entry_id,
+            "gnulinux-simple-1234"
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_selector_language() {
+        assert_eq!(resolve("0").unwrap(), "gnulinux-simple-1234");
+        assert_eq!(resolve("osprober-efi-ABCD").unwrap(), "osprober-efi-ABCD");
+        assert_eq!(resolve("re:^Fedora").unwrap(), "fedora-6.9.4");
+        assert_eq!(
+            resolve("recovery mode").unwrap(),
+            "gnulinux-6.1.0-18-amd64-recovery-1234"
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use std::io::{Error, ErrorKind, Result};
+
+/// The size of a grubenv block as created by grub-editenv
+pub(crate) const GRUB_ENV_SIZE: usize = 1024;
+const GRUB_ENV_HEADER: &str = "# GRUB Environment Block\n\
+    # WARNING: Do not edit this file by tools other than grub-editenv!!!\n";
+
+/// A struct representing the variables stored in a grubenv block
+/// Fields:
+/// * `vars` - The variables in the order they appear in the block
+#[derive(Clone, Debug, Default)]
+pub(crate) struct GrubEnv {
+    vars: Vec<(String, String)>,
+}
+
+impl GrubEnv {
+    pub fn parse(content: &str) -> Self {
+        let vars = content
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.trim().to_string()))
+            .collect();
+        Self { vars }
+    }
+
+    pub fn vars(&self) -> &[(String, String)] {
+        &self.vars
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.vars
+            .iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Set a variable, in place of the first time it appears, dropping later copies
+    pub fn set(&mut self, key: &str, value: &str) {
+        match self.vars.iter().position(|(k, _)| k == key) {
+            Some(first) => {
+                self.vars[first].1 = value.to_string();
+                let mut seen = false;
+                self.vars
+                    .retain(|(k, _)| k != key || !std::mem::replace(&mut seen, true));
+            }
+            None => self.vars.push((key.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.vars.retain(|(k, _)| k != key);
+    }
+
+    /// Serialize the variables into a block padded with `#` like grub-editenv does
+    /// # Arguments
+    /// * `size` - The size of the block, GRUB requires it to stay the same
+    pub fn to_block(&self, size: usize) -> Result<String> {
+        let mut block = GRUB_ENV_HEADER.to_string();
+        for (key, value) in &self.vars {
+            block.push_str(&format!("{}={}\n", key, value));
+        }
+        if block.len() > size {
+            return Err(Error::new(ErrorKind::OutOfMemory, "grubenv block is full"));
+        }
+        block.push_str(&"#".repeat(size - block.len()));
+        Ok(block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_block() {
+        let block = format!(
+            "{}saved_entry=gnulinux-advanced-1234>gnulinux-6.1.0-18\nboot_success=1\n{}",
+            GRUB_ENV_HEADER,
+            "#".repeat(500)
+        );
+        let env = GrubEnv::parse(&block);
+        assert_eq!(
+            env.vars(),
+            [
+                (
+                    "saved_entry".to_string(),
+                    "gnulinux-advanced-1234>gnulinux-6.1.0-18".to_string()
+                ),
+                ("boot_success".to_string(), "1".to_string()),
+            ]
+        );
+        assert_eq!(env.get("missing"), None);
+    }
+
+    #[test]
+    fn changes_variables() {
+        let mut env = GrubEnv::parse("a=1\nb=2\na=3\n");
+        // The last copy is the one GRUB loads
+        assert_eq!(env.get("a"), Some("3"));
+        env.set("a", "4");
+        assert_eq!(env.get("a"), Some("4"));
+        assert_eq!(env.vars().len(), 2);
+        env.set("c", "5");
+        env.remove("b");
+        assert_eq!(
+            env.vars(),
+            [
+                ("a".to_string(), "4".to_string()),
+                ("c".to_string(), "5".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn writes_blocks_of_the_given_size() {
+        let mut env = GrubEnv::default();
+        env.set("next_entry", "rescue");
+        let block = env.to_block(GRUB_ENV_SIZE).unwrap();
+        assert_eq!(block.len(), GRUB_ENV_SIZE);
+        assert!(block.starts_with(GRUB_ENV_HEADER));
+        assert!(block.contains("\nnext_entry=rescue\n#"));
+        assert_eq!(GrubEnv::parse(&block).vars(), env.vars());
+
+        let exact = GRUB_ENV_HEADER.len() + "next_entry=rescue\n".len();
+        assert_eq!(env.to_block(exact).unwrap().len(), exact);
+        let err = env.to_block(exact - 1).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::OutOfMemory);
+        assert_eq!(err.to_string(), "grubenv block is full");
+    }
+}
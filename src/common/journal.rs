@@ -0,0 +1,527 @@
+use crate::common::dry_run;
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// The record format version written by this bootmgr
+pub(crate) const JOURNAL_VERSION: u32 = 1;
+
+/// The changes made by the running command, None while not recording
+static SESSION: Mutex<Option<Vec<Change>>> = Mutex::new(None);
+
+/// A piece of boot configuration a change was made to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum ChangeTarget {
+    /// A file of the GRUB directory, named relative to the directory holding it
+    GrubFile(String),
+    /// A file on the EFI system partition of a boot loader, named relative to the partition
+    EspFile(String, String),
+    /// A file at a fixed path
+    File(PathBuf),
+    /// An EFI variable, named as accepted by `Platform::get_efi_var`
+    EfiVar(String),
+    /// The BCD store, the old value is its export and the new one its listing
+    Bcd,
+}
+
+impl ChangeTarget {
+    fn to_line(&self) -> String {
+        match self {
+            ChangeTarget::GrubFile(name) => format!("grub {}", name),
+            ChangeTarget::EspFile(loader, name) => format!("esp {} {}", loader, name),
+            ChangeTarget::File(path) => format!("file {}", path.display()),
+            ChangeTarget::EfiVar(name) => format!("efi {}", name),
+            ChangeTarget::Bcd => "bcd".to_string(),
+        }
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        let (kind, rest) = line.split_once(' ').unwrap_or((line, ""));
+        match kind {
+            "grub" => Some(ChangeTarget::GrubFile(rest.to_string())),
+            "esp" => rest
+                .split_once(' ')
+                .map(|(loader, name)| ChangeTarget::EspFile(loader.to_string(), name.to_string())),
+            "file" => Some(ChangeTarget::File(PathBuf::from(rest))),
+            "efi" => Some(ChangeTarget::EfiVar(rest.to_string())),
+            "bcd" => Some(ChangeTarget::Bcd),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ChangeTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChangeTarget::GrubFile(name) => write!(f, "GRUB {}", name),
+            ChangeTarget::EspFile(loader, name) => write!(f, "{} {}", loader, name),
+            ChangeTarget::File(path) => write!(f, "{}", path.display()),
+            ChangeTarget::EfiVar(name) => write!(f, "EFI variable {}", name),
+            ChangeTarget::Bcd => write!(f, "BCD store"),
+        }
+    }
+}
+
+/// A struct representing one change of the boot configuration
+/// Fields:
+/// * `target` - What was changed
+/// * `old` - The value before the command, None if it did not exist
+/// * `new` - The value after the command, None if it was deleted
+#[derive(Clone, Debug)]
+pub(crate) struct Change {
+    pub target: ChangeTarget,
+    pub old: Option<Vec<u8>>,
+    pub new: Option<Vec<u8>>,
+}
+
+/// A struct representing the changes one bootmgr command made
+/// Fields:
+/// * `time` - When the command ran, in seconds since the Unix epoch
+/// * `user` - The user who ran the command
+/// * `command` - The command line
+/// * `changes` - The changes, each target once
+#[derive(Clone, Debug)]
+pub(crate) struct JournalRecord {
+    pub time: u64,
+    pub user: String,
+    pub command: String,
+    pub changes: Vec<Change>,
+}
+
+impl JournalRecord {
+    /// Parse the records of a journal file
+    ///
+    /// A record is a `record <version> <time> <user>` line, a `command` line,
+    /// one `change <old length> <new length> <target>` line per change, each
+    /// followed by the old and new data with a newline after each, and an
+    /// `end` line. A missing value has the length `-`.
+    /// # Returns
+    /// * `Result<Vec<Self>>` - The records, oldest first
+    pub fn parse_all(data: &[u8]) -> Result<Vec<Self>> {
+        let invalid =
+            |msg: &str| Error::new(ErrorKind::InvalidData, format!("Invalid journal: {}", msg));
+        let next_line = |pos: &mut usize| -> Result<String> {
+            let rest = &data[*pos..];
+            let end = rest
+                .iter()
+                .position(|&b| b == b'\n')
+                .ok_or(invalid("unexpected end of file"))?;
+            *pos += end + 1;
+            Ok(String::from_utf8_lossy(&rest[..end]).to_string())
+        };
+        let next_data = |pos: &mut usize, len: &str| -> Result<Option<Vec<u8>>> {
+            if len == "-" {
+                return Ok(None);
+            }
+            let len = len
+                .parse::<usize>()
+                .map_err(|_| invalid(&format!("bad length '{}'", len)))?;
+            if data.len() < *pos + len + 1 || data[*pos + len] != b'\n' {
+                return Err(invalid("truncated change"));
+            }
+            let value = data[*pos..*pos + len].to_vec();
+            *pos += len + 1;
+            Ok(Some(value))
+        };
+
+        let mut records = Vec::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            let header = next_line(&mut pos)?;
+            let mut fields = header.splitn(4, ' ');
+            let (Some("record"), Some(version), Some(time), Some(user)) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                return Err(invalid(&format!("unexpected line '{}'", header)));
+            };
+            let version = version
+                .parse::<u32>()
+                .map_err(|_| invalid("bad record version"))?;
+            if version > JOURNAL_VERSION {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!(
+                        "Journal record version {} is newer than the supported version {}",
+                        version, JOURNAL_VERSION
+                    ),
+                ));
+            }
+            let time = time
+                .parse::<u64>()
+                .map_err(|_| invalid("bad record time"))?;
+            let command = next_line(&mut pos)?
+                .strip_prefix("command ")
+                .ok_or(invalid("missing command"))?
+                .to_string();
+
+            let mut changes = Vec::new();
+            loop {
+                let line = next_line(&mut pos)?;
+                if line == "end" {
+                    break;
+                }
+                let mut fields = line.splitn(4, ' ');
+                let (Some("change"), Some(old_len), Some(new_len), Some(target)) =
+                    (fields.next(), fields.next(), fields.next(), fields.next())
+                else {
+                    return Err(invalid(&format!("unexpected line '{}'", line)));
+                };
+                let target = ChangeTarget::parse(target)
+                    .ok_or(invalid(&format!("unknown target '{}'", target)))?;
+                let old = next_data(&mut pos, old_len)?;
+                let new = next_data(&mut pos, new_len)?;
+                changes.push(Change { target, old, new });
+            }
+            records.push(JournalRecord {
+                time,
+                user: user.to_string(),
+                command,
+                changes,
+            });
+        }
+        Ok(records)
+    }
+
+    /// Serialize the record as read by `parse_all`
+    ///
+    /// Newlines in the command line, e.g. in an argument, are written as `\n`
+    /// so the record stays readable.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let len = |value: &Option<Vec<u8>>| match value {
+            Some(value) => value.len().to_string(),
+            None => "-".to_string(),
+        };
+        let mut ret = format!(
+            "record {} {} {}\ncommand {}\n",
+            JOURNAL_VERSION,
+            self.time,
+            self.user,
+            self.command.replace('\n', "\\n")
+        )
+        .into_bytes();
+        for change in &self.changes {
+            ret.extend_from_slice(
+                format!(
+                    "change {} {} {}\n",
+                    len(&change.old),
+                    len(&change.new),
+                    change.target.to_line()
+                )
+                .as_bytes(),
+            );
+            for value in [&change.old, &change.new].into_iter().flatten() {
+                ret.extend_from_slice(value);
+                ret.push(b'\n');
+            }
+        }
+        ret.extend_from_slice(b"end\n");
+        ret
+    }
+}
+
+/// Start recording the changes of the running command
+pub(crate) fn begin() {
+    *SESSION.lock().unwrap() = Some(Vec::new());
+}
+
+/// Stop recording and get the changes made since `begin`
+/// # Returns
+/// * `Vec<Change>` - The changes that left a value different from before
+pub(crate) fn finish() -> Vec<Change> {
+    SESSION
+        .lock()
+        .unwrap()
+        .take()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|c| c.old != c.new)
+        .collect()
+}
+
+/// Get the changes recorded so far, without stopping
+/// # Returns
+/// * `Vec<Change>` - The changes since `begin`, empty while not recording
+pub(crate) fn changes() -> Vec<Change> {
+    SESSION.lock().unwrap().clone().unwrap_or_default()
+}
+
+/// Put back what was recorded for a target after its change was rolled back
+/// # Arguments
+/// * `target` - The target rolled back
+/// * `earlier` - The change recorded before the rolled back one, None to forget the target
+pub(crate) fn reset(target: &ChangeTarget, earlier: Option<&Change>) {
+    if let Some(changes) = SESSION.lock().unwrap().as_mut() {
+        changes.retain(|c| c.target != *target);
+        changes.extend(earlier.cloned());
+    }
+}
+
+/// Make a change and record its old and new value
+///
+/// Nothing is recorded outside of `begin` and `finish` or in dry-run mode. A
+/// target changed more than once keeps its first old value. A failed change
+/// is recorded too, with the value read back after it.
+/// # Arguments
+/// * `target` - What is changed
+/// * `read` - Reads the current value, None if it does not exist
+/// * `change` - Makes the change
+pub(crate) fn track<T>(
+    target: ChangeTarget,
+    read: impl Fn() -> Result<Option<Vec<u8>>>,
+    change: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    track_with(target, &read, &read, change)
+}
+
+/// Make a change and record its old and new value, read in different ways
+/// # Arguments
+/// * `target` - What is changed
+/// * `read_old` - Reads the value to restore on undo
+/// * `read_new` - Reads the value to compare with before undo
+/// * `change` - Makes the change
+pub(crate) fn track_with<T>(
+    target: ChangeTarget,
+    read_old: impl Fn() -> Result<Option<Vec<u8>>>,
+    read_new: impl Fn() -> Result<Option<Vec<u8>>>,
+    change: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    let recording = SESSION.lock().unwrap().is_some();
+    if !recording || dry_run::is_enabled() {
+        return change();
+    }
+    let known = SESSION
+        .lock()
+        .unwrap()
+        .iter()
+        .flatten()
+        .any(|c| c.target == target);
+    let old = match known {
+        true => None,
+        false => Some(read_old()?),
+    };
+    // A change failing after part of it was written is recorded as well, so
+    // it can be rolled back
+    let ret = change();
+    let new = match read_new() {
+        Ok(new) => new,
+        Err(e) => return ret.and(Err(e)),
+    };
+
+    let mut session = SESSION.lock().unwrap();
+    let changes = session.get_or_insert_with(Vec::new);
+    match changes.iter_mut().find(|c| c.target == target) {
+        Some(c) => c.new = new,
+        None => changes.push(Change {
+            target,
+            old: old.unwrap_or_default(),
+            new,
+        }),
+    }
+    ret
+}
+
+/// Read a file for `track`
+/// # Returns
+/// * `Result<Option<Vec<u8>>>` - The content, None if the file does not exist
+pub(crate) fn read_file(path: &std::path::Path) -> Result<Option<Vec<u8>>> {
+    match std::fs::read(path) {
+        Ok(data) => Ok(Some(data)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::fake::lock_session;
+
+    /// A record touching every kind of target once
+    fn one_change_per_target() -> JournalRecord {
+        JournalRecord {
+            time: 1760000000,
+            user: "alice".to_string(),
+            command: "bootmgr set --grub latest".to_string(),
+            changes: vec![
+                Change {
+                    target: ChangeTarget::GrubFile("grub/grubenv".to_string()),
+                    old: Some(b"saved_entry=0\n###\n".to_vec()),
+                    new: Some(b"saved_entry=latest\n#\n".to_vec()),
+                },
+                Change {
+                    target: ChangeTarget::EfiVar("BootNext".to_string()),
+                    old: None,
+                    new: Some(vec![0x01, 0x00, b'\n', 0xff]),
+                },
+                Change {
+                    target: ChangeTarget::EspFile(
+                        "systemd-boot".to_string(),
+                        "loader/loader.conf".to_string(),
+                    ),
+                    old: Some(Vec::new()),
+                    new: None,
+                },
+                Change {
+                    target: ChangeTarget::File(PathBuf::from("/etc/default/grub")),
+                    old: Some(b"GRUB_DEFAULT=0".to_vec()),
+                    new: Some(b"GRUB_DEFAULT=saved".to_vec()),
+                },
+                Change {
+                    target: ChangeTarget::Bcd,
+                    old: Some(b"export".to_vec()),
+                    new: Some(b"listing".to_vec()),
+                },
+            ],
+        }
+    }
+
+    fn assert_same(a: &JournalRecord, b: &JournalRecord) {
+        assert_eq!((a.time, &a.user, &a.command), (b.time, &b.user, &b.command));
+        assert_eq!(a.changes.len(), b.changes.len());
+        for (a, b) in a.changes.iter().zip(&b.changes) {
+            assert_eq!((&a.target, &a.old, &a.new), (&b.target, &b.old, &b.new));
+        }
+    }
+
+    #[test]
+    fn appends_records_one_after_another() {
+        let first = one_change_per_target();
+        let mut second = one_change_per_target();
+        second.time += 60;
+        second.user = "bob smith".to_string();
+        second.changes.truncate(1);
+        let mut data = first.to_bytes();
+        data.extend(second.to_bytes());
+
+        let records = JournalRecord::parse_all(&data).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_same(&records[0], &first);
+        assert_same(&records[1], &second);
+        assert!(JournalRecord::parse_all(b"").unwrap().is_empty());
+    }
+
+    #[test]
+    fn marks_missing_values_with_a_dash() {
+        let mut record = one_change_per_target();
+        record.changes.drain(..1);
+        record.changes.truncate(2);
+        assert_eq!(
+            String::from_utf8_lossy(&record.to_bytes()),
+            "record 1 1760000000 alice\n\
+             command bootmgr set --grub latest\n\
+             change - 4 efi BootNext\n\
+             \u{1}\u{0}\n\u{fffd}\n\
+             change 0 - esp systemd-boot loader/loader.conf\n\
+             \n\
+             end\n"
+        );
+        let records = JournalRecord::parse_all(&record.to_bytes()).unwrap();
+        assert_same(&records[0], &record);
+    }
+
+    #[test]
+    fn escapes_newlines_in_the_command() {
+        let mut record = one_change_per_target();
+        record.changes.clear();
+        record.command = "bootmgr set --grub \"a\nb\"".to_string();
+        assert_eq!(
+            String::from_utf8_lossy(&record.to_bytes()),
+            "record 1 1760000000 alice\n\
+             command bootmgr set --grub \"a\\nb\"\n\
+             end\n"
+        );
+        // A newline in an argument does not break the record
+        let records = JournalRecord::parse_all(&record.to_bytes()).unwrap();
+        assert_eq!(records[0].command, "bootmgr set --grub \"a\\nb\"");
+    }
+
+    #[test]
+    fn rejects_truncated_and_newer_records() {
+        let data = one_change_per_target().to_bytes();
+        let error = |data: &[u8]| JournalRecord::parse_all(data).unwrap_err();
+
+        assert_eq!(
+            error(&data[..data.len() - 1]).to_string(),
+            "Invalid journal: unexpected end of file"
+        );
+        let cut = data.len() - b"listing\nend\n".len() + 3;
+        assert_eq!(
+            error(&data[..cut]).to_string(),
+            "Invalid journal: truncated change"
+        );
+        assert_eq!(
+            error(b"hello\n").to_string(),
+            "Invalid journal: unexpected line 'hello'"
+        );
+        assert_eq!(
+            error(b"record 1 0 root\ncommand x\nchange 1 - disk sda\nx\nend\n").to_string(),
+            "Invalid journal: unknown target 'disk sda'"
+        );
+        assert_eq!(
+            error(b"record 1 0 root\ncommand x\nchange 9x - efi Timeout\nend\n").to_string(),
+            "Invalid journal: bad length '9x'"
+        );
+        let newer = error(b"record 2 0 root\ncommand x\nend\n");
+        assert_eq!(newer.kind(), ErrorKind::Unsupported);
+        assert_eq!(
+            newer.to_string(),
+            "Journal record version 2 is newer than the supported version 1"
+        );
+    }
+
+    #[test]
+    fn records_each_target_once() {
+        let _session = lock_session();
+        let value = std::cell::RefCell::new(Some(b"1".to_vec()));
+        let target = || ChangeTarget::EfiVar("Timeout".to_string());
+        let set = |v: Option<&[u8]>| {
+            track(
+                target(),
+                || Ok(value.borrow().clone()),
+                || {
+                    *value.borrow_mut() = v.map(<[u8]>::to_vec);
+                    Ok(())
+                },
+            )
+        };
+
+        // Not recorded outside of a session
+        set(Some(b"2")).unwrap();
+        assert!(finish().is_empty());
+
+        begin();
+        set(Some(b"3")).unwrap();
+        set(None).unwrap();
+        let changes = changes();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].old.as_deref(), Some(&b"2"[..]));
+        assert_eq!(changes[0].new, None);
+        // A value put back is no change
+        set(Some(b"2")).unwrap();
+        assert!(finish().is_empty());
+    }
+
+    #[test]
+    fn records_a_change_failing_after_it_wrote() {
+        let _session = lock_session();
+        let value = std::cell::RefCell::new(Some(b"old".to_vec()));
+        begin();
+        let result: Result<()> = track(
+            ChangeTarget::File(PathBuf::from("/boot/loader.conf")),
+            || Ok(value.borrow().clone()),
+            || {
+                *value.borrow_mut() = Some(b"torn".to_vec());
+                Err(Error::other("read back a different content"))
+            },
+        );
+        let changes = finish();
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "read back a different content"
+        );
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].old.as_deref(), Some(&b"old"[..]));
+        assert_eq!(changes[0].new.as_deref(), Some(&b"torn"[..]));
+    }
+}
@@ -1 +1,21 @@
+pub(crate) mod backup_archive;
+pub(crate) mod config;
+pub(crate) mod default_grub;
+pub(crate) mod desired_state;
+pub(crate) mod dry_run;
+pub(crate) mod extlinux;
 pub(crate) mod file_operations;
+pub(crate) mod grub_cfg;
+pub(crate) mod grub_helper;
+pub(crate) mod grub_menu;
+pub(crate) mod grub_script;
+pub(crate) mod grub_selector;
+pub(crate) mod grubenv;
+pub(crate) mod journal;
+pub(crate) mod output;
+pub(crate) mod prompt;
+pub(crate) mod refind;
+pub(crate) mod selector;
+pub(crate) mod systemd_boot;
+pub(crate) mod timestamp;
+pub(crate) mod toml;
@@ -0,0 +1,99 @@
+use crate::bootloader::BootEntry;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// The ways `list` and `set` print their results
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl OutputFormat {
+    /// The name of the format, as accepted by `--format` and the configuration
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(OutputFormat::Text),
+            "json" => Some(OutputFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Use an output format for the rest of the process
+pub(crate) fn set_format(format: OutputFormat) {
+    JSON.store(format == OutputFormat::Json, Ordering::Relaxed);
+}
+
+pub(crate) fn is_json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// Print a note about a change being made
+///
+/// With JSON output the note goes to stderr, so stdout holds only the JSON.
+pub(crate) fn note(msg: &str) {
+    match is_json() {
+        true => eprintln!("{}", msg),
+        false => println!("{}", msg),
+    }
+}
+
+/// Quote a string as a JSON string
+pub(crate) fn json_string(s: &str) -> String {
+    let mut ret = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            '\n' => ret.push_str("\\n"),
+            '\r' => ret.push_str("\\r"),
+            '\t' => ret.push_str("\\t"),
+            c if (c as u32) < 0x20 => ret.push_str(&format!("\\u{:04x}", c as u32)),
+            c => ret.push(c),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+/// Make a JSON array of strings
+pub(crate) fn json_array(items: &[String]) -> String {
+    let quoted: Vec<String> = items.iter().map(|i| json_string(i)).collect();
+    format!("[{}]", quoted.join(","))
+}
+
+/// Make the JSON object of a boot menu
+/// # Arguments
+/// * `loader` - The name of the boot loader or firmware
+/// * `entries` - The entries of the menu
+/// * `aliases` - The configured aliases with the id each one resolves to
+pub(crate) fn menu_json(
+    loader: &str,
+    entries: &[BootEntry],
+    aliases: &[(String, String)],
+) -> String {
+    let entries: Vec<String> = entries
+        .iter()
+        .map(|e| {
+            format!(
+                "{{\"id\":{},\"title\":{},\"default\":{},\"oneshot\":{}}}",
+                json_string(&e.id),
+                json_string(&e.title),
+                e.is_default,
+                e.is_oneshot
+            )
+        })
+        .collect();
+    let aliases: Vec<String> = aliases
+        .iter()
+        .map(|(name, id)| format!("{}:{}", json_string(name), json_string(id)))
+        .collect();
+    format!(
+        "{{\"loader\":{},\"entries\":[{}],\"aliases\":{{{}}}}}",
+        json_string(loader),
+        entries.join(","),
+        aliases.join(",")
+    )
+}
@@ -0,0 +1,14 @@
+use std::io::{BufRead, Result, Write};
+
+/// Ask a yes/no question on the terminal
+/// # Arguments
+/// * `question` - The question, shown followed by `[y/N]`
+/// # Returns
+/// * `Result<bool>` - Whether the answer was yes, end of input counts as no
+pub(crate) fn confirm(question: &str) -> Result<bool> {
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
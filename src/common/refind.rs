@@ -0,0 +1,446 @@
+use crate::common::selector::Selectable;
+
+/// The directories of the ESP rEFInd is installed to, holding refind.conf
+///
+/// Macs boot rEFInd as the fallback loader of `EFI/BOOT` when it is not
+/// blessed through its own firmware entry.
+pub(crate) const REFIND_DIRS: [&str; 2] = ["EFI/refind", "EFI/BOOT"];
+/// The name of the rEFInd configuration file
+pub(crate) const REFIND_CONF: &str = "refind.conf";
+
+/// A struct representing a manual boot stanza of rEFInd
+/// Fields:
+/// * `entry_title` - The title given to `menuentry`
+/// * `entry_loader` - The EFI program or kernel started by `loader`
+/// * `entry_initrd` - The image loaded by `initrd`
+/// * `entry_options` - The options passed to the loader
+/// * `entry_volume` - The volume holding the loader, by label, GUID or number
+/// * `entry_ostype` - The `ostype` deciding the icon and options of the entry
+/// * `entry_submenus` - The titles of the `submenuentry` stanzas
+/// * `entry_file` - The configuration file defining the entry
+/// * `entry_is_default` - Whether the entry is picked by `default_selection`
+#[derive(Clone, Debug, Default)]
+pub struct RefindEntry {
+    pub entry_title: String,
+    pub entry_loader: Option<String>,
+    pub entry_initrd: Option<String>,
+    pub entry_options: Option<String>,
+    pub entry_volume: Option<String>,
+    pub entry_ostype: Option<String>,
+    pub entry_submenus: Vec<String>,
+    pub entry_file: String,
+    pub entry_is_default: bool,
+}
+
+impl Selectable for RefindEntry {
+    fn id(&self) -> &str {
+        self.entry_loader.as_deref().unwrap_or(&self.entry_title)
+    }
+
+    fn title(&self) -> &str {
+        &self.entry_title
+    }
+}
+
+/// A struct representing one rEFInd configuration file, keeping comments on edits
+/// Fields:
+/// * `name` - The file name, relative to the directory of refind.conf
+/// * `lines` - The lines of the file
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RefindConf {
+    pub name: String,
+    lines: Vec<String>,
+}
+
+impl RefindConf {
+    pub fn parse(name: &str, content: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            lines: content.lines().map(str::to_string).collect(),
+        }
+    }
+
+    /// Get the files named by the `include` tokens outside of stanzas
+    pub fn includes(&self) -> Vec<String> {
+        let mut depth = 0;
+        let mut ret = Vec::new();
+        for tokens in self.lines.iter().map(|l| tokenize(l)) {
+            if depth == 0 && tokens.len() > 1 && tokens[0] == "include" {
+                ret.push(tokens[1].clone());
+            }
+            depth = next_depth(depth, &tokens);
+        }
+        ret
+    }
+
+    pub fn to_content(&self) -> String {
+        let mut ret = self.lines.join("\n");
+        ret.push('\n');
+        ret
+    }
+}
+
+/// A struct representing refind.conf together with the files it includes
+///
+/// Like rEFInd, only refind.conf may include other files, and a setting in
+/// an included file is overridden by the lines after its `include`.
+/// Fields:
+/// * `files` - refind.conf followed by the included files
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RefindConfig {
+    pub files: Vec<RefindConf>,
+}
+
+impl RefindConfig {
+    /// Get the manual boot stanzas in menu order
+    pub fn entries(&self) -> Vec<RefindEntry> {
+        let mut entries: Vec<RefindEntry> = Vec::new();
+        let mut depth = 0;
+        let mut current: Option<RefindEntry> = None;
+        let mut disabled = false;
+        for (file, line) in self.walk() {
+            let conf = &self.files[file];
+            let tokens = tokenize(&conf.lines[line]);
+            let Some(key) = tokens.first() else {
+                continue;
+            };
+            let value = tokens.get(1).cloned();
+            match (depth, key.as_str()) {
+                (0, "menuentry") => {
+                    current = Some(RefindEntry {
+                        entry_title: value.unwrap_or_default(),
+                        entry_file: conf.name.clone(),
+                        ..RefindEntry::default()
+                    });
+                    disabled = false;
+                }
+                (1, "submenuentry") => {
+                    if let Some(entry) = current.as_mut() {
+                        entry.entry_submenus.push(value.unwrap_or_default());
+                    }
+                }
+                (1, "loader") => set_field(&mut current, |e| &mut e.entry_loader, value),
+                (1, "initrd") => set_field(&mut current, |e| &mut e.entry_initrd, value),
+                (1, "options") => set_field(&mut current, |e| &mut e.entry_options, value),
+                (1, "volume") => set_field(&mut current, |e| &mut e.entry_volume, value),
+                (1, "ostype") => set_field(&mut current, |e| &mut e.entry_ostype, value),
+                (1, "disabled") => disabled = true,
+                _ => {}
+            }
+            depth = next_depth(depth, &tokens);
+            if depth == 0
+                && key == "}"
+                && let Some(entry) = current.take()
+                && !disabled
+            {
+                entries.push(entry);
+            }
+        }
+
+        if let Some(default) = self.get("default_selection")
+            && let Some(index) = find_default(&entries, &default)
+        {
+            entries[index].entry_is_default = true;
+        }
+        entries
+    }
+
+    /// Get the tokens of the effective setting
+    /// # Arguments
+    /// * `key` - The name of the setting, e.g. `timeout`
+    /// # Returns
+    /// * `Option<Vec<String>>` - The values of the last assignment, None if it is not set
+    pub fn get(&self, key: &str) -> Option<Vec<String>> {
+        let (file, line) = self.find(key)?;
+        Some(tokenize(&self.files[file].lines[line]).split_off(1))
+    }
+
+    /// Change a setting where it takes effect, keeping the rest of the files
+    ///
+    /// A new setting goes after its commented out example in refind.conf if
+    /// there is one, at the end of refind.conf otherwise.
+    /// # Arguments
+    /// * `key` - The name of the setting
+    /// * `value` - The value, quoted as needed
+    /// # Returns
+    /// * `usize` - The index of the changed file
+    pub fn set(&mut self, key: &str, value: &str) -> usize {
+        let line = format!("{} {}", key, value);
+        if let Some((file, pos)) = self.find(key) {
+            let lines = &mut self.files[file].lines;
+            let indent: String = lines[pos]
+                .chars()
+                .take_while(|c| c.is_whitespace())
+                .collect();
+            lines[pos] = format!("{}{}", indent, line);
+            return file;
+        }
+        let lines = &mut self.files[0].lines;
+        match lines.iter().rposition(|l| {
+            let l = l.trim_start().trim_start_matches('#');
+            tokenize(l).first().is_some_and(|k| k == key)
+        }) {
+            Some(pos) => lines.insert(pos + 1, line),
+            None => lines.push(line),
+        }
+        0
+    }
+
+    /// Find the line of the last top level assignment of a setting
+    fn find(&self, key: &str) -> Option<(usize, usize)> {
+        let mut depth = 0;
+        let mut ret = None;
+        for (file, line) in self.walk() {
+            let tokens = tokenize(&self.files[file].lines[line]);
+            if depth == 0 && tokens.first().is_some_and(|k| k == key) {
+                ret = Some((file, line));
+            }
+            depth = next_depth(depth, &tokens);
+        }
+        ret
+    }
+
+    /// List the lines of all files in the order rEFInd reads them
+    fn walk(&self) -> Vec<(usize, usize)> {
+        let mut ret = Vec::new();
+        let Some(main) = self.files.first() else {
+            return ret;
+        };
+        let mut depth = 0;
+        for (i, line) in main.lines.iter().enumerate() {
+            ret.push((0, i));
+            let tokens = tokenize(line);
+            if depth == 0
+                && tokens.len() > 1
+                && tokens[0] == "include"
+                && let Some(file) = self.files.iter().skip(1).position(|f| f.name == tokens[1])
+            {
+                let file = file + 1;
+                ret.extend((0..self.files[file].lines.len()).map(|l| (file, l)));
+            }
+            depth = next_depth(depth, &tokens);
+        }
+        ret
+    }
+}
+
+/// Quote a value of refind.conf unless it is a single token
+pub(crate) fn quote(value: &str) -> String {
+    match tokenize(value) == [value] {
+        true => value.to_string(),
+        false => format!("\"{}\"", value),
+    }
+}
+
+/// Find the entry picked by the values of `default_selection`
+///
+/// Each value, and each part of a quoted list like `"+,vmlinuz"`, is tried in
+/// turn as a case-insensitive substring of the title or of the loader file
+/// name. Times of day and menu positions, which count the scanned entries
+/// too, are skipped.
+fn find_default(entries: &[RefindEntry], default: &[String]) -> Option<usize> {
+    default
+        .iter()
+        .flat_map(|v| v.split(','))
+        .filter(|v| !v.is_empty() && !v.contains(':') && v.parse::<usize>().is_err() && *v != "+")
+        .map(|v| v.to_lowercase())
+        .find_map(|value| {
+            entries.iter().position(|e| {
+                let loader = e.entry_loader.as_deref().unwrap_or_default();
+                let loader = loader.rsplit(['/', '\\']).next().unwrap_or_default();
+                e.entry_title.to_lowercase().contains(&value)
+                    || loader.to_lowercase().contains(&value)
+            })
+        })
+}
+
+fn set_field(
+    entry: &mut Option<RefindEntry>,
+    field: fn(&mut RefindEntry) -> &mut Option<String>,
+    value: Option<String>,
+) {
+    if let Some(entry) = entry.as_mut() {
+        *field(entry) = value;
+    }
+}
+
+/// Track the nesting of stanzas across a line
+fn next_depth(depth: usize, tokens: &[String]) -> usize {
+    match tokens.last().map(String::as_str) {
+        Some("{") => depth + 1,
+        _ if tokens.first().is_some_and(|t| t == "}") => depth.saturating_sub(1),
+        _ => depth,
+    }
+}
+
+/// Split a line like rEFInd does
+///
+/// Tokens are separated by whitespace, `=` and `,`, double quotes group a
+/// token and `#` starts a comment outside of quotes. Braces are tokens of
+/// their own.
+fn tokenize(line: &str) -> Vec<String> {
+    let mut ret = Vec::new();
+    let mut token = String::new();
+    let mut quoted = false;
+    let mut in_quotes = false;
+    for c in line.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                quoted = true;
+            }
+            _ if in_quotes => token.push(c),
+            '#' => break,
+            '{' | '}' => {
+                if !token.is_empty() || quoted {
+                    ret.push(std::mem::take(&mut token));
+                }
+                quoted = false;
+                ret.push(c.to_string());
+            }
+            c if c.is_whitespace() || c == '=' || c == ',' => {
+                if !token.is_empty() || quoted {
+                    ret.push(std::mem::take(&mut token));
+                }
+                quoted = false;
+            }
+            c => token.push(c),
+        }
+    }
+    if !token.is_empty() || quoted {
+        ret.push(token);
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAIN: &str = r#"timeout 20
+#default_selection 1
+include themes/theme.conf
+
+menuentry "Arch Linux" {
+    icon     /EFI/refind/icons/os_arch.png
+    volume   "Arch Linux"
+    loader   /boot/vmlinuz-linux
+    initrd   /boot/initramfs-linux.img
+    options  "root=PARTUUID=5028fa50 rw add_efi_memmap"
+    submenuentry "Boot using fallback initramfs" {
+        initrd /boot/initramfs-linux-fallback.img
+    }
+}
+
+menuentry "Old kernel" {
+    loader /boot/vmlinuz-old
+    disabled
+}
+
+menuentry Windows {
+    loader \EFI\Microsoft\Boot\bootmgfw.efi
+    ostype Windows
+}
+"#;
+
+    fn config(theme: &str) -> RefindConfig {
+        RefindConfig {
+            files: vec![
+                RefindConf::parse(REFIND_CONF, MAIN),
+                RefindConf::parse("themes/theme.conf", theme),
+            ],
+        }
+    }
+
+    #[test]
+    fn tokenizes_like_refind() {
+        assert_eq!(
+            tokenize(r#"  options "root=/dev/sda2 ro", quiet # comment"#),
+            ["options", "root=/dev/sda2 ro", "quiet"]
+        );
+        assert_eq!(tokenize("timeout=5"), ["timeout", "5"]);
+        assert_eq!(tokenize(r#"menuentry "" {"#), ["menuentry", "", "{"]);
+        assert!(tokenize("# only a comment").is_empty());
+        assert_eq!(quote("Windows"), "Windows");
+        assert_eq!(quote("Arch Linux"), "\"Arch Linux\"");
+    }
+
+    #[test]
+    fn parses_stanzas() {
+        let config = config("");
+        assert_eq!(config.files[0].includes(), ["themes/theme.conf"]);
+        let entries = config.entries();
+        let titles: Vec<&str> = entries.iter().map(|e| e.title()).collect();
+        assert_eq!(titles, ["Arch Linux", "Windows"]);
+
+        let arch = &entries[0];
+        assert_eq!(arch.id(), "/boot/vmlinuz-linux");
+        assert_eq!(arch.entry_volume.as_deref(), Some("Arch Linux"));
+        assert_eq!(
+            arch.entry_options.as_deref(),
+            Some("root=PARTUUID=5028fa50 rw add_efi_memmap")
+        );
+        // The initrd of the submenu does not replace the one of the entry
+        assert_eq!(
+            arch.entry_initrd.as_deref(),
+            Some("/boot/initramfs-linux.img")
+        );
+        assert_eq!(arch.entry_submenus, ["Boot using fallback initramfs"]);
+        assert_eq!(entries[1].entry_ostype.as_deref(), Some("Windows"));
+        assert!(entries.iter().all(|e| !e.entry_is_default));
+    }
+
+    #[test]
+    fn finds_the_default_selection() {
+        let mut config = config("");
+        config.set("default_selection", "bootmgfw 23:30 2:00");
+        assert!(config.entries()[1].entry_is_default);
+        config.set("default_selection", "\"1,arch\"");
+        assert!(config.entries()[0].entry_is_default);
+        config.set("default_selection", "+");
+        assert!(config.entries().iter().all(|e| !e.entry_is_default));
+        config.set("default_selection", "+,vmlinuz");
+        assert!(config.entries()[0].entry_is_default);
+    }
+
+    #[test]
+    fn reads_settings_in_include_order() {
+        // The included file is read where it is included, after the timeout of refind.conf
+        let mut config = config("timeout 5\nbanner themes/banner.png\n");
+        assert_eq!(config.get("timeout"), Some(vec!["5".to_string()]));
+        assert_eq!(
+            config.get("banner"),
+            Some(vec!["themes/banner.png".to_string()])
+        );
+        assert_eq!(config.get("loader"), None);
+        // and overridden by the lines after the include
+        config.files[0].lines.insert(3, "timeout 10".to_string());
+        assert_eq!(config.get("timeout"), Some(vec!["10".to_string()]));
+    }
+
+    #[test]
+    fn changes_a_setting_in_the_include_defining_it() {
+        let mut config = config("banner themes/banner.png\n");
+        assert_eq!(config.set("banner", "none.png"), 1);
+        assert_eq!(config.files[1].to_content(), "banner none.png\n");
+    }
+
+    #[test]
+    fn adds_settings_after_their_commented_out_example() {
+        let mut config = config("banner themes/banner.png\n");
+        assert_eq!(config.set("default_selection", "Windows"), 0);
+        assert!(
+            config.files[0]
+                .to_content()
+                .starts_with("timeout 20\n#default_selection 1\ndefault_selection Windows\n")
+        );
+        // Settings without an example go at the end of refind.conf
+        config.set("scanfor", "manual");
+        assert!(
+            config.files[0]
+                .to_content()
+                .ends_with("}\nscanfor manual\n")
+        );
+        assert!(config.entries()[1].entry_is_default);
+    }
+}
@@ -0,0 +1,215 @@
+use regex::{Regex, RegexBuilder};
+use std::io::{Error, ErrorKind, Result};
+
+/// An entry that can be picked with a selector
+pub(crate) trait Selectable {
+    /// The identifier written to the boot configuration
+    fn id(&self) -> &str;
+
+    /// The human readable title of the entry
+    fn title(&self) -> &str;
+}
+
+/// Resolve a selector against a list of entries
+///
+/// A selector is tried as, in order:
+/// * an index into the list
+/// * a regular expression when prefixed with `re:`
+/// * a glob when prefixed with `glob:`
+/// * an exact identifier
+/// * an exact title
+/// * a glob when containing `*`, `?` or `[`
+/// * a case-insensitive substring of the identifier or title
+///
+/// # Arguments
+/// * `entries` - The entries to choose from
+/// * `selector` - The selector given by the user
+/// * `kind` - The kind of entries, used in error messages
+/// # Returns
+/// * `Result<&T>` - The only entry matching the selector
+pub(crate) fn select<'a, T: Selectable>(
+    entries: &'a [T],
+    selector: &str,
+    kind: &str,
+) -> Result<&'a T> {
+    if let Ok(index) = selector.parse::<usize>() {
+        return entries.get(index).ok_or(Error::new(
+            ErrorKind::NotFound,
+            format!("{} entry index {} out of range", kind, index),
+        ));
+    }
+
+    let pattern = if let Some(pattern) = selector.strip_prefix("re:") {
+        Some(build_regex(pattern)?)
+    } else if let Some(pattern) = selector.strip_prefix("glob:") {
+        Some(build_regex(&glob_to_regex(pattern))?)
+    } else {
+        None
+    };
+    if let Some(pattern) = pattern {
+        let matches = filter(entries, |e| {
+            pattern.is_match(e.id()) || pattern.is_match(e.title())
+        });
+        return unique(matches, selector, kind);
+    }
+
+    // Titles such as `Windows [safe mode]` are matched exactly before as a glob
+    let glob = match selector.contains(['*', '?', '[']) {
+        true => Some(build_regex(&glob_to_regex(selector))?),
+        false => None,
+    };
+    let lower = selector.to_lowercase();
+    let exact_id = |e: &T| e.id() == selector;
+    let exact_title = |e: &T| e.title() == selector;
+    let glob_match = |e: &T| {
+        glob.as_ref()
+            .is_some_and(|p| p.is_match(e.id()) || p.is_match(e.title()))
+    };
+    let substring =
+        |e: &T| e.id().to_lowercase().contains(&lower) || e.title().to_lowercase().contains(&lower);
+    let stages: [&dyn Fn(&T) -> bool; 4] = [&exact_id, &exact_title, &glob_match, &substring];
+    for stage in stages {
+        let matches = filter(entries, stage);
+        if !matches.is_empty() {
+            return unique(matches, selector, kind);
+        }
+    }
+    unique(Vec::new(), selector, kind)
+}
+
+fn filter<T>(entries: &[T], predicate: impl Fn(&T) -> bool) -> Vec<&T> {
+    entries.iter().filter(|e| predicate(e)).collect()
+}
+
+fn unique<'a, T: Selectable>(matches: Vec<&'a T>, selector: &str, kind: &str) -> Result<&'a T> {
+    match matches.as_slice() {
+        [entry] => Ok(entry),
+        [] => Err(Error::new(
+            ErrorKind::NotFound,
+            format!("No {} entry matches '{}'", kind, selector),
+        )),
+        _ => {
+            let candidates: Vec<String> = matches
+                .iter()
+                .map(|e| format!("  {} ({})", e.title(), e.id()))
+                .collect();
+            Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Selector '{}' matches more than one {} entry:\n{}",
+                    selector,
+                    kind,
+                    candidates.join("\n")
+                ),
+            ))
+        }
+    }
+}
+
+fn build_regex(pattern: &str) -> Result<Regex> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .build()
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))
+}
+
+/// Translate a shell style glob into an anchored regular expression
+pub(crate) fn glob_to_regex(glob: &str) -> String {
+    let mut ret = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => ret.push_str(".*"),
+            '?' => ret.push('.'),
+            '[' => {
+                ret.push('[');
+                if chars.peek() == Some(&'!') {
+                    chars.next();
+                    ret.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' {
+                        ret.push('\\');
+                    }
+                    ret.push(c);
+                }
+                ret.push(']');
+            }
+            c => ret.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    ret.push('$');
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Entry(&'static str, &'static str);
+
+    impl Selectable for Entry {
+        fn id(&self) -> &str {
+            self.0
+        }
+
+        fn title(&self) -> &str {
+            self.1
+        }
+    }
+
+    const ENTRIES: [Entry; 4] = [
+        Entry("Boot0000", "Windows Boot Manager"),
+        Entry("Boot0001", "Windows [safe mode]"),
+        Entry("Boot0002", "debian"),
+        Entry("Boot0003", "Debian rescue"),
+    ];
+
+    fn id(selector: &str) -> Result<&'static str> {
+        select(&ENTRIES, selector, "firmware").map(|e| e.0)
+    }
+
+    #[test]
+    fn selects_by_each_form() {
+        assert_eq!(id("2").unwrap(), "Boot0002");
+        assert_eq!(id("re:^deb.*cue$").unwrap(), "Boot0003");
+        assert_eq!(id("glob:*boot manager").unwrap(), "Boot0000");
+        assert_eq!(id("Boot0001").unwrap(), "Boot0001");
+        // An exact title wins over the substring matching both Debian entries
+        assert_eq!(id("debian").unwrap(), "Boot0002");
+        assert_eq!(id("RESCUE").unwrap(), "Boot0003");
+        assert_eq!(id("Boot000[3]").unwrap(), "Boot0003");
+    }
+
+    #[test]
+    fn matches_titles_with_glob_characters_exactly() {
+        assert_eq!(id("Windows [safe mode]").unwrap(), "Boot0001");
+        assert_eq!(
+            id("glob:Windows [safe mode]").unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn rejects_ambiguous_and_unknown_selectors() {
+        let err = id("windows").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert_eq!(
+            err.to_string(),
+            "Selector 'windows' matches more than one firmware entry:\n  \
+             Windows Boot Manager (Boot0000)\n  Windows [safe mode] (Boot0001)"
+        );
+        assert_eq!(
+            id("4").unwrap_err().to_string(),
+            "firmware entry index 4 out of range"
+        );
+        assert_eq!(
+            id("fedora").unwrap_err().to_string(),
+            "No firmware entry matches 'fedora'"
+        );
+        assert_eq!(id("re:(").unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+}
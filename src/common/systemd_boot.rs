@@ -0,0 +1,414 @@
+use crate::common::selector::{Selectable, glob_to_regex};
+use regex::Regex;
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{Read, Result, Seek, SeekFrom};
+
+/// The path of the systemd-boot settings on the ESP
+pub(crate) const LOADER_CONF_PATH: &str = "loader/loader.conf";
+/// The directory of Type #1 boot loader entries on the ESP
+pub(crate) const ENTRIES_DIR: &str = "loader/entries";
+/// The directory of Type #2 unified kernel images on the ESP
+pub(crate) const UKI_DIR: &str = "EFI/Linux";
+/// The vendor GUID of the EFI variables shared by systemd-boot and the OS
+pub(crate) const LOADER_VENDOR_GUID: &str = "4a67b082-0a4c-41cf-b6c7-440b29bb8c4f";
+/// The EFI variable overriding the `default` of loader.conf
+pub(crate) const LOADER_ENTRY_DEFAULT: &str = "LoaderEntryDefault";
+/// The EFI variable selecting the entry of the next boot only
+pub(crate) const LOADER_ENTRY_ONESHOT: &str = "LoaderEntryOneShot";
+
+/// A struct representing a systemd-boot entry
+/// Fields:
+/// * `entry_id` - The file name of the entry, e.g. `arch.conf` or `arch-linux.efi`
+/// * `entry_title` - The title shown in the menu
+/// * `entry_version` - The version of the kernel
+/// * `entry_linux` - The kernel loaded by a Type #1 entry
+/// * `entry_initrd` - The images loaded as initrd by a Type #1 entry
+/// * `entry_options` - The kernel command line
+/// * `entry_efi` - The EFI program started by a Type #1 entry
+/// * `entry_sort_key` - The key the menu is sorted by
+/// * `entry_machine_id` - The machine id of the installation, sorting entries of the same sort key
+/// * `entry_is_uki` - Whether the entry is a unified kernel image
+/// * `entry_is_default` - Whether the entry is the default entry
+/// * `entry_is_oneshot` - Whether the entry is booted on the next boot only
+#[derive(Clone, Debug, Default)]
+pub struct SdBootEntry {
+    pub entry_id: String,
+    pub entry_title: String,
+    pub entry_version: Option<String>,
+    pub entry_linux: Option<String>,
+    pub entry_initrd: Vec<String>,
+    pub entry_options: Option<String>,
+    pub entry_efi: Option<String>,
+    pub entry_sort_key: Option<String>,
+    pub entry_machine_id: Option<String>,
+    pub entry_is_uki: bool,
+    pub entry_is_default: bool,
+    pub entry_is_oneshot: bool,
+}
+
+impl Selectable for SdBootEntry {
+    fn id(&self) -> &str {
+        &self.entry_id
+    }
+
+    fn title(&self) -> &str {
+        &self.entry_title
+    }
+}
+
+impl SdBootEntry {
+    /// Parse a Type #1 boot loader entry
+    /// # Arguments
+    /// * `id` - The file name of the entry
+    /// * `content` - The content of the entry file
+    pub fn parse_conf(id: &str, content: &str) -> Self {
+        let mut entry = Self {
+            entry_id: id.to_string(),
+            ..Self::default()
+        };
+        let mut options = Vec::new();
+        for (key, value) in content.lines().filter_map(split_key_value) {
+            match key {
+                "title" => entry.entry_title = value.to_string(),
+                "version" => entry.entry_version = Some(value.to_string()),
+                "linux" => entry.entry_linux = Some(value.to_string()),
+                "initrd" => entry.entry_initrd.push(value.to_string()),
+                "options" => options.push(value.to_string()),
+                "efi" => entry.entry_efi = Some(value.to_string()),
+                "sort-key" => entry.entry_sort_key = Some(value.to_string()),
+                "machine-id" => entry.entry_machine_id = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        if !options.is_empty() {
+            entry.entry_options = Some(options.join(" "));
+        }
+        if entry.entry_title.is_empty() {
+            entry.entry_title = id.trim_end_matches(".conf").to_string();
+        }
+        entry
+    }
+
+    /// Parse a Type #2 unified kernel image from its PE sections
+    /// # Arguments
+    /// * `id` - The file name of the image
+    /// * `file` - The image
+    pub fn parse_uki(id: &str, file: &mut File) -> Result<Self> {
+        let sections = read_pe_sections(file, &[".osrel", ".cmdline", ".uname"])?;
+        let [osrel, cmdline, uname] = sections;
+        let osrel = osrel.unwrap_or_default();
+        let field = |key: &str| {
+            osrel
+                .lines()
+                .filter_map(|l| l.strip_prefix(key)?.strip_prefix('='))
+                .map(|v| v.trim().trim_matches('"').to_string())
+                .next()
+        };
+
+        let version = uname.or_else(|| field("VERSION_ID"));
+        let name = field("PRETTY_NAME")
+            .or_else(|| field("NAME"))
+            .unwrap_or(id.trim_end_matches(".efi").to_string());
+        Ok(Self {
+            entry_id: id.to_string(),
+            entry_title: match &version {
+                Some(version) => format!("{} ({})", name, version),
+                None => name,
+            },
+            entry_version: version,
+            entry_options: cmdline.map(|c| c.trim().to_string()),
+            entry_sort_key: field("IMAGE_ID").or_else(|| field("ID")),
+            entry_is_uki: true,
+            ..Self::default()
+        })
+    }
+}
+
+/// A struct representing loader/loader.conf, keeping comments on edits
+/// Fields:
+/// * `lines` - The lines of the file
+#[derive(Clone, Debug, Default)]
+pub(crate) struct LoaderConf {
+    lines: Vec<String>,
+}
+
+impl LoaderConf {
+    pub fn parse(content: &str) -> Self {
+        Self {
+            lines: content.lines().map(str::to_string).collect(),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.lines
+            .iter()
+            .filter_map(|l| split_key_value(l))
+            .filter(|(k, _)| *k == key)
+            .map(|(_, v)| v)
+            .next_back()
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) {
+        let line = format!("{} {}", key, value);
+        match self
+            .lines
+            .iter()
+            .rposition(|l| split_key_value(l).is_some_and(|(k, _)| k == key))
+        {
+            Some(pos) => self.lines[pos] = line,
+            None => self.lines.push(line),
+        }
+    }
+
+    pub fn to_content(&self) -> String {
+        let mut ret = self.lines.join("\n");
+        ret.push('\n');
+        ret
+    }
+}
+
+/// Compare two entries in the menu order of the Boot Loader Specification
+///
+/// Entries with a sort key come first, ordered by sort key, machine id and
+/// newest version. The others, and ties, follow by newest file name.
+pub(crate) fn compare_entries(a: &SdBootEntry, b: &SdBootEntry) -> Ordering {
+    let version = |e: &SdBootEntry| e.entry_version.clone().unwrap_or_default();
+    match (&a.entry_sort_key, &b.entry_sort_key) {
+        (Some(a_key), Some(b_key)) => a_key
+            .cmp(b_key)
+            .then_with(|| a.entry_machine_id.cmp(&b.entry_machine_id))
+            .then_with(|| compare_versions(&version(b), &version(a))),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+    .then_with(|| compare_versions(&b.entry_id, &a.entry_id))
+}
+
+/// Compare two versions like systemd's `strverscmp_improved`
+///
+/// Runs of digits compare as numbers and sort after letters, `~` sorts
+/// before anything, even the end of the version.
+pub(crate) fn compare_versions(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+    loop {
+        match (a.first(), b.first()) {
+            (Some(b'~'), Some(b'~')) => {}
+            (Some(b'~'), _) => return Ordering::Less,
+            (_, Some(b'~')) => return Ordering::Greater,
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let digits = |s: &[u8]| s.iter().take_while(|c| c.is_ascii_digit()).count();
+                let (a_len, b_len) = (digits(a), digits(b));
+                let trim = |s: &[u8]| -> Vec<u8> {
+                    s.iter().copied().skip_while(|c| *c == b'0').collect()
+                };
+                let (a_num, b_num) = (trim(&a[..a_len]), trim(&b[..b_len]));
+                let ord = a_num
+                    .len()
+                    .cmp(&b_num.len())
+                    .then_with(|| a_num.cmp(&b_num));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+                a = &a[a_len..];
+                b = &b[b_len..];
+                continue;
+            }
+            (Some(x), _) if x.is_ascii_digit() => return Ordering::Greater,
+            (_, Some(y)) if y.is_ascii_digit() => return Ordering::Less,
+            (Some(x), Some(y)) if x != y => return x.cmp(y),
+            _ => {}
+        }
+        a = &a[1..];
+        b = &b[1..];
+    }
+}
+
+/// Check whether a `default` pattern of loader.conf picks an entry
+pub(crate) fn default_matches(pattern: &str, id: &str) -> bool {
+    Regex::new(&glob_to_regex(pattern)).is_ok_and(|r| r.is_match(id))
+}
+
+/// Decode a systemd-boot EFI variable, a null terminated UTF-16 string
+pub(crate) fn decode_loader_var(data: &[u8]) -> String {
+    let chars: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|c| *c != 0)
+        .collect();
+    String::from_utf16_lossy(&chars)
+}
+
+/// Encode a systemd-boot EFI variable, a null terminated UTF-16 string
+pub(crate) fn encode_loader_var(value: &str) -> Vec<u8> {
+    value
+        .encode_utf16()
+        .chain(Some(0))
+        .flat_map(|c| c.to_le_bytes())
+        .collect()
+}
+
+fn split_key_value(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim();
+    if line.starts_with('#') {
+        return None;
+    }
+    let (key, value) = line.split_once(char::is_whitespace)?;
+    Some((key, value.trim()))
+}
+
+/// Read named sections of a PE image, without loading the whole image
+fn read_pe_sections<const N: usize>(
+    file: &mut File,
+    names: &[&str; N],
+) -> Result<[Option<String>; N]> {
+    let mut ret = [const { None }; N];
+    let read_at = |file: &mut File, offset: u64, len: usize| -> Result<Vec<u8>> {
+        let mut buf = vec![0u8; len];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut buf)?;
+        Ok(buf)
+    };
+    let u16_at = |b: &[u8], i: usize| u16::from_le_bytes([b[i], b[i + 1]]);
+    let u32_at = |b: &[u8], i: usize| u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]]);
+
+    let dos = read_at(file, 0, 0x40)?;
+    if &dos[..2] != b"MZ" {
+        return Ok(ret);
+    }
+    let pe = u32_at(&dos, 0x3c) as u64;
+    let coff = read_at(file, pe, 24)?;
+    if &coff[..4] != b"PE\0\0" {
+        return Ok(ret);
+    }
+    let count = u16_at(&coff, 6) as usize;
+    let optional_size = u16_at(&coff, 20) as u64;
+    let table = read_at(file, pe + 24 + optional_size, count * 40)?;
+
+    for header in table.chunks_exact(40) {
+        let name = String::from_utf8_lossy(&header[..8]);
+        let name = name.trim_end_matches('\0');
+        let Some(index) = names.iter().position(|n| *n == name) else {
+            continue;
+        };
+        let size = u32_at(header, 8).min(u32_at(header, 16)) as usize;
+        let data = read_at(file, u32_at(header, 20) as u64, size)?;
+        ret[index] = Some(
+            String::from_utf8_lossy(&data)
+                .trim_end_matches('\0')
+                .to_string(),
+        );
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: &str, sort_key: Option<&str>, version: Option<&str>) -> SdBootEntry {
+        SdBootEntry {
+            entry_id: id.to_string(),
+            entry_sort_key: sort_key.map(str::to_string),
+            entry_version: version.map(str::to_string),
+            ..SdBootEntry::default()
+        }
+    }
+
+    #[test]
+    fn parses_type1_entry() {
+        let entry = SdBootEntry::parse_conf(
+            "arch-6.8.conf",
+            "# Arch\ntitle   Arch Linux\nversion 6.8.1-arch1-1\nsort-key arch\n\
+             machine-id 0123\nlinux /vmlinuz-linux\ninitrd /amd-ucode.img\n\
+             initrd /initramfs-linux.img\noptions root=UUID=1234\noptions rw quiet\n",
+        );
+        assert_eq!(entry.entry_title, "Arch Linux");
+        assert_eq!(entry.entry_version.as_deref(), Some("6.8.1-arch1-1"));
+        assert_eq!(entry.entry_sort_key.as_deref(), Some("arch"));
+        assert_eq!(entry.entry_machine_id.as_deref(), Some("0123"));
+        assert_eq!(entry.entry_linux.as_deref(), Some("/vmlinuz-linux"));
+        assert_eq!(
+            entry.entry_initrd,
+            ["/amd-ucode.img", "/initramfs-linux.img"]
+        );
+        assert_eq!(
+            entry.entry_options.as_deref(),
+            Some("root=UUID=1234 rw quiet")
+        );
+        assert!(!entry.entry_is_uki);
+    }
+
+    #[test]
+    fn titles_entries_by_file_name() {
+        let entry = SdBootEntry::parse_conf("windows.conf", "efi /EFI/Microsoft/bootmgfw.efi\n");
+        assert_eq!(entry.entry_title, "windows");
+        assert_eq!(
+            entry.entry_efi.as_deref(),
+            Some("/EFI/Microsoft/bootmgfw.efi")
+        );
+    }
+
+    #[test]
+    fn compares_versions() {
+        assert_eq!(compare_versions("6.10", "6.9"), Ordering::Greater);
+        assert_eq!(compare_versions("6.1.0", "6.1"), Ordering::Greater);
+        assert_eq!(compare_versions("6.1~rc1", "6.1"), Ordering::Less);
+        assert_eq!(compare_versions("007", "7"), Ordering::Equal);
+        assert_eq!(compare_versions("1a", "12"), Ordering::Less);
+        assert_eq!(compare_versions("abc", "abd"), Ordering::Less);
+    }
+
+    #[test]
+    fn sorts_entries_like_the_boot_loader_specification() {
+        let mut entries = [
+            entry("arch-6.8.conf", None, None),
+            entry("fedora-6.7.conf", Some("fedora"), Some("6.7.5")),
+            entry("arch-6.10.conf", None, None),
+            entry("fedora-6.9.conf", Some("fedora"), Some("6.9.2")),
+            entry("debian.conf", Some("debian"), Some("6.1.0")),
+        ];
+        entries.sort_by(compare_entries);
+        let ids: Vec<&str> = entries.iter().map(|e| e.entry_id.as_str()).collect();
+        assert_eq!(
+            ids,
+            [
+                "debian.conf",
+                "fedora-6.9.conf",
+                "fedora-6.7.conf",
+                "arch-6.10.conf",
+                "arch-6.8.conf",
+            ]
+        );
+    }
+
+    #[test]
+    fn matches_default_patterns() {
+        assert!(default_matches("arch-*", "arch-6.8.conf"));
+        assert!(default_matches("arch-6.8.conf", "arch-6.8.conf"));
+        assert!(!default_matches("arch-*", "fedora-6.9.conf"));
+    }
+
+    #[test]
+    fn edits_loader_conf_in_place() {
+        let mut conf = LoaderConf::parse("# menu\ntimeout 3\ndefault arch-*\ndefault fedora-*\n");
+        assert_eq!(conf.get("default"), Some("fedora-*"));
+        conf.set("default", "debian.conf");
+        conf.set("editor", "no");
+        assert_eq!(
+            conf.to_content(),
+            "# menu\ntimeout 3\ndefault arch-*\ndefault debian.conf\neditor no\n"
+        );
+    }
+
+    #[test]
+    fn encodes_loader_variables() {
+        let data = encode_loader_var("arch.conf");
+        assert_eq!(data.len(), 20);
+        assert_eq!(decode_loader_var(&data), "arch.conf");
+    }
+}
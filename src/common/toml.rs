@@ -0,0 +1,706 @@
+use std::io::{Error, ErrorKind, Result};
+
+/// A value of a TOML file
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum TomlValue {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<TomlValue>),
+}
+
+impl TomlValue {
+    fn type_name(&self) -> &'static str {
+        match self {
+            TomlValue::String(_) => "a string",
+            TomlValue::Integer(_) => "an integer",
+            TomlValue::Boolean(_) => "a boolean",
+            TomlValue::Array(_) => "an array",
+        }
+    }
+}
+
+/// A struct representing a table of a TOML file
+/// Fields:
+/// * `name` - The dotted name of the table as written in errors, quoting the parts that are not bare keys, empty for the keys before the first header
+/// * `path` - The parts of the name, e.g. `["profiles", "a.b"]` for `[profiles."a.b"]`
+/// * `entries` - The keys of the table with their values, in file order
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TomlTable {
+    pub name: String,
+    pub path: Vec<String>,
+    pub entries: Vec<(String, TomlValue)>,
+}
+
+impl TomlTable {
+    pub fn get(&self, key: &str) -> Option<&TomlValue> {
+        self.entries.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    /// Get a string value
+    /// # Returns
+    /// * `Result<Option<String>>` - The value, None if the key is missing, an error if it is not a string
+    pub fn get_str(&self, key: &str) -> Result<Option<String>> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(TomlValue::String(s)) => Ok(Some(s.clone())),
+            Some(value) => Err(self.type_error(key, "a string", value)),
+        }
+    }
+
+    /// Get an integer value
+    /// # Returns
+    /// * `Result<Option<i64>>` - The value, None if the key is missing, an error if it is not an integer
+    pub fn get_integer(&self, key: &str) -> Result<Option<i64>> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(TomlValue::Integer(i)) => Ok(Some(*i)),
+            Some(value) => Err(self.type_error(key, "an integer", value)),
+        }
+    }
+
+    /// Get a boolean value
+    /// # Returns
+    /// * `Result<Option<bool>>` - The value, None if the key is missing, an error if it is not a boolean
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(TomlValue::Boolean(b)) => Ok(Some(*b)),
+            Some(value) => Err(self.type_error(key, "a boolean", value)),
+        }
+    }
+
+    /// Get an array of strings
+    /// # Returns
+    /// * `Result<Option<Vec<String>>>` - The strings, None if the key is missing, an error if it is not an array of strings
+    pub fn get_str_array(&self, key: &str) -> Result<Option<Vec<String>>> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(TomlValue::Array(items)) => items
+                .iter()
+                .map(|item| match item {
+                    TomlValue::String(s) => Ok(s.clone()),
+                    _ => Err(self.type_error(key, "an array of strings", item)),
+                })
+                .collect::<Result<Vec<_>>>()
+                .map(Some),
+            Some(value) => Err(self.type_error(key, "an array of strings", value)),
+        }
+    }
+
+    /// Fail on keys other than the given ones
+    /// # Arguments
+    /// * `known` - The keys the table may hold
+    pub fn check_keys(&self, known: &[&str]) -> Result<()> {
+        match self
+            .entries
+            .iter()
+            .find(|(k, _)| !known.contains(&k.as_str()))
+        {
+            Some((key, _)) => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Unknown key {}", self.key_name(key)),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// The name of a key of the table as written in errors, e.g. `grub.default`
+    pub fn key_name(&self, key: &str) -> String {
+        match self.name.is_empty() {
+            true => format_key(key),
+            false => format!("{}.{}", self.name, format_key(key)),
+        }
+    }
+
+    fn type_error(&self, key: &str, expected: &str, value: &TomlValue) -> Error {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "{} must be {}, not {}",
+                self.key_name(key),
+                expected,
+                value.type_name()
+            ),
+        )
+    }
+}
+
+/// Parse a TOML file
+///
+/// The subset of TOML configuration files need is understood: tables, bare,
+/// quoted and dotted keys, basic, literal and multi-line strings, integers,
+/// booleans and arrays, which may span lines, and comments. Inline tables,
+/// arrays of tables, floats and dates are not.
+/// # Returns
+/// * `Result<Vec<TomlTable>>` - The root table followed by the tables in file order, the tables of dotted keys follow the table they are set in
+pub(crate) fn parse(content: &str) -> Result<Vec<TomlTable>> {
+    let mut parser = Parser {
+        chars: content.chars().collect(),
+        pos: 0,
+        line: 1,
+    };
+    let mut tables = vec![TomlTable::default()];
+    // The table of the last header and the tables its dotted keys defined
+    let mut current = 0;
+    let mut dotted: Vec<usize> = Vec::new();
+    loop {
+        parser.skip_whitespace();
+        match parser.peek() {
+            None => break,
+            Some('#') | Some('\n') | Some('\r') => parser.end_of_line()?,
+            Some('[') => {
+                parser.pos += 1;
+                parser.skip_whitespace();
+                let path = parser.key()?;
+                parser.skip_whitespace();
+                parser.expect(']')?;
+                if tables.iter().any(|t| t.path == path) {
+                    return Err(
+                        parser.error(&format!("table [{}] is defined twice", format_path(&path)))
+                    );
+                }
+                check_not_value(&parser, &tables, &path)?;
+                parser.end_of_line()?;
+                tables.push(TomlTable {
+                    name: format_path(&path),
+                    path,
+                    entries: Vec::new(),
+                });
+                current = tables.len() - 1;
+                dotted.clear();
+            }
+            Some(_) => {
+                let mut path = parser.key()?;
+                parser.skip_whitespace();
+                parser.expect('=')?;
+                parser.skip_whitespace();
+                let key = path.pop().unwrap_or_default();
+                let index = match path.is_empty() {
+                    true => current,
+                    false => {
+                        let mut full = tables[current].path.clone();
+                        full.extend(path);
+                        match tables.iter().position(|t| t.path == full) {
+                            Some(index) if dotted.contains(&index) => index,
+                            Some(_) => {
+                                return Err(parser.error(&format!(
+                                    "table [{}] is defined twice",
+                                    format_path(&full)
+                                )));
+                            }
+                            None => {
+                                check_not_value(&parser, &tables, &full)?;
+                                tables.push(TomlTable {
+                                    name: format_path(&full),
+                                    path: full,
+                                    entries: Vec::new(),
+                                });
+                                dotted.push(tables.len() - 1);
+                                tables.len() - 1
+                            }
+                        }
+                    }
+                };
+                let table = &tables[index];
+                let mut full = table.path.clone();
+                full.push(key.clone());
+                if table.get(&key).is_some() || tables.iter().any(|t| t.path == full) {
+                    return Err(parser.error(&format!("{} is set twice", table.key_name(&key))));
+                }
+                let value = parser.value()?;
+                parser.end_of_line()?;
+                tables[index].entries.push((key, value));
+            }
+        }
+    }
+    Ok(tables)
+}
+
+/// Fail if a key on the way to a table is set to a value
+/// # Arguments
+/// * `parser` - The parser, for the line of the error
+/// * `tables` - The tables parsed so far
+/// * `path` - The path of the table
+fn check_not_value(parser: &Parser, tables: &[TomlTable], path: &[String]) -> Result<()> {
+    for i in 0..path.len() {
+        if let Some(table) = tables.iter().find(|t| t.path == path[..i])
+            && table.get(&path[i]).is_some()
+        {
+            return Err(parser.error(&format!("{} is set twice", format_path(&path[..=i]))));
+        }
+    }
+    Ok(())
+}
+
+/// Write a key as in a TOML file, quoted unless it is a bare key
+fn format_key(key: &str) -> String {
+    match !key.is_empty() && key.chars().all(is_bare_key_char) {
+        true => key.to_string(),
+        false => format!("\"{}\"", key.replace('\\', "\\\\").replace('"', "\\\"")),
+    }
+}
+
+/// Write a dotted key as in a TOML file
+fn format_path(path: &[String]) -> String {
+    path.iter()
+        .map(|k| format_key(k))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+fn is_bare_key_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+fn error_at(line: usize, msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("line {}: {}", line, msg))
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    /// Whether the text at the current position starts with the given one
+    fn looking_at(&self, text: &str) -> bool {
+        text.chars()
+            .enumerate()
+            .all(|(i, c)| self.chars.get(self.pos + i) == Some(&c))
+    }
+
+    fn error(&self, msg: &str) -> Error {
+        error_at(self.line, msg)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some('\n') | Some('\r') => Err(self.error(&format!(
+                "expected '{}' before the end of the line",
+                expected
+            ))),
+            Some(c) => Err(self.error(&format!("expected '{}', found '{}'", expected, c))),
+            None => Err(self.error(&format!("expected '{}' at end of file", expected))),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(' ') | Some('\t')) {
+            self.pos += 1;
+        }
+    }
+
+    /// Skip whitespace, newlines and comments, as allowed inside arrays
+    fn skip_blank(&mut self) {
+        loop {
+            match self.peek() {
+                Some(' ') | Some('\t') | Some('\r') | Some('\n') => {
+                    self.next();
+                }
+                Some('#') => {
+                    while !matches!(self.peek(), None | Some('\n')) {
+                        self.next();
+                    }
+                }
+                _ => break,
+            }
+        }
+    }
+
+    /// Consume an optional comment and the end of the line
+    fn end_of_line(&mut self) -> Result<()> {
+        self.skip_whitespace();
+        if self.peek() == Some('#') {
+            while !matches!(self.peek(), None | Some('\n')) {
+                self.next();
+            }
+        }
+        if self.peek() == Some('\r') {
+            self.next();
+        }
+        match self.next() {
+            None | Some('\n') => Ok(()),
+            Some(c) => Err(self.error(&format!("unexpected '{}' after the value", c))),
+        }
+    }
+
+    /// Parse a bare, quoted or dotted key
+    /// # Returns
+    /// * `Result<Vec<String>>` - The parts of the key, more than one if it is dotted
+    fn key(&mut self) -> Result<Vec<String>> {
+        let mut parts = Vec::new();
+        loop {
+            let part = match self.peek() {
+                Some('"') | Some('\'') => self.string()?,
+                _ => {
+                    let start = self.pos;
+                    while matches!(self.peek(), Some(c) if is_bare_key_char(c)) {
+                        self.pos += 1;
+                    }
+                    if start == self.pos {
+                        return Err(self.error("expected a key"));
+                    }
+                    self.chars[start..self.pos].iter().collect()
+                }
+            };
+            parts.push(part);
+            self.skip_whitespace();
+            if self.peek() != Some('.') {
+                return Ok(parts);
+            }
+            self.pos += 1;
+            self.skip_whitespace();
+        }
+    }
+
+    fn value(&mut self) -> Result<TomlValue> {
+        match self.peek() {
+            Some('"') if self.looking_at("\"\"\"") => {
+                Ok(TomlValue::String(self.multiline_string()?))
+            }
+            Some('\'') if self.looking_at("'''") => Ok(TomlValue::String(self.multiline_string()?)),
+            Some('"') | Some('\'') => Ok(TomlValue::String(self.string()?)),
+            Some('[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                loop {
+                    self.skip_blank();
+                    if self.peek() == Some(']') {
+                        self.pos += 1;
+                        return Ok(TomlValue::Array(items));
+                    }
+                    items.push(self.value()?);
+                    self.skip_blank();
+                    match self.next() {
+                        Some(',') => {}
+                        Some(']') => return Ok(TomlValue::Array(items)),
+                        _ => return Err(self.error("expected ',' or ']' in array")),
+                    }
+                }
+            }
+            _ => {
+                let start = self.pos;
+                while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || "+-_".contains(c))
+                {
+                    self.pos += 1;
+                }
+                let word: String = self.chars[start..self.pos].iter().collect();
+                match word.as_str() {
+                    "" => match self.peek() {
+                        None | Some('\n') | Some('\r') | Some('#') => {
+                            Err(self.error("expected a value"))
+                        }
+                        Some(c) => Err(self.error(&format!("expected a value, found '{}'", c))),
+                    },
+                    "true" => Ok(TomlValue::Boolean(true)),
+                    "false" => Ok(TomlValue::Boolean(false)),
+                    _ => word
+                        .replace('_', "")
+                        .parse::<i64>()
+                        .map(TomlValue::Integer)
+                        .map_err(|_| self.error(&format!("invalid value '{}'", word))),
+                }
+            }
+        }
+    }
+
+    /// Parse a basic string with escapes or a literal string
+    fn string(&mut self) -> Result<String> {
+        let quote = self.next().unwrap_or_default();
+        let mut ret = String::new();
+        loop {
+            let c = match self.peek() {
+                None | Some('\n') => return Err(self.error("unterminated string")),
+                Some(c) => c,
+            };
+            self.pos += 1;
+            match c {
+                c if c == quote => return Ok(ret),
+                '\\' if quote == '"' => ret.push(self.escape()?),
+                c => ret.push(c),
+            }
+        }
+    }
+
+    /// Parse a multi-line basic or literal string, `"""` or `'''`
+    ///
+    /// A newline right after the opening quotes is left out. In a basic
+    /// string, a `\` at the end of a line drops the newline and the
+    /// whitespace up to the next character.
+    fn multiline_string(&mut self) -> Result<String> {
+        let start = self.line;
+        let quote = self.peek().unwrap_or_default();
+        let delimiter: String = [quote; 3].iter().collect();
+        self.pos += 3;
+        if self.looking_at("\r\n") {
+            self.pos += 1;
+        }
+        if self.peek() == Some('\n') {
+            self.next();
+        }
+        let mut ret = String::new();
+        loop {
+            if self.looking_at(&delimiter) {
+                self.pos += 3;
+                // Up to two quotes before the closing ones are part of the string
+                for _ in 0..2 {
+                    if self.peek() == Some(quote) {
+                        ret.push(quote);
+                        self.pos += 1;
+                    }
+                }
+                return Ok(ret);
+            }
+            match self.next() {
+                None => return Err(error_at(start, "unterminated string")),
+                Some('\\') if quote == '"' => match self.peek() {
+                    Some(' ') | Some('\t') | Some('\r') | Some('\n') => {
+                        self.skip_whitespace();
+                        if self.looking_at("\r\n") {
+                            self.pos += 1;
+                        }
+                        if self.peek() != Some('\n') {
+                            return Err(self.error("invalid escape, '\\' must end the line"));
+                        }
+                        while matches!(self.peek(), Some(' ' | '\t' | '\r' | '\n')) {
+                            self.next();
+                        }
+                    }
+                    _ => ret.push(self.escape()?),
+                },
+                Some(c) => ret.push(c),
+            }
+        }
+    }
+
+    /// Parse the escape after a `\` in a basic string
+    fn escape(&mut self) -> Result<char> {
+        match self.peek() {
+            Some('b') => self.escaped('\u{8}'),
+            Some('t') => self.escaped('\t'),
+            Some('n') => self.escaped('\n'),
+            Some('f') => self.escaped('\u{c}'),
+            Some('r') => self.escaped('\r'),
+            Some('"') => self.escaped('"'),
+            Some('\\') => self.escaped('\\'),
+            Some('u') => self.unicode_escape(4),
+            Some('U') => self.unicode_escape(8),
+            Some('\n') | None => Err(self.error("unterminated string")),
+            Some(c) => Err(self.error(&format!("invalid escape \\{}", c))),
+        }
+    }
+
+    fn escaped(&mut self, c: char) -> Result<char> {
+        self.pos += 1;
+        Ok(c)
+    }
+
+    /// Parse a `\uXXXX` or `\UXXXXXXXX` escape
+    /// # Arguments
+    /// * `digits` - The number of hexadecimal digits after the letter
+    fn unicode_escape(&mut self, digits: usize) -> Result<char> {
+        let letter = self.next().unwrap_or_default();
+        let mut hex = String::new();
+        while hex.len() < digits
+            && let Some(c) = self.peek().filter(char::is_ascii_hexdigit)
+        {
+            hex.push(c);
+            self.pos += 1;
+        }
+        match hex.len() == digits {
+            true => u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32),
+            false => None,
+        }
+        .ok_or(self.error(&format!("invalid escape \\{}{}", letter, hex)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table<'a>(tables: &'a [TomlTable], name: &str) -> &'a TomlTable {
+        tables.iter().find(|t| t.name == name).unwrap()
+    }
+
+    fn string(value: &str) -> String {
+        match parse(&format!("key = {}\n", value)).unwrap()[0].get("key") {
+            Some(TomlValue::String(s)) => s.clone(),
+            other => panic!("not a string: {:?}", other),
+        }
+    }
+
+    fn error(content: &str) -> String {
+        parse(content).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn parses_tables_and_values() {
+        let tables = parse(
+            "# comment\r\n\
+             top = 1\n\
+             \n\
+             [grub]  # trailing comment\n\
+             default = \"latest\"\n\
+             sticky = true\n\
+             count = -1_000\n\
+             keywords = [\n  \"fedora\", # first\n  'arch',\n]\n\
+             [ aliases . grub ]\n\
+             lts = 'kernel:6.1.*'\n",
+        )
+        .unwrap();
+        assert_eq!(tables.len(), 3);
+        assert_eq!(tables[0].get("top"), Some(&TomlValue::Integer(1)));
+        let grub = table(&tables, "grub");
+        assert_eq!(grub.get_str("default").unwrap().as_deref(), Some("latest"));
+        assert_eq!(grub.get_bool("sticky").unwrap(), Some(true));
+        assert_eq!(grub.get_integer("count").unwrap(), Some(-1000));
+        assert_eq!(
+            grub.get_str_array("keywords").unwrap(),
+            Some(vec!["fedora".to_string(), "arch".to_string()])
+        );
+        let aliases = table(&tables, "aliases.grub");
+        assert_eq!(aliases.path, ["aliases", "grub"]);
+        assert_eq!(aliases.entries[0].0, "lts");
+    }
+
+    #[test]
+    fn keeps_dots_of_quoted_keys() {
+        let tables = parse("[profiles.\"a.b\"]\n\"c.d\" = 1\n").unwrap();
+        let profile = &tables[1];
+        assert_eq!(profile.path, ["profiles", "a.b"]);
+        assert_eq!(profile.name, "profiles.\"a.b\"");
+        assert_eq!(profile.get("c.d"), Some(&TomlValue::Integer(1)));
+        assert_eq!(profile.key_name("c.d"), "profiles.\"a.b\".\"c.d\"");
+
+        // A quoted key with a dot and a dotted key are different keys
+        let tables = parse("\"a.b\" = 1\na.b = 2\n").unwrap();
+        assert_eq!(tables[0].get("a.b"), Some(&TomlValue::Integer(1)));
+        assert_eq!(tables[1].path, ["a"]);
+        assert_eq!(tables[1].get("b"), Some(&TomlValue::Integer(2)));
+    }
+
+    #[test]
+    fn puts_dotted_keys_in_tables() {
+        let tables =
+            parse("[aliases]\nfirmware.win = \"Windows\"\nfirmware.deb = 'debian'\n").unwrap();
+        let firmware = table(&tables, "aliases.firmware");
+        assert_eq!(firmware.entries.len(), 2);
+        assert_eq!(firmware.get_str("deb").unwrap().as_deref(), Some("debian"));
+        assert!(table(&tables, "aliases").entries.is_empty());
+    }
+
+    #[test]
+    fn unescapes_basic_strings() {
+        assert_eq!(string(r#""a\tb\nc\\d\"e""#), "a\tb\nc\\d\"e");
+        assert_eq!(string(r#""\b\f\r""#), "\u{8}\u{c}\r");
+        assert_eq!(string(r#""\u00e9\U0001F600""#), "é\u{1F600}");
+        assert_eq!(string(r#"'C:\Windows\n'"#), "C:\\Windows\\n");
+        assert_eq!(string("\"\""), "");
+    }
+
+    #[test]
+    fn parses_multiline_strings() {
+        assert_eq!(string("\"\"\"\nfirst\nsecond\"\"\""), "first\nsecond");
+        assert_eq!(string("\"\"\"a \\\n    b \\   \r\n\n  c\"\"\""), "a b c");
+        assert_eq!(string("\"\"\"tab\\there\"\"\""), "tab\there");
+        assert_eq!(string("'''\r\nC:\\dir\\n\n'''"), "C:\\dir\\n\n");
+        assert_eq!(string("\"\"\"say \"hi\"\"\"\"\""), "say \"hi\"\"");
+        assert_eq!(string("'''it's'''"), "it's");
+
+        let tables = parse("a = \"\"\"\n1\n2\n\"\"\"\nb = 3\n").unwrap();
+        assert_eq!(tables[0].get("b"), Some(&TomlValue::Integer(3)));
+        // Lines in a multi-line string are counted
+        assert_eq!(
+            error("a = '''\n\n'''\nb = no\n"),
+            "line 4: invalid value 'no'"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        assert_eq!(error("a = 1\na = 2\n"), "line 2: a is set twice");
+        assert_eq!(
+            error("[grub]\nx = 1\n[grub]\n"),
+            "line 3: table [grub] is defined twice"
+        );
+        assert_eq!(error("a = 1\n[a.b]\n"), "line 2: a is set twice");
+        assert_eq!(error("a.b = 1\na = 2\n"), "line 2: a is set twice");
+        assert_eq!(
+            error("[a.b]\n[a]\nb.c = 1\n"),
+            "line 3: table [a.b] is defined twice"
+        );
+        assert_eq!(error("\n\nkey = \"open\n"), "line 3: unterminated string");
+        assert_eq!(
+            error("key = \"\"\"\nopen\n\n"),
+            "line 1: unterminated string"
+        );
+        assert_eq!(error("key = \"\\q\"\n"), "line 1: invalid escape \\q");
+        assert_eq!(error("key = \"\\u12\"\n"), "line 1: invalid escape \\u12");
+        assert_eq!(
+            error("key = \"\\UD800DC00\"\n"),
+            "line 1: invalid escape \\UD800DC00"
+        );
+        assert_eq!(
+            error("key = \"\"\"a \\ b\"\"\"\n"),
+            "line 1: invalid escape, '\\' must end the line"
+        );
+        assert_eq!(
+            error("key = 1 2\n"),
+            "line 1: unexpected '2' after the value"
+        );
+        assert_eq!(error("\nkey = yes\n"), "line 2: invalid value 'yes'");
+        assert_eq!(
+            error("key = /boot\n"),
+            "line 1: expected a value, found '/'"
+        );
+        assert_eq!(error("key =\n"), "line 1: expected a value");
+        assert_eq!(
+            error("key = [1,,2]\n"),
+            "line 1: expected a value, found ','"
+        );
+        assert_eq!(error("key 1\n"), "line 1: expected '=', found '1'");
+        assert_eq!(
+            error("[grub\n"),
+            "line 1: expected ']' before the end of the line"
+        );
+        assert_eq!(error("= 1\n"), "line 1: expected a key");
+        assert_eq!(
+            error("key = [\n1,\n2\n3]\n"),
+            "line 4: expected ',' or ']' in array"
+        );
+    }
+
+    #[test]
+    fn reports_wrong_types_and_keys() {
+        let tables = parse("[grub]\ndefault = 1\n\"odd key\" = true\n").unwrap();
+        let grub = &tables[1];
+        assert_eq!(
+            grub.get_str("default").unwrap_err().to_string(),
+            "grub.default must be a string, not an integer"
+        );
+        assert_eq!(
+            grub.check_keys(&["default"]).unwrap_err().to_string(),
+            "Unknown key grub.\"odd key\""
+        );
+    }
+}
@@ -0,0 +1,280 @@
+use crate::bootloader::{BootloaderKind, grub, refind, systemd_boot};
+use crate::common::journal::{Change, ChangeTarget, JournalRecord};
+use crate::common::{dry_run, file_operations, journal, timestamp};
+use crate::platform::{Platform, VolumeDir};
+use std::io::{Error, ErrorKind, Result, Write};
+use std::path::PathBuf;
+
+/// The name of the journal file in the data directory
+const JOURNAL_FILE: &str = "journal";
+/// The bytes of an EFI variable shown in the history
+const EFI_SHOWN_BYTES: usize = 16;
+
+/// Append the changes of the running command to the journal
+/// # Arguments
+/// * `changes` - The changes made by the command
+pub(crate) fn save_record(platform: &dyn Platform, changes: Vec<Change>) -> Result<()> {
+    let record = JournalRecord {
+        time: timestamp::now(),
+        user: current_user(),
+        command: std::env::args()
+            .map(
+                |arg| match arg.contains(char::is_whitespace) || arg.is_empty() {
+                    true => format!("\"{}\"", arg),
+                    false => arg,
+                },
+            )
+            .collect::<Vec<_>>()
+            .join(" "),
+        changes,
+    };
+    let dir = platform.get_data_dir()?;
+    std::fs::create_dir_all(&dir)?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(JOURNAL_FILE))?;
+    file.write_all(&record.to_bytes())?;
+    file.sync_all()
+}
+
+/// Show the recorded changes, newest first and numbered as `undo` counts them
+/// # Arguments
+/// * `count` - Show only this many records
+pub(crate) fn show_history(platform: &dyn Platform, count: Option<usize>) -> Result<()> {
+    let records = read_journal(platform)?;
+    if records.is_empty() {
+        println!("No changes recorded");
+        return Ok(());
+    }
+    for (i, record) in records
+        .iter()
+        .rev()
+        .take(count.unwrap_or(usize::MAX))
+        .enumerate()
+    {
+        println!(
+            "{:>3}  {}  {}  {}",
+            i + 1,
+            timestamp::format_utc(record.time),
+            record.user,
+            record.command
+        );
+        for change in &record.changes {
+            show_change(change);
+        }
+    }
+    Ok(())
+}
+
+/// Revert the newest recorded changes
+///
+/// Every target must still hold the value the newest record left, otherwise
+/// nothing is reverted. The reverted records are removed from the journal.
+/// # Arguments
+/// * `count` - The number of records to revert
+pub(crate) fn undo(platform: &mut dyn Platform, count: usize) -> Result<()> {
+    let mut records = read_journal(platform)?;
+    if count > records.len() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Cannot undo {} records, the journal holds {}",
+                count,
+                records.len()
+            ),
+        ));
+    }
+
+    // The values the targets get back, checked newest record first
+    let mut reverted: Vec<(ChangeTarget, Option<Vec<u8>>)> = Vec::new();
+    for (i, record) in records.iter().rev().take(count).enumerate() {
+        for change in &record.changes {
+            match reverted.iter_mut().find(|(t, _)| *t == change.target) {
+                // The BCD store is restored from an export but checked by its
+                // listing, so only its current state can be compared
+                Some((ChangeTarget::Bcd, value)) => value.clone_from(&change.old),
+                Some((_, value)) if *value != change.new => {
+                    return Err(changed_error(&change.target, i + 1));
+                }
+                Some((_, value)) => value.clone_from(&change.old),
+                None => {
+                    if read_target(platform, &change.target)? != change.new {
+                        return Err(changed_error(&change.target, i + 1));
+                    }
+                    reverted.push((change.target.clone(), change.old.clone()));
+                }
+            }
+        }
+    }
+
+    for (target, value) in &reverted {
+        write_target(platform, target, value.as_deref())?;
+        println!("Reverted {}", target);
+    }
+    if dry_run::is_enabled() {
+        return Ok(());
+    }
+    records.truncate(records.len() - count);
+    let path = platform.get_data_dir()?.join(JOURNAL_FILE);
+    let temp = path.with_extension("tmp");
+    std::fs::write(
+        &temp,
+        records
+            .iter()
+            .flat_map(|r| r.to_bytes())
+            .collect::<Vec<_>>(),
+    )?;
+    std::fs::rename(&temp, &path)?;
+    println!(
+        "Undid {} record{}",
+        count,
+        if count == 1 { "" } else { "s" }
+    );
+    Ok(())
+}
+
+/// Read the journal, a missing journal has no records
+fn read_journal(platform: &dyn Platform) -> Result<Vec<JournalRecord>> {
+    match journal::read_file(&platform.get_data_dir()?.join(JOURNAL_FILE))? {
+        Some(data) => JournalRecord::parse_all(&data),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn changed_error(target: &ChangeTarget, record: usize) -> Error {
+    Error::other(format!(
+        "{} was changed outside bootmgr since record {}, nothing was reverted",
+        target, record
+    ))
+}
+
+/// Get the user running bootmgr, the one who invoked sudo if any
+fn current_user() -> String {
+    ["SUDO_USER", "USER", "USERNAME"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok().filter(|v| !v.is_empty()))
+        .unwrap_or("unknown".to_string())
+}
+
+/// Show the old and new value of a change
+fn show_change(change: &Change) {
+    match &change.target {
+        ChangeTarget::EfiVar(_) => println!(
+            "     {}: {} -> {}",
+            change.target,
+            describe_efivar(change.old.as_deref()),
+            describe_efivar(change.new.as_deref())
+        ),
+        ChangeTarget::Bcd => println!("     {}: changed", change.target),
+        _ => {
+            let text = |value: &Option<Vec<u8>>| {
+                String::from_utf8_lossy(value.as_deref().unwrap_or_default()).to_string()
+            };
+            println!("     {}:", change.target);
+            // grubenv pads itself with a line of '#' whose length changes with the content
+            for line in dry_run::changed_lines(&text(&change.old), &text(&change.new))
+                .iter()
+                .filter(|line| line.len() == 1 || !line[1..].chars().all(|c| c == '#'))
+            {
+                println!("       {}", line);
+            }
+        }
+    }
+}
+
+/// Describe the value of an EFI variable in the history
+fn describe_efivar(value: Option<&[u8]>) -> String {
+    let Some(value) = value else {
+        return "not set".to_string();
+    };
+    let hex: Vec<String> = value
+        .iter()
+        .take(EFI_SHOWN_BYTES)
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    match value.len() > EFI_SHOWN_BYTES {
+        true => format!("{} ... ({} bytes)", hex.join(" "), value.len()),
+        false => hex.join(" "),
+    }
+}
+
+/// Get the file a change target names, with the volume holding it kept mounted
+fn resolve_file(
+    platform: &mut dyn Platform,
+    target: &ChangeTarget,
+) -> Result<(Option<VolumeDir>, PathBuf)> {
+    let (dir, name) = match target {
+        ChangeTarget::GrubFile(name) => (platform.mount_grub_dir()?, name),
+        ChangeTarget::EspFile(loader, name) => match BootloaderKind::from_name(loader) {
+            Some(BootloaderKind::SystemdBoot) => (systemd_boot::mount_esp(platform)?, name),
+            Some(BootloaderKind::Refind) => (refind::mount_esp(platform)?, name),
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("No EFI system partition is known for {}", loader),
+                ));
+            }
+        },
+        ChangeTarget::File(path) => return Ok((None, path.clone())),
+        ChangeTarget::EfiVar(_) | ChangeTarget::Bcd => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} is not a file", target),
+            ));
+        }
+    };
+    let path = dir.path.join(name);
+    Ok((Some(dir), path))
+}
+
+/// Read the current value of a change target the way the journal records it
+fn read_target(platform: &mut dyn Platform, target: &ChangeTarget) -> Result<Option<Vec<u8>>> {
+    match target {
+        ChangeTarget::EfiVar(name) => platform.get_efi_var(name),
+        ChangeTarget::Bcd => platform.list_fw_store(),
+        _ => {
+            let (_dir, path) = resolve_file(platform, target)?;
+            journal::read_file(&path)
+        }
+    }
+}
+
+/// Give a change target a recorded value back
+/// # Arguments
+/// * `target` - The target to write
+/// * `value` - The value, None to delete the target
+pub(crate) fn write_target(
+    platform: &mut dyn Platform,
+    target: &ChangeTarget,
+    value: Option<&[u8]>,
+) -> Result<()> {
+    match (target, value) {
+        (ChangeTarget::EfiVar(name), Some(data)) => platform.set_efi_var(name, data),
+        (ChangeTarget::EfiVar(name), None) => platform.delete_efi_var(name),
+        (ChangeTarget::Bcd, Some(data)) => platform.import_fw_store(data),
+        (ChangeTarget::Bcd, None) => Err(Error::new(
+            ErrorKind::InvalidData,
+            "The journal holds no export of the BCD store",
+        )),
+        (ChangeTarget::GrubFile(name), Some(data)) if name == file_operations::GRUB_ENV_PATH => {
+            grub::write_grub_env(platform, |_| Ok(data.to_vec()))
+        }
+        (_, Some(data)) => {
+            let (_dir, path) = resolve_file(platform, target)?;
+            dry_run::write_file(&path, &String::from_utf8_lossy(data), || {
+                std::fs::write(&path, data)
+            })
+        }
+        (_, None) => {
+            let (_dir, path) = resolve_file(platform, target)?;
+            match dry_run::is_enabled() {
+                true => {
+                    println!("Would delete {}", path.display());
+                    Ok(())
+                }
+                false => std::fs::remove_file(&path),
+            }
+        }
+    }
+}
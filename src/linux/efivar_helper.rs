@@ -0,0 +1,499 @@
+use crate::common::dry_run;
+use crate::common::journal::{self, ChangeTarget};
+use crate::common::selector::{Selectable, select};
+use crate::linux::load_option::{
+    LOAD_OPTION_ACTIVE, LOAD_OPTION_HIDDEN, LoadOption, Partition, build_file_path_list,
+    parse_guid, ucs2_encode,
+};
+use crate::platform::{FwEntrySpec, FwEntryUpdate, FwLoader};
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::{Error, ErrorKind, Result, Write};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+const EFIVARS_PATH: &str = "/sys/firmware/efi/efivars";
+const EFI_GLOBAL_GUID: &str = "8be4df61-93ca-11d2-aa0d-00e098032b8c";
+const FS_IMMUTABLE_FL: libc::c_long = 0x0000_0010;
+const EFI_VARIABLE_DEFAULT_ATTRIBUTES: u32 = 0x0000_0007;
+
+#[derive(Clone)]
+struct EfiEntry {
+    pub num: u16,
+    pub id: String,
+    pub option: LoadOption,
+}
+
+impl Selectable for EfiEntry {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn title(&self) -> &str {
+        &self.option.description
+    }
+}
+
+/// Show the firmware entries, numbered from 0 as index selectors count them
+pub(crate) fn show_efi_list() -> Result<()> {
+    let entries = get_efi_entries()?;
+    let order = get_boot_order()?;
+    println!("The firmware boot entries(EFI):");
+    for (index, i) in entries.iter().enumerate() {
+        println!(
+            "{:>3} {}{} {} ({})",
+            index,
+            if order.first() == Some(&i.num) {
+                "*"
+            } else {
+                " "
+            },
+            if !i.option.is_active() {
+                "!"
+            } else if i.option.is_hidden() {
+                "-"
+            } else {
+                " "
+            },
+            i.option.description,
+            i.id
+        );
+    }
+    Ok(())
+}
+
+pub(crate) fn show_efi_status() -> Result<()> {
+    let entries = get_efi_entries()?;
+    let describe = |num: Option<u16>| match num {
+        Some(num) => match entries.iter().find(|e| e.num == num) {
+            Some(e) => format!("{} ({})", e.option.description, e.id),
+            None => boot_var_name(num),
+        },
+        None => "not set".to_string(),
+    };
+    println!("Firmware:");
+    println!("  Current: {}", describe(read_efivar_u16("BootCurrent")?));
+    println!("  Next:    {}", describe(read_efivar_u16("BootNext")?));
+    println!(
+        "  Default: {}",
+        describe(get_boot_order()?.first().copied())
+    );
+    Ok(())
+}
+
+/// Get the EFI boot entry booted by default
+/// # Returns
+/// * `Result<Option<String>>` - The `Boot####` variable first in the boot order
+pub(crate) fn get_efi_default() -> Result<Option<String>> {
+    Ok(get_boot_order()?.first().map(|num| boot_var_name(*num)))
+}
+
+/// Get the EFI boot order
+/// # Returns
+/// * `Result<Vec<String>>` - The `Boot####` variables of `BootOrder`
+pub(crate) fn get_efi_order() -> Result<Vec<String>> {
+    Ok(get_boot_order()?.into_iter().map(boot_var_name).collect())
+}
+
+pub(crate) fn set_efi_entry(entry: String) -> Result<()> {
+    let num = find_efi_entry(&entry)?.num;
+    let mut order = get_boot_order()?;
+    order.retain(|i| *i != num);
+    order.insert(0, num);
+    set_boot_order(&order)
+}
+
+/// Get the EFI boot entry booted on the next boot only
+/// # Returns
+/// * `Result<Option<String>>` - The `Boot####` variable of `BootNext`
+pub(crate) fn get_efi_oneshot() -> Result<Option<String>> {
+    Ok(read_efivar_u16("BootNext")?.map(boot_var_name))
+}
+
+pub(crate) fn set_efi_oneshot(entry: String) -> Result<()> {
+    let num = find_efi_entry(&entry)?.num;
+    write_efivar("BootNext", &num.to_le_bytes())
+}
+
+pub(crate) fn create_efi_entry(spec: &FwEntrySpec) -> Result<()> {
+    let partition = get_partition(&spec.disk, spec.part)?;
+    let entries = get_efi_entries()?;
+    let num = (0..=u16::MAX)
+        .find(|n| !entries.iter().any(|e| e.num == *n))
+        .ok_or(Error::other("No free boot entry number"))?;
+
+    let option = LoadOption {
+        attributes: LOAD_OPTION_ACTIVE,
+        description: spec.label.clone(),
+        file_path_list: build_file_path_list(&partition, &spec.loader),
+        optional_data: spec.args.as_deref().map(ucs2_encode).unwrap_or_default(),
+    };
+    write_efivar(&boot_var_name(num), &option.to_bytes())?;
+
+    let mut order = get_boot_order()?;
+    order.push(num);
+    set_boot_order(&order)?;
+    println!(
+        "Created firmware entry {} ({})",
+        spec.label,
+        boot_var_name(num)
+    );
+    Ok(())
+}
+
+pub(crate) fn delete_efi_entry(entry: String) -> Result<()> {
+    let target = find_efi_entry(&entry)?;
+    let mut order = get_boot_order()?;
+    order.retain(|i| *i != target.num);
+    set_boot_order(&order)?;
+
+    if read_efivar("BootNext")?.as_deref() == Some(&target.num.to_le_bytes()[..]) {
+        delete_efivar("BootNext")?;
+    }
+    delete_efivar(&target.id)?;
+    println!(
+        "Deleted firmware entry {} ({})",
+        target.option.description, target.id
+    );
+    Ok(())
+}
+
+pub(crate) fn modify_efi_entry(entry: String, update: &FwEntryUpdate) -> Result<()> {
+    let mut target = find_efi_entry(&entry)?;
+    let option = &mut target.option;
+    if let Some(label) = &update.label {
+        option.description = label.clone();
+    }
+    if let Some(loader) = &update.loader {
+        option.set_loader_path(loader);
+    }
+    if let Some(args) = &update.args {
+        option.optional_data = match args.is_empty() {
+            true => Vec::new(),
+            false => ucs2_encode(args),
+        };
+    }
+    if let Some(active) = update.active {
+        option.set_attribute(LOAD_OPTION_ACTIVE, active);
+    }
+    if let Some(hidden) = update.hidden {
+        option.set_attribute(LOAD_OPTION_HIDDEN, hidden);
+    }
+
+    write_efivar(&target.id, &target.option.to_bytes())?;
+    println!(
+        "Modified firmware entry {} ({})",
+        target.option.description, target.id
+    );
+    Ok(())
+}
+
+pub(crate) fn show_efi_timeout() -> Result<()> {
+    match read_efivar("Timeout")? {
+        Some(data) if data.len() >= 2 => {
+            println!(
+                "Firmware timeout: {}s",
+                u16::from_le_bytes([data[0], data[1]])
+            )
+        }
+        _ => println!("Firmware timeout: not set"),
+    }
+    Ok(())
+}
+
+pub(crate) fn get_efi_timeout() -> Result<Option<u16>> {
+    read_efivar_u16("Timeout")
+}
+
+pub(crate) fn set_efi_timeout(timeout: u16) -> Result<()> {
+    write_efivar("Timeout", &timeout.to_le_bytes())
+}
+
+/// Find the partition of the firmware entry starting a loader
+/// # Arguments
+/// * `loader` - A case-insensitive part of the loader path, e.g. `systemd-boot`
+/// # Returns
+/// * `Result<Option<String>>` - The GPT partition GUID, None if no entry starts the loader
+pub(crate) fn find_loader_partition(loader: &str) -> Result<Option<String>> {
+    let loader = loader.to_lowercase();
+    Ok(get_efi_entries()?
+        .into_iter()
+        .filter(|e| {
+            e.option
+                .loader_path()
+                .is_some_and(|p| p.to_lowercase().contains(&loader))
+        })
+        .find_map(|e| e.option.partition_guid()))
+}
+
+/// Get the loaders started by the EFI boot entries
+/// # Returns
+/// * `Result<Vec<FwLoader>>` - The entries in boot order, followed by the others
+pub(crate) fn get_efi_loaders() -> Result<Vec<FwLoader>> {
+    let current = read_efivar_u16("BootCurrent")?;
+    Ok(get_efi_entries()?
+        .into_iter()
+        .map(|e| FwLoader {
+            path: e.option.loader_path(),
+            is_current: current == Some(e.num),
+            description: e.option.description,
+            id: e.id,
+        })
+        .collect())
+}
+
+/// Get the names of the EFI variables holding the boot configuration
+/// # Returns
+/// * `Result<Vec<String>>` - Every `Boot####` variable, then `BootOrder` and `Timeout` if set
+pub(crate) fn get_boot_var_names() -> Result<Vec<String>> {
+    // Entries that cannot be parsed are saved as well
+    let mut names = Vec::new();
+    for num in get_boot_nums()? {
+        if read_efivar(&boot_var_name(num))?.is_some() {
+            names.push(boot_var_name(num));
+        }
+    }
+    for name in ["BootOrder", "Timeout"] {
+        if read_efivar(name)?.is_some() {
+            names.push(name.to_string());
+        }
+    }
+    Ok(names)
+}
+
+fn find_efi_entry(entry: &str) -> Result<EfiEntry> {
+    let entries = get_efi_entries()?;
+    select(&entries, entry, "EFI").cloned()
+}
+
+/// Get the firmware entries, skipping with a warning those that cannot be parsed
+fn get_efi_entries() -> Result<Vec<EfiEntry>> {
+    let mut entries = Vec::new();
+    for num in get_boot_nums()? {
+        let id = boot_var_name(num);
+        if let Some(data) = read_efivar(&id)? {
+            match LoadOption::parse(&data) {
+                Ok(option) => entries.push(EfiEntry { num, id, option }),
+                Err(e) => eprintln!("Warning: skipping firmware entry {}: {}", id, e),
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Get the numbers of the `Boot####` variables, those of the boot order first
+fn get_boot_nums() -> Result<Vec<u16>> {
+    if !Path::new(EFIVARS_PATH).exists() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            "EFI variables not available, the system is not booted in UEFI mode",
+        ));
+    }
+    let mut nums = get_boot_order()?;
+    let prefix = "Boot";
+    let suffix = format!("-{}", EFI_GLOBAL_GUID);
+    for file in fs::read_dir(EFIVARS_PATH)? {
+        let name = file?.file_name().to_string_lossy().to_string();
+        let num = name
+            .strip_prefix(prefix)
+            .and_then(|s| s.strip_suffix(&suffix))
+            .filter(|s| s.len() == 4)
+            .and_then(|s| u16::from_str_radix(s, 16).ok());
+        if let Some(num) = num
+            && !nums.contains(&num)
+        {
+            nums.push(num);
+        }
+    }
+    Ok(nums)
+}
+
+fn get_boot_order() -> Result<Vec<u16>> {
+    Ok(read_efivar("BootOrder")?
+        .unwrap_or_default()
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect())
+}
+
+fn set_boot_order(order: &[u16]) -> Result<()> {
+    let data: Vec<u8> = order.iter().flat_map(|i| i.to_le_bytes()).collect();
+    write_efivar("BootOrder", &data)
+}
+
+/// Read a 16-bit EFI variable such as `BootCurrent`
+fn read_efivar_u16(name: &str) -> Result<Option<u16>> {
+    Ok(read_efivar(name)?
+        .filter(|data| data.len() >= 2)
+        .map(|data| u16::from_le_bytes([data[0], data[1]])))
+}
+
+fn boot_var_name(num: u16) -> String {
+    format!("Boot{:04X}", num)
+}
+
+/// Get the efivarfs file of a variable, names without a vendor GUID are global variables
+fn efivar_path(name: &str) -> PathBuf {
+    match name.contains('-') {
+        true => Path::new(EFIVARS_PATH).join(name),
+        false => Path::new(EFIVARS_PATH).join(format!("{}-{}", name, EFI_GLOBAL_GUID)),
+    }
+}
+
+/// Read an EFI variable without its attribute header
+/// # Arguments
+/// * `name` - The variable name, optionally followed by `-<vendor GUID>`
+/// # Returns
+/// * `Result<Option<Vec<u8>>>` - The variable data, None if it does not exist
+pub(crate) fn read_efivar(name: &str) -> Result<Option<Vec<u8>>> {
+    match fs::read(efivar_path(name)) {
+        Ok(data) if data.len() >= 4 => Ok(Some(data[4..].to_vec())),
+        Ok(_) => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("EFI variable {} is malformed", name),
+        )),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub(crate) fn write_efivar(name: &str, data: &[u8]) -> Result<()> {
+    let write = || {
+        let path = efivar_path(name);
+        if path.exists() {
+            clear_immutable(&path)?;
+        }
+        let mut buf = EFI_VARIABLE_DEFAULT_ATTRIBUTES.to_le_bytes().to_vec();
+        buf.extend_from_slice(data);
+
+        // efivarfs requires the whole variable to be written in a single call
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        if file.write(&buf)? != buf.len() {
+            return Err(Error::new(
+                ErrorKind::WriteZero,
+                format!("Short write to EFI variable {}", name),
+            ));
+        }
+        Ok(())
+    };
+    journal::track(
+        ChangeTarget::EfiVar(name.to_string()),
+        || read_efivar(name),
+        || dry_run::write_efivar(name, data, write),
+    )
+}
+
+pub(crate) fn delete_efivar(name: &str) -> Result<()> {
+    let delete = || {
+        let path = efivar_path(name);
+        clear_immutable(&path)?;
+        fs::remove_file(path)
+    };
+    journal::track(
+        ChangeTarget::EfiVar(name.to_string()),
+        || read_efivar(name),
+        || dry_run::delete_efivar(name, delete),
+    )
+}
+
+/// efivarfs marks most variables immutable to protect against accidental rm
+fn clear_immutable(path: &Path) -> Result<()> {
+    let file = OpenOptions::new().read(true).open(path)?;
+    let mut flags: libc::c_long = 0;
+    unsafe {
+        if libc::ioctl(file.as_raw_fd(), libc::FS_IOC_GETFLAGS, &mut flags) != 0 {
+            return Err(Error::last_os_error());
+        }
+        if flags & FS_IMMUTABLE_FL != 0 {
+            flags &= !FS_IMMUTABLE_FL;
+            if libc::ioctl(file.as_raw_fd(), libc::FS_IOC_SETFLAGS, &flags) != 0 {
+                return Err(Error::last_os_error());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Look up the partition layout of a disk partition through sysfs
+/// # Arguments
+/// * `disk` - The disk device, e.g. `/dev/sda` or `/dev/nvme0n1`
+/// * `part` - The partition number on the disk
+fn get_partition(disk: &str, part: u32) -> Result<Partition> {
+    let disk_name = Path::new(disk)
+        .file_name()
+        .ok_or(Error::new(ErrorKind::InvalidInput, "Invalid disk"))?
+        .to_string_lossy()
+        .to_string();
+    let part_name = if disk_name.ends_with(|c: char| c.is_ascii_digit()) {
+        format!("{}p{}", disk_name, part)
+    } else {
+        format!("{}{}", disk_name, part)
+    };
+
+    let sys_part = Path::new("/sys/class/block").join(&part_name);
+    if !sys_part.exists() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("Partition {} not found", part_name),
+        ));
+    }
+    let block_size = read_sysfs_u64(
+        &Path::new("/sys/class/block")
+            .join(&disk_name)
+            .join("queue/logical_block_size"),
+    )?;
+    let start = read_sysfs_u64(&sys_part.join("start"))? * 512 / block_size;
+    let size = read_sysfs_u64(&sys_part.join("size"))? * 512 / block_size;
+
+    let part_uuid = get_part_uuid(&part_name)?;
+    let (signature, gpt) = match parse_guid(&part_uuid) {
+        Some(guid) => (guid, true),
+        None => {
+            // MBR partitions are identified as <disk signature>-<partition number>
+            let disk_sig = part_uuid
+                .split('-')
+                .next()
+                .and_then(|s| u32::from_str_radix(s, 16).ok())
+                .ok_or(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Unrecognized partition uuid {}", part_uuid),
+                ))?;
+            let mut signature = [0u8; 16];
+            signature[..4].copy_from_slice(&disk_sig.to_le_bytes());
+            (signature, false)
+        }
+    };
+
+    Ok(Partition {
+        number: part,
+        start,
+        size,
+        signature,
+        gpt,
+    })
+}
+
+fn get_part_uuid(part_name: &str) -> Result<String> {
+    for link in fs::read_dir("/dev/disk/by-partuuid")? {
+        let link = link?;
+        let target = fs::read_link(link.path())?;
+        if target.file_name().map(|n| n.to_string_lossy() == part_name) == Some(true) {
+            return Ok(link.file_name().to_string_lossy().to_string());
+        }
+    }
+    Err(Error::new(
+        ErrorKind::NotFound,
+        format!("Partition uuid of {} not found", part_name),
+    ))
+}
+
+fn read_sysfs_u64(path: &Path) -> Result<u64> {
+    fs::read_to_string(path)?
+        .trim()
+        .parse::<u64>()
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
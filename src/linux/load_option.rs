@@ -0,0 +1,325 @@
+use crate::common::file_operations::normalize_loader_path;
+use std::io::{Error, ErrorKind, Result};
+
+pub(crate) const LOAD_OPTION_ACTIVE: u32 = 0x0000_0001;
+pub(crate) const LOAD_OPTION_HIDDEN: u32 = 0x0000_0008;
+
+const MEDIA_DEVICE_PATH: u8 = 0x04;
+const MEDIA_HARDDRIVE_DP: u8 = 0x01;
+const MEDIA_FILEPATH_DP: u8 = 0x04;
+const END_DEVICE_PATH: u8 = 0x7f;
+const END_ENTIRE_DEVICE_PATH: u8 = 0xff;
+
+/// A struct representing an EFI_LOAD_OPTION stored in a `Boot####` variable
+/// Fields:
+/// * `attributes` - The LOAD_OPTION_* attribute bits
+/// * `description` - The human readable description of the entry
+/// * `file_path_list` - The raw device path list of the loader
+/// * `optional_data` - The data passed to the loader
+#[derive(Clone, Debug, Default)]
+pub(crate) struct LoadOption {
+    pub attributes: u32,
+    pub description: String,
+    pub file_path_list: Vec<u8>,
+    pub optional_data: Vec<u8>,
+}
+
+/// A struct representing the partition a loader lives on
+/// Fields:
+/// * `number` - The partition number, starting from 1
+/// * `start` - The first logical block of the partition
+/// * `size` - The size of the partition in logical blocks
+/// * `signature` - The GPT partition GUID or the MBR disk signature
+/// * `gpt` - Whether the disk uses a GUID partition table
+pub(crate) struct Partition {
+    pub number: u32,
+    pub start: u64,
+    pub size: u64,
+    pub signature: [u8; 16],
+    pub gpt: bool,
+}
+
+impl LoadOption {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < 6 {
+            return Err(invalid("load option is too short"));
+        }
+        let attributes = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+        let path_len = u16::from_le_bytes([data[4], data[5]]) as usize;
+
+        let mut pos = 6;
+        let mut description = Vec::new();
+        loop {
+            let c = data
+                .get(pos..pos + 2)
+                .ok_or(invalid("unterminated load option description"))?;
+            pos += 2;
+            match u16::from_le_bytes([c[0], c[1]]) {
+                0 => break,
+                c => description.push(c),
+            }
+        }
+
+        let file_path_list = data
+            .get(pos..pos + path_len)
+            .ok_or(invalid("load option device path is truncated"))?
+            .to_vec();
+
+        Ok(Self {
+            attributes,
+            description: String::from_utf16_lossy(&description),
+            file_path_list,
+            optional_data: data[pos + path_len..].to_vec(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut ret = Vec::new();
+        ret.extend_from_slice(&self.attributes.to_le_bytes());
+        ret.extend_from_slice(&(self.file_path_list.len() as u16).to_le_bytes());
+        ret.extend_from_slice(&ucs2_encode(&self.description));
+        ret.extend_from_slice(&self.file_path_list);
+        ret.extend_from_slice(&self.optional_data);
+        ret
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.attributes & LOAD_OPTION_ACTIVE != 0
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.attributes & LOAD_OPTION_HIDDEN != 0
+    }
+
+    pub fn set_attribute(&mut self, attribute: u32, enabled: bool) {
+        if enabled {
+            self.attributes |= attribute;
+        } else {
+            self.attributes &= !attribute;
+        }
+    }
+
+    /// Replace the media file path node, keeping the device nodes before it
+    pub fn set_loader_path(&mut self, loader: &str) {
+        let mut list = Vec::new();
+        for (node_type, sub_type, body) in self.nodes() {
+            if node_type == MEDIA_DEVICE_PATH && sub_type == MEDIA_FILEPATH_DP {
+                break;
+            }
+            push_node(&mut list, node_type, sub_type, body);
+        }
+        push_node(
+            &mut list,
+            MEDIA_DEVICE_PATH,
+            MEDIA_FILEPATH_DP,
+            &ucs2_encode(&normalize_loader_path(loader)),
+        );
+        push_node(&mut list, END_DEVICE_PATH, END_ENTIRE_DEVICE_PATH, &[]);
+        self.file_path_list = list;
+    }
+
+    /// Get the path of the loader from the media file path node
+    pub fn loader_path(&self) -> Option<String> {
+        self.nodes()
+            .find(|(t, s, _)| *t == MEDIA_DEVICE_PATH && *s == MEDIA_FILEPATH_DP)
+            .map(|(_, _, body)| {
+                let chars: Vec<u16> = body
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .take_while(|c| *c != 0)
+                    .collect();
+                String::from_utf16_lossy(&chars)
+            })
+    }
+
+    /// Get the GPT partition GUID from the hard drive node
+    pub fn partition_guid(&self) -> Option<String> {
+        let (_, _, body) = self
+            .nodes()
+            .find(|(t, s, _)| *t == MEDIA_DEVICE_PATH && *s == MEDIA_HARDDRIVE_DP)?;
+        // Signature type 2 is a GUID, 1 an MBR disk signature
+        if body.len() < 38 || body[37] != 2 {
+            return None;
+        }
+        let g = &body[20..36];
+        Some(format!(
+            "{:02x}{:02x}{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+            g[3],
+            g[2],
+            g[1],
+            g[0],
+            g[5],
+            g[4],
+            g[7],
+            g[6],
+            g[8],
+            g[9],
+            g[10],
+            g[11],
+            g[12],
+            g[13],
+            g[14],
+            g[15]
+        ))
+    }
+
+    fn nodes(&self) -> impl Iterator<Item = (u8, u8, &[u8])> {
+        let list = &self.file_path_list;
+        let mut pos = 0;
+        std::iter::from_fn(move || {
+            let header = list.get(pos..pos + 4)?;
+            let len = u16::from_le_bytes([header[2], header[3]]) as usize;
+            if header[0] == END_DEVICE_PATH || len < 4 {
+                return None;
+            }
+            let body = list.get(pos + 4..pos + len)?;
+            pos += len;
+            Some((header[0], header[1], body))
+        })
+    }
+}
+
+/// Build a device path list pointing at a loader on a partition
+/// # Arguments
+/// * `partition` - The partition holding the loader
+/// * `loader` - The path of the loader on the partition
+/// # Returns
+/// * `Vec<u8>` - The raw device path list
+pub(crate) fn build_file_path_list(partition: &Partition, loader: &str) -> Vec<u8> {
+    let mut hd = Vec::new();
+    hd.extend_from_slice(&partition.number.to_le_bytes());
+    hd.extend_from_slice(&partition.start.to_le_bytes());
+    hd.extend_from_slice(&partition.size.to_le_bytes());
+    hd.extend_from_slice(&partition.signature);
+    hd.push(if partition.gpt { 2 } else { 1 });
+    hd.push(if partition.gpt { 2 } else { 1 });
+
+    let mut ret = Vec::new();
+    push_node(&mut ret, MEDIA_DEVICE_PATH, MEDIA_HARDDRIVE_DP, &hd);
+    push_node(
+        &mut ret,
+        MEDIA_DEVICE_PATH,
+        MEDIA_FILEPATH_DP,
+        &ucs2_encode(&normalize_loader_path(loader)),
+    );
+    push_node(&mut ret, END_DEVICE_PATH, END_ENTIRE_DEVICE_PATH, &[]);
+    ret
+}
+
+fn push_node(list: &mut Vec<u8>, node_type: u8, sub_type: u8, body: &[u8]) {
+    list.push(node_type);
+    list.push(sub_type);
+    list.extend_from_slice(&((body.len() + 4) as u16).to_le_bytes());
+    list.extend_from_slice(body);
+}
+
+/// Encode a string as null terminated UCS-2
+pub(crate) fn ucs2_encode(s: &str) -> Vec<u8> {
+    s.encode_utf16()
+        .chain(Some(0))
+        .flat_map(|c| c.to_le_bytes())
+        .collect()
+}
+
+/// Parse a textual GUID into its mixed-endian binary form
+pub(crate) fn parse_guid(guid: &str) -> Option<[u8; 16]> {
+    let hex: String = guid.chars().filter(|c| *c != '-').collect();
+    if hex.len() != 32 || guid.len() != 36 {
+        return None;
+    }
+    let mut raw = [0u8; 16];
+    for (i, byte) in raw.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    raw[0..4].reverse();
+    raw[4..6].reverse();
+    raw[6..8].reverse();
+    Some(raw)
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An active `debian` entry starting shim from the first GPT partition,
+    /// as written by efibootmgr
+    const DEBIAN: [u8; 118] = [
+        0x01, 0x00, 0x00, 0x00, 0x62, 0x00, 0x64, 0x00, 0x65, 0x00, 0x62, 0x00, 0x69, 0x00, 0x61,
+        0x00, 0x6e, 0x00, 0x00, 0x00, 0x04, 0x01, 0x2a, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x08,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x28,
+        0x73, 0x2a, 0xc1, 0x1f, 0xf8, 0xd2, 0x11, 0xba, 0x4b, 0x00, 0xa0, 0xc9, 0x3e, 0xc9, 0x3b,
+        0x02, 0x02, 0x04, 0x04, 0x34, 0x00, 0x5c, 0x00, 0x45, 0x00, 0x46, 0x00, 0x49, 0x00, 0x5c,
+        0x00, 0x64, 0x00, 0x65, 0x00, 0x62, 0x00, 0x69, 0x00, 0x61, 0x00, 0x6e, 0x00, 0x5c, 0x00,
+        0x73, 0x00, 0x68, 0x00, 0x69, 0x00, 0x6d, 0x00, 0x78, 0x00, 0x36, 0x00, 0x34, 0x00, 0x2e,
+        0x00, 0x65, 0x00, 0x66, 0x00, 0x69, 0x00, 0x00, 0x00, 0x7f, 0xff, 0x04, 0x00,
+    ];
+
+    fn esp() -> Partition {
+        Partition {
+            number: 1,
+            start: 2048,
+            size: 1050624,
+            signature: parse_guid("c12a7328-f81f-11d2-ba4b-00a0c93ec93b").unwrap(),
+            gpt: true,
+        }
+    }
+
+    #[test]
+    fn encodes_an_entry_like_efibootmgr() {
+        let option = LoadOption {
+            attributes: LOAD_OPTION_ACTIVE,
+            description: "debian".to_string(),
+            file_path_list: build_file_path_list(&esp(), "/EFI/debian/shimx64.efi"),
+            optional_data: Vec::new(),
+        };
+        assert_eq!(option.to_bytes(), DEBIAN);
+
+        let parsed = LoadOption::parse(&DEBIAN).unwrap();
+        assert_eq!(parsed.description, "debian");
+        assert!(parsed.is_active() && !parsed.is_hidden());
+        assert_eq!(
+            parsed.loader_path().as_deref(),
+            Some("\\EFI\\debian\\shimx64.efi")
+        );
+        assert_eq!(
+            parsed.partition_guid().as_deref(),
+            Some("c12a7328-f81f-11d2-ba4b-00a0c93ec93b")
+        );
+        assert!(parsed.optional_data.is_empty());
+    }
+
+    #[test]
+    fn keeps_the_partition_when_changing_the_loader() {
+        let mut option = LoadOption::parse(&DEBIAN).unwrap();
+        option.set_attribute(LOAD_OPTION_HIDDEN, true);
+        option.set_loader_path("EFI/debian/grubx64.efi");
+        option.optional_data = ucs2_encode("quiet");
+
+        let parsed = LoadOption::parse(&option.to_bytes()).unwrap();
+        assert_eq!(parsed.attributes, LOAD_OPTION_ACTIVE | LOAD_OPTION_HIDDEN);
+        assert_eq!(
+            parsed.loader_path().as_deref(),
+            Some("\\EFI\\debian\\grubx64.efi")
+        );
+        assert_eq!(
+            parsed.partition_guid().as_deref(),
+            Some("c12a7328-f81f-11d2-ba4b-00a0c93ec93b")
+        );
+        assert_eq!(parsed.optional_data, ucs2_encode("quiet"));
+    }
+
+    #[test]
+    fn rejects_truncated_entries() {
+        let error = |data: &[u8]| LoadOption::parse(data).unwrap_err().to_string();
+        assert_eq!(error(&DEBIAN[..4]), "load option is too short");
+        assert_eq!(error(&DEBIAN[..15]), "unterminated load option description");
+        assert_eq!(
+            error(&DEBIAN[..DEBIAN.len() - 1]),
+            "load option device path is truncated"
+        );
+    }
+}
@@ -0,0 +1,296 @@
+mod efivar_helper;
+mod load_option;
+mod mount_helper;
+
+use crate::bootloader::{BootEntry, BootloaderKind, grub};
+use crate::common::dry_run;
+use crate::common::file_operations;
+use crate::common::output;
+use crate::platform::{FwEntrySpec, FwEntryUpdate, FwLoader, Handle, Platform, VolumeDir};
+use std::ffi::OsStr;
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const GRUB_BOOT_DIR: &str = "boot";
+/// Where the ESP is mounted when no firmware entry points at the boot loader
+const ESP_MOUNT_POINTS: [&str; 3] = ["efi", "boot/efi", "boot"];
+
+/// The system-wide configuration file, relative to the managed root
+const SYSTEM_CONFIG_PATH: &str = "etc/bootmgr/config.toml";
+/// The configuration file of the user, relative to their configuration directory
+const USER_CONFIG_PATH: &str = "bootmgr/config.toml";
+
+fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+fn rerun_as_root() -> Result<()> {
+    let exe_path = std::env::current_exe()?;
+    let status = Command::new("sudo")
+        .arg(exe_path)
+        .args(std::env::args().skip(1))
+        .status()?;
+    std::process::exit(status.code().unwrap_or(1));
+}
+
+/// Get the configuration directory of the user, the one who invoked sudo if any
+fn user_config_dir() -> Option<PathBuf> {
+    if let Some(user) = std::env::var_os("SUDO_USER") {
+        let name = std::ffi::CString::new(user.into_vec()).ok()?;
+        let passwd = unsafe { libc::getpwnam(name.as_ptr()) };
+        if passwd.is_null() {
+            return None;
+        }
+        let home = unsafe { std::ffi::CStr::from_ptr((*passwd).pw_dir) };
+        return Some(PathBuf::from(OsStr::from_bytes(home.to_bytes())).join(".config"));
+    }
+    match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")),
+    }
+}
+
+impl Platform for Handle {
+    fn check_permission(&self) -> Result<bool> {
+        Ok(is_root())
+    }
+
+    fn rerun_as_superuser(&self) -> Result<()> {
+        rerun_as_root()
+    }
+
+    fn mount_grub_dir(&mut self) -> Result<VolumeDir> {
+        if let Some(loc) = &self.grub_loc {
+            let path = PathBuf::from(loc);
+            if !grub::grub_path(&path, file_operations::GRUB_CFG_PATH).exists() {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!("GRUB not found in {}", loc),
+                ));
+            }
+            return Ok(VolumeDir { path });
+        }
+
+        let path = self.root().join(GRUB_BOOT_DIR);
+        if !grub::grub_path(&path, file_operations::GRUB_CFG_PATH).exists() {
+            return Err(Error::new(ErrorKind::NotFound, "GRUB location not found"));
+        }
+
+        self.grub_loc = Some(path.to_string_lossy().to_string());
+        Ok(VolumeDir { path })
+    }
+
+    fn find_grub_fw_entry(&mut self) -> Result<Option<String>> {
+        // Only the GRUB of the running system is known to be the one it was booted through
+        if self.mount_grub_dir()?.path != Path::new("/").join(GRUB_BOOT_DIR) {
+            return Ok(None);
+        }
+        Ok(self
+            .get_fw_loaders()?
+            .into_iter()
+            .find(|l| {
+                l.is_current
+                    && l.path.as_deref().and_then(BootloaderKind::from_loader_path)
+                        == Some(BootloaderKind::Grub)
+            })
+            .map(|l| l.id))
+    }
+
+    fn find_volume_root(&self, dir: &Path) -> Result<Option<PathBuf>> {
+        mount_helper::find_path_mount(dir)
+    }
+
+    fn find_uuid_volume(&self, uuid: &str) -> Result<Option<PathBuf>> {
+        mount_helper::find_uuid_mount(uuid)
+    }
+
+    fn show_fw_status(&self) -> Result<()> {
+        efivar_helper::show_efi_status()
+    }
+
+    fn get_boot_cmdline(&self) -> Result<Option<String>> {
+        Ok(Some(std::fs::read_to_string("/proc/cmdline")?))
+    }
+
+    fn show_fw_entry(&self) -> Result<()> {
+        efivar_helper::show_efi_list()
+    }
+
+    fn get_fw_loaders(&self) -> Result<Vec<FwLoader>> {
+        efivar_helper::get_efi_loaders()
+    }
+
+    fn get_fw_default(&self) -> Result<Option<String>> {
+        efivar_helper::get_efi_default()
+    }
+
+    fn get_fw_order(&self) -> Result<Vec<String>> {
+        efivar_helper::get_efi_order()
+    }
+
+    fn set_fw_entry(&self, entry: String) -> Result<()> {
+        output::note(&format!("Set EFI firmware entry to {}", entry));
+        efivar_helper::set_efi_entry(entry)
+    }
+
+    fn get_fw_oneshot(&self) -> Result<Option<String>> {
+        efivar_helper::get_efi_oneshot()
+    }
+
+    fn set_fw_oneshot(&self, entry: String) -> Result<()> {
+        output::note(&format!("Boot EFI firmware entry {} once", entry));
+        efivar_helper::set_efi_oneshot(entry)
+    }
+
+    fn create_fw_entry(&self, spec: &FwEntrySpec) -> Result<()> {
+        efivar_helper::create_efi_entry(spec)
+    }
+
+    fn delete_fw_entry(&self, entry: String) -> Result<()> {
+        efivar_helper::delete_efi_entry(entry)
+    }
+
+    fn modify_fw_entry(&self, entry: String, update: &FwEntryUpdate) -> Result<()> {
+        efivar_helper::modify_efi_entry(entry, update)
+    }
+
+    fn show_fw_timeout(&self) -> Result<()> {
+        efivar_helper::show_efi_timeout()
+    }
+
+    fn get_fw_timeout(&self) -> Result<Option<u16>> {
+        efivar_helper::get_efi_timeout()
+    }
+
+    fn set_fw_timeout(&self, timeout: u16) -> Result<()> {
+        output::note(&format!("Set EFI firmware timeout to {}s", timeout));
+        efivar_helper::set_efi_timeout(timeout)
+    }
+
+    fn get_efi_var(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        efivar_helper::read_efivar(name)
+    }
+
+    fn set_efi_var(&self, name: &str, data: &[u8]) -> Result<()> {
+        efivar_helper::write_efivar(name, data)
+    }
+
+    fn delete_efi_var(&self, name: &str) -> Result<()> {
+        efivar_helper::delete_efivar(name)
+    }
+
+    fn mount_loader_esp(&mut self, loader: &str, markers: &[&str]) -> Result<VolumeDir> {
+        if self.root_dir.is_none()
+            && let Ok(Some(partuuid)) = efivar_helper::find_loader_partition(loader)
+            && let Some(path) = mount_helper::find_partuuid_mount(&partuuid)?
+        {
+            return Ok(VolumeDir { path });
+        }
+        ESP_MOUNT_POINTS
+            .iter()
+            .map(|dir| self.root().join(dir))
+            .find(|dir| markers.iter().any(|m| dir.join(m).exists()))
+            .map(|path| VolumeDir { path })
+            .ok_or(Error::new(
+                ErrorKind::NotFound,
+                format!("{} not found on a mounted EFI system partition", loader),
+            ))
+    }
+
+    fn get_boot_var_names(&self) -> Result<Vec<String>> {
+        efivar_helper::get_boot_var_names()
+    }
+
+    fn export_fw_store(&self) -> Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    fn import_fw_store(&self, _data: &[u8]) -> Result<()> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "A BCD store can only be imported on Windows",
+        ))
+    }
+
+    fn list_fw_store(&self) -> Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    fn get_bootmgr_entries(&self) -> Result<Vec<BootEntry>> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "The Windows Boot Manager can only be managed from Windows",
+        ))
+    }
+
+    fn set_bootmgr_default(&self, _entry: &str) -> Result<()> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "The Windows Boot Manager can only be managed from Windows",
+        ))
+    }
+
+    fn get_config_files(&self) -> Result<Vec<PathBuf>> {
+        let mut files = vec![self.root().join(SYSTEM_CONFIG_PATH)];
+        files.extend(user_config_dir().map(|dir| dir.join(USER_CONFIG_PATH)));
+        Ok(files)
+    }
+
+    fn get_data_dir(&self) -> Result<PathBuf> {
+        Ok(self.root().join("var/lib/bootmgr"))
+    }
+
+    fn get_root_dir(&self) -> Result<PathBuf> {
+        Ok(self.root())
+    }
+
+    fn reboot(&self) -> Result<()> {
+        dry_run::run_command(&["systemctl", "reboot"], || {
+            output::note("Rebooting");
+            // Without systemd, e.g. in an initramfs shell, ask the kernel directly
+            if Command::new("systemctl")
+                .arg("reboot")
+                .status()
+                .is_ok_and(|s| s.success())
+            {
+                return Ok(());
+            }
+            unsafe {
+                libc::sync();
+                if libc::reboot(libc::RB_AUTOBOOT) != 0 {
+                    return Err(Error::last_os_error());
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn run_in_root(&self, command: &[String]) -> Result<()> {
+        let root = self.root().to_string_lossy().to_string();
+        let mut argv: Vec<&str> = match root == "/" {
+            true => Vec::new(),
+            false => vec!["chroot", root.as_str()],
+        };
+        argv.extend(command.iter().map(String::as_str));
+        dry_run::run_command(&argv, || {
+            output::note(&format!("Running {}", command.join(" ")));
+            let status = Command::new(argv[0]).args(&argv[1..]).status()?;
+            if !status.success() {
+                return Err(Error::other(format!(
+                    "{} failed with {}",
+                    command[0], status
+                )));
+            }
+            Ok(())
+        })
+    }
+}
+
+impl Handle {
+    /// The root directory of the managed installation, `/` unless `--root` is given
+    fn root(&self) -> PathBuf {
+        PathBuf::from(self.root_dir.as_deref().unwrap_or("/"))
+    }
+}
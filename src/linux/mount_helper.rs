@@ -0,0 +1,99 @@
+use std::fs;
+use std::io::Result;
+use std::path::{Path, PathBuf};
+
+const MOUNTS_PATH: &str = "/proc/self/mounts";
+const BY_UUID_DIR: &str = "/dev/disk/by-uuid";
+const BY_PARTUUID_DIR: &str = "/dev/disk/by-partuuid";
+
+/// A struct representing a mounted filesystem
+/// Fields:
+/// * `source` - The mounted device, e.g. `/dev/nvme0n1p2`
+/// * `target` - The directory the filesystem is mounted on
+struct Mount {
+    source: String,
+    target: PathBuf,
+}
+
+/// Find where the filesystem with the given uuid is mounted
+/// # Arguments
+/// * `uuid` - The filesystem uuid as used by `search --fs-uuid`
+/// # Returns
+/// * `Result<Option<PathBuf>>` - The mount point, None if the filesystem is unknown or not mounted
+pub(crate) fn find_uuid_mount(uuid: &str) -> Result<Option<PathBuf>> {
+    // FAT serials are listed in upper case, other uuids in lower case
+    let device = [uuid.to_string(), uuid.to_lowercase(), uuid.to_uppercase()]
+        .iter()
+        .find_map(|u| fs::canonicalize(Path::new(BY_UUID_DIR).join(u)).ok());
+    match device {
+        Some(device) => find_device_mount(&device),
+        None => Ok(None),
+    }
+}
+
+/// Find where the partition with the given GPT partition GUID is mounted
+/// # Arguments
+/// * `partuuid` - The partition GUID
+/// # Returns
+/// * `Result<Option<PathBuf>>` - The mount point, None if the partition is unknown or not mounted
+pub(crate) fn find_partuuid_mount(partuuid: &str) -> Result<Option<PathBuf>> {
+    match fs::canonicalize(Path::new(BY_PARTUUID_DIR).join(partuuid.to_lowercase())) {
+        Ok(device) => find_device_mount(&device),
+        Err(_) => Ok(None),
+    }
+}
+
+fn find_device_mount(device: &Path) -> Result<Option<PathBuf>> {
+    Ok(get_mounts()?
+        .into_iter()
+        .find(|m| fs::canonicalize(&m.source).is_ok_and(|s| s == device))
+        .map(|m| m.target))
+}
+
+/// Find the mount point of the filesystem holding a path
+/// # Arguments
+/// * `path` - An existing path
+/// # Returns
+/// * `Result<Option<PathBuf>>` - The deepest mount point containing the path
+pub(crate) fn find_path_mount(path: &Path) -> Result<Option<PathBuf>> {
+    let path = fs::canonicalize(path)?;
+    Ok(get_mounts()?
+        .into_iter()
+        .filter(|m| path.starts_with(&m.target))
+        .max_by_key(|m| m.target.as_os_str().len())
+        .map(|m| m.target))
+}
+
+fn get_mounts() -> Result<Vec<Mount>> {
+    let content = fs::read_to_string(MOUNTS_PATH)?;
+    Ok(content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(' ');
+            let source = unescape(fields.next()?);
+            let target = PathBuf::from(unescape(fields.next()?));
+            Some(Mount { source, target })
+        })
+        .collect())
+}
+
+/// Decode the octal escapes of /proc/self/mounts, e.g. `\040` for a space
+fn unescape(field: &str) -> String {
+    let mut ret = String::new();
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            ret.push(c);
+            continue;
+        }
+        let octal: String = chars.by_ref().take(3).collect();
+        match u8::from_str_radix(&octal, 8) {
+            Ok(b) => ret.push(b as char),
+            Err(_) => {
+                ret.push('\\');
+                ret.push_str(&octal);
+            }
+        }
+    }
+    ret
+}
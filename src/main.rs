@@ -1,49 +1,541 @@
-use crate::cli::Commands;
-use crate::interface::{Handle, Interface};
+use crate::bootloader::extlinux::Extlinux;
+use crate::bootloader::grub::{Grub, GrubDefaultsUpdate};
+use crate::bootloader::refind::Refind;
+use crate::bootloader::systemd_boot::SystemdBoot;
+use crate::bootloader::{
+    BootEntry, Bootloader, BootloaderKind, detect_bootloader, make_bootloader,
+};
+use crate::cli::{Cli, Commands, FwCommands, MenuCommands, ProfileCommands, SdBootCommands};
+use crate::common::backup_archive::BackupPart;
+use crate::common::config::Config;
+use crate::common::dry_run;
+use crate::common::grub_menu::GrubMenuAction;
+use crate::common::grub_selector::resolve_grub_selector;
+use crate::common::journal;
+use crate::common::output::{self, OutputFormat};
+use crate::common::prompt;
+use crate::common::selector::select;
+use crate::platform::{FwEntrySpec, FwEntryUpdate, Handle, Platform};
+use crate::reboot::RebootTarget;
+use crate::transaction::Transaction;
 use clap::Parser;
 use std::io::{Error, ErrorKind, Result};
 
+mod backup;
+mod bootloader;
 mod cli;
 mod common;
-mod interface;
+mod history;
+mod plan;
+mod platform;
+mod profile;
+mod reboot;
+mod targets;
+mod transaction;
 
+#[cfg(target_os = "linux")]
+mod linux;
 #[cfg(windows)]
 mod win;
 
-fn main() -> Result<()> {
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
     let mut handle = Handle::new();
-    let cmd = Commands::parse();
+    let cli = Cli::parse();
+    handle.root_dir = cli.root;
+    if cli.dry_run {
+        dry_run::enable();
+    }
+    let mut grub = Grub::default();
+    if let Some(platform) = cli.grub_platform {
+        grub.ctx.platform = platform;
+    }
+    if let Some(cpu) = cli.grub_cpu {
+        grub.ctx.cpu = cpu;
+    }
+    for feature in &cli.grub_feature {
+        let (name, value) = split_assignment(feature)?;
+        grub.ctx.set_feature(name, value);
+    }
+    for var in &cli.grub_env {
+        let (name, value) = split_assignment(var)?;
+        grub.ctx.set_env(name, value);
+    }
+    let loader = cli.loader.as_deref().and_then(BootloaderKind::from_name);
+    let config = Config::load(&handle.get_config_files()?)?;
+    handle.grub_keywords = config.grub_keywords.clone();
+    handle.grub_loc = config.grub_location.clone();
+    output::set_format(
+        cli.format
+            .as_deref()
+            .and_then(OutputFormat::from_name)
+            .or(config.format)
+            .unwrap_or(OutputFormat::Text),
+    );
+
+    // Undo reverts recorded changes and is not recorded itself
+    if !matches!(
+        cli.command,
+        Commands::History { .. } | Commands::Undo { .. }
+    ) {
+        journal::begin();
+    }
+    let result = run_command(cli.command, &mut handle, &grub, &config, loader);
+    save_changes(&handle);
+    result?;
+    if dry_run::is_enabled() {
+        output::note("Dry run, nothing was changed");
+    }
+    Ok(())
+}
+
+/// Append the changes recorded since `journal::begin` to the journal
+///
+/// Recording stops, so changes are saved once even if this is called again.
+fn save_changes(platform: &dyn Platform) {
+    let changes = journal::finish();
+    if !changes.is_empty()
+        && let Err(e) = history::save_record(platform, changes)
+    {
+        eprintln!("Failed to record the changes in the journal: {}", e);
+    }
+}
+
+/// Run a command of the command line
+/// # Arguments
+/// * `cmd` - The command
+/// * `grub` - The GRUB boot loader with the settings given on the command line
+/// * `config` - The settings of the configuration files
+/// * `loader` - The boot loader given by `--loader`
+fn run_command(
+    cmd: Commands,
+    handle: &mut Handle,
+    grub: &Grub,
+    config: &Config,
+    loader: Option<BootloaderKind>,
+) -> Result<()> {
     let mut arg_p = false;
 
     match cmd {
         Commands::List {
-            grub,
+            grub: grub_p,
             firmware,
+            systemd_boot,
+            refind,
+            extlinux,
             description,
         } => {
+            arg_p = true;
             handle.grub_desc = description;
-            if grub {
-                arg_p = true;
-                handle.show_grub_entry()?
+            let mut layers: Vec<Option<BootloaderKind>> = [
+                (grub_p, Some(BootloaderKind::Grub)),
+                (firmware, None),
+                (systemd_boot, Some(BootloaderKind::SystemdBoot)),
+                (refind, Some(BootloaderKind::Refind)),
+                (extlinux, Some(BootloaderKind::Extlinux)),
+            ]
+            .into_iter()
+            .filter_map(|(selected, layer)| selected.then_some(layer))
+            .collect();
+            let detected = layers.is_empty();
+            if detected {
+                layers.push(Some(open_bootloader(handle, loader, grub)?.kind()));
             }
-            if firmware {
-                arg_p = true;
-                handle.show_fw_entry()?
+
+            if output::is_json() {
+                let mut menus = Vec::new();
+                for layer in layers {
+                    let menu = get_menu(handle, grub, config, layer)?;
+                    menus.push(output::menu_json(&menu.name, &menu.entries, &menu.aliases));
+                }
+                println!("[{}]", menus.join(","));
+                return Ok(());
+            }
+            for layer in layers {
+                match layer {
+                    None => handle.show_fw_entry()?,
+                    Some(kind) if detected => make_bootloader(kind, grub).show_entries(handle)?,
+                    Some(BootloaderKind::Grub) => grub.show_grub_entry(handle)?,
+                    Some(BootloaderKind::SystemdBoot) => SystemdBoot.show_entries(handle)?,
+                    Some(BootloaderKind::Refind) => Refind.show_refind_entry(handle)?,
+                    Some(BootloaderKind::Extlinux) => Extlinux.show_extlinux_entry(handle)?,
+                }
+                let has_aliases = match layer {
+                    None => !config.firmware_aliases.is_empty(),
+                    Some(BootloaderKind::Grub) => !config.grub_aliases.is_empty(),
+                    Some(_) => false,
+                };
+                if has_aliases {
+                    let menu = get_menu(handle, grub, config, layer)?;
+                    show_aliases(&menu.entries, &menu.aliases);
+                }
             }
         }
         Commands::Set {
-            grub,
+            grub: grub_entry,
+            entry,
+            sticky,
+            force,
+            persist,
             firmware,
+            systemd_boot,
+            oneshot,
+            refind,
+            refind_timeout,
+            extlinux,
+            target,
             description,
         } => {
             handle.grub_desc = description;
-            if let Some(grub_entry) = grub {
+            let mut transaction = Transaction::default();
+            if let Some(target) = target {
                 arg_p = true;
-                handle.set_grub_entry(grub_entry)?
+                let targets = targets::collect_targets(handle, grub, loader)?;
+                let target = select(&targets, &target, "target")?;
+                targets::stage_default(&mut transaction, handle, grub, target)?;
+                if transaction.is_empty() {
+                    output::note(&format!("{} is already booted by default", target.title));
+                }
+            }
+            if let Some(grub_entry) = grub_entry.map(|e| config.grub_selector(&e)) {
+                let selector = grub_entry.clone();
+                transaction.stage(
+                    format!("set the default GRUB entry to {}", grub_entry),
+                    move |p| grub.set_grub_entry(p, &grub_entry, sticky, force),
+                    move |p| {
+                        make_bootloader(BootloaderKind::Grub, grub).is_selected(p, &selector, false)
+                    },
+                );
+                if persist {
+                    transaction.stage(
+                        "set GRUB_DEFAULT=saved".to_string(),
+                        move |p| {
+                            let update = GrubDefaultsUpdate {
+                                default: Some("saved".to_string()),
+                                ..Default::default()
+                            };
+                            grub.update_grub_defaults(p, &update, true)
+                        },
+                        move |p| {
+                            Ok(grub.get_grub_default(p, "GRUB_DEFAULT")?.as_deref()
+                                == Some("saved"))
+                        },
+                    );
+                }
+            }
+            if let Some(entry) = entry {
+                let kind = match loader {
+                    Some(kind) => kind,
+                    None => detect_bootloader(handle)?,
+                };
+                let entry = match kind {
+                    BootloaderKind::Grub => config.grub_selector(&entry),
+                    _ => entry,
+                };
+                let selector = entry.clone();
+                transaction.stage(
+                    format!(
+                        "{} the {} entry {}",
+                        if oneshot {
+                            "boot once"
+                        } else {
+                            "set the default"
+                        },
+                        kind.title(),
+                        entry
+                    ),
+                    move |p| {
+                        let mut bootloader = make_bootloader(kind, grub);
+                        match oneshot {
+                            true => bootloader.set_oneshot(p, &entry),
+                            false => bootloader.set_default(p, &entry),
+                        }
+                    },
+                    move |p| make_bootloader(kind, grub).is_selected(p, &selector, oneshot),
+                );
+                if kind == BootloaderKind::Grub && oneshot {
+                    stage_grub_refresh(&mut transaction, grub);
+                }
+            }
+            if let Some(fw_entry) = firmware.map(|e| config.firmware_selector(&e)) {
+                let selector = fw_entry.clone();
+                transaction.stage(
+                    format!("set the firmware entry {} first", fw_entry),
+                    move |p| p.set_fw_entry(fw_entry),
+                    move |p| {
+                        let loaders = p.get_fw_loaders()?;
+                        let id = select(&loaders, &selector, "firmware")?.id.clone();
+                        Ok(p.get_fw_default()? == Some(id))
+                    },
+                );
+            }
+            if let Some(sdboot_entry) = systemd_boot {
+                let selector = sdboot_entry.clone();
+                transaction.stage(
+                    format!(
+                        "{} the systemd-boot entry {}",
+                        if oneshot {
+                            "boot once"
+                        } else {
+                            "set the default"
+                        },
+                        sdboot_entry
+                    ),
+                    move |p| match oneshot {
+                        true => SystemdBoot.set_oneshot(p, &sdboot_entry),
+                        false => SystemdBoot.set_default(p, &sdboot_entry),
+                    },
+                    move |p| SystemdBoot.is_selected(p, &selector, oneshot),
+                );
+            }
+            if let Some(refind_entry) = refind {
+                let selector = refind_entry.clone();
+                transaction.stage(
+                    format!("set the default rEFInd entry {}", refind_entry),
+                    move |p| Refind.set_default(p, &refind_entry),
+                    move |p| Refind.is_selected(p, &selector, false),
+                );
             }
-            if let Some(fw_entry) = firmware {
+            if let Some(timeout) = refind_timeout {
+                transaction.stage(
+                    format!("set the rEFInd timeout to {}", timeout),
+                    move |p| Refind.set_timeout(p, &timeout.to_string()),
+                    move |p| Ok(Refind.get_timeout(p)? == Some(timeout.to_string())),
+                );
+            }
+            if let Some(extlinux_entry) = extlinux {
+                let selector = extlinux_entry.clone();
+                transaction.stage(
+                    format!("set the default extlinux entry {}", extlinux_entry),
+                    move |p| Extlinux.set_default(p, &extlinux_entry),
+                    move |p| Extlinux.is_selected(p, &selector, false),
+                );
+            }
+            if !transaction.is_empty() {
                 arg_p = true;
-                handle.set_fw_entry(fw_entry)?
+                let applied = transaction.descriptions();
+                transaction.commit(handle)?;
+                if output::is_json() {
+                    println!(
+                        "{{\"applied\":{},\"dry_run\":{}}}",
+                        output::json_array(&applied),
+                        dry_run::is_enabled()
+                    );
+                }
+            }
+        }
+        Commands::Targets { description } => {
+            arg_p = true;
+            handle.grub_desc = description;
+            targets::show_targets(handle, grub, loader)?
+        }
+        Commands::Show {
+            grub: grub_entry,
+            systemd_boot,
+            refind,
+            extlinux,
+            description,
+        } => {
+            handle.grub_desc = description;
+            if let Some(grub_entry) = grub_entry {
+                arg_p = true;
+                grub.show_grub_details(handle, &grub_entry)?
+            }
+            if let Some(sdboot_entry) = systemd_boot {
+                arg_p = true;
+                SystemdBoot.show_sdboot_details(handle, &sdboot_entry)?
+            }
+            if let Some(refind_entry) = refind {
+                arg_p = true;
+                Refind.show_refind_details(handle, &refind_entry)?
+            }
+            if let Some(extlinux_entry) = extlinux {
+                arg_p = true;
+                Extlinux.show_extlinux_details(handle, &extlinux_entry)?
+            }
+        }
+        Commands::Status => {
+            arg_p = true;
+            match handle.show_fw_status() {
+                Err(e) if e.kind() == ErrorKind::NotFound => println!("Firmware: {}", e),
+                result => result?,
+            }
+            grub.show_status(handle)?
+        }
+        Commands::Timeout { timeout } => {
+            arg_p = true;
+            let mut bootloader = open_bootloader(handle, loader, grub)?;
+            match timeout {
+                Some(timeout) => bootloader.set_timeout(handle, &timeout)?,
+                None => match bootloader.get_timeout(handle)? {
+                    Some(timeout) => println!("{} timeout: {}", bootloader.kind().title(), timeout),
+                    None => println!("{} timeout: not set", bootloader.kind().title()),
+                },
+            }
+        }
+        Commands::Detect => {
+            arg_p = true;
+            show_detected_bootloader(handle, loader, grub)?
+        }
+        Commands::Backup { file } => {
+            arg_p = true;
+            backup::create_backup(handle, grub, &file)?
+        }
+        Commands::Restore {
+            file,
+            grub: grub_p,
+            efi,
+            bcd,
+            yes,
+        } => {
+            arg_p = true;
+            let parts: Vec<BackupPart> = [
+                (grub_p, BackupPart::Grub),
+                (efi, BackupPart::Efi),
+                (bcd, BackupPart::Bcd),
+            ]
+            .into_iter()
+            .filter_map(|(selected, part)| selected.then_some(part))
+            .collect();
+            backup::restore_backup(handle, grub, &file, &parts, yes)?
+        }
+        Commands::History { count } => {
+            arg_p = true;
+            history::show_history(handle, count)?
+        }
+        Commands::Undo { count } => {
+            arg_p = true;
+            history::undo(handle, count)?
+        }
+        Commands::Plan { file } => {
+            arg_p = true;
+            plan::show_plan(handle, grub, &file)?
+        }
+        Commands::Apply { file } => {
+            arg_p = true;
+            plan::apply_state(handle, grub, &file)?
+        }
+        Commands::Reboot {
+            target,
+            firmware,
+            grub: grub_entry,
+            yes,
+        } => {
+            arg_p = true;
+            let target = RebootTarget::resolve(
+                handle,
+                grub,
+                config,
+                loader,
+                target.as_deref(),
+                firmware.as_deref(),
+                grub_entry.as_deref(),
+            )?;
+            // The machine goes down before run() could save the changes
+            reboot::reboot_into(handle, grub, &target, yes, prompt::confirm, |p| {
+                save_changes(p);
+                p.reboot()
+            })?
+        }
+        Commands::Profile { command } => {
+            arg_p = true;
+            match command {
+                ProfileCommands::Use { name } => profile::use_profile(handle, grub, config, &name)?,
+                ProfileCommands::List => profile::show_profiles(handle, grub, config)?,
+            }
+        }
+        Commands::Check { description } => {
+            arg_p = true;
+            handle.grub_desc = description;
+            grub.check_grub_entries(handle)?
+        }
+        Commands::GrubConfig {
+            default,
+            timeout,
+            savedefault,
+            disable_submenu,
+            no_update,
+        } => {
+            arg_p = true;
+            let update = GrubDefaultsUpdate {
+                default,
+                timeout,
+                savedefault,
+                disable_submenu,
+            };
+            grub.update_grub_defaults(handle, &update, !no_update)?
+        }
+        Commands::Menu {
+            command,
+            description,
+        } => {
+            arg_p = true;
+            handle.grub_desc = description;
+            grub.set_grub_menu(
+                handle,
+                match command {
+                    MenuCommands::ClearFail => GrubMenuAction::ClearFail,
+                    MenuCommands::ShowOnce => GrubMenuAction::ShowOnce,
+                    MenuCommands::Hide => GrubMenuAction::Hide,
+                },
+            )?
+        }
+        Commands::SdBoot { command } => {
+            arg_p = true;
+            match command {
+                SdBootCommands::Timeout { timeout } => match timeout {
+                    Some(timeout) => SystemdBoot.set_timeout(handle, &timeout)?,
+                    None => SystemdBoot.show_sdboot_timeout(handle)?,
+                },
+            }
+        }
+        Commands::Fw { command } => {
+            arg_p = true;
+            match command {
+                FwCommands::Create {
+                    disk,
+                    part,
+                    loader_path,
+                    label,
+                    args,
+                } => handle.create_fw_entry(&FwEntrySpec {
+                    disk,
+                    part,
+                    loader: loader_path,
+                    label,
+                    args,
+                })?,
+                FwCommands::Delete { entry } => handle.delete_fw_entry(entry)?,
+                FwCommands::Modify {
+                    entry,
+                    label,
+                    loader_path,
+                    args,
+                    active,
+                    inactive,
+                    hidden,
+                    visible,
+                } => handle.modify_fw_entry(
+                    entry,
+                    &FwEntryUpdate {
+                        label,
+                        loader: loader_path,
+                        args,
+                        active: flag_pair(active, inactive),
+                        hidden: flag_pair(hidden, visible),
+                    },
+                )?,
+                FwCommands::Timeout { seconds } => match seconds {
+                    Some(seconds) => handle.set_fw_timeout(seconds)?,
+                    None => handle.show_fw_timeout()?,
+                },
+                FwCommands::Setup => handle.boot_to_fw_setup()?,
             }
         }
     }
@@ -55,3 +547,170 @@ fn main() -> Result<()> {
     }
     Ok(())
 }
+
+/// Stage re-resolving the stored GRUB selector
+///
+/// Only changes to GRUB that keep its default entry refresh it, setting the
+/// default stores a new selector or none, and read-only commands never write
+/// grubenv.
+fn stage_grub_refresh<'a>(transaction: &mut Transaction<'a>, grub: &'a Grub) {
+    transaction.stage(
+        "re-resolve the stored GRUB selector".to_string(),
+        move |p| grub.refresh_grub_selector(p),
+        move |p| grub.grub_selector_is_current(p),
+    );
+}
+
+/// Get the boot loader to manage, the one given by `--loader` or the detected one
+/// # Arguments
+/// * `loader` - The boot loader given by `--loader`
+/// * `grub` - The GRUB settings given on the command line
+fn open_bootloader(
+    handle: &mut Handle,
+    loader: Option<BootloaderKind>,
+    grub: &Grub,
+) -> Result<Box<dyn Bootloader>> {
+    let kind = match loader {
+        Some(kind) => kind,
+        None => detect_bootloader(handle)?,
+    };
+    Ok(make_bootloader(kind, grub))
+}
+
+/// A struct representing a menu listed by `list`
+/// Fields:
+/// * `name` - The boot loader or `firmware`
+/// * `entries` - The entries of the menu
+/// * `aliases` - The configured aliases of the menu with the ids they resolve to
+struct Menu {
+    name: String,
+    entries: Vec<BootEntry>,
+    aliases: Vec<(String, String)>,
+}
+
+/// Get a menu listed by `list` and the entries the configured aliases of it point at
+/// # Arguments
+/// * `grub` - The GRUB settings given on the command line
+/// * `config` - The settings of the configuration files
+/// * `layer` - The boot loader, None for the firmware
+/// # Returns
+/// * `Result<Menu>` - The menu with the alias names and the ids they resolve to
+fn get_menu(
+    handle: &mut Handle,
+    grub: &Grub,
+    config: &Config,
+    layer: Option<BootloaderKind>,
+) -> Result<Menu> {
+    let mut aliases = Vec::new();
+    let Some(kind) = layer else {
+        let loaders = handle.get_fw_loaders()?;
+        let default = handle.get_fw_default()?;
+        for (name, selector) in &config.firmware_aliases {
+            match select(&loaders, selector, "firmware") {
+                Ok(found) => aliases.push((name.clone(), found.id.clone())),
+                Err(e) => eprintln!("Alias {}: {}", name, e),
+            }
+        }
+        let entries = loaders
+            .into_iter()
+            .map(|l| BootEntry {
+                is_default: default.as_deref() == Some(l.id.as_str()),
+                id: l.id,
+                title: l.description,
+                is_oneshot: false,
+            })
+            .collect();
+        return Ok(Menu {
+            name: "firmware".to_string(),
+            entries,
+            aliases,
+        });
+    };
+    if kind == BootloaderKind::Grub && !config.grub_aliases.is_empty() {
+        let grub_entries = grub.get_grub_entry(handle)?;
+        for (name, selector) in &config.grub_aliases {
+            match resolve_grub_selector(&grub_entries, selector) {
+                Ok(found) => aliases.push((name.clone(), found.entry_id.clone())),
+                Err(e) => eprintln!("Alias {}: {}", name, e),
+            }
+        }
+    }
+    let entries = make_bootloader(kind, grub).list_entries(handle)?;
+    Ok(Menu {
+        name: kind.title().to_string(),
+        entries,
+        aliases,
+    })
+}
+
+/// Show the entries the configured aliases of a menu point at
+fn show_aliases(entries: &[BootEntry], aliases: &[(String, String)]) {
+    if aliases.is_empty() {
+        return;
+    }
+    println!("Aliases:");
+    for (name, id) in aliases {
+        match entries.iter().find(|e| e.id == *id) {
+            Some(entry) => println!("  {} -> {} ({})", name, entry.title, id),
+            None => println!("  {} -> {}", name, id),
+        }
+    }
+}
+
+/// Show the boot loader each firmware entry starts, then the one bootmgr manages
+/// # Arguments
+/// * `loader` - The boot loader given by `--loader`
+/// * `grub` - The GRUB settings given on the command line
+fn show_detected_bootloader(
+    handle: &mut Handle,
+    loader: Option<BootloaderKind>,
+    grub: &Grub,
+) -> Result<()> {
+    match handle.get_fw_loaders() {
+        Ok(loaders) => {
+            println!("The firmware boot loaders:");
+            for i in loaders {
+                println!(
+                    "{} {} ({}): {}",
+                    if i.is_current { "*" } else { " " },
+                    i.description,
+                    i.id,
+                    i.path
+                        .as_deref()
+                        .and_then(BootloaderKind::from_loader_path)
+                        .map_or("unknown", |kind| kind.title())
+                );
+            }
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => println!("Firmware: {}", e),
+        Err(e) => return Err(e),
+    }
+    let mut bootloader = open_bootloader(handle, loader, grub)?;
+    println!(
+        "Boot loader: {}{}",
+        bootloader.kind().title(),
+        if loader.is_some() { " (--loader)" } else { "" }
+    );
+    match bootloader.get_default(handle)? {
+        Some(entry) => println!("Default entry: {} ({})", entry.title, entry.id),
+        None => println!("Default entry: not set"),
+    }
+    Ok(())
+}
+
+/// Turn a pair of mutually exclusive flags into an optional switch
+fn flag_pair(on: bool, off: bool) -> Option<bool> {
+    match (on, off) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
+}
+
+/// Split a `NAME=VALUE` argument
+fn split_assignment(arg: &str) -> Result<(&str, &str)> {
+    arg.split_once('=').ok_or(Error::new(
+        ErrorKind::InvalidInput,
+        format!("Expected NAME=VALUE, got '{}'", arg),
+    ))
+}
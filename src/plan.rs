@@ -0,0 +1,284 @@
+use crate::bootloader::grub::Grub;
+use crate::bootloader::{BootEntry, Bootloader};
+use crate::common::desired_state::{DesiredState, OneshotPolicy};
+use crate::common::grub_selector::{STORED_SELECTOR_KEY, resolve_grub_selector};
+use crate::common::selector::select;
+use crate::platform::Platform;
+use crate::transaction::Transaction;
+use std::io::{Error, ErrorKind, Result};
+
+/// A struct representing a setting of the desired state compared with the machine
+/// Fields:
+/// * `name` - The setting as written in the file, e.g. `firmware.order`
+/// * `current` - The value on the machine
+/// * `desired` - The value the setting converges to
+struct PlannedSetting {
+    name: &'static str,
+    current: String,
+    desired: String,
+}
+
+impl PlannedSetting {
+    fn in_sync(&self) -> bool {
+        self.current == self.desired
+    }
+}
+
+/// Show how the machine differs from a desired state file
+/// # Arguments
+/// * `grub` - The GRUB boot loader
+/// * `file` - The desired state file
+pub(crate) fn show_plan(platform: &mut dyn Platform, grub: &Grub, file: &str) -> Result<()> {
+    let state = read_state(file)?;
+    let (settings, _) = build_plan(platform, grub, &state)?;
+    show_settings(&settings);
+    Ok(())
+}
+
+/// Change the machine to match a desired state file
+///
+/// Only the settings that differ are changed, all of them in one transaction,
+/// so applying the same file again changes nothing.
+/// # Arguments
+/// * `grub` - The GRUB boot loader
+/// * `file` - The desired state file
+pub(crate) fn apply_state(platform: &mut dyn Platform, grub: &Grub, file: &str) -> Result<()> {
+    let state = read_state(file)?;
+    let (settings, transaction) = build_plan(platform, grub, &state)?;
+    show_settings(&settings);
+    if transaction.is_empty() {
+        println!("Nothing to change, the machine matches {}", file);
+        return Ok(());
+    }
+    transaction.commit(platform)?;
+    let changed = settings.iter().filter(|s| !s.in_sync()).count();
+    println!(
+        "Applied {} change{}",
+        changed,
+        if changed == 1 { "" } else { "s" }
+    );
+    Ok(())
+}
+
+fn read_state(file: &str) -> Result<DesiredState> {
+    let content = std::fs::read_to_string(file)
+        .map_err(|e| Error::new(e.kind(), format!("{}: {}", file, e)))?;
+    DesiredState::parse(&content).map_err(|e| Error::new(e.kind(), format!("{}: {}", file, e)))
+}
+
+fn show_settings(settings: &[PlannedSetting]) {
+    if settings.is_empty() {
+        println!("The file manages no setting");
+        return;
+    }
+    for setting in settings {
+        match setting.in_sync() {
+            true => println!("  {}: {} (unchanged)", setting.name, setting.current),
+            false => println!(
+                "~ {}: {} -> {}",
+                setting.name, setting.current, setting.desired
+            ),
+        }
+    }
+    let changed = settings.iter().filter(|s| !s.in_sync()).count();
+    println!(
+        "Plan: {} to change, {} unchanged",
+        changed,
+        settings.len() - changed
+    );
+}
+
+/// Compare the desired state with the machine and stage the changes it needs
+/// # Arguments
+/// * `grub` - The GRUB boot loader
+/// * `state` - The desired state
+/// # Returns
+/// * `Result<(Vec<PlannedSetting>, Transaction)>` - The managed settings and the steps changing those that differ
+fn build_plan<'a>(
+    platform: &mut dyn Platform,
+    grub: &'a Grub,
+    state: &DesiredState,
+) -> Result<(Vec<PlannedSetting>, Transaction<'a>)> {
+    let mut settings = Vec::new();
+    let mut transaction = Transaction::default();
+
+    if let Some(order) = &state.firmware_order {
+        let loaders = platform.get_fw_loaders()?;
+        let mut ids: Vec<String> = Vec::new();
+        for selector in order {
+            let id = select(&loaders, selector, "firmware")?.id.clone();
+            if ids.contains(&id) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("firmware.order names {} twice", id),
+                ));
+            }
+            ids.push(id);
+        }
+        let current = platform.get_fw_order()?;
+        let desired: Vec<String> = ids
+            .iter()
+            .chain(current.iter().filter(|id| !ids.contains(id)))
+            .cloned()
+            .collect();
+        let setting = PlannedSetting {
+            name: "firmware.order",
+            current: current.join(", "),
+            desired: desired.join(", "),
+        };
+        if !setting.in_sync() {
+            let leading = ids.clone();
+            transaction.stage(
+                format!("set the firmware boot order to {}", ids.join(", ")),
+                move |p| {
+                    for id in ids.into_iter().rev() {
+                        p.set_fw_entry(id)?;
+                    }
+                    Ok(())
+                },
+                move |p| Ok(p.get_fw_order()?.starts_with(&leading)),
+            );
+        }
+        settings.push(setting);
+    }
+
+    if let Some(timeout) = state.firmware_timeout {
+        let describe = |t: Option<u16>| t.map_or("not set".to_string(), |t| format!("{}s", t));
+        let setting = PlannedSetting {
+            name: "firmware.timeout",
+            current: describe(platform.get_fw_timeout()?),
+            desired: describe(Some(timeout)),
+        };
+        if !setting.in_sync() {
+            transaction.stage(
+                format!("set the firmware timeout to {}s", timeout),
+                move |p| p.set_fw_timeout(timeout),
+                move |p| Ok(p.get_fw_timeout()? == Some(timeout)),
+            );
+        }
+        settings.push(setting);
+    }
+
+    if state.grub_default.is_some() || state.grub_oneshot.is_some() {
+        let entries = grub.get_grub_entry(platform)?;
+        let env = grub.read_grub_env(platform)?;
+        let describe = |id: Option<&str>| match id {
+            Some(id) => match entries.iter().find(|e| e.is_named_by(id)) {
+                Some(e) => format!("{} ({})", e.entry_name, e.saved_id()),
+                None => id.to_string(),
+            },
+            None => "not set".to_string(),
+        };
+
+        if let Some(selector) = &state.grub_default {
+            let id = resolve_grub_selector(&entries, selector)?.saved_id();
+            let describe_default = |id: Option<&str>, stored: Option<&str>| match stored {
+                Some(stored) => format!("{}, selector '{}'", describe(id), stored),
+                None => describe(id),
+            };
+            let setting = PlannedSetting {
+                name: "grub.default",
+                current: describe_default(
+                    entries
+                        .iter()
+                        .find(|e| e.entry_is_default)
+                        .map(|e| e.saved_id())
+                        .as_deref(),
+                    env.get(STORED_SELECTOR_KEY),
+                ),
+                desired: describe_default(
+                    Some(&id),
+                    state.grub_sticky.then_some(selector.as_str()),
+                ),
+            };
+            if !setting.in_sync() {
+                let selector = selector.clone();
+                let sticky = state.grub_sticky;
+                transaction.stage(
+                    format!("set the default GRUB entry to {}", selector),
+                    move |p| grub.set_grub_entry(p, &selector, sticky, false),
+                    move |p| Ok(grub.read_grub_env(p)?.get("saved_entry") == Some(id.as_str())),
+                );
+            }
+            settings.push(setting);
+        }
+
+        if let Some(policy) = &state.grub_oneshot {
+            let desired = match policy {
+                OneshotPolicy::Clear => None,
+                OneshotPolicy::Entry(selector) => {
+                    Some(resolve_grub_selector(&entries, selector)?.saved_id())
+                }
+            };
+            let current = env.get("next_entry").filter(|id| !id.is_empty());
+            let setting = PlannedSetting {
+                name: "grub.oneshot",
+                current: describe(current),
+                desired: describe(desired.as_deref()),
+            };
+            if !setting.in_sync() {
+                let check = desired.clone();
+                let selector = match policy {
+                    OneshotPolicy::Clear => None,
+                    OneshotPolicy::Entry(selector) => Some(selector.clone()),
+                };
+                let mut oneshot_grub = Grub {
+                    ctx: grub.ctx.clone(),
+                };
+                transaction.stage(
+                    match policy {
+                        OneshotPolicy::Clear => "clear the GRUB one-shot entry".to_string(),
+                        OneshotPolicy::Entry(selector) => {
+                            format!("boot the GRUB entry {} once", selector)
+                        }
+                    },
+                    move |p| match selector {
+                        Some(selector) => oneshot_grub.set_oneshot(p, &selector),
+                        None => grub
+                            .update_grub_env(p, |env| {
+                                env.remove("next_entry");
+                                Ok(())
+                            })
+                            .map(|_| ()),
+                    },
+                    move |p| {
+                        let env = grub.read_grub_env(p)?;
+                        Ok(env.get("next_entry").filter(|id| !id.is_empty()) == check.as_deref())
+                    },
+                );
+            }
+            settings.push(setting);
+        }
+    }
+
+    if let Some(selector) = &state.windows_default {
+        let entries = platform.get_bootmgr_entries()?;
+        let target = select(&entries, selector, "Windows Boot Manager")?;
+        let describe = |e: Option<&BootEntry>| {
+            e.map_or("not set".to_string(), |e| format!("{} ({})", e.title, e.id))
+        };
+        let setting = PlannedSetting {
+            name: "windows.default",
+            current: describe(entries.iter().find(|e| e.is_default)),
+            desired: describe(Some(target)),
+        };
+        if !setting.in_sync() {
+            let id = target.id.clone();
+            transaction.stage(
+                format!("set the Windows Boot Manager default to {}", selector),
+                {
+                    let id = id.clone();
+                    move |p| p.set_bootmgr_default(&id)
+                },
+                move |p| {
+                    Ok(p.get_bootmgr_entries()?
+                        .iter()
+                        .any(|e| e.id == id && e.is_default))
+                },
+            );
+        }
+        settings.push(setting);
+    }
+
+    Ok((settings, transaction))
+}
@@ -0,0 +1,630 @@
+use crate::bootloader::BootEntry;
+use crate::common::dry_run;
+use crate::common::output;
+use crate::common::selector::Selectable;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+const EFI_OS_INDICATIONS_BOOT_TO_FW_UI: u64 = 0x0000_0000_0000_0001;
+
+/// The operating system the boot configuration is managed from
+///
+/// A platform provides permissions, access to the volumes boot loaders live
+/// on and the firmware boot entries and variables. Boot loaders are built on
+/// top of it, see [`crate::bootloader::Bootloader`].
+pub(crate) trait Platform {
+    /// Check if the current user has permission to run the program
+    /// # Returns
+    /// * `bool` - true if the user has permission, false otherwise
+    fn check_permission(&self) -> Result<bool>;
+
+    /// Rerun the program as a superuser
+    fn rerun_as_superuser(&self) -> Result<()>;
+
+    /// Find the directory holding the `grub` or `grub2` directory
+    /// # Returns
+    /// * `Result<VolumeDir>` - The directory, mounted while it lives
+    fn mount_grub_dir(&mut self) -> Result<VolumeDir>;
+
+    /// Find the firmware entry starting the GRUB of [`Platform::mount_grub_dir`]
+    /// # Returns
+    /// * `Result<Option<String>>` - The id of the entry, None if it cannot be told apart from other entries starting GRUB
+    fn find_grub_fw_entry(&mut self) -> Result<Option<String>>;
+
+    /// Find the root of the filesystem holding a directory
+    /// # Arguments
+    /// * `dir` - A directory returned by one of the mount methods
+    /// # Returns
+    /// * `Result<Option<PathBuf>>` - The root of the filesystem, None if it is unknown
+    fn find_volume_root(&self, dir: &Path) -> Result<Option<PathBuf>>;
+
+    /// Find where the filesystem with the given uuid is reachable
+    /// # Arguments
+    /// * `uuid` - The filesystem uuid as used by `search --fs-uuid`
+    /// # Returns
+    /// * `Result<Option<PathBuf>>` - The root of the filesystem, None if it is not reachable
+    fn find_uuid_volume(&self, uuid: &str) -> Result<Option<PathBuf>>;
+
+    /// Find the EFI system partition a boot loader is installed on
+    ///
+    /// It is the partition of the firmware entry starting the loader. On
+    /// Linux the usual mount points are searched when there is no such entry.
+    /// # Arguments
+    /// * `loader` - A case-insensitive part of the loader path, e.g. `systemd-boot`
+    /// * `markers` - Paths of which one exists on the partition of the loader
+    /// # Returns
+    /// * `Result<VolumeDir>` - The root directory of the partition, mounted while it lives
+    fn mount_loader_esp(&mut self, loader: &str, markers: &[&str]) -> Result<VolumeDir>;
+
+    /// Get the root directory of the managed Linux installation
+    fn get_root_dir(&self) -> Result<PathBuf>;
+
+    /// Get the /boot directory of the managed Linux installation
+    fn get_boot_dir(&self) -> Result<PathBuf> {
+        Ok(self.get_root_dir()?.join("boot"))
+    }
+
+    /// Run a command of the managed Linux installation, chrooted into it if needed
+    /// # Arguments
+    /// * `command` - The command as seen inside the installation, followed by its arguments
+    fn run_in_root(&self, command: &[String]) -> Result<()>;
+
+    /// Get the kernel command line of the running system
+    /// # Returns
+    /// * `Result<Option<String>>` - The command line, None if not booted through GRUB
+    fn get_boot_cmdline(&self) -> Result<Option<String>>;
+
+    /// Show the currently booted, next and default firmware entries
+    fn show_fw_status(&self) -> Result<()>;
+
+    /// Show the firmware boot entries
+    fn show_fw_entry(&self) -> Result<()>;
+
+    /// Get the loaders started by the firmware boot entries
+    /// # Returns
+    /// * `Result<Vec<FwLoader>>` - The entries in boot order
+    fn get_fw_loaders(&self) -> Result<Vec<FwLoader>>;
+
+    /// Get the firmware boot entry booted by default
+    /// # Returns
+    /// * `Result<Option<String>>` - The id of the first entry of the boot order, None if it is empty
+    fn get_fw_default(&self) -> Result<Option<String>>;
+
+    /// Get the firmware boot order
+    /// # Returns
+    /// * `Result<Vec<String>>` - The ids of the entries in boot order
+    fn get_fw_order(&self) -> Result<Vec<String>>;
+
+    /// Set the firmware boot entry
+    /// # Arguments
+    /// * `entry` - The firmware boot entry to set
+    fn set_fw_entry(&self, entry: String) -> Result<()>;
+
+    /// Get the firmware boot entry booted on the next boot only
+    /// # Returns
+    /// * `Result<Option<String>>` - The id of the entry, None if the next boot follows the boot order
+    fn get_fw_oneshot(&self) -> Result<Option<String>>;
+
+    /// Boot a firmware boot entry on the next boot only, keeping the boot order
+    /// # Arguments
+    /// * `entry` - The firmware boot entry to boot once
+    fn set_fw_oneshot(&self, entry: String) -> Result<()>;
+
+    /// Create a firmware boot entry and append it to the boot order
+    /// # Arguments
+    /// * `spec` - The description of the firmware boot entry to create
+    fn create_fw_entry(&self, spec: &FwEntrySpec) -> Result<()>;
+
+    /// Delete a firmware boot entry and remove it from the boot order
+    /// # Arguments
+    /// * `entry` - The firmware boot entry to delete
+    fn delete_fw_entry(&self, entry: String) -> Result<()>;
+
+    /// Modify an existing firmware boot entry
+    /// # Arguments
+    /// * `entry` - The firmware boot entry to modify
+    /// * `update` - The changes to apply, unset fields are left untouched
+    fn modify_fw_entry(&self, entry: String, update: &FwEntryUpdate) -> Result<()>;
+
+    /// Show the firmware boot menu timeout
+    fn show_fw_timeout(&self) -> Result<()>;
+
+    /// Get the firmware boot menu timeout
+    /// # Returns
+    /// * `Result<Option<u16>>` - The timeout in seconds, None if it is not set
+    fn get_fw_timeout(&self) -> Result<Option<u16>>;
+
+    /// Set the firmware boot menu timeout
+    /// # Arguments
+    /// * `timeout` - The timeout in seconds
+    fn set_fw_timeout(&self, timeout: u16) -> Result<()>;
+
+    /// Read an EFI variable
+    /// # Arguments
+    /// * `name` - The name of the variable, optionally followed by `-<vendor GUID>`
+    /// # Returns
+    /// * `Result<Option<Vec<u8>>>` - The variable data, None if it does not exist
+    fn get_efi_var(&self, name: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Write an EFI variable
+    /// # Arguments
+    /// * `name` - The name of the variable, optionally followed by `-<vendor GUID>`
+    /// * `data` - The variable data
+    fn set_efi_var(&self, name: &str, data: &[u8]) -> Result<()>;
+
+    /// Delete an EFI variable
+    /// # Arguments
+    /// * `name` - The name of the variable, optionally followed by `-<vendor GUID>`
+    fn delete_efi_var(&self, name: &str) -> Result<()>;
+
+    /// Get the names of the EFI variables holding the firmware boot configuration
+    /// # Returns
+    /// * `Result<Vec<String>>` - The `Boot####` variables, `BootOrder` and `Timeout` that exist
+    fn get_boot_var_names(&self) -> Result<Vec<String>>;
+
+    /// Export the boot store the platform keeps besides the EFI variables
+    /// # Returns
+    /// * `Result<Option<Vec<u8>>>` - The exported store, None if the platform has none
+    fn export_fw_store(&self) -> Result<Option<Vec<u8>>>;
+
+    /// Import a boot store exported by `export_fw_store`, replacing the current one
+    /// # Arguments
+    /// * `data` - The exported store
+    fn import_fw_store(&self, data: &[u8]) -> Result<()>;
+
+    /// List the boot store the platform keeps besides the EFI variables
+    /// # Returns
+    /// * `Result<Option<Vec<u8>>>` - A listing that changes with the store, None if the platform has none
+    fn list_fw_store(&self) -> Result<Option<Vec<u8>>>;
+
+    /// Get the entries of the Windows Boot Manager menu
+    /// # Returns
+    /// * `Result<Vec<BootEntry>>` - The Windows boot loaders, marking the default and next ones
+    fn get_bootmgr_entries(&self) -> Result<Vec<BootEntry>>;
+
+    /// Set the default entry of the Windows Boot Manager menu
+    /// # Arguments
+    /// * `entry` - The selector of the entry
+    fn set_bootmgr_default(&self, entry: &str) -> Result<()>;
+
+    /// Get the configuration files of bootmgr
+    /// # Returns
+    /// * `Result<Vec<PathBuf>>` - The system-wide file, then the one of the user, which may not exist
+    fn get_config_files(&self) -> Result<Vec<PathBuf>>;
+
+    /// Get the directory bootmgr keeps its own state in, e.g. the change journal
+    fn get_data_dir(&self) -> Result<PathBuf>;
+
+    /// Restart the machine now
+    fn reboot(&self) -> Result<()>;
+
+    /// Ask the firmware to enter its setup UI on the next boot
+    fn boot_to_fw_setup(&self) -> Result<()> {
+        let supported = read_efi_u64(self.get_efi_var("OsIndicationsSupported")?);
+        if supported & EFI_OS_INDICATIONS_BOOT_TO_FW_UI == 0 {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "Firmware does not support booting to the setup UI",
+            ));
+        }
+        let indications = read_efi_u64(self.get_efi_var("OsIndications")?);
+        self.set_efi_var(
+            "OsIndications",
+            &(indications | EFI_OS_INDICATIONS_BOOT_TO_FW_UI).to_le_bytes(),
+        )?;
+        output::note(match dry_run::is_enabled() {
+            true => "The next boot would enter the firmware setup",
+            false => "The next boot will enter the firmware setup",
+        });
+        Ok(())
+    }
+}
+
+/// Decode a little-endian EFI variable of up to 8 bytes, missing variables are 0
+fn read_efi_u64(data: Option<Vec<u8>>) -> u64 {
+    let mut buf = [0u8; 8];
+    if let Some(data) = data {
+        let len = data.len().min(8);
+        buf[..len].copy_from_slice(&data[..len]);
+    }
+    u64::from_le_bytes(buf)
+}
+
+#[derive(Default)]
+pub struct Handle {
+    pub grub_desc: Option<String>,
+    pub grub_loc: Option<String>,
+    pub grub_keywords: Vec<String>,
+    pub root_dir: Option<String>,
+}
+
+impl Handle {
+    pub(crate) fn new() -> Self {
+        let s = Self::default();
+        match s.check_permission() {
+            Ok(true) => {}
+            Ok(false) => {
+                eprintln!("No admin permission, restarting as administrator");
+                let _ = s.rerun_as_superuser();
+                exit(1);
+            }
+            Err(e) => {
+                eprintln!("Failed to check permission: {}", e);
+                exit(1);
+            }
+        }
+        s
+    }
+}
+
+/// A struct describing a firmware boot entry to create
+/// Fields:
+/// * `disk` - The disk holding the loader, a device on Linux or a disk number on Windows
+/// * `part` - The partition number of the loader on the disk
+/// * `loader` - The path of the loader on the partition
+/// * `label` - The description of the firmware boot entry
+/// * `args` - The optional arguments passed to the loader
+pub struct FwEntrySpec {
+    pub disk: String,
+    pub part: u32,
+    pub loader: String,
+    pub label: String,
+    pub args: Option<String>,
+}
+
+/// A struct describing changes to an existing firmware boot entry
+/// Fields:
+/// * `label` - The new description of the entry
+/// * `loader` - The new path of the loader on the same partition
+/// * `args` - The new arguments passed to the loader, empty to clear them
+/// * `active` - Whether the entry should be active
+/// * `hidden` - Whether the entry should be hidden from the firmware menu
+#[derive(Default)]
+pub struct FwEntryUpdate {
+    pub label: Option<String>,
+    pub loader: Option<String>,
+    pub args: Option<String>,
+    pub active: Option<bool>,
+    pub hidden: Option<bool>,
+}
+
+/// A struct representing the loader a firmware boot entry starts
+/// Fields:
+/// * `id` - The id of the firmware entry, e.g. `Boot0001` or a BCD identifier
+/// * `description` - The description of the firmware entry
+/// * `path` - The path of the loader on its partition, None if the entry does not start a file
+/// * `is_current` - Whether the running system was booted through the entry
+#[derive(Clone, Debug)]
+pub struct FwLoader {
+    pub id: String,
+    pub description: String,
+    pub path: Option<String>,
+    pub is_current: bool,
+}
+
+impl Selectable for FwLoader {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn title(&self) -> &str {
+        &self.description
+    }
+}
+
+/// A struct representing a directory on a volume of a boot loader
+/// Fields:
+/// * `path` - The directory the volume is reachable at
+/// * `_mount` - The temporary mount backing the directory on Windows
+pub struct VolumeDir {
+    pub path: PathBuf,
+    #[cfg(windows)]
+    pub(crate) _mount: TempMount,
+}
+
+#[cfg(windows)]
+pub struct TempMount {
+    pub(crate) device: String,
+    pub(crate) mount_point: String,
+}
+
+/// A platform keeping its firmware entries and variables in memory, for tests
+#[cfg(test)]
+pub(crate) mod fake {
+    use super::*;
+    use crate::common::grubenv::{GRUB_ENV_SIZE, GrubEnv};
+    use crate::common::journal::{self, ChangeTarget};
+    use crate::common::selector::select;
+    use std::cell::RefCell;
+    use std::collections::BTreeMap;
+    use std::rc::Rc;
+    use std::sync::{Mutex, MutexGuard};
+
+    /// Serializes the tests sharing the change journal and dry-run mode of the process
+    static SESSION_LOCK: Mutex<()> = Mutex::new(());
+
+    /// Take the lock of the change journal and dry-run mode
+    pub(crate) fn lock_session() -> MutexGuard<'static, ()> {
+        SESSION_LOCK.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// A struct representing a machine with firmware entries only
+    /// Fields:
+    /// * `loaders` - The firmware entries
+    /// * `vars` - The EFI variables, `BootOrder` and `BootNext` hold entry ids separated by `,`
+    /// * `failing` - The variables whose writes fail
+    /// * `grub_dir` - The directory holding `grub/`, None if GRUB is not installed
+    /// * `grub_fw_entry` - The firmware entry known to start GRUB
+    #[derive(Default)]
+    pub(crate) struct FakePlatform {
+        pub loaders: Vec<FwLoader>,
+        pub vars: RefCell<BTreeMap<String, Vec<u8>>>,
+        pub failing: Rc<RefCell<Vec<String>>>,
+        pub grub_dir: Option<PathBuf>,
+        pub grub_fw_entry: Option<String>,
+    }
+
+    impl FakePlatform {
+        /// Make a machine with firmware entries, booting them in the given order
+        /// # Arguments
+        /// * `ids` - The ids of the entries, e.g. `Boot0001`
+        pub fn with_entries(ids: &[&str]) -> Self {
+            // Drop rules out the struct update syntax, so every field is given
+            let platform = Self {
+                loaders: ids
+                    .iter()
+                    .map(|id| FwLoader {
+                        id: id.to_string(),
+                        description: format!("Entry {}", id),
+                        path: None,
+                        is_current: false,
+                    })
+                    .collect(),
+                vars: RefCell::default(),
+                failing: Rc::default(),
+                grub_dir: None,
+                grub_fw_entry: None,
+            };
+            platform
+                .vars
+                .borrow_mut()
+                .insert("BootOrder".to_string(), ids.join(",").into_bytes());
+            platform
+        }
+
+        /// Install GRUB in a temporary directory, removed with the platform
+        /// # Arguments
+        /// * `name` - The name of the test, to keep the directories of tests apart
+        /// * `cfg` - The content of grub.cfg
+        /// * `env` - The variables of grubenv, e.g. `saved_entry=debian\n`
+        pub fn with_grub(mut self, name: &str, cfg: &str, env: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("bootmgr-test-{}-{}", std::process::id(), name));
+            std::fs::create_dir_all(dir.join("grub")).unwrap();
+            std::fs::write(dir.join("grub/grub.cfg"), cfg).unwrap();
+            let env = GrubEnv::parse(env).to_block(GRUB_ENV_SIZE).unwrap();
+            std::fs::write(dir.join("grub/grubenv"), env).unwrap();
+            self.grub_dir = Some(dir);
+            self
+        }
+
+        /// Give a firmware entry the path of its loader
+        /// # Arguments
+        /// * `id` - The id of the entry
+        /// * `path` - The path of the loader, e.g. `\EFI\debian\shimx64.efi`
+        pub fn with_loader_path(mut self, id: &str, path: &str) -> Self {
+            if let Some(fw) = self.loaders.iter_mut().find(|l| l.id == id) {
+                fw.path = Some(path.to_string());
+            }
+            self
+        }
+
+        /// Get an EFI variable as text
+        pub fn var(&self, name: &str) -> Option<String> {
+            self.vars
+                .borrow()
+                .get(name)
+                .map(|v| String::from_utf8_lossy(v).to_string())
+        }
+
+        fn find(&self, entry: &str) -> Result<String> {
+            Ok(select(&self.loaders, entry, "firmware")?.id.clone())
+        }
+    }
+
+    impl Drop for FakePlatform {
+        fn drop(&mut self) {
+            if let Some(dir) = &self.grub_dir {
+                let _ = std::fs::remove_dir_all(dir);
+            }
+        }
+    }
+
+    fn unsupported<T>() -> Result<T> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "not supported by the fake platform",
+        ))
+    }
+
+    impl Platform for FakePlatform {
+        fn check_permission(&self) -> Result<bool> {
+            Ok(true)
+        }
+
+        fn rerun_as_superuser(&self) -> Result<()> {
+            unsupported()
+        }
+
+        fn mount_grub_dir(&mut self) -> Result<VolumeDir> {
+            match &self.grub_dir {
+                #[cfg(not(windows))]
+                Some(path) => Ok(VolumeDir { path: path.clone() }),
+                _ => Err(Error::new(ErrorKind::NotFound, "no GRUB installation")),
+            }
+        }
+
+        fn find_grub_fw_entry(&mut self) -> Result<Option<String>> {
+            Ok(self.grub_fw_entry.clone())
+        }
+
+        fn find_volume_root(&self, _dir: &Path) -> Result<Option<PathBuf>> {
+            Ok(None)
+        }
+
+        fn find_uuid_volume(&self, _uuid: &str) -> Result<Option<PathBuf>> {
+            Ok(None)
+        }
+
+        fn mount_loader_esp(&mut self, _loader: &str, _markers: &[&str]) -> Result<VolumeDir> {
+            Err(Error::new(ErrorKind::NotFound, "no ESP"))
+        }
+
+        fn get_root_dir(&self) -> Result<PathBuf> {
+            unsupported()
+        }
+
+        fn run_in_root(&self, _command: &[String]) -> Result<()> {
+            unsupported()
+        }
+
+        fn get_boot_cmdline(&self) -> Result<Option<String>> {
+            Ok(None)
+        }
+
+        fn show_fw_status(&self) -> Result<()> {
+            unsupported()
+        }
+
+        fn show_fw_entry(&self) -> Result<()> {
+            unsupported()
+        }
+
+        fn get_fw_loaders(&self) -> Result<Vec<FwLoader>> {
+            Ok(self.loaders.clone())
+        }
+
+        fn get_fw_default(&self) -> Result<Option<String>> {
+            Ok(self.get_fw_order()?.into_iter().next())
+        }
+
+        fn get_fw_order(&self) -> Result<Vec<String>> {
+            Ok(self
+                .var("BootOrder")
+                .map(|order| order.split(',').map(str::to_string).collect())
+                .unwrap_or_default())
+        }
+
+        fn set_fw_entry(&self, entry: String) -> Result<()> {
+            let id = self.find(&entry)?;
+            let mut order = self.get_fw_order()?;
+            order.retain(|o| *o != id);
+            order.insert(0, id);
+            self.set_efi_var("BootOrder", order.join(",").as_bytes())
+        }
+
+        fn get_fw_oneshot(&self) -> Result<Option<String>> {
+            Ok(self.var("BootNext"))
+        }
+
+        fn set_fw_oneshot(&self, entry: String) -> Result<()> {
+            let id = self.find(&entry)?;
+            self.set_efi_var("BootNext", id.as_bytes())
+        }
+
+        fn create_fw_entry(&self, _spec: &FwEntrySpec) -> Result<()> {
+            unsupported()
+        }
+
+        fn delete_fw_entry(&self, _entry: String) -> Result<()> {
+            unsupported()
+        }
+
+        fn modify_fw_entry(&self, _entry: String, _update: &FwEntryUpdate) -> Result<()> {
+            unsupported()
+        }
+
+        fn show_fw_timeout(&self) -> Result<()> {
+            unsupported()
+        }
+
+        fn get_fw_timeout(&self) -> Result<Option<u16>> {
+            Ok(None)
+        }
+
+        fn set_fw_timeout(&self, _timeout: u16) -> Result<()> {
+            unsupported()
+        }
+
+        fn get_efi_var(&self, name: &str) -> Result<Option<Vec<u8>>> {
+            Ok(self.vars.borrow().get(name).cloned())
+        }
+
+        fn set_efi_var(&self, name: &str, data: &[u8]) -> Result<()> {
+            journal::track(
+                ChangeTarget::EfiVar(name.to_string()),
+                || self.get_efi_var(name),
+                || {
+                    if self.failing.borrow().iter().any(|f| f == name) {
+                        return Err(Error::other(format!("writing {} failed", name)));
+                    }
+                    self.vars
+                        .borrow_mut()
+                        .insert(name.to_string(), data.to_vec());
+                    Ok(())
+                },
+            )
+        }
+
+        fn delete_efi_var(&self, name: &str) -> Result<()> {
+            journal::track(
+                ChangeTarget::EfiVar(name.to_string()),
+                || self.get_efi_var(name),
+                || {
+                    if self.failing.borrow().iter().any(|f| f == name) {
+                        return Err(Error::other(format!("deleting {} failed", name)));
+                    }
+                    self.vars.borrow_mut().remove(name);
+                    Ok(())
+                },
+            )
+        }
+
+        fn get_boot_var_names(&self) -> Result<Vec<String>> {
+            Ok(self.vars.borrow().keys().cloned().collect())
+        }
+
+        fn export_fw_store(&self) -> Result<Option<Vec<u8>>> {
+            Ok(None)
+        }
+
+        fn import_fw_store(&self, _data: &[u8]) -> Result<()> {
+            unsupported()
+        }
+
+        fn list_fw_store(&self) -> Result<Option<Vec<u8>>> {
+            Ok(None)
+        }
+
+        fn get_bootmgr_entries(&self) -> Result<Vec<BootEntry>> {
+            unsupported()
+        }
+
+        fn set_bootmgr_default(&self, _entry: &str) -> Result<()> {
+            unsupported()
+        }
+
+        fn get_config_files(&self) -> Result<Vec<PathBuf>> {
+            Ok(Vec::new())
+        }
+
+        fn get_data_dir(&self) -> Result<PathBuf> {
+            unsupported()
+        }
+
+        fn reboot(&self) -> Result<()> {
+            panic!("the fake platform cannot reboot")
+        }
+    }
+}
@@ -0,0 +1,292 @@
+use crate::bootloader::Bootloader;
+use crate::bootloader::grub::Grub;
+use crate::common::config::{Config, Profile};
+use crate::common::dry_run;
+use crate::common::output;
+use crate::common::selector::select;
+use crate::platform::Platform;
+use crate::transaction::Transaction;
+use std::io::Result;
+
+/// Apply a named profile, its firmware and GRUB entries in one transaction
+/// # Arguments
+/// * `grub` - The GRUB boot loader
+/// * `config` - The configuration holding the profiles and aliases
+/// * `name` - The name of the profile
+pub(crate) fn use_profile(
+    platform: &mut dyn Platform,
+    grub: &Grub,
+    config: &Config,
+    name: &str,
+) -> Result<()> {
+    let profile = config.find_profile(name)?;
+    let oneshot = profile.oneshot;
+    let mut transaction = Transaction::default();
+
+    if let Some(fw_entry) = profile
+        .firmware
+        .as_deref()
+        .map(|e| config.firmware_selector(e))
+    {
+        let selector = fw_entry.clone();
+        transaction.stage(
+            match oneshot {
+                true => format!("boot the firmware entry {} once", fw_entry),
+                false => format!("set the firmware entry {} first", fw_entry),
+            },
+            move |p| match oneshot {
+                true => p.set_fw_oneshot(fw_entry),
+                false => p.set_fw_entry(fw_entry),
+            },
+            move |p| is_fw_selected(p, &selector, oneshot),
+        );
+    }
+    if let Some(grub_entry) = profile.grub.as_deref().map(|e| config.grub_selector(e)) {
+        let selector = grub_entry.clone();
+        let mut oneshot_grub = grub.clone();
+        let mut check = grub.clone();
+        transaction.stage(
+            match oneshot {
+                true => format!("boot the GRUB entry {} once", grub_entry),
+                false => format!("set the default GRUB entry to {}", grub_entry),
+            },
+            move |p| match oneshot {
+                true => oneshot_grub.set_oneshot(p, &grub_entry),
+                false => grub.set_grub_entry(p, &grub_entry, false, false),
+            },
+            move |p| check.is_selected(p, &selector, oneshot),
+        );
+    }
+
+    let applied = transaction.descriptions();
+    transaction.commit(platform)?;
+    if output::is_json() {
+        println!(
+            "{{\"profile\":{},\"applied\":{},\"dry_run\":{}}}",
+            output::json_string(name),
+            output::json_array(&applied),
+            dry_run::is_enabled()
+        );
+        return Ok(());
+    }
+    match oneshot {
+        true => println!("Using profile {} on the next boot", name),
+        false => println!("Using profile {}", name),
+    }
+    Ok(())
+}
+
+/// Show the profiles, marking those in effect
+///
+/// A profile is in effect when each entry it names is the default, or the
+/// one-shot entry for a one-shot profile.
+/// # Arguments
+/// * `grub` - The GRUB boot loader
+/// * `config` - The configuration holding the profiles and aliases
+pub(crate) fn show_profiles(
+    platform: &mut dyn Platform,
+    grub: &Grub,
+    config: &Config,
+) -> Result<()> {
+    let mut states = Vec::new();
+    for profile in &config.profiles {
+        states.push(is_in_effect(platform, grub, config, profile));
+    }
+
+    if output::is_json() {
+        let profiles: Vec<String> = config
+            .profiles
+            .iter()
+            .zip(&states)
+            .map(|(profile, state)| {
+                let optional = |s: &Option<String>| {
+                    s.as_deref().map_or("null".to_string(), output::json_string)
+                };
+                format!(
+                    "{{\"name\":{},\"firmware\":{},\"grub\":{},\"oneshot\":{},\"active\":{}}}",
+                    output::json_string(&profile.name),
+                    optional(&profile.firmware),
+                    optional(&profile.grub),
+                    profile.oneshot,
+                    state
+                        .as_ref()
+                        .map_or("null".to_string(), |active| active.to_string())
+                )
+            })
+            .collect();
+        println!("[{}]", profiles.join(","));
+        return Ok(());
+    }
+
+    if config.profiles.is_empty() {
+        println!("No profile is configured");
+        return Ok(());
+    }
+    println!("Boot profiles:");
+    for (profile, state) in config.profiles.iter().zip(states) {
+        let mut parts = Vec::new();
+        if let Some(firmware) = &profile.firmware {
+            parts.push(format!("firmware {}", firmware));
+        }
+        if let Some(grub) = &profile.grub {
+            parts.push(format!("grub {}", grub));
+        }
+        if profile.oneshot {
+            parts.push("once".to_string());
+        }
+        match state {
+            Ok(active) => println!(
+                "{} {} ({})",
+                if active { "*" } else { " " },
+                profile.name,
+                parts.join(", ")
+            ),
+            Err(e) => println!("? {} ({}): {}", profile.name, parts.join(", "), e),
+        }
+    }
+    Ok(())
+}
+
+fn is_in_effect(
+    platform: &mut dyn Platform,
+    grub: &Grub,
+    config: &Config,
+    profile: &Profile,
+) -> Result<bool> {
+    if let Some(firmware) = &profile.firmware
+        && !is_fw_selected(
+            platform,
+            &config.firmware_selector(firmware),
+            profile.oneshot,
+        )?
+    {
+        return Ok(false);
+    }
+    if let Some(selector) = &profile.grub {
+        return grub.clone().is_selected(
+            platform,
+            &config.grub_selector(selector),
+            profile.oneshot,
+        );
+    }
+    Ok(true)
+}
+
+/// Check whether a firmware entry is the first of the boot order or the one-shot entry
+pub(crate) fn is_fw_selected(
+    platform: &mut dyn Platform,
+    selector: &str,
+    oneshot: bool,
+) -> Result<bool> {
+    let loaders = platform.get_fw_loaders()?;
+    let id = select(&loaders, selector, "firmware")?.id.clone();
+    Ok(match oneshot {
+        true => platform.get_fw_oneshot()?,
+        false => platform.get_fw_default()?,
+    } == Some(id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::journal;
+    use crate::platform::fake::{FakePlatform, lock_session};
+
+    const CONFIG: &str = r#"
+[aliases.firmware]
+win = "Boot0002"
+
+[profiles.windows]
+firmware = "win"
+grub = "rescue"
+
+[profiles.rescue-once]
+firmware = "win"
+grub = "rescue"
+oneshot = true
+
+[profiles.fedora]
+grub = "fedora"
+"#;
+
+    /// Make a machine booting Boot0001, then GRUB's `debian` entry
+    fn machine(name: &str) -> FakePlatform {
+        FakePlatform::with_entries(&["Boot0001", "Boot0002"]).with_grub(
+            name,
+            "menuentry 'Debian' --id debian {\n}\nmenuentry 'Rescue' --id rescue {\n}\n",
+            "saved_entry=debian\n",
+        )
+    }
+
+    fn grubenv(platform: &FakePlatform) -> String {
+        std::fs::read_to_string(platform.grub_dir.as_ref().unwrap().join("grub/grubenv")).unwrap()
+    }
+
+    fn in_effect(platform: &mut FakePlatform, config: &Config, name: &str) -> bool {
+        let profile = config.find_profile(name).unwrap();
+        is_in_effect(platform, &Grub::default(), config, profile).unwrap()
+    }
+
+    #[test]
+    fn sets_the_defaults_of_a_profile() {
+        let _session = lock_session();
+        let config = Config::parse(CONFIG).unwrap();
+        let mut platform = machine("profile-default");
+        assert!(!in_effect(&mut platform, &config, "windows"));
+
+        journal::begin();
+        use_profile(&mut platform, &Grub::default(), &config, "windows").unwrap();
+        journal::finish();
+
+        assert_eq!(
+            platform.var("BootOrder").as_deref(),
+            Some("Boot0002,Boot0001")
+        );
+        assert!(grubenv(&platform).contains("saved_entry=rescue\n"));
+        assert!(in_effect(&mut platform, &config, "windows"));
+        assert!(!in_effect(&mut platform, &config, "rescue-once"));
+    }
+
+    #[test]
+    fn boots_a_oneshot_profile_once() {
+        let _session = lock_session();
+        let config = Config::parse(CONFIG).unwrap();
+        let mut platform = machine("profile-oneshot");
+
+        journal::begin();
+        use_profile(&mut platform, &Grub::default(), &config, "rescue-once").unwrap();
+        journal::finish();
+
+        assert_eq!(platform.var("BootNext").as_deref(), Some("Boot0002"));
+        assert_eq!(
+            platform.var("BootOrder").as_deref(),
+            Some("Boot0001,Boot0002")
+        );
+        let env = grubenv(&platform);
+        assert!(env.contains("saved_entry=debian\n") && env.contains("next_entry=rescue\n"));
+        assert!(in_effect(&mut platform, &config, "rescue-once"));
+        assert!(!in_effect(&mut platform, &config, "windows"));
+    }
+
+    #[test]
+    fn changes_nothing_for_an_unknown_entry() {
+        let _session = lock_session();
+        let config = Config::parse(CONFIG).unwrap();
+        let mut platform = machine("profile-unknown");
+        let env = grubenv(&platform);
+
+        journal::begin();
+        let err = use_profile(&mut platform, &Grub::default(), &config, "fedora").unwrap_err();
+        journal::finish();
+
+        assert!(err.to_string().starts_with("Step 1 of 1 cannot be applied"));
+        assert_eq!(grubenv(&platform), env);
+        // Listing the profiles shows the error instead of failing
+        show_profiles(&mut platform, &Grub::default(), &config).unwrap();
+        assert!(
+            config
+                .find_profile("missing")
+                .is_err_and(|e| e.kind() == std::io::ErrorKind::NotFound)
+        );
+    }
+}
@@ -0,0 +1,320 @@
+use crate::bootloader::grub::Grub;
+use crate::bootloader::{BootloaderKind, make_bootloader};
+use crate::common::config::Config;
+use crate::common::dry_run;
+use crate::common::grub_selector::resolve_grub_selector;
+use crate::common::selector::select;
+use crate::platform::Platform;
+use crate::profile::is_fw_selected;
+use crate::targets::{OsTarget, collect_targets};
+use crate::transaction::Transaction;
+use std::io::{Error, ErrorKind, Result};
+
+/// A struct representing the entries booted once by `reboot`
+/// Fields:
+/// * `firmware` - The selector of the firmware entry, booted through BootNext or the bootsequence
+/// * `entry` - The boot loader and the selector of its entry, e.g. a GRUB entry booted through next_entry
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RebootTarget {
+    pub firmware: Option<String>,
+    pub entry: Option<(BootloaderKind, String)>,
+}
+
+impl RebootTarget {
+    /// Find the entries a target names
+    ///
+    /// The target is tried as a profile, a firmware alias, a GRUB alias, a
+    /// firmware selector, a target of the `targets` view and last a GRUB
+    /// selector. `firmware` and `grub` replace the entry the target gives for
+    /// their layer.
+    /// # Arguments
+    /// * `grub` - The GRUB boot loader
+    /// * `config` - The configuration holding the profiles and aliases
+    /// * `loader` - The boot loader given by `--loader`
+    /// * `target` - The target given on the command line
+    /// * `firmware` - The firmware entry given by `--firmware`
+    /// * `grub_entry` - The GRUB entry given by `--grub`
+    pub fn resolve(
+        platform: &mut dyn Platform,
+        grub: &Grub,
+        config: &Config,
+        loader: Option<BootloaderKind>,
+        target: Option<&str>,
+        firmware: Option<&str>,
+        grub_entry: Option<&str>,
+    ) -> Result<Self> {
+        let mut ret = match target {
+            Some(target) => Self::resolve_name(platform, grub, config, loader, target)?,
+            None => RebootTarget::default(),
+        };
+        if let Some(firmware) = firmware {
+            ret.firmware = Some(config.firmware_selector(firmware));
+        }
+        if let Some(grub_entry) = grub_entry {
+            ret.entry = Some((BootloaderKind::Grub, config.grub_selector(grub_entry)));
+        }
+        Ok(ret)
+    }
+
+    fn resolve_name(
+        platform: &mut dyn Platform,
+        grub: &Grub,
+        config: &Config,
+        loader: Option<BootloaderKind>,
+        target: &str,
+    ) -> Result<Self> {
+        if let Some(profile) = config.profiles.iter().find(|p| p.name == target) {
+            return Ok(RebootTarget {
+                firmware: profile
+                    .firmware
+                    .as_deref()
+                    .map(|e| config.firmware_selector(e)),
+                entry: profile
+                    .grub
+                    .as_deref()
+                    .map(|e| (BootloaderKind::Grub, config.grub_selector(e))),
+            });
+        }
+        if config
+            .firmware_aliases
+            .iter()
+            .any(|(name, _)| name == target)
+        {
+            return Ok(RebootTarget {
+                firmware: Some(config.firmware_selector(target)),
+                entry: None,
+            });
+        }
+        if config.grub_aliases.iter().any(|(name, _)| name == target) {
+            return Ok(RebootTarget {
+                firmware: None,
+                entry: Some((BootloaderKind::Grub, config.grub_selector(target))),
+            });
+        }
+
+        // Entries that do not exist, or a layer that is not available, fall through
+        match platform
+            .get_fw_loaders()
+            .and_then(|loaders| select(&loaders, target, "firmware").map(|_| ()))
+        {
+            Ok(()) => {
+                return Ok(RebootTarget {
+                    firmware: Some(target.to_string()),
+                    entry: None,
+                });
+            }
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            Err(_) => {}
+        }
+        match collect_targets(platform, grub, loader)
+            .and_then(|targets| select(&targets, target, "target").cloned())
+        {
+            Ok(target) => return Ok(RebootTarget::from(target)),
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            Err(_) => {}
+        }
+        match grub
+            .get_grub_entry(platform)
+            .and_then(|entries| resolve_grub_selector(&entries, target).map(|_| ()))
+        {
+            Ok(()) => Ok(RebootTarget {
+                firmware: None,
+                entry: Some((BootloaderKind::Grub, target.to_string())),
+            }),
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            Err(_) => Err(Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "'{}' names no profile, alias, firmware entry or target",
+                    target
+                ),
+            )),
+        }
+    }
+}
+
+impl From<OsTarget> for RebootTarget {
+    fn from(target: OsTarget) -> Self {
+        RebootTarget {
+            firmware: target.firmware,
+            entry: target.loader.zip(target.entry),
+        }
+    }
+}
+
+/// Boot a target on the next boot only and restart the machine
+///
+/// The one-shot entries are set in one transaction, verified, and rolled
+/// back if any of them fails, before the machine is restarted.
+/// # Arguments
+/// * `grub` - The GRUB boot loader
+/// * `target` - The entries to boot once
+/// * `yes` - Reboot without asking
+/// * `confirm` - Asks whether to reboot, `prompt::confirm` outside of tests
+/// * `restart` - Restarts the machine once the entries are set, [`Platform::reboot`] outside of tests
+pub(crate) fn reboot_into(
+    platform: &mut dyn Platform,
+    grub: &Grub,
+    target: &RebootTarget,
+    yes: bool,
+    confirm: impl FnOnce(&str) -> Result<bool>,
+    restart: impl FnOnce(&mut dyn Platform) -> Result<()>,
+) -> Result<()> {
+    let mut transaction = Transaction::default();
+    if let Some(fw_entry) = target.firmware.clone() {
+        let selector = fw_entry.clone();
+        transaction.stage(
+            format!("boot the firmware entry {} once", fw_entry),
+            move |p| p.set_fw_oneshot(fw_entry),
+            move |p| is_fw_selected(p, &selector, true),
+        );
+    }
+    if let Some((kind, entry)) = target.entry.clone() {
+        let selector = entry.clone();
+        transaction.stage(
+            format!("boot the {} entry {} once", kind.title(), entry),
+            move |p| make_bootloader(kind, grub).set_oneshot(p, &entry),
+            move |p| make_bootloader(kind, grub).is_selected(p, &selector, true),
+        );
+    }
+    if transaction.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Give a target, --firmware or --grub to reboot into",
+        ));
+    }
+
+    let steps = transaction.descriptions().join(", then ");
+    if !yes && !dry_run::is_enabled() && !confirm(&format!("Reboot to {}?", steps))? {
+        println!("Reboot cancelled");
+        return Ok(());
+    }
+    transaction.commit(platform)?;
+    restart(platform)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::journal::{self, ChangeTarget};
+    use crate::platform::fake::{FakePlatform, lock_session};
+    use std::path::PathBuf;
+
+    /// Make a machine with two firmware entries and GRUB with two entries
+    /// # Arguments
+    /// * `name` - The name of the test, to keep the GRUB directories apart
+    /// * `env_room` - Whether the grubenv block has room for `next_entry`
+    fn machine(name: &str, env_room: bool) -> FakePlatform {
+        let platform = FakePlatform::with_entries(&["Boot0001", "Boot0002"]).with_grub(
+            name,
+            "menuentry 'Debian' --id debian {\n}\nmenuentry 'Rescue' --id rescue {\n}\n",
+            "",
+        );
+        if !env_room {
+            let full = grubenv(&platform);
+            std::fs::write(grubenv_path(&platform), full.trim_end_matches('#')).unwrap();
+        }
+        platform
+    }
+
+    fn grubenv_path(platform: &FakePlatform) -> PathBuf {
+        platform.grub_dir.as_ref().unwrap().join("grub/grubenv")
+    }
+
+    fn grubenv(platform: &FakePlatform) -> String {
+        std::fs::read_to_string(grubenv_path(platform)).unwrap()
+    }
+
+    fn target() -> RebootTarget {
+        RebootTarget {
+            firmware: Some("Boot0002".to_string()),
+            entry: Some((BootloaderKind::Grub, "rescue".to_string())),
+        }
+    }
+
+    #[test]
+    fn restarts_after_setting_the_entries() {
+        let _session = lock_session();
+        journal::begin();
+        let mut platform = machine("restart", true);
+        let mut question = String::new();
+        let mut restarted = false;
+        reboot_into(
+            &mut platform,
+            &Grub::default(),
+            &target(),
+            false,
+            |q| {
+                question = q.to_string();
+                Ok(true)
+            },
+            |p| {
+                // Everything is set and recorded by the time the machine goes down
+                assert_eq!(p.get_fw_oneshot()?.as_deref(), Some("Boot0002"));
+                let targets: Vec<ChangeTarget> =
+                    journal::changes().into_iter().map(|c| c.target).collect();
+                assert!(targets.contains(&ChangeTarget::EfiVar("BootNext".to_string())));
+                assert!(targets.contains(&ChangeTarget::GrubFile("grub/grubenv".to_string())));
+                restarted = true;
+                Ok(())
+            },
+        )
+        .unwrap();
+        journal::finish();
+
+        assert!(restarted);
+        assert_eq!(
+            question,
+            "Reboot to boot the firmware entry Boot0002 once, then boot the GRUB entry rescue once?"
+        );
+        assert!(grubenv(&platform).contains("\nnext_entry=rescue\n"));
+    }
+
+    #[test]
+    fn does_not_restart_when_cancelled() {
+        let _session = lock_session();
+        journal::begin();
+        let mut platform = machine("cancel", true);
+        reboot_into(
+            &mut platform,
+            &Grub::default(),
+            &target(),
+            false,
+            |_| Ok(false),
+            |_| panic!("restarted after the reboot was cancelled"),
+        )
+        .unwrap();
+        let changes = journal::finish();
+
+        assert!(changes.is_empty());
+        assert_eq!(platform.var("BootNext"), None);
+        assert!(!grubenv(&platform).contains("next_entry"));
+    }
+
+    #[test]
+    fn does_not_restart_when_a_step_is_rolled_back() {
+        let _session = lock_session();
+        journal::begin();
+        let mut platform = machine("rollback", false);
+        let before = grubenv(&platform);
+        let err = reboot_into(
+            &mut platform,
+            &Grub::default(),
+            &target(),
+            true,
+            |_| panic!("asked although --yes was given"),
+            |_| panic!("restarted after a step failed"),
+        )
+        .unwrap_err();
+        let changes = journal::finish();
+
+        assert_eq!(
+            err.to_string(),
+            "Step 2 of 2 failed: boot the GRUB entry rescue once: grubenv block is full, \
+             rolled back 1 changes"
+        );
+        assert!(changes.is_empty());
+        assert_eq!(platform.var("BootNext"), None);
+        assert_eq!(grubenv(&platform), before);
+    }
+}
@@ -0,0 +1,339 @@
+use crate::bootloader::grub::Grub;
+use crate::bootloader::{BootloaderKind, detect_bootloader, make_bootloader};
+use crate::common::output;
+use crate::common::selector::Selectable;
+use crate::platform::{FwLoader, Platform};
+use crate::transaction::Transaction;
+use std::io::{ErrorKind, Result};
+
+/// A struct representing an operating system that can be booted, across the boot layers
+/// Fields:
+/// * `id` - The firmware entry id, the boot loader entry id, or both joined by `/`
+/// * `title` - The title of the boot loader entry, or the description of the firmware entry
+/// * `firmware` - The id of the firmware entry to boot, None without firmware entries
+/// * `loader` - The boot loader whose menu holds the entry
+/// * `entry` - The id of the entry in the menu of the boot loader
+/// * `chainload` - The loader the GRUB entry chainloads, e.g. the Windows Boot Manager
+/// * `is_default` - Whether the target is booted by default
+/// * `is_ambiguous` - Whether the menu the firmware entry starts is unknown, as other entries start the same boot loader
+#[derive(Clone, Debug, Default)]
+pub(crate) struct OsTarget {
+    pub id: String,
+    pub title: String,
+    pub firmware: Option<String>,
+    pub loader: Option<BootloaderKind>,
+    pub entry: Option<String>,
+    pub chainload: Option<String>,
+    pub is_default: bool,
+    pub is_ambiguous: bool,
+}
+
+impl Selectable for OsTarget {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn title(&self) -> &str {
+        &self.title
+    }
+}
+
+impl OsTarget {
+    /// Describe the layers the target is booted through
+    fn describe_layers(&self) -> String {
+        let mut layers = Vec::new();
+        if let Some(firmware) = &self.firmware {
+            layers.push(format!("firmware {}", firmware));
+        }
+        if let Some(loader) = self.loader {
+            layers.push(loader.title().to_string());
+        }
+        if let Some(chainload) = &self.chainload {
+            layers.push(format!("chainload {}", chainload));
+        }
+        match self.is_ambiguous {
+            true => format!(
+                "{} (menu unknown, other firmware entries start it too)",
+                layers.join(" > ")
+            ),
+            false => layers.join(" > "),
+        }
+    }
+}
+
+/// Get the bootable operating systems
+///
+/// Only one menu of each boot loader is found, so a firmware entry starting
+/// a known boot loader is replaced by the entries of that menu when it is
+/// the only entry starting the loader, or the platform tells it starts the
+/// GRUB found. Other firmware entries, and those whose menu cannot be read,
+/// are targets of their own, marked ambiguous when it is unknown which of
+/// them starts the menu. Without firmware entries, e.g. on BIOS machines, the
+/// menu of the boot loader is taken.
+/// # Arguments
+/// * `grub` - The GRUB boot loader
+/// * `loader` - The boot loader given by `--loader`, used without firmware entries
+/// # Returns
+/// * `Result<Vec<OsTarget>>` - The targets, in firmware boot order
+pub(crate) fn collect_targets(
+    platform: &mut dyn Platform,
+    grub: &Grub,
+    loader: Option<BootloaderKind>,
+) -> Result<Vec<OsTarget>> {
+    let loaders = match platform.get_fw_loaders() {
+        Ok(loaders) => loaders,
+        Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::Unsupported) => {
+            let kind = match loader {
+                Some(kind) => kind,
+                None => detect_bootloader(platform)?,
+            };
+            return menu_targets(platform, grub, kind, None, true);
+        }
+        Err(e) => return Err(e),
+    };
+    let default = platform.get_fw_default()?;
+    let loader_kind = |fw: &FwLoader| {
+        fw.path
+            .as_deref()
+            .and_then(BootloaderKind::from_loader_path)
+    };
+    // A platform that cannot locate GRUB knows no entry starting it
+    let grub_fw = platform.find_grub_fw_entry().unwrap_or(None);
+
+    let mut ret = Vec::new();
+    for fw in &loaders {
+        let is_default = default.as_deref() == Some(fw.id.as_str());
+        let kind = loader_kind(fw);
+        let mut is_ambiguous = false;
+        if let Some(kind) = kind {
+            let starting: Vec<&str> = loaders
+                .iter()
+                .filter(|l| loader_kind(l) == Some(kind))
+                .map(|l| l.id.as_str())
+                .collect();
+            let menu_fw = match (kind, &grub_fw) {
+                (BootloaderKind::Grub, Some(id)) => Some(id.as_str()),
+                _ if starting.len() == 1 => Some(starting[0]),
+                _ => None,
+            };
+            is_ambiguous = menu_fw.is_none();
+            if menu_fw == Some(fw.id.as_str()) {
+                match menu_targets(platform, grub, kind, Some(fw), is_default) {
+                    Ok(targets) => {
+                        ret.extend(targets);
+                        continue;
+                    }
+                    Err(e) => eprintln!("Cannot read the menu of {}: {}", kind.title(), e),
+                }
+            }
+        }
+        ret.push(OsTarget {
+            id: fw.id.clone(),
+            title: fw.description.clone(),
+            firmware: Some(fw.id.clone()),
+            loader: kind,
+            is_default,
+            is_ambiguous,
+            ..Default::default()
+        });
+    }
+    Ok(ret)
+}
+
+/// Make the targets of the menu of a boot loader
+/// # Arguments
+/// * `kind` - The boot loader
+/// * `fw` - The firmware entry starting the boot loader
+/// * `fw_default` - Whether the boot loader is started by default
+fn menu_targets(
+    platform: &mut dyn Platform,
+    grub: &Grub,
+    kind: BootloaderKind,
+    fw: Option<&FwLoader>,
+    fw_default: bool,
+) -> Result<Vec<OsTarget>> {
+    let prefix = match fw {
+        Some(fw) => fw.id.clone(),
+        None => kind.name().to_string(),
+    };
+    let entries = make_bootloader(kind, grub).list_entries(platform)?;
+    let chainloads = match kind {
+        BootloaderKind::Grub => grub
+            .get_grub_entry(platform)?
+            .into_iter()
+            .filter_map(|e| e.entry_chainloader.map(|c| (e.entry_id, c)))
+            .collect(),
+        _ => Vec::new(),
+    };
+    Ok(entries
+        .into_iter()
+        .map(|e| OsTarget {
+            id: format!("{}/{}", prefix, e.id),
+            chainload: chainloads
+                .iter()
+                .find(|(id, _)| *id == e.id)
+                .map(|(_, c)| c.clone()),
+            title: e.title,
+            firmware: fw.map(|fw| fw.id.clone()),
+            loader: Some(kind),
+            entry: Some(e.id),
+            is_default: fw_default && e.is_default,
+            is_ambiguous: false,
+        })
+        .collect())
+}
+
+/// Show the bootable operating systems and the layers each one is booted through
+/// # Arguments
+/// * `grub` - The GRUB boot loader
+/// * `loader` - The boot loader given by `--loader`, used without firmware entries
+pub(crate) fn show_targets(
+    platform: &mut dyn Platform,
+    grub: &Grub,
+    loader: Option<BootloaderKind>,
+) -> Result<()> {
+    let targets = collect_targets(platform, grub, loader)?;
+    if output::is_json() {
+        let optional = |s: Option<&str>| s.map_or("null".to_string(), output::json_string);
+        let targets: Vec<String> = targets
+            .iter()
+            .map(|t| {
+                format!(
+                    "{{\"id\":{},\"title\":{},\"firmware\":{},\"loader\":{},\"entry\":{},\"chainload\":{},\"default\":{},\"ambiguous\":{}}}",
+                    output::json_string(&t.id),
+                    output::json_string(&t.title),
+                    optional(t.firmware.as_deref()),
+                    optional(t.loader.map(|k| k.name())),
+                    optional(t.entry.as_deref()),
+                    optional(t.chainload.as_deref()),
+                    t.is_default,
+                    t.is_ambiguous
+                )
+            })
+            .collect();
+        println!("[{}]", targets.join(","));
+        return Ok(());
+    }
+
+    println!("Boot targets:");
+    for target in &targets {
+        println!(
+            "{} {} ({})",
+            if target.is_default { "*" } else { " " },
+            target.title,
+            target.id
+        );
+        println!("      {}", target.describe_layers());
+    }
+    Ok(())
+}
+
+/// Stage the changes making a target the default
+///
+/// Layers already booting the target are left untouched: the firmware entry
+/// is moved first in the boot order and the boot loader entry, which may
+/// chainload another loader, becomes the default.
+/// # Arguments
+/// * `transaction` - The transaction the changes are staged in
+/// * `grub` - The GRUB boot loader
+/// * `target` - The target
+pub(crate) fn stage_default<'a>(
+    transaction: &mut Transaction<'a>,
+    platform: &mut dyn Platform,
+    grub: &'a Grub,
+    target: &OsTarget,
+) -> Result<()> {
+    if let Some(fw_id) = target.firmware.clone()
+        && platform.get_fw_default()?.as_ref() != Some(&fw_id)
+    {
+        let id = fw_id.clone();
+        transaction.stage(
+            format!("set the firmware entry {} first", fw_id),
+            move |p| p.set_fw_entry(fw_id),
+            move |p| Ok(p.get_fw_default()?.as_ref() == Some(&id)),
+        );
+    }
+    if let (Some(kind), Some(entry)) = (target.loader, target.entry.clone())
+        && !make_bootloader(kind, grub).is_selected(platform, &entry, false)?
+    {
+        let selector = entry.clone();
+        transaction.stage(
+            format!("set the default {} entry {}", kind.title(), entry),
+            move |p| make_bootloader(kind, grub).set_default(p, &entry),
+            move |p| make_bootloader(kind, grub).is_selected(p, &selector, false),
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::fake::FakePlatform;
+
+    const GRUB_CFG: &str =
+        "menuentry 'Debian' --id debian {\n}\nmenuentry 'Rescue' --id rescue {\n}\n";
+
+    /// Make a machine with two firmware entries starting GRUB and the Windows Boot Manager
+    fn machine(name: &str) -> FakePlatform {
+        FakePlatform::with_entries(&["Boot0001", "Boot0002", "Boot0003"])
+            .with_loader_path("Boot0001", "\\EFI\\debian\\shimx64.efi")
+            .with_loader_path("Boot0002", "\\EFI\\fedora\\grubx64.efi")
+            .with_loader_path("Boot0003", "\\EFI\\Microsoft\\Boot\\bootmgfw.efi")
+            .with_grub(name, GRUB_CFG, "")
+    }
+
+    fn ids(targets: &[OsTarget]) -> Vec<&str> {
+        targets.iter().map(|t| t.id.as_str()).collect()
+    }
+
+    #[test]
+    fn expands_the_only_entry_starting_a_loader() {
+        let mut platform = FakePlatform::with_entries(&["Boot0001", "Boot0003"])
+            .with_loader_path("Boot0001", "\\EFI\\debian\\shimx64.efi")
+            .with_grub("targets-only", GRUB_CFG, "saved_entry=debian\n");
+        let targets = collect_targets(&mut platform, &Grub::default(), None).unwrap();
+
+        assert_eq!(
+            ids(&targets),
+            ["Boot0001/debian", "Boot0001/rescue", "Boot0003"]
+        );
+        assert_eq!(targets[1].firmware.as_deref(), Some("Boot0001"));
+        assert_eq!(targets[1].entry.as_deref(), Some("rescue"));
+        assert_eq!(targets[1].describe_layers(), "firmware Boot0001 > GRUB");
+        assert!(targets[0].is_default);
+        assert!(!targets[2].is_default);
+    }
+
+    #[test]
+    fn marks_entries_starting_the_same_loader_ambiguous() {
+        let mut platform = machine("targets-ambiguous");
+        let targets = collect_targets(&mut platform, &Grub::default(), None).unwrap();
+
+        assert_eq!(ids(&targets), ["Boot0001", "Boot0002", "Boot0003"]);
+        assert!(targets[0].is_ambiguous && targets[1].is_ambiguous);
+        assert_eq!(targets[1].loader, Some(BootloaderKind::Grub));
+        assert_eq!(targets[1].entry, None);
+        assert_eq!(
+            targets[1].describe_layers(),
+            "firmware Boot0002 > GRUB (menu unknown, other firmware entries start it too)"
+        );
+        assert!(!targets[2].is_ambiguous);
+        assert_eq!(targets[2].loader, None);
+    }
+
+    #[test]
+    fn expands_the_entry_starting_the_grub_found() {
+        let mut platform = machine("targets-found");
+        platform.grub_fw_entry = Some("Boot0002".to_string());
+        let targets = collect_targets(&mut platform, &Grub::default(), None).unwrap();
+
+        assert_eq!(
+            ids(&targets),
+            ["Boot0001", "Boot0002/debian", "Boot0002/rescue", "Boot0003"]
+        );
+        assert!(!targets[0].is_ambiguous);
+        assert_eq!(targets[0].describe_layers(), "firmware Boot0001 > GRUB");
+        assert!(targets[0].is_default);
+    }
+}
@@ -0,0 +1,269 @@
+use crate::common::dry_run;
+use crate::common::journal::{self, Change, ChangeTarget};
+use crate::common::output;
+use crate::history;
+use crate::platform::Platform;
+use std::io::{Error, Result};
+
+type Apply<'a> = Box<dyn FnOnce(&mut dyn Platform) -> Result<()> + 'a>;
+type Verify<'a> = Box<dyn FnMut(&mut dyn Platform) -> Result<bool> + 'a>;
+
+/// A struct representing one staged change of a transaction
+/// Fields:
+/// * `description` - What the step changes, shown while applying
+/// * `apply` - Makes the change
+/// * `verify` - Re-reads the boot configuration and tells whether the change took effect
+struct Step<'a> {
+    description: String,
+    apply: Apply<'a>,
+    verify: Verify<'a>,
+}
+
+/// A set of changes applied together
+///
+/// The steps are applied in the order they were staged and each one is
+/// verified by re-reading what it changed. Every verification also runs once
+/// before the first step is applied, so a selector naming no entry is
+/// rejected before anything changes. If a step fails, every change made by
+/// the earlier steps is reverted from the values the change journal
+/// recorded, so the journal must be recording while the transaction runs.
+#[derive(Default)]
+pub(crate) struct Transaction<'a> {
+    steps: Vec<Step<'a>>,
+}
+
+impl<'a> Transaction<'a> {
+    /// Stage a change
+    /// # Arguments
+    /// * `description` - What the step changes
+    /// * `apply` - Makes the change
+    /// * `verify` - Tells whether the change took effect
+    pub fn stage(
+        &mut self,
+        description: String,
+        apply: impl FnOnce(&mut dyn Platform) -> Result<()> + 'a,
+        verify: impl FnMut(&mut dyn Platform) -> Result<bool> + 'a,
+    ) {
+        self.steps.push(Step {
+            description,
+            apply: Box::new(apply),
+            verify: Box::new(verify),
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// The descriptions of the staged steps, in order
+    pub fn descriptions(&self) -> Vec<String> {
+        self.steps.iter().map(|s| s.description.clone()).collect()
+    }
+
+    /// Apply the staged changes, rolling all of them back if one fails
+    pub fn commit(self, platform: &mut dyn Platform) -> Result<()> {
+        let before = journal::changes();
+        let count = self.steps.len();
+        let mut steps = self.steps;
+        // Verifying resolves the selectors of every step before anything changes
+        for (i, step) in steps.iter_mut().enumerate() {
+            (step.verify)(platform).map_err(|e| {
+                Error::new(
+                    e.kind(),
+                    format!(
+                        "Step {} of {} cannot be applied: {}: {}",
+                        i + 1,
+                        count,
+                        step.description,
+                        e
+                    ),
+                )
+            })?;
+        }
+        for (i, mut step) in steps.into_iter().enumerate() {
+            let result = (step.apply)(platform).and_then(|_| {
+                // Nothing was written in dry-run mode, so there is nothing to re-read
+                if dry_run::is_enabled() || (step.verify)(platform)? {
+                    return Ok(());
+                }
+                Err(Error::other(
+                    "the change did not take effect when read back",
+                ))
+            });
+            if let Err(e) = result {
+                let mut message = format!(
+                    "Step {} of {} failed: {}: {}",
+                    i + 1,
+                    count,
+                    step.description,
+                    e
+                );
+                let (reverted, failed) = rollback(platform, &before);
+                if reverted > 0 {
+                    message += &format!(", rolled back {} changes", reverted);
+                }
+                if !failed.is_empty() {
+                    message += &format!(", could not roll back {}", failed.join("; "));
+                }
+                return Err(Error::new(e.kind(), message));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Give every target changed since a journal snapshot its value back
+///
+/// A target that cannot be reverted does not stop the others from being tried.
+/// # Arguments
+/// * `before` - The changes recorded before the transaction started
+/// # Returns
+/// * `(usize, Vec<String>)` - The number of targets reverted, and each target that could not be with the reason
+fn rollback(platform: &mut dyn Platform, before: &[Change]) -> (usize, Vec<String>) {
+    let mut count = 0;
+    let mut failed = Vec::new();
+    for change in journal::changes().iter().rev() {
+        let earlier = before.iter().find(|c| c.target == change.target);
+        let value = match earlier {
+            Some(earlier) if earlier.new == change.new => continue,
+            // The journal keeps no export of the BCD store made mid-command
+            Some(_) if change.target == ChangeTarget::Bcd => {
+                failed.push(format!(
+                    "{}: the BCD store was changed twice",
+                    change.target
+                ));
+                continue;
+            }
+            Some(earlier) => &earlier.new,
+            None if change.old == change.new => continue,
+            None => &change.old,
+        };
+        match history::write_target(platform, &change.target, value.as_deref()) {
+            Ok(()) => {
+                journal::reset(&change.target, earlier);
+                output::note(&format!("Rolled back {}", change.target));
+                count += 1;
+            }
+            Err(e) => failed.push(format!("{}: {}", change.target, e)),
+        }
+    }
+    (count, failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::fake::{FakePlatform, lock_session};
+    use crate::profile::is_fw_selected;
+
+    /// Stage moving a firmware entry first in the boot order
+    fn stage_fw_default(transaction: &mut Transaction, selector: &str) {
+        let entry = selector.to_string();
+        let check = selector.to_string();
+        transaction.stage(
+            format!("set the firmware entry {} first", selector),
+            move |p| p.set_fw_entry(entry),
+            move |p| is_fw_selected(p, &check, false),
+        );
+    }
+
+    /// Stage writing an EFI variable
+    fn stage_var(transaction: &mut Transaction, name: &'static str, value: &'static str) {
+        transaction.stage(
+            format!("set {}", name),
+            move |p| p.set_efi_var(name, value.as_bytes()),
+            move |p| Ok(p.get_efi_var(name)?.as_deref() == Some(value.as_bytes())),
+        );
+    }
+
+    #[test]
+    fn applies_every_step() {
+        let _session = lock_session();
+        journal::begin();
+        let mut platform = FakePlatform::with_entries(&["Boot0001", "Boot0002"]);
+        let mut transaction = Transaction::default();
+        stage_fw_default(&mut transaction, "Boot0002");
+        stage_var(&mut transaction, "Timeout", "5");
+        transaction.commit(&mut platform).unwrap();
+        journal::finish();
+
+        assert_eq!(
+            platform.var("BootOrder").as_deref(),
+            Some("Boot0002,Boot0001")
+        );
+        assert_eq!(platform.var("Timeout").as_deref(), Some("5"));
+    }
+
+    #[test]
+    fn rolls_back_earlier_steps() {
+        let _session = lock_session();
+        journal::begin();
+        let mut platform = FakePlatform::with_entries(&["Boot0001", "Boot0002"]);
+        platform.failing.borrow_mut().push("Timeout".to_string());
+        let mut transaction = Transaction::default();
+        stage_fw_default(&mut transaction, "Boot0002");
+        stage_var(&mut transaction, "Timeout", "5");
+        let err = transaction.commit(&mut platform).unwrap_err();
+        let changes = journal::finish();
+
+        assert!(
+            err.to_string()
+                .starts_with("Step 2 of 2 failed: set Timeout")
+        );
+        assert!(err.to_string().ends_with("rolled back 1 changes"));
+        assert_eq!(
+            platform.var("BootOrder").as_deref(),
+            Some("Boot0001,Boot0002")
+        );
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn tries_every_target_when_rolling_back() {
+        let _session = lock_session();
+        journal::begin();
+        let mut platform = FakePlatform::with_entries(&["Boot0001", "Boot0002"]);
+        let failing = platform.failing.clone();
+        let mut transaction = Transaction::default();
+        stage_fw_default(&mut transaction, "Boot0002");
+        stage_var(&mut transaction, "Timeout", "5");
+        transaction.stage(
+            "break the timeout".to_string(),
+            move |_| {
+                failing.borrow_mut().push("Timeout".to_string());
+                Err(Error::other("failed on purpose"))
+            },
+            |_| Ok(true),
+        );
+        let err = transaction.commit(&mut platform).unwrap_err();
+        journal::finish();
+
+        assert_eq!(
+            err.to_string(),
+            "Step 3 of 3 failed: break the timeout: failed on purpose, rolled back 1 changes, \
+             could not roll back EFI variable Timeout: deleting Timeout failed"
+        );
+        assert_eq!(
+            platform.var("BootOrder").as_deref(),
+            Some("Boot0001,Boot0002")
+        );
+        assert_eq!(platform.var("Timeout").as_deref(), Some("5"));
+    }
+
+    #[test]
+    fn resolves_every_selector_first() {
+        let _session = lock_session();
+        journal::begin();
+        let mut platform = FakePlatform::with_entries(&["Boot0001", "Boot0002"]);
+        let mut transaction = Transaction::default();
+        stage_var(&mut transaction, "Timeout", "5");
+        stage_fw_default(&mut transaction, "Boot0009");
+        let err = transaction.commit(&mut platform).unwrap_err();
+        let changes = journal::finish();
+
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+        assert!(err.to_string().starts_with("Step 2 of 2 cannot be applied"));
+        assert_eq!(platform.var("Timeout"), None);
+        assert!(changes.is_empty());
+    }
+}
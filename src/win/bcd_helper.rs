@@ -1,7 +1,27 @@
+use crate::bootloader::{BootEntry, BootloaderKind};
+use crate::common::dry_run;
+use crate::common::file_operations::normalize_loader_path;
+use crate::common::journal::{self, ChangeTarget};
+use crate::common::output;
+use crate::common::selector::{Selectable, select};
+use crate::platform::{FwEntrySpec, FwEntryUpdate, FwLoader};
+use regex::Regex;
+use std::cell::RefCell;
 use std::io::{Error, ErrorKind, Result};
 use std::process::Command;
 
-#[derive(Default, Debug)]
+/// Elements copied from {bootmgr} that only make sense for the Windows Boot Manager
+const BOOTMGR_ONLY_ELEMENTS: [&str; 7] = [
+    "locale",
+    "inherit",
+    "default",
+    "resumeobject",
+    "displayorder",
+    "toolsdisplayorder",
+    "timeout",
+];
+
+#[derive(Clone, Default, Debug)]
 struct BcdEntry {
     pub id: Option<String>,
     pub device: Option<String>,
@@ -14,68 +34,469 @@ struct BcdEntry {
     pub displayorder: Vec<Option<String>>,
     pub toolsdisplayorder: Option<String>,
     pub timeout: Option<u32>,
+    pub bootsequence: Option<String>,
 }
 
 impl BcdEntry {
     pub fn entry_on_disk(&self) -> bool {
         self.device != None && self.path != None
     }
+
+    /// Get the identifier to pass to bcdedit
+    ///
+    /// Identifiers are only known for entries in the display order, entries
+    /// removed from it are found again by their identifier.
+    /// # Returns
+    /// * `Result<String>` - The identifier, an error if it is not known
+    pub fn identifier(&self) -> Result<String> {
+        self.id.clone().ok_or(Error::new(
+            ErrorKind::NotFound,
+            format!(
+                "Firmware entry {} has no identifier, select it by {{GUID}}",
+                self.title()
+            ),
+        ))
+    }
 }
 
+impl Selectable for BcdEntry {
+    fn id(&self) -> &str {
+        self.id.as_deref().unwrap_or_default()
+    }
+
+    fn title(&self) -> &str {
+        self.description.as_deref().unwrap_or_default()
+    }
+}
+
+/// Show the firmware entries, numbered from 0 as index selectors count them
+///
+/// `{fwbootmgr}` holds the display order and is shown first, unnumbered.
 pub(crate) fn show_bcd_list() -> Result<()> {
     let entries = get_bcd_entries()?;
     println!("The firmware boot entries(BCD):");
-    for i in &entries {
+    println!(">      {} ({})", entries[0].title(), entries[0].id());
+    for (index, i) in entries[1..].iter().enumerate() {
         println!(
-            "{}",
-            format!(
-                "{}{} {} ({})",
-                if i.id == entries[0].id { ">" } else { "  " },
-                if i.id == entries[0].displayorder[0] {
-                    "*"
-                } else {
-                    " "
-                },
-                i.description.clone().unwrap(),
-                i.id.clone().unwrap()
-            )
+            "{:>3} {} {} ({})",
+            index,
+            if i.id == entries[0].displayorder[0] {
+                "*"
+            } else {
+                " "
+            },
+            i.title(),
+            i.id()
         );
     }
     Ok(())
 }
 
+pub(crate) fn show_bcd_status() -> Result<()> {
+    let entries = get_bcd_entries()?;
+    let describe = |id: Option<String>| match id {
+        Some(id) => match entries.iter().skip(1).find(|e| e.id == Some(id.clone())) {
+            Some(e) => format!("{} ({})", e.description.clone().unwrap_or_default(), id),
+            None => id,
+        },
+        None => "not set".to_string(),
+    };
+
+    let output = run_bcdedit(&["/enum", "{current}"])?;
+    let current = split_sections(&output)
+        .into_iter()
+        .map(|section| parse_entry(section, Vec::new()))
+        .find_map(|e| e.description);
+
+    println!("Firmware:");
+    println!(
+        "  Current: {}",
+        current.map_or("unknown".to_string(), |d| format!("{} ({{current}})", d))
+    );
+    println!("  Next:    {}", describe(entries[0].bootsequence.clone()));
+    println!(
+        "  Default: {}",
+        describe(entries[0].displayorder.first().cloned().flatten())
+    );
+    Ok(())
+}
+
+/// Get the firmware entry booted by default
+/// # Returns
+/// * `Result<Option<String>>` - The identifier first in the display order of `{fwbootmgr}`
+pub(crate) fn get_bcd_default() -> Result<Option<String>> {
+    let entries = get_bcd_entries()?;
+    Ok(entries
+        .first()
+        .and_then(|e| e.displayorder.first().cloned().flatten()))
+}
+
+/// Get the firmware boot order
+/// # Returns
+/// * `Result<Vec<String>>` - The identifiers of the display order of `{fwbootmgr}`
+pub(crate) fn get_bcd_order() -> Result<Vec<String>> {
+    let entries = get_bcd_entries()?;
+    Ok(entries
+        .first()
+        .map(|e| e.displayorder.iter().flatten().cloned().collect())
+        .unwrap_or_default())
+}
+
 pub(crate) fn set_bcd_entry(entry: String) -> Result<()> {
+    let id = find_bcd_entry(&entry)?.identifier()?;
+    run_bcdedit(&[
+        "/set",
+        "{fwbootmgr}",
+        "displayorder",
+        id.as_str(),
+        "/addfirst",
+    ])?;
+    Ok(())
+}
+
+/// Get the firmware entry booted on the next boot only
+/// # Returns
+/// * `Result<Option<String>>` - The identifier of the bootsequence of `{fwbootmgr}`
+pub(crate) fn get_bcd_oneshot() -> Result<Option<String>> {
     let entries = get_bcd_entries()?;
-    let find_entry = entries.iter().skip(1).any(|i| {
-        i.description.clone().unwrap().to_lowercase() == entry.to_lowercase()
-            || i.id.clone().unwrap().to_lowercase() == entry.to_lowercase()
-    });
-    if !find_entry {
-        Err(Error::new(ErrorKind::NotFound, "BCD entry not found"))
-    } else {
-        if Command::new("bcdedit.exe")
-            .args(&[
+    Ok(entries.first().and_then(|e| e.bootsequence.clone()))
+}
+
+pub(crate) fn set_bcd_oneshot(entry: String) -> Result<()> {
+    let id = find_bcd_entry(&entry)?.identifier()?;
+    run_bcdedit(&["/set", "{fwbootmgr}", "bootsequence", id.as_str()])?;
+    Ok(())
+}
+
+/// Create a firmware entry, bcdedit /create cannot make firmware applications,
+/// so the entry is copied from {bootmgr} and retargeted
+pub(crate) fn create_bcd_entry(spec: &FwEntrySpec) -> Result<()> {
+    let output = run_bcdedit(&["/copy", "{bootmgr}", "/d", spec.label.as_str()])?;
+    let id = Regex::new(r"\{[0-9a-fA-F-]{36}\}")
+        .unwrap()
+        .find(&output)
+        .map(|m| m.as_str().to_string())
+        // The copy is not made in dry-run mode, so it has no identifier yet
+        .or(dry_run::is_enabled().then(|| "{new-entry}".to_string()))
+        .ok_or(Error::new(
+            ErrorKind::InvalidData,
+            "Failed to get identifier of the new BCD entry",
+        ))?;
+
+    for element in BOOTMGR_ONLY_ELEMENTS {
+        let _ = run_bcdedit(&["/deletevalue", id.as_str(), element]);
+    }
+
+    let device = format!(
+        "partition=\\Device\\Harddisk{}\\Partition{}",
+        spec.disk, spec.part
+    );
+    let path = normalize_loader_path(&spec.loader);
+    run_bcdedit(&["/set", id.as_str(), "device", device.as_str()])?;
+    run_bcdedit(&["/set", id.as_str(), "path", path.as_str()])?;
+    if let Some(args) = &spec.args {
+        run_bcdedit(&["/set", id.as_str(), "loadoptions", args.as_str()])?;
+    }
+    run_bcdedit(&[
+        "/set",
+        "{fwbootmgr}",
+        "displayorder",
+        id.as_str(),
+        "/addlast",
+    ])?;
+    println!("Created firmware entry {} ({})", spec.label, id);
+    Ok(())
+}
+
+pub(crate) fn delete_bcd_entry(entry: String) -> Result<()> {
+    let target = find_bcd_entry(&entry)?;
+    let id = target.identifier()?;
+    if id.to_lowercase() == "{bootmgr}" {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "Refusing to delete the Windows Boot Manager",
+        ));
+    }
+    run_bcdedit(&[
+        "/set",
+        "{fwbootmgr}",
+        "displayorder",
+        id.as_str(),
+        "/remove",
+    ])?;
+    run_bcdedit(&["/delete", id.as_str()])?;
+    println!(
+        "Deleted firmware entry {} ({})",
+        target.description.unwrap_or_default(),
+        id
+    );
+    Ok(())
+}
+
+/// Modify a firmware entry, BCD has no notion of hidden entries and tracks
+/// active entries through the {fwbootmgr} display order
+pub(crate) fn modify_bcd_entry(entry: String, update: &FwEntryUpdate) -> Result<()> {
+    if update.hidden.is_some() {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            "Hiding firmware entries is not supported by BCD",
+        ));
+    }
+    let entries = get_bcd_entries()?;
+    let target = find_bcd_entry(&entry)?;
+    let id = target.identifier()?;
+
+    if let Some(label) = &update.label {
+        run_bcdedit(&["/set", id.as_str(), "description", label.as_str()])?;
+    }
+    if let Some(loader) = &update.loader {
+        let path = normalize_loader_path(loader);
+        run_bcdedit(&["/set", id.as_str(), "path", path.as_str()])?;
+    }
+    if let Some(args) = &update.args {
+        if args.is_empty() {
+            run_bcdedit(&["/deletevalue", id.as_str(), "loadoptions"])?;
+        } else {
+            run_bcdedit(&["/set", id.as_str(), "loadoptions", args.as_str()])?;
+        }
+    }
+    let in_order = entries[0].displayorder.contains(&target.id);
+    match update.active {
+        Some(true) if !in_order => {
+            run_bcdedit(&[
                 "/set",
                 "{fwbootmgr}",
                 "displayorder",
-                entry.as_str(),
-                "/addfirst",
-            ])
-            .status()
-            .is_err()
-        {
-            return Err(Error::new(ErrorKind::Other, "set BCD entry failed"));
+                id.as_str(),
+                "/addlast",
+            ])?;
         }
-        Ok(())
+        Some(false) if in_order => {
+            run_bcdedit(&[
+                "/set",
+                "{fwbootmgr}",
+                "displayorder",
+                id.as_str(),
+                "/remove",
+            ])?;
+        }
+        _ => {}
     }
+    println!(
+        "Modified firmware entry {} ({})",
+        update
+            .label
+            .clone()
+            .or(target.description)
+            .unwrap_or_default(),
+        id
+    );
+    Ok(())
 }
 
-pub(crate) fn get_grub_location(description: Option<String>) -> Result<Option<String>> {
+pub(crate) fn show_bcd_timeout() -> Result<()> {
+    match get_bcd_entries()?[0].timeout {
+        Some(timeout) => println!("Firmware timeout: {}s", timeout),
+        None => println!("Firmware timeout: not set"),
+    }
+    Ok(())
+}
+
+pub(crate) fn get_bcd_timeout() -> Result<Option<u16>> {
+    Ok(get_bcd_entries()?[0]
+        .timeout
+        .map(|t| t.min(u16::MAX as u32) as u16))
+}
+
+pub(crate) fn set_bcd_timeout(timeout: u16) -> Result<()> {
+    let timeout = timeout.to_string();
+    run_bcdedit(&["/set", "{fwbootmgr}", "timeout", timeout.as_str()])?;
+    Ok(())
+}
+
+/// Get the Windows boot loaders of the Windows Boot Manager menu
+/// # Returns
+/// * `Result<Vec<BootEntry>>` - The loaders, marking the default one and the one of the bootsequence
+pub(crate) fn get_bootmgr_entries() -> Result<Vec<BootEntry>> {
+    let bootmgr = run_bcdedit(&["/enum", "{bootmgr}", "/v"])?;
+    let default = find_element(&bootmgr, "default");
+    let next = find_element(&bootmgr, "bootsequence");
+    let output = run_bcdedit(&["/enum", "osloader", "/v"])?;
+    Ok(split_sections(&output)
+        .iter()
+        .filter_map(|section| {
+            let id = find_element(section, "identifier")?;
+            Some(BootEntry {
+                title: find_element(section, "description")
+                    .unwrap_or_default()
+                    .to_string(),
+                is_default: Some(id) == default,
+                is_oneshot: Some(id) == next,
+                id: id.to_string(),
+            })
+        })
+        .collect())
+}
+
+/// Set the default entry of the Windows Boot Manager menu
+/// # Arguments
+/// * `entry` - The selector of the Windows boot loader
+pub(crate) fn set_bootmgr_default(entry: &str) -> Result<()> {
+    let entries = get_bootmgr_entries()?;
+    let id = select(&entries, entry, "Windows Boot Manager")?.id.clone();
+    run_bcdedit(&["/default", id.as_str()])?;
+    Ok(())
+}
+
+/// Get the value of an element in a section of bcdedit output
+fn find_element<'a>(section: &'a str, key: &str) -> Option<&'a str> {
+    section.lines().find_map(|line| {
+        let (name, value) = line.trim().split_once(char::is_whitespace)?;
+        (name == key).then(|| value.trim())
+    })
+}
+
+fn find_bcd_entry(entry: &str) -> Result<BcdEntry> {
+    let entries = get_bcd_entries()?;
+    match select(&entries[1..], entry, "BCD") {
+        Ok(found) => Ok(found.clone()),
+        // Entries removed from the display order are only reachable by identifier
+        Err(e) if e.kind() == ErrorKind::NotFound && entry.starts_with('{') => {
+            let output = run_bcdedit(&["/enum", entry])?;
+            split_sections(&output)
+                .into_iter()
+                .map(|section| parse_entry(section, vec![Some(entry.to_string())]))
+                .find(|e| e.id.is_some())
+                .ok_or(e)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Export the system BCD store
+/// # Returns
+/// * `Result<Vec<u8>>` - The store as written by `bcdedit /export`
+pub(crate) fn export_bcd_store() -> Result<Vec<u8>> {
+    // Named after the process, so concurrent runs do not share the file
+    let path = std::env::temp_dir().join(format!("bootmgr-{}-export.bcd", std::process::id()));
+    let path_str = path.to_string_lossy().to_string();
+    run_bcdedit(&["/export", path_str.as_str()])?;
+    let data = std::fs::read(&path);
+    let _ = std::fs::remove_file(&path);
+    // bcdedit leaves a transaction log next to the export
+    let _ = std::fs::remove_file(path.with_extension("bcd.LOG"));
+    data
+}
+
+/// List every object of the system BCD store with its elements
+/// # Returns
+/// * `Result<Vec<u8>>` - The output of `bcdedit /enum all /v`
+pub(crate) fn list_bcd_store() -> Result<Vec<u8>> {
+    Ok(run_bcdedit(&["/enum", "all", "/v"])?.into_bytes())
+}
+
+/// Replace the system BCD store with an exported one
+///
+/// In dry-run mode the differences between the `bcdedit /enum all /v`
+/// listings of the system store and the exported one are shown.
+/// # Arguments
+/// * `data` - The store as written by `bcdedit /export`
+pub(crate) fn import_bcd_store(data: &[u8]) -> Result<()> {
+    let path = std::env::temp_dir().join(format!("bootmgr-{}-import.bcd", std::process::id()));
+    let path_str = path.to_string_lossy().to_string();
+    if dry_run::is_enabled() {
+        std::fs::write(&path, data)?;
+        let imported = run_bcdedit(&["/store", path_str.as_str(), "/enum", "all", "/v"]);
+        let _ = std::fs::remove_file(&path);
+        let imported = imported?;
+        let current = String::from_utf8_lossy(&list_bcd_store()?).to_string();
+        match current == imported {
+            true => output::note("BCD store: unchanged"),
+            false => output::note(
+                dry_run::unified_diff("BCD store", Some(&current), &imported)
+                    .trim_end_matches('\n'),
+            ),
+        }
+    }
+    dry_run::run_command(&["bcdedit.exe", "/import", path_str.as_str()], || {
+        std::fs::write(&path, data)?;
+        let result = run_bcdedit(&["/import", path_str.as_str()]);
+        let _ = std::fs::remove_file(&path);
+        result.map(|_| ())
+    })
+}
+
+/// Run bcdedit and fail if it reports an error
+///
+/// Commands other than `/enum` and `/export`, also on a store given by
+/// `/store`, change the BCD store, in dry-run mode they are only printed.
+/// # Returns
+/// * `Result<String>` - The standard output of bcdedit, empty in dry-run mode
+fn run_bcdedit(args: &[&str]) -> Result<String> {
+    let run = || {
+        let output = Command::new("bcdedit.exe").args(args).output()?;
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+        if !output.status.success() {
+            return Err(Error::other(format!(
+                "bcdedit {} failed: {}",
+                args.join(" "),
+                stdout.trim()
+            )));
+        }
+        Ok(stdout)
+    };
+    let command = match args {
+        ["/store", _, rest @ ..] => rest.first(),
+        _ => args.first(),
+    };
+    match command {
+        Some(&"/enum") | Some(&"/export") => run(),
+        _ => track_bcd_change(|| dry_run::run_command(&[&["bcdedit.exe"], args].concat(), run)),
+    }
+}
+
+/// Change the BCD store and record the change in the change journal
+///
+/// The store is only exported before its first change of a command. A first
+/// change leaving the `bcdedit /enum all /v` listing as it was is forgotten,
+/// as the export and the listing cannot be compared.
+fn track_bcd_change(change: impl FnOnce() -> Result<String>) -> Result<String> {
+    let before = RefCell::new(None);
+    let ret = journal::track_with(
+        ChangeTarget::Bcd,
+        || {
+            *before.borrow_mut() = Some(list_bcd_store()?);
+            export_bcd_store().map(Some)
+        },
+        || list_bcd_store().map(Some),
+        change,
+    );
+    if let Some(before) = before.take()
+        && journal::changes()
+            .iter()
+            .any(|c| c.target == ChangeTarget::Bcd && c.new.as_ref() == Some(&before))
+    {
+        journal::reset(&ChangeTarget::Bcd, None);
+    }
+    ret
+}
+
+/// Find the partition of the firmware entry starting GRUB
+/// # Arguments
+/// * `description` - A part of the description of the entry
+/// * `keywords` - More parts of descriptions telling an entry starts GRUB, from the configuration
+/// # Returns
+/// * `Result<Option<String>>` - The device of the partition, None if no entry matches
+pub(crate) fn get_grub_location(
+    description: Option<String>,
+    keywords: &[String],
+) -> Result<Option<String>> {
     let mut grub_keywords: Vec<String> = vec![
         "grub".to_string(),
         "debian".to_string(),
         "ubuntu".to_string(),
     ];
+    grub_keywords.extend(keywords.iter().map(|k| k.to_lowercase()));
 
     if let Some(desc) = description {
         grub_keywords.push(desc.to_lowercase());
@@ -100,6 +521,74 @@ pub(crate) fn get_grub_location(description: Option<String>) -> Result<Option<St
     Ok(None)
 }
 
+/// Find the firmware entry starting the GRUB of a partition
+/// # Arguments
+/// * `device` - The device of the partition, as returned by [`get_grub_location`]
+/// # Returns
+/// * `Result<Option<String>>` - The id of the entry, None unless exactly one entry on the partition starts GRUB
+pub(crate) fn get_device_grub_entry(device: &str) -> Result<Option<String>> {
+    let entries: Vec<BcdEntry> = get_bcd_entries()?
+        .into_iter()
+        .filter(|e| e.entry_on_disk())
+        .filter(|e| {
+            e.device
+                .as_ref()
+                .and_then(|d| d.split_once('='))
+                .is_some_and(|(_, value)| value.trim().eq_ignore_ascii_case(device))
+        })
+        .filter(|e| {
+            e.path.as_deref().and_then(BootloaderKind::from_loader_path)
+                == Some(BootloaderKind::Grub)
+        })
+        .collect();
+    Ok(match entries.as_slice() {
+        [entry] => Some(entry.id().to_string()),
+        _ => None,
+    })
+}
+
+/// Find the partition of the firmware entry starting a loader
+/// # Arguments
+/// * `loader` - A case-insensitive part of the loader path, e.g. `systemd-boot`
+/// # Returns
+/// * `Result<Option<String>>` - The device of the partition, None if no entry starts the loader
+pub(crate) fn get_loader_location(loader: &str) -> Result<Option<String>> {
+    let loader = loader.to_lowercase();
+    Ok(get_bcd_entries()?
+        .into_iter()
+        .filter(|e| e.entry_on_disk())
+        .filter(|e| {
+            e.path
+                .as_ref()
+                .is_some_and(|p| p.to_lowercase().contains(&loader))
+        })
+        .find_map(|e| {
+            e.device?
+                .split_once('=')
+                .map(|(_, value)| value.trim().to_string())
+        }))
+}
+
+/// Get the loaders started by the firmware entries of the BCD store
+///
+/// Windows always runs through the Windows Boot Manager, so `{bootmgr}` is
+/// the current entry.
+/// # Returns
+/// * `Result<Vec<FwLoader>>` - The firmware entries as listed by bcdedit, without `{fwbootmgr}`
+pub(crate) fn get_bcd_loaders() -> Result<Vec<FwLoader>> {
+    // {fwbootmgr} holds the display order, it starts no loader
+    Ok(get_bcd_entries()?
+        .into_iter()
+        .skip(1)
+        .map(|e| FwLoader {
+            id: e.id().to_string(),
+            description: e.title().to_string(),
+            is_current: e.id.as_deref() == Some("{bootmgr}"),
+            path: e.path,
+        })
+        .collect())
+}
+
 fn get_bcd_entries() -> Result<Vec<BcdEntry>> {
     let output = run_bcdedit_enum()?;
     Ok(parse_bcd_entries(output))
@@ -152,6 +641,7 @@ fn parse_entry(section: String, order: Vec<Option<String>>) -> BcdEntry {
             "default" => ret.default = value,
             "resumeobject" => ret.resumeobject = value,
             "toolsdisplayorder" => ret.toolsdisplayorder = value,
+            "bootsequence" => ret.bootsequence = value,
             "timeout" => {
                 ret.timeout = value.unwrap().trim().parse::<u32>().ok();
             }
@@ -0,0 +1,234 @@
+use crate::common::dry_run;
+use crate::common::journal::{self, ChangeTarget};
+use crate::win::make_os_str;
+use std::io::{Error, Result};
+use windows::Win32::Foundation::{CloseHandle, HANDLE, LUID};
+use windows::Win32::Security::{
+    AdjustTokenPrivileges, LUID_AND_ATTRIBUTES, LookupPrivilegeValueW, SE_PRIVILEGE_ENABLED,
+    SE_SYSTEM_ENVIRONMENT_NAME, TOKEN_ADJUST_PRIVILEGES, TOKEN_PRIVILEGES, TOKEN_QUERY,
+};
+use windows::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
+use windows::Win32::System::WindowsProgramming::{
+    GetFirmwareEnvironmentVariableExW, SetFirmwareEnvironmentVariableExW,
+};
+use windows::core::PCWSTR;
+
+const EFI_GLOBAL_GUID: &str = "{8BE4DF61-93CA-11D2-AA0D-00E098032B8C}";
+const EFI_VARIABLE_DEFAULT_ATTRIBUTES: u32 = 0x0000_0007;
+const ERROR_ENVVAR_NOT_FOUND: i32 = 203;
+const MAX_VARIABLE_SIZE: usize = 4096;
+/// The EFI global vendor GUID in its in-memory layout
+const EFI_GLOBAL_GUID_BYTES: [u8; 16] = [
+    0x61, 0xdf, 0xe4, 0x8b, 0xca, 0x93, 0xd2, 0x11, 0xaa, 0x0d, 0x00, 0xe0, 0x98, 0x03, 0x2b, 0x8c,
+];
+const SYSTEM_ENVIRONMENT_NAME_INFORMATION: u32 = 1;
+const STATUS_BUFFER_TOO_SMALL: i32 = 0xC000_0023_u32 as i32;
+const ENUM_BUFFER_SIZE: u32 = 64 * 1024;
+
+#[link(name = "ntdll")]
+unsafe extern "system" {
+    fn NtEnumerateSystemEnvironmentValuesEx(
+        information_class: u32,
+        buffer: *mut core::ffi::c_void,
+        buffer_length: *mut u32,
+    ) -> i32;
+}
+
+/// Enable a privilege of the process, e.g. the one required to access firmware variables
+/// # Arguments
+/// * `name` - The name of the privilege
+pub(crate) fn enable_privilege(name: PCWSTR) -> Result<()> {
+    unsafe {
+        let mut token_handle = HANDLE::default();
+        OpenProcessToken(
+            GetCurrentProcess(),
+            TOKEN_ADJUST_PRIVILEGES | TOKEN_QUERY,
+            &mut token_handle,
+        )?;
+
+        let mut luid = LUID::default();
+        let result = LookupPrivilegeValueW(PCWSTR::null(), name, &mut luid).and_then(|_| {
+            let privileges = TOKEN_PRIVILEGES {
+                PrivilegeCount: 1,
+                Privileges: [LUID_AND_ATTRIBUTES {
+                    Luid: luid,
+                    Attributes: SE_PRIVILEGE_ENABLED,
+                }],
+            };
+            AdjustTokenPrivileges(token_handle, false, Some(&privileges), 0, None, None)
+        });
+
+        CloseHandle(token_handle)?;
+        Ok(result?)
+    }
+}
+
+/// Split a variable name into the name and vendor GUID the firmware API expects
+///
+/// Names without a `-<vendor GUID>` suffix are global variables.
+fn split_vendor(name: &str) -> (Vec<u16>, Vec<u16>) {
+    match name.split_once('-') {
+        Some((name, guid)) => (
+            make_os_str(name),
+            make_os_str(&format!("{{{}}}", guid.to_uppercase())),
+        ),
+        None => (make_os_str(name), make_os_str(EFI_GLOBAL_GUID)),
+    }
+}
+
+/// Read an EFI variable
+/// # Arguments
+/// * `name` - The variable name, optionally followed by `-<vendor GUID>`
+/// # Returns
+/// * `Result<Option<Vec<u8>>>` - The variable data, None if it does not exist
+pub(crate) fn read_efivar(name: &str) -> Result<Option<Vec<u8>>> {
+    enable_privilege(SE_SYSTEM_ENVIRONMENT_NAME)?;
+    let (name_w, guid_w) = split_vendor(name);
+    let mut buf = vec![0u8; MAX_VARIABLE_SIZE];
+
+    let size = unsafe {
+        GetFirmwareEnvironmentVariableExW(
+            PCWSTR(name_w.as_ptr()),
+            PCWSTR(guid_w.as_ptr()),
+            Some(buf.as_mut_ptr() as *mut _),
+            buf.len() as u32,
+            None,
+        )
+    };
+    if size == 0 {
+        let err = Error::last_os_error();
+        return match err.raw_os_error() {
+            Some(ERROR_ENVVAR_NOT_FOUND) => Ok(None),
+            _ => Err(err),
+        };
+    }
+    buf.truncate(size as usize);
+    Ok(Some(buf))
+}
+
+/// Get the names of the EFI variables holding the boot configuration
+/// # Returns
+/// * `Result<Vec<String>>` - Every `Boot####` variable, those of the boot order first, then `BootOrder` and `Timeout` if set
+pub(crate) fn get_boot_var_names() -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    let order = read_efivar("BootOrder")?;
+    for num in order.as_deref().unwrap_or_default().chunks_exact(2) {
+        let name = format!("Boot{:04X}", u16::from_le_bytes([num[0], num[1]]));
+        if !names.contains(&name) && read_efivar(&name)?.is_some() {
+            names.push(name);
+        }
+    }
+    let mut others: Vec<String> = get_global_var_names()?
+        .into_iter()
+        .filter(|name| is_boot_var_name(name) && !names.contains(name))
+        .collect();
+    others.sort();
+    names.extend(others);
+    if order.is_some() {
+        names.push("BootOrder".to_string());
+    }
+    if read_efivar("Timeout")?.is_some() {
+        names.push("Timeout".to_string());
+    }
+    Ok(names)
+}
+
+/// Check whether a variable name is `Boot` followed by four hex digits
+fn is_boot_var_name(name: &str) -> bool {
+    name.strip_prefix("Boot")
+        .is_some_and(|num| num.len() == 4 && num.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Get the names of the firmware variables of the EFI global vendor
+///
+/// The Win32 API cannot enumerate firmware variables, ntdll can.
+fn get_global_var_names() -> Result<Vec<String>> {
+    enable_privilege(SE_SYSTEM_ENVIRONMENT_NAME)?;
+    let mut length = ENUM_BUFFER_SIZE;
+    let buf = loop {
+        let mut buf = vec![0u32; (length as usize).div_ceil(4)];
+        let status = unsafe {
+            NtEnumerateSystemEnvironmentValuesEx(
+                SYSTEM_ENVIRONMENT_NAME_INFORMATION,
+                buf.as_mut_ptr().cast(),
+                &mut length,
+            )
+        };
+        match status {
+            0 => break buf,
+            // The needed length is returned, grow the buffer if it is not
+            STATUS_BUFFER_TOO_SMALL if length as usize > buf.len() * 4 => continue,
+            STATUS_BUFFER_TOO_SMALL => length = (buf.len() * 8) as u32,
+            _ => {
+                return Err(Error::other(format!(
+                    "NtEnumerateSystemEnvironmentValuesEx failed: 0x{:08X}",
+                    status as u32
+                )));
+            }
+        }
+    };
+    let data: Vec<u8> = buf.iter().flat_map(|w| w.to_le_bytes()).collect();
+    let data = &data[..(length as usize).min(data.len())];
+
+    // VARIABLE_NAME records: next entry offset, vendor GUID, null terminated name
+    let mut names = Vec::new();
+    let mut offset = 0;
+    while offset + 20 <= data.len() {
+        let next = u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
+        let end = match next {
+            0 => data.len(),
+            next => (offset + next).min(data.len()),
+        };
+        if data[offset + 4..offset + 20] == EFI_GLOBAL_GUID_BYTES {
+            let name: Vec<u16> = data[offset + 20..end]
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .take_while(|c| *c != 0)
+                .collect();
+            names.push(String::from_utf16_lossy(&name));
+        }
+        if next == 0 {
+            break;
+        }
+        offset += next;
+    }
+    Ok(names)
+}
+
+/// Write an EFI variable with the default attributes
+pub(crate) fn write_efivar(name: &str, data: &[u8]) -> Result<()> {
+    journal::track(
+        ChangeTarget::EfiVar(name.to_string()),
+        || read_efivar(name),
+        || dry_run::write_efivar(name, data, || set_efivar(name, data)),
+    )
+}
+
+/// Delete an EFI variable
+pub(crate) fn delete_efivar(name: &str) -> Result<()> {
+    journal::track(
+        ChangeTarget::EfiVar(name.to_string()),
+        || read_efivar(name),
+        || dry_run::delete_efivar(name, || set_efivar(name, &[])),
+    )
+}
+
+/// Set an EFI variable, empty data deletes it
+fn set_efivar(name: &str, data: &[u8]) -> Result<()> {
+    enable_privilege(SE_SYSTEM_ENVIRONMENT_NAME)?;
+    let (name_w, guid_w) = split_vendor(name);
+
+    unsafe {
+        SetFirmwareEnvironmentVariableExW(
+            PCWSTR(name_w.as_ptr()),
+            PCWSTR(guid_w.as_ptr()),
+            match data.is_empty() {
+                true => None,
+                false => Some(data.as_ptr() as *const _),
+            },
+            data.len() as u32,
+            EFI_VARIABLE_DEFAULT_ATTRIBUTES,
+        )?;
+    }
+    Ok(())
+}
@@ -1,19 +1,33 @@
 mod bcd_helper;
+mod efivar_helper;
 mod volume_helper;
 
-use crate::common::file_operations;
-use crate::interface::{Handle, Interface, TempMount};
+use crate::bootloader::BootEntry;
+use crate::common::dry_run;
+use crate::common::output;
+use crate::platform::{
+    FwEntrySpec, FwEntryUpdate, FwLoader, Handle, Platform, TempMount, VolumeDir,
+};
 use std::ffi::OsStr;
-use std::fs::File;
-use std::io::{Error, ErrorKind, Result, Write};
+use std::io::{Error, ErrorKind, Result};
 use std::os::windows::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 use windows::Win32::Foundation::{CloseHandle, HANDLE, HWND};
-use windows::Win32::Security::{GetTokenInformation, TOKEN_ELEVATION, TOKEN_QUERY, TokenElevation};
+use windows::Win32::Security::{
+    GetTokenInformation, SE_SHUTDOWN_NAME, TOKEN_ELEVATION, TOKEN_QUERY, TokenElevation,
+};
+use windows::Win32::System::Shutdown::{
+    EWX_REBOOT, ExitWindowsEx, SHTDN_REASON_FLAG_PLANNED, SHTDN_REASON_MAJOR_OTHER,
+};
 use windows::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
 use windows::Win32::UI::Shell::ShellExecuteW;
 use windows::Win32::UI::WindowsAndMessaging::SW_SHOWNORMAL;
 use windows::core::{PCWSTR, w};
 
+/// The name of the configuration files, in the data directory and the user's roaming directory
+const CONFIG_FILE: &str = "config.toml";
+
 fn is_admin() -> Result<bool> {
     unsafe {
         let mut token_handle = HANDLE::default();
@@ -67,7 +81,7 @@ fn rerun_as_administrator() -> Result<()> {
     }
 }
 
-impl Interface for Handle {
+impl Platform for Handle {
     fn check_permission(&self) -> Result<bool> {
         Ok(is_admin()?)
     }
@@ -75,44 +89,199 @@ impl Interface for Handle {
         rerun_as_administrator()
     }
 
-    fn get_file(&mut self, path: &str) -> Result<File> {
-        let device = self.get_grub_loc()?;
+    fn mount_grub_dir(&mut self) -> Result<VolumeDir> {
+        let device = match &self.grub_loc {
+            Some(loc) => loc.clone(),
+            None => bcd_helper::get_grub_location(self.grub_desc.clone(), &self.grub_keywords)?
+                .ok_or(Error::new(ErrorKind::NotFound, "GRUB location not found"))?,
+        };
+        self.grub_loc = Some(device.clone());
+
         let mount = TempMount::new(&device)?;
+        Ok(VolumeDir {
+            path: mount.path(),
+            _mount: mount,
+        })
+    }
 
-        let file = file_operations::open_file_ro(mount.path().join(path))?;
+    fn find_grub_fw_entry(&mut self) -> Result<Option<String>> {
+        let device = match &self.grub_loc {
+            Some(loc) => loc.clone(),
+            None => match bcd_helper::get_grub_location(self.grub_desc.clone(), &self.grub_keywords)? {
+                Some(device) => device,
+                None => return Ok(None),
+            },
+        };
+        bcd_helper::get_device_grub_entry(&device)
+    }
 
-        Ok(file)
+    fn find_volume_root(&self, dir: &Path) -> Result<Option<PathBuf>> {
+        // Only the partition GRUB was loaded from can be reached from Windows
+        Ok(Some(dir.to_path_buf()))
     }
 
-    fn write_file(&mut self, path: &str, content: &str) -> Result<()> {
-        let device = self.get_grub_loc()?;
-        let mount = TempMount::new(&device)?;
+    fn find_uuid_volume(&self, _uuid: &str) -> Result<Option<PathBuf>> {
+        Ok(None)
+    }
 
-        let mut file = file_operations::open_file_wo(mount.path().join(path))?;
+    fn show_fw_status(&self) -> Result<()> {
+        bcd_helper::show_bcd_status()
+    }
 
-        file.write_all(content.as_bytes())?;
-        Ok(())
+    fn get_boot_cmdline(&self) -> Result<Option<String>> {
+        Ok(None)
     }
 
     fn show_fw_entry(&self) -> Result<()> {
         bcd_helper::show_bcd_list()
     }
 
+    fn get_fw_loaders(&self) -> Result<Vec<FwLoader>> {
+        bcd_helper::get_bcd_loaders()
+    }
+
+    fn get_fw_default(&self) -> Result<Option<String>> {
+        bcd_helper::get_bcd_default()
+    }
+
+    fn get_fw_order(&self) -> Result<Vec<String>> {
+        bcd_helper::get_bcd_order()
+    }
+
     fn set_fw_entry(&self, entry: String) -> Result<()> {
-        println!("Set BCD firmware entry to {}", entry);
+        output::note(&format!("Set BCD firmware entry to {}", entry));
         bcd_helper::set_bcd_entry(entry)
     }
 
-    fn get_grub_loc(&mut self) -> Result<String> {
-        if let Some(loc) = &self.grub_loc {
-            return Ok(loc.clone());
+    fn get_fw_oneshot(&self) -> Result<Option<String>> {
+        bcd_helper::get_bcd_oneshot()
+    }
+
+    fn set_fw_oneshot(&self, entry: String) -> Result<()> {
+        output::note(&format!("Boot BCD firmware entry {} once", entry));
+        bcd_helper::set_bcd_oneshot(entry)
+    }
+
+    fn create_fw_entry(&self, spec: &FwEntrySpec) -> Result<()> {
+        bcd_helper::create_bcd_entry(spec)
+    }
+
+    fn delete_fw_entry(&self, entry: String) -> Result<()> {
+        bcd_helper::delete_bcd_entry(entry)
+    }
+
+    fn modify_fw_entry(&self, entry: String, update: &FwEntryUpdate) -> Result<()> {
+        bcd_helper::modify_bcd_entry(entry, update)
+    }
+
+    fn show_fw_timeout(&self) -> Result<()> {
+        bcd_helper::show_bcd_timeout()
+    }
+
+    fn get_fw_timeout(&self) -> Result<Option<u16>> {
+        bcd_helper::get_bcd_timeout()
+    }
+
+    fn set_fw_timeout(&self, timeout: u16) -> Result<()> {
+        output::note(&format!("Set BCD firmware timeout to {}s", timeout));
+        bcd_helper::set_bcd_timeout(timeout)
+    }
+
+    fn get_efi_var(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        efivar_helper::read_efivar(name)
+    }
+
+    fn set_efi_var(&self, name: &str, data: &[u8]) -> Result<()> {
+        efivar_helper::write_efivar(name, data)
+    }
+
+    fn delete_efi_var(&self, name: &str) -> Result<()> {
+        efivar_helper::delete_efivar(name)
+    }
+
+    fn mount_loader_esp(&mut self, loader: &str, _markers: &[&str]) -> Result<VolumeDir> {
+        let device = bcd_helper::get_loader_location(loader)?.ok_or(Error::new(
+            ErrorKind::NotFound,
+            format!("No firmware entry starts {}", loader),
+        ))?;
+        let mount = TempMount::new(&device)?;
+        Ok(VolumeDir {
+            path: mount.path(),
+            _mount: mount,
+        })
+    }
+
+    fn get_boot_var_names(&self) -> Result<Vec<String>> {
+        efivar_helper::get_boot_var_names()
+    }
+
+    fn export_fw_store(&self) -> Result<Option<Vec<u8>>> {
+        Ok(Some(bcd_helper::export_bcd_store()?))
+    }
+
+    fn import_fw_store(&self, data: &[u8]) -> Result<()> {
+        bcd_helper::import_bcd_store(data)
+    }
+
+    fn list_fw_store(&self) -> Result<Option<Vec<u8>>> {
+        Ok(Some(bcd_helper::list_bcd_store()?))
+    }
+
+    fn get_bootmgr_entries(&self) -> Result<Vec<BootEntry>> {
+        bcd_helper::get_bootmgr_entries()
+    }
+
+    fn set_bootmgr_default(&self, entry: &str) -> Result<()> {
+        output::note(&format!("Set Windows Boot Manager default to {}", entry));
+        bcd_helper::set_bootmgr_default(entry)
+    }
+
+    fn get_config_files(&self) -> Result<Vec<PathBuf>> {
+        let mut files = vec![self.get_data_dir()?.join(CONFIG_FILE)];
+        if let Some(app_data) = std::env::var_os("APPDATA") {
+            files.push(PathBuf::from(app_data).join("bootmgr").join(CONFIG_FILE));
         }
+        Ok(files)
+    }
+
+    fn get_data_dir(&self) -> Result<PathBuf> {
+        let program_data = std::env::var_os("ProgramData")
+            .ok_or(Error::new(ErrorKind::NotFound, "ProgramData is not set"))?;
+        Ok(PathBuf::from(program_data).join("bootmgr"))
+    }
 
-        let loc = bcd_helper::get_grub_location(self.grub_desc.clone())?
-            .ok_or(Error::new(ErrorKind::NotFound, "GRUB location not found"))?;
+    fn reboot(&self) -> Result<()> {
+        dry_run::run_command(&["shutdown", "/r", "/t", "0"], || {
+            output::note("Rebooting");
+            let exited = efivar_helper::enable_privilege(SE_SHUTDOWN_NAME).and_then(|_| unsafe {
+                Ok(ExitWindowsEx(
+                    EWX_REBOOT,
+                    SHTDN_REASON_MAJOR_OTHER | SHTDN_REASON_FLAG_PLANNED,
+                )?)
+            });
+            if exited.is_ok() {
+                return Ok(());
+            }
+            let status = Command::new("shutdown").args(["/r", "/t", "0"]).status()?;
+            if !status.success() {
+                return Err(Error::other(format!("shutdown failed with {}", status)));
+            }
+            Ok(())
+        })
+    }
+
+    fn get_root_dir(&self) -> Result<PathBuf> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "A Linux installation can only be managed from Linux",
+        ))
+    }
 
-        self.grub_loc = Some(loc.clone());
-        Ok(loc)
+    fn run_in_root(&self, _command: &[String]) -> Result<()> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "Commands of a Linux installation can only be run from Linux",
+        ))
     }
 }
 
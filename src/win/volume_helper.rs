@@ -1,4 +1,4 @@
-use crate::interface::TempMount;
+use crate::platform::TempMount;
 use crate::win::make_os_str;
 use std::io::{Error, ErrorKind, Result};
 use std::path::PathBuf;